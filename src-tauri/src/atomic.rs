@@ -0,0 +1,168 @@
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replaces `path` with `bytes` without ever leaving a truncated file behind.
+///
+/// The data is written to a temporary file in the same directory, flushed to
+/// disk and renamed over the original, so readers see either the old or the
+/// new contents. The original file's permissions are carried over, and
+/// symlinks are written through rather than replaced.
+pub fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let dir = parent_dir(&path);
+    let permissions = fs::metadata(&path).ok().map(|meta| meta.permissions());
+
+    let (tmp_path, file) = create_temp(dir, &path)?;
+    let result = write_temp(file, bytes, permissions).and_then(|()| fs::rename(&tmp_path, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+    // The new contents are in place by now; failing to sync the directory
+    // only risks losing the rename in a crash, so it isn't reported.
+    let _ = sync_dir(dir);
+    Ok(())
+}
+
+/// Copies the current contents of `path` into a rolling set of `count`
+/// backups: `name.bak` is the newest, followed by `name.bak.1`,
+/// `name.bak.2` and so on. Does nothing when `count` is zero or the file
+/// doesn't exist yet.
+pub fn backup(path: &Path, count: usize) -> io::Result<()> {
+    if count == 0 || !path.exists() {
+        return Ok(());
+    }
+    for index in (0..count - 1).rev() {
+        let from = backup_path(path, index);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, index + 1))?;
+        }
+    }
+    fs::copy(path, backup_path(path, 0))?;
+    Ok(())
+}
+
+fn backup_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    if index > 0 {
+        name.push(format!(".{index}"));
+    }
+    path.with_file_name(name)
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+fn create_temp(dir: &Path, path: &Path) -> io::Result<(PathBuf, File)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy();
+    let pid = std::process::id();
+    for attempt in 0u32.. {
+        let tmp_path = dir.join(format!(".{name}.{pid}.{attempt}.tmp"));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)
+        {
+            Ok(file) => return Ok((tmp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("ran out of temporary file names")
+}
+
+fn write_temp(mut file: File, bytes: &[u8], permissions: Option<Permissions>) -> io::Result<()> {
+    file.write_all(bytes)?;
+    if let Some(permissions) = permissions {
+        file.set_permissions(permissions)?;
+    }
+    file.sync_all()
+}
+
+/// Makes the rename itself durable. Directories can't be opened for syncing
+/// on Windows, where the rename is already flushed by the filesystem.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn replaces_contents_without_leaving_temp_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("note.md");
+        write(&path, b"first").unwrap();
+        write(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        assert_eq!(names(dir), ["note.md"]);
+    }
+
+    #[test]
+    fn temp_file_is_removed_when_the_rename_fails() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        // A directory can't be replaced by a file.
+        let path = dir.join("note.md");
+        fs::create_dir(&path).unwrap();
+        assert!(write(&path, b"text").is_err());
+        assert_eq!(names(dir), ["note.md"]);
+    }
+
+    #[test]
+    fn backups_roll_over() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("note.md");
+        for version in ["1", "2", "3", "4"] {
+            backup(&path, 3).unwrap();
+            write(&path, version.as_bytes()).unwrap();
+        }
+        assert_eq!(
+            names(dir),
+            ["note.md", "note.md.bak", "note.md.bak.1", "note.md.bak.2"]
+        );
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("note.md"), "4");
+        assert_eq!(read("note.md.bak"), "3");
+        assert_eq!(read("note.md.bak.1"), "2");
+        assert_eq!(read("note.md.bak.2"), "1");
+    }
+
+    #[test]
+    fn no_backups_when_disabled() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("note.md");
+        write(&path, b"1").unwrap();
+        backup(&path, 0).unwrap();
+        write(&path, b"2").unwrap();
+        assert_eq!(names(dir), ["note.md"]);
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::document;
+use crate::error::{Error, Result};
+use crate::export::{self, Format};
+
+const USAGE: &str = "\
+Usage: mark-it-down render [OPTIONS] <INPUT>...
+       mark-it-down render --stdin [OPTIONS]
+
+Renders markdown files without opening a window.
+
+Options:
+  -o, --output <PATH>  Output file, or output directory when rendering
+                       several files or a directory
+      --to <FORMAT>    html, txt, pdf or docx (default: from the output
+                       extension, otherwise html)
+      --stdin          Read markdown from standard input
+  -h, --help           Print this help
+
+With a single input and no --output, the result is written to standard
+output. Directories are searched recursively for .md and .markdown files;
+each is written next to its source, or under --output keeping the folder
+structure.";
+
+struct Args {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    format: Option<Format>,
+    stdin: bool,
+}
+
+fn parse(args: &[String]) -> std::result::Result<Option<Args>, String> {
+    let mut parsed = Args {
+        inputs: Vec::new(),
+        output: None,
+        format: None,
+        stdin: false,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--stdin" => parsed.stdin = true,
+            "-o" | "--output" => {
+                let value = args.next().ok_or(format!("{arg} needs a path"))?;
+                parsed.output = Some(PathBuf::from(value));
+            }
+            "--to" => {
+                let value = args.next().ok_or("--to needs a format")?;
+                parsed.format = Some(Format::parse(value).map_err(|e| e.to_string())?);
+            }
+            _ if arg.starts_with("--to=") => {
+                parsed.format = Some(Format::parse(&arg[5..]).map_err(|e| e.to_string())?);
+            }
+            _ if arg.starts_with("--output=") => {
+                parsed.output = Some(PathBuf::from(&arg[9..]));
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("unknown option {arg}"));
+            }
+            _ => parsed.inputs.push(PathBuf::from(arg)),
+        }
+    }
+    if parsed.stdin && !parsed.inputs.is_empty() {
+        return Err("--stdin can't be combined with input files".into());
+    }
+    if !parsed.stdin && parsed.inputs.is_empty() {
+        return Err("no input files".into());
+    }
+    Ok(Some(parsed))
+}
+
+/// A file to render and where its output goes, relative to the output
+/// directory when there is one.
+struct Job {
+    source: PathBuf,
+    relative: PathBuf,
+}
+
+/// Expands directories into the markdown files below them.
+fn collect(inputs: &[PathBuf]) -> Result<Vec<Job>> {
+    let mut jobs = Vec::new();
+    for input in inputs {
+        if !input.is_dir() {
+            let name = input.file_name().map(PathBuf::from).unwrap_or_default();
+            jobs.push(Job {
+                source: input.clone(),
+                relative: name,
+            });
+            continue;
+        }
+        for entry in WalkDir::new(input).sort_by_file_name() {
+            let entry = entry.map_err(|e| {
+                let path = e.path().unwrap_or(input).to_path_buf();
+                match e.into_io_error() {
+                    Some(err) => Error::io(err, &path),
+                    None => Error::Io(format!("{}: filesystem loop", path.display())),
+                }
+            })?;
+            if entry.file_type().is_file() && document::is_markdown(entry.path()) {
+                jobs.push(Job {
+                    source: entry.path().to_path_buf(),
+                    relative: entry
+                        .path()
+                        .strip_prefix(input)
+                        .unwrap_or(entry.path())
+                        .to_path_buf(),
+                });
+            }
+        }
+    }
+    Ok(jobs)
+}
+
+/// Where each job's output goes: under `output` when given, otherwise next
+/// to its source.
+fn targets(jobs: &[Job], output: Option<&Path>, format: Format) -> Vec<PathBuf> {
+    jobs.iter()
+        .map(|job| {
+            match output {
+                Some(dir) => dir.join(&job.relative),
+                None => job.source.clone(),
+            }
+            .with_extension(format.extension())
+        })
+        .collect()
+}
+
+/// The jobs whose output goes where an earlier job's does, as the earlier
+/// source, the later source and the shared target.
+fn collisions<'a>(jobs: &'a [Job], targets: &'a [PathBuf]) -> Vec<(&'a Path, &'a Path, &'a Path)> {
+    let mut sources: HashMap<&Path, &Path> = HashMap::new();
+    let mut collisions = Vec::new();
+    for (job, target) in jobs.iter().zip(targets) {
+        match sources.insert(target, &job.source) {
+            Some(other) if other != job.source => {
+                collisions.push((other, job.source.as_path(), target.as_path()));
+            }
+            _ => {}
+        }
+    }
+    collisions
+}
+
+fn read_source(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).map_err(|e| Error::io(e, path))?;
+    Ok(document::decode(&bytes).0)
+}
+
+fn write_output(path: &Path, bytes: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| Error::io(e, dir))?;
+    }
+    fs::write(path, bytes).map_err(|e| Error::io(e, path))
+}
+
+/// Whether writing to `target` would overwrite `source`, however either path
+/// is spelled.
+fn overwrites(target: &Path, source: &Path) -> bool {
+    target == source || same_file::is_same_file(target, source).unwrap_or(false)
+}
+
+fn overwrite_error(source: &Path) -> Error {
+    Error::Invalid(format!(
+        "{}: output would overwrite the source",
+        source.display()
+    ))
+}
+
+fn write_stdout(bytes: &[u8]) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout
+        .write_all(bytes)
+        .and_then(|()| stdout.flush())
+        .map_err(|e| Error::Io(format!("stdout: {e}")))
+}
+
+/// Runs `render` with the arguments after the subcommand name and returns
+/// the process exit code: 0 on success, 1 if any file failed and 2 for
+/// usage errors.
+pub fn render(args: &[String]) -> i32 {
+    let args = match parse(args) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{USAGE}");
+            return 0;
+        }
+        Err(message) => {
+            eprintln!("mark-it-down render: {message}\n\n{USAGE}");
+            return 2;
+        }
+    };
+    // Without --to, a single output file picks the format by extension.
+    let output_format = args
+        .output
+        .as_deref()
+        .and_then(|path| path.extension())
+        .and_then(|ext| Format::parse(&ext.to_string_lossy()).ok());
+
+    if args.stdin {
+        let mut bytes = Vec::new();
+        if let Err(err) = io::stdin().read_to_end(&mut bytes) {
+            eprintln!("mark-it-down render: stdin: {err}");
+            return 1;
+        }
+        let source = document::decode(&bytes).0;
+        let format = args.format.or(output_format).unwrap_or(Format::Html);
+        let rendered = export::render(&source, format, None);
+        let result = match &args.output {
+            Some(path) => write_output(path, &rendered),
+            None => write_stdout(&rendered),
+        };
+        return report(result.map(|()| 0));
+    }
+
+    let single = args.inputs.len() == 1 && !args.inputs[0].is_dir();
+    if single {
+        let source_path = &args.inputs[0];
+        let format = args.format.or(output_format).unwrap_or(Format::Html);
+        if args
+            .output
+            .as_deref()
+            .is_some_and(|path| overwrites(path, source_path))
+        {
+            return report(Err(overwrite_error(source_path)));
+        }
+        let result = read_source(source_path).and_then(|source| {
+            let rendered = export::render(&source, format, Some(source_path));
+            match &args.output {
+                Some(path) => write_output(path, &rendered),
+                None => write_stdout(&rendered),
+            }
+        });
+        return report(result.map(|()| 0));
+    }
+
+    // Batch mode: --output, if given, is a directory.
+    let format = args.format.unwrap_or(Format::Html);
+    let jobs = match collect(&args.inputs) {
+        Ok(jobs) => jobs,
+        Err(err) => return report(Err(err)),
+    };
+    let targets = targets(&jobs, args.output.as_deref(), format);
+    // Files with the same name from different folders would overwrite each
+    // other's output, so nothing is written.
+    let collisions = collisions(&jobs, &targets);
+    for (other, source, target) in &collisions {
+        eprintln!(
+            "mark-it-down render: {} and {} would both be written to {}",
+            other.display(),
+            source.display(),
+            target.display()
+        );
+    }
+    if !collisions.is_empty() {
+        return 1;
+    }
+
+    let mut failed = 0;
+    for (job, target) in jobs.iter().zip(&targets) {
+        if overwrites(target, &job.source) {
+            eprintln!("mark-it-down render: {}", overwrite_error(&job.source));
+            failed += 1;
+            continue;
+        }
+        let result = read_source(&job.source).and_then(|source| {
+            let rendered = export::render(&source, format, Some(&job.source));
+            write_output(target, &rendered)
+        });
+        match result {
+            Ok(()) => eprintln!("{} -> {}", job.source.display(), target.display()),
+            Err(err) => {
+                eprintln!("mark-it-down render: {err}");
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("{failed} of {} files failed", jobs.len());
+        1
+    } else {
+        0
+    }
+}
+
+fn report(result: Result<i32>) -> i32 {
+    result.unwrap_or_else(|err| {
+        eprintln!("mark-it-down render: {err}");
+        1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> std::result::Result<Option<Args>, String> {
+        parse(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn arguments() {
+        let parsed = args(&["a.md", "-o", "out", "--to", "PDF", "b.md"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(parsed.inputs, [Path::new("a.md"), Path::new("b.md")]);
+        assert_eq!(parsed.output.as_deref(), Some(Path::new("out")));
+        assert_eq!(parsed.format, Some(Format::Pdf));
+        assert!(!parsed.stdin);
+
+        let parsed = args(&["--stdin", "--to=docx", "--output=x.docx"])
+            .unwrap()
+            .unwrap();
+        assert!(parsed.stdin && parsed.inputs.is_empty());
+        assert_eq!(parsed.output.as_deref(), Some(Path::new("x.docx")));
+        assert_eq!(parsed.format, Some(Format::Docx));
+
+        assert!(args(&["a.md", "--help"]).unwrap().is_none());
+        assert!(args(&["-h"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn usage_errors() {
+        for (arguments, message) in [
+            (&[][..], "no input files"),
+            (
+                &["--stdin", "a.md"][..],
+                "--stdin can't be combined with input files",
+            ),
+            (&["a.md", "-o"][..], "-o needs a path"),
+            (&["a.md", "--to"][..], "--to needs a format"),
+            (&["a.md", "--verbose"][..], "unknown option --verbose"),
+        ] {
+            assert_eq!(args(arguments).err().as_deref(), Some(message));
+        }
+        assert!(args(&["a.md", "--to=rtf"]).is_err());
+    }
+
+    #[test]
+    fn output_collisions() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for name in [
+            "a/note.md",
+            "a/sub/note.md",
+            "b/note.md",
+            "b/readme.markdown",
+            "b/x.txt",
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "# Note").unwrap();
+        }
+        let inputs = [dir.join("a"), dir.join("b"), dir.join("b/note.md")];
+        let jobs = collect(&inputs).unwrap();
+        let relative: Vec<&Path> = jobs.iter().map(|job| job.relative.as_path()).collect();
+        assert_eq!(
+            relative,
+            [
+                Path::new("note.md"),
+                Path::new("sub/note.md"),
+                Path::new("note.md"),
+                Path::new("readme.markdown"),
+                Path::new("note.md"),
+            ]
+        );
+
+        // Next to their sources, only the file given twice shares a target,
+        // which is no collision.
+        let targets = targets(&jobs, None, Format::Html);
+        assert_eq!(targets[0], dir.join("a/note.html"));
+        assert!(collisions(&jobs, &targets).is_empty());
+
+        // In one output folder, a/note.md and b/note.md meet; b/note.md given
+        // again is still no collision.
+        let out = dir.join("out");
+        let targets = super::targets(&jobs, Some(&out), Format::Pdf);
+        assert_eq!(targets[1], out.join("sub/note.pdf"));
+        let found = collisions(&jobs, &targets);
+        assert_eq!(
+            found,
+            [(
+                jobs[0].source.as_path(),
+                jobs[2].source.as_path(),
+                out.join("note.pdf").as_path()
+            )]
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::html;
+use crate::rtf;
+
+/// The rich flavours the webview saw in a paste event, used when the
+/// system clipboard can't be read directly.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Flavours {
+    html: Option<String>,
+    rtf: Option<String>,
+}
+
+/// Converts the rich text on the clipboard to markdown, preferring HTML
+/// over RTF. Returns `None` when there's only plain text, so the editor
+/// pastes that as is.
+#[tauri::command]
+pub async fn paste_markdown(flavours: Flavours) -> Result<Option<String>> {
+    let system_html = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get().html())
+        .ok();
+    if let Some(html) = system_html
+        .or(flavours.html)
+        .filter(|html| !html.trim().is_empty())
+    {
+        return Ok(Some(html_to_markdown(&html)));
+    }
+    if let Some(rtf) = system_rtf().or(flavours.rtf.map(String::into_bytes)) {
+        if !rtf.is_empty() {
+            return Ok(Some(rtf::to_markdown(&rtf)));
+        }
+    }
+    Ok(None)
+}
+
+/// The clipboard's text without formatting, for Paste as Plain Text.
+#[tauri::command]
+pub async fn clipboard_text() -> Result<String> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .map_err(|e| Error::Invalid(format!("Can't read the clipboard: {e}")))
+}
+
+/// Only images on the web stay linked: pasted `data:` and `file:` images
+/// would bloat the document or point at temporary files, so they keep just
+/// their alt text.
+fn html_to_markdown(html: &str) -> String {
+    html::to_markdown(html, &mut |src| {
+        let src = src.trim();
+        (src.starts_with("http://") || src.starts_with("https://")).then(|| src.to_string())
+    })
+}
+
+/// Word and WordPad put RTF on the Windows clipboard, which arboard
+/// doesn't read.
+#[cfg(windows)]
+fn system_rtf() -> Option<Vec<u8>> {
+    let _clipboard = clipboard_win::Clipboard::new_attempts(10).ok()?;
+    let format = clipboard_win::register_format("Rich Text Format")?;
+    let mut rtf = Vec::new();
+    clipboard_win::raw::get_vec(format.get(), &mut rtf).ok()?;
+    // The data is a C string.
+    if let Some(end) = rtf.iter().position(|&byte| byte == 0) {
+        rtf.truncate(end);
+    }
+    Some(rtf)
+}
+
+#[cfg(not(windows))]
+fn system_rtf() -> Option<Vec<u8>> {
+    None
+}
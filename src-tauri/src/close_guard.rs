@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use tauri::{AppHandle, CloseRequestApi, Emitter, ExitRequestApi, Manager, State, Window};
+use tauri_plugin_dialog::{
+    DialogExt, MessageDialogButtons, MessageDialogKind, MessageDialogResult,
+};
+
+use crate::registry::{DocumentEntry, DocumentRegistry};
+
+pub const SAVE_REQUESTED_EVENT: &str = "save-requested";
+
+const SAVE: &str = "Save";
+const DISCARD: &str = "Don't Save";
+const CANCEL: &str = "Cancel";
+
+enum Choice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+/// Keeps windows from closing, and the app from quitting, while documents
+/// have unsaved changes.
+#[derive(Default)]
+pub struct CloseGuard {
+    /// Windows that still have to save before the app quits, while a
+    /// "Save" on quit is in progress.
+    saving_for_quit: Mutex<Option<HashSet<String>>>,
+    /// Set once the user has decided, so the exit we trigger ourselves
+    /// isn't intercepted again.
+    allow_exit: AtomicBool,
+}
+
+impl CloseGuard {
+    fn exit(&self, app: &AppHandle) {
+        self.allow_exit.store(true, Ordering::SeqCst);
+        app.exit(0);
+    }
+}
+
+pub fn on_close_requested(window: &Window, api: &CloseRequestApi) {
+    let app = window.app_handle();
+    if app.state::<CloseGuard>().allow_exit.load(Ordering::SeqCst) {
+        return;
+    }
+    let dirty = app
+        .state::<DocumentRegistry>()
+        .dirty_documents(Some(window.label()));
+    if dirty.is_empty() {
+        return;
+    }
+
+    api.prevent_close();
+    let target = window.clone();
+    ask(
+        app,
+        Some(window),
+        &format!(
+            "Do you want to save the changes you made to {}?",
+            describe(&dirty)
+        ),
+        move |choice| match choice {
+            Choice::Save => {
+                let _ = target.emit_to(target.label(), SAVE_REQUESTED_EVENT, ());
+            }
+            Choice::Discard => {
+                let _ = target.destroy();
+            }
+            Choice::Cancel => {}
+        },
+    );
+}
+
+pub fn on_exit_requested(app: &AppHandle, api: &ExitRequestApi) {
+    let guard = app.state::<CloseGuard>();
+    if guard.allow_exit.load(Ordering::SeqCst) {
+        return;
+    }
+    let dirty = app.state::<DocumentRegistry>().dirty_documents(None);
+    if dirty.is_empty() {
+        return;
+    }
+
+    api.prevent_exit();
+    let handle = app.clone();
+    ask(
+        app,
+        None,
+        &format!(
+            "Do you want to save the changes you made to {} before quitting?",
+            describe(&dirty)
+        ),
+        move |choice| {
+            let guard = handle.state::<CloseGuard>();
+            match choice {
+                Choice::Save => {
+                    let windows: HashSet<String> =
+                        dirty.iter().map(|doc| doc.window.clone()).collect();
+                    for label in &windows {
+                        let _ = handle.emit_to(label, SAVE_REQUESTED_EVENT, ());
+                    }
+                    *guard.saving_for_quit.lock().unwrap() = Some(windows);
+                }
+                Choice::Discard => guard.exit(&handle),
+                Choice::Cancel => {}
+            }
+        },
+    );
+}
+
+fn ask<F>(app: &AppHandle, parent: Option<&Window>, message: &str, on_choice: F)
+where
+    F: FnOnce(Choice) + Send + 'static,
+{
+    let mut dialog = app
+        .dialog()
+        .message(message)
+        .title("Unsaved Changes")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::YesNoCancelCustom(
+            SAVE.into(),
+            DISCARD.into(),
+            CANCEL.into(),
+        ));
+    if let Some(parent) = parent {
+        dialog = dialog.parent(parent);
+    }
+    dialog.show_with_result(move |result| {
+        on_choice(match result {
+            MessageDialogResult::Yes => Choice::Save,
+            MessageDialogResult::No => Choice::Discard,
+            MessageDialogResult::Custom(label) if label == SAVE => Choice::Save,
+            MessageDialogResult::Custom(label) if label == DISCARD => Choice::Discard,
+            _ => Choice::Cancel,
+        })
+    });
+}
+
+fn describe(dirty: &[DocumentEntry]) -> String {
+    match dirty {
+        [doc] => doc
+            .path
+            .as_deref()
+            .and_then(|path| path.file_name())
+            .map(|name| format!("\"{}\"", name.to_string_lossy()))
+            .unwrap_or_else(|| "\"Untitled.md\"".into()),
+        _ => format!("{} documents", dirty.len()),
+    }
+}
+
+/// Called by a window after it saved in response to `save-requested`.
+/// Closes the window, or quits once every window asked to save has done so.
+#[tauri::command]
+pub fn finish_close(app: AppHandle, window: Window, guard: State<'_, CloseGuard>) {
+    let mut saving = guard.saving_for_quit.lock().unwrap();
+    if let Some(windows) = saving.as_mut() {
+        windows.remove(window.label());
+        if windows.is_empty() {
+            *saving = None;
+            drop(saving);
+            guard.exit(&app);
+        }
+        return;
+    }
+    drop(saving);
+    let _ = window.destroy();
+}
+
+/// Called by a window whose save was cancelled or failed; aborts any quit
+/// in progress so nothing is lost.
+#[tauri::command]
+pub fn cancel_close(guard: State<'_, CloseGuard>) {
+    *guard.saving_for_quit.lock().unwrap() = None;
+}
@@ -0,0 +1,348 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use encoding_rs::{UTF_16BE, UTF_16LE, WINDOWS_1252};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::atomic;
+use crate::error::{Error, Result};
+use crate::recent;
+use crate::scope;
+use crate::settings::SettingsStore;
+use crate::watcher::DocumentWatcher;
+
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf16le,
+    Utf16be,
+    /// Also used for Latin-1 files, since Windows-1252 is a superset of it.
+    Windows1252,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+}
+
+/// Everything needed to write a document back in the shape it was read.
+/// The frontend keeps this alongside the buffer and passes it back on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextFormat {
+    pub encoding: Encoding,
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    pub trailing_newline: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenedDocument {
+    pub path: PathBuf,
+    /// Document text with line endings normalised to `\n`.
+    pub text: String,
+    pub format: TextFormat,
+}
+
+pub fn is_markdown(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            MARKDOWN_EXTENSIONS
+                .iter()
+                .any(|md| ext.eq_ignore_ascii_case(md))
+        })
+}
+
+/// Decodes raw file contents, returning `\n`-normalised text and the
+/// format the bytes were stored in.
+pub fn decode(bytes: &[u8]) -> (String, TextFormat) {
+    let (encoding, bom, body) = detect_encoding(bytes);
+    let text = match encoding {
+        Encoding::Utf8 => String::from_utf8_lossy(body).into_owned(),
+        Encoding::Utf16le => UTF_16LE.decode_without_bom_handling(body).0.into_owned(),
+        Encoding::Utf16be => UTF_16BE.decode_without_bom_handling(body).0.into_owned(),
+        Encoding::Windows1252 => WINDOWS_1252
+            .decode_without_bom_handling(body)
+            .0
+            .into_owned(),
+    };
+
+    let line_ending = detect_line_ending(&text);
+    let trailing_newline = text.ends_with(['\n', '\r']);
+    let format = TextFormat {
+        encoding,
+        bom,
+        line_ending,
+        trailing_newline,
+    };
+
+    (normalize_line_endings(&text), format)
+}
+
+/// Encodes `\n`-separated text back into bytes using `format`.
+///
+/// If the original file ended with a newline and the buffer no longer does,
+/// one is added back so editors that trim the final newline don't produce a
+/// spurious diff.
+pub fn encode(text: &str, format: &TextFormat) -> Result<Vec<u8>> {
+    let mut text = normalize_line_endings(text);
+    if format.trailing_newline && !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    if format.line_ending != LineEnding::Lf {
+        text = text.replace('\n', format.line_ending.as_str());
+    }
+
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    match format.encoding {
+        Encoding::Utf8 => {
+            if format.bom {
+                bytes.extend_from_slice(UTF8_BOM);
+            }
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        // encoding_rs only decodes UTF-16, so encode by hand.
+        Encoding::Utf16le => {
+            if format.bom {
+                bytes.extend_from_slice(UTF16LE_BOM);
+            }
+            bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        }
+        Encoding::Utf16be => {
+            if format.bom {
+                bytes.extend_from_slice(UTF16BE_BOM);
+            }
+            bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        }
+        Encoding::Windows1252 => {
+            let (encoded, _, had_errors) = WINDOWS_1252.encode(&text);
+            if had_errors {
+                return Err(Error::Encoding(
+                    "document contains characters that cannot be saved as Windows-1252".into(),
+                ));
+            }
+            bytes.extend_from_slice(&encoded);
+        }
+    }
+    Ok(bytes)
+}
+
+fn detect_encoding(bytes: &[u8]) -> (Encoding, bool, &[u8]) {
+    if let Some(body) = bytes.strip_prefix(UTF8_BOM) {
+        return (Encoding::Utf8, true, body);
+    }
+    if let Some(body) = bytes.strip_prefix(UTF16LE_BOM) {
+        return (Encoding::Utf16le, true, body);
+    }
+    if let Some(body) = bytes.strip_prefix(UTF16BE_BOM) {
+        return (Encoding::Utf16be, true, body);
+    }
+    // ASCII in UTF-16 is valid UTF-8 too, zero bytes and all, so sniff first.
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return (encoding, false, bytes);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (Encoding::Utf8, false, bytes);
+    }
+    (Encoding::Windows1252, false, bytes)
+}
+
+/// Guesses BOM-less UTF-16 from the distribution of zero bytes: mostly-ASCII
+/// text leaves every other byte zero, which never happens in 8-bit encodings.
+fn sniff_utf16(bytes: &[u8]) -> Option<Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.len() / 2;
+    let even_zeros = bytes.iter().step_by(2).filter(|&&b| b == 0).count();
+    let odd_zeros = bytes.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+
+    if odd_zeros * 2 > units && even_zeros * 10 < units {
+        Some(Encoding::Utf16le)
+    } else if even_zeros * 2 > units && odd_zeros * 10 < units {
+        Some(Encoding::Utf16be)
+    } else {
+        None
+    }
+}
+
+/// Picks the most common line ending. Mixed files are normalised to it on
+/// save, which is the only case where untouched lines can change.
+fn detect_line_ending(text: &str) -> LineEnding {
+    let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                crlf += 1;
+            }
+            '\r' => cr += 1,
+            '\n' => lf += 1,
+            _ => {}
+        }
+    }
+
+    if crlf > lf && crlf >= cr {
+        LineEnding::Crlf
+    } else if cr > lf && cr > crlf {
+        LineEnding::Cr
+    } else {
+        LineEnding::Lf
+    }
+}
+
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+#[tauri::command]
+pub async fn open_document(
+    app: AppHandle,
+    path: PathBuf,
+    watcher: State<'_, DocumentWatcher>,
+) -> Result<OpenedDocument> {
+    scope::check(&app, &path)?;
+    let bytes = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+    let _ = watcher.watch(&path, &bytes);
+    let (text, format) = decode(&bytes);
+    recent::record(&app, &path);
+    Ok(OpenedDocument { path, text, format })
+}
+
+/// Saves `text` atomically, first rotating up to `backups` copies of the
+/// previous version next to the file. `backups` defaults to the setting.
+#[tauri::command]
+pub async fn save_document(
+    app: AppHandle,
+    path: PathBuf,
+    text: String,
+    format: Option<TextFormat>,
+    backups: Option<usize>,
+    watcher: State<'_, DocumentWatcher>,
+    settings: State<'_, SettingsStore>,
+) -> Result<()> {
+    scope::check(&app, &path)?;
+    let bytes = encode(&text, &format.unwrap_or_default())?;
+    let backups = backups.unwrap_or_else(|| settings.get().backups);
+    atomic::backup(&path, backups).map_err(|e| Error::io(e, &path))?;
+    // Record the new contents first so the watcher ignores our own write.
+    let _ = watcher.watch(&path, &bytes);
+    atomic::write(&path, &bytes).map_err(|e| Error::io(e, &path))?;
+    recent::record(&app, &path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> (String, TextFormat) {
+        let (text, format) = decode(bytes);
+        assert_eq!(encode(&text, &format).unwrap(), bytes);
+        (text, format)
+    }
+
+    #[test]
+    fn utf8_bom() {
+        let (text, format) = round_trip(b"\xEF\xBB\xBF# Title\n");
+        assert_eq!(text, "# Title\n");
+        assert_eq!(format.encoding, Encoding::Utf8);
+        assert!(format.bom);
+    }
+
+    #[test]
+    fn utf16_with_bom() {
+        let (text, format) = round_trip(b"\xFF\xFEh\0i\0\n\0");
+        assert_eq!(text, "hi\n");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16le, true));
+
+        let (text, format) = round_trip(b"\xFE\xFF\0h\0i\0\n");
+        assert_eq!(text, "hi\n");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16be, true));
+    }
+
+    #[test]
+    fn utf16_sniffed_without_bom() {
+        let le: Vec<u8> = "# Notes\nsome text\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let (text, format) = round_trip(&le);
+        assert_eq!(text, "# Notes\nsome text\n");
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16le, false));
+
+        let be: Vec<u8> = "# Notes\nsome text\n"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        let (_, format) = round_trip(&be);
+        assert_eq!((format.encoding, format.bom), (Encoding::Utf16be, false));
+    }
+
+    #[test]
+    fn windows_1252_fallback() {
+        let (text, format) = round_trip(b"caf\xE9 \x93quoted\x94\n");
+        assert_eq!(text, "café \u{201C}quoted\u{201D}\n");
+        assert_eq!(format.encoding, Encoding::Windows1252);
+        assert!(matches!(encode("日本", &format), Err(Error::Encoding(_))));
+    }
+
+    #[test]
+    fn crlf() {
+        let (text, format) = round_trip(b"one\r\ntwo\r\n");
+        assert_eq!(text, "one\ntwo\n");
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+        assert_eq!(
+            encode("one\ntwo\nthree", &format).unwrap(),
+            b"one\r\ntwo\r\nthree\r\n"
+        );
+    }
+
+    #[test]
+    fn mixed_line_endings_use_the_most_common() {
+        let (text, format) = decode(b"a\r\nb\r\nc\n");
+        assert_eq!(text, "a\nb\nc\n");
+        assert_eq!(format.line_ending, LineEnding::Crlf);
+    }
+
+    #[test]
+    fn missing_trailing_newline_is_kept() {
+        let (text, format) = round_trip(b"no newline");
+        assert_eq!(text, "no newline");
+        assert!(!format.trailing_newline);
+        assert_eq!(encode("edited", &format).unwrap(), b"edited");
+    }
+
+    #[test]
+    fn trailing_newline_is_restored() {
+        let (_, format) = decode(b"text\n");
+        assert_eq!(encode("trimmed", &format).unwrap(), b"trimmed\n");
+        assert_eq!(encode("", &format).unwrap(), b"");
+    }
+}
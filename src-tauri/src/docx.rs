@@ -0,0 +1,1099 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pulldown_cmark::{Alignment, Event, Tag, TagEnd};
+
+use crate::export::{resolve_local, ImageAccess};
+use crate::markdown;
+use crate::settings::FontFamily;
+use crate::zip::ZipWriter;
+
+/// A4 with one inch margins, in twentieths of a point.
+const PAGE_WIDTH: u32 = 11906;
+const PAGE_HEIGHT: u32 = 16838;
+const PAGE_MARGIN: u32 = 1440;
+const TEXT_WIDTH: u32 = PAGE_WIDTH - 2 * PAGE_MARGIN;
+/// Drawing sizes are in English Metric Units.
+const EMU_PER_TWIP: u64 = 635;
+/// Images are sized as if they were 96 dpi, like a browser does.
+const EMU_PER_PIXEL: u64 = 9525;
+/// Indentation of each list or quote level, in twips.
+const INDENT: u32 = 720;
+
+const MAIN_NS: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+const RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PACKAGE_RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/package/2006/relationships";
+const DRAWING_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing";
+const GRAPHIC_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/main";
+const PICTURE_NS: &str = "http://schemas.openxmlformats.org/drawingml/2006/picture";
+
+const XML_DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n";
+
+/// Escapes text for XML, dropping the control characters XML 1.0 can't
+/// contain at all.
+fn xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(ch),
+            _ if ch < ' ' => {}
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Word bookmark names are at most 40 letters, digits and underscores. A
+/// leading underscore hides them from Word's bookmark list.
+fn bookmark_name(anchor: &str) -> String {
+    std::iter::once('_')
+        .chain(
+            anchor
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }),
+        )
+        .take(40)
+        .collect()
+}
+
+/// The format and pixel size of a PNG, JPEG, GIF or BMP image, read from
+/// its header.
+fn image_info(bytes: &[u8]) -> Option<(&'static str, u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let le16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let be32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le32 = |at: usize| Some(i32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(("png", be32(16)?, be32(20)?));
+    }
+    if bytes.starts_with(b"GIF8") {
+        return Some(("gif", le16(6)?.into(), le16(8)?.into()));
+    }
+    if bytes.starts_with(b"BM") {
+        return Some(("bmp", le32(18)?.unsigned_abs(), le32(22)?.unsigned_abs()));
+    }
+    if bytes.starts_with(&[0xff, 0xd8]) {
+        // Walk the segments up to the start of frame, which has the size.
+        let mut at = 2;
+        while at + 4 <= bytes.len() {
+            if bytes[at] != 0xff {
+                return None;
+            }
+            let marker = bytes[at + 1];
+            match marker {
+                0xff => at += 1,
+                0xd0..=0xd9 | 0x01 => at += 2,
+                0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                    return Some(("jpeg", be16(at + 7)?.into(), be16(at + 5)?.into()));
+                }
+                _ => at += 2 + usize::from(be16(at + 2)?),
+            }
+        }
+    }
+    None
+}
+
+fn content_type(extension: &str) -> &'static str {
+    match extension {
+        "png" => "image/png",
+        "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "image/bmp",
+    }
+}
+
+fn font_name(family: FontFamily) -> &'static str {
+    match family {
+        FontFamily::Sans => "Calibri",
+        FontFamily::Serif => "Cambria",
+        FontFamily::Mono => "Consolas",
+    }
+}
+
+/// A paragraph being built: its properties, runs and the bookmark placed at
+/// its start.
+struct Paragraph {
+    properties: String,
+    runs: String,
+    bookmark: Option<String>,
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    cell: usize,
+    head: bool,
+}
+
+struct Image {
+    url: String,
+    title: String,
+    alt: String,
+}
+
+/// Collects WordprocessingML while walking the markdown events.
+struct Writer<'a> {
+    base: Option<&'a Path>,
+    access: ImageAccess<'a>,
+    body: String,
+    /// Body of each footnote, by id.
+    footnotes: BTreeMap<i32, String>,
+    footnote_ids: HashMap<String, i32>,
+    /// The footnote being written, if any, and whether its first paragraph
+    /// still needs the footnote mark.
+    footnote: Option<(i32, bool)>,
+    paragraph: Option<Paragraph>,
+
+    bold: usize,
+    italic: usize,
+    strike: usize,
+    /// Whether each open link became a hyperlink element.
+    links: Vec<bool>,
+    quotes: usize,
+    /// Numbering instance of each open list.
+    lists: Vec<u32>,
+    /// Ordered lists, as the level and start number of each numbering
+    /// instance after the shared bullet one.
+    ordered: Vec<(usize, u64)>,
+    /// Whether the current list item's numbered paragraph was written.
+    item_started: bool,
+    heading: Option<String>,
+    code: Option<String>,
+    table: Option<Table>,
+    image: Option<Image>,
+
+    /// Relationships of the document part after the fixed ones.
+    relationships: Vec<String>,
+    hyperlinks: HashMap<String, String>,
+    media: HashMap<PathBuf, (String, u32, u32)>,
+    files: Vec<(String, Vec<u8>)>,
+    extensions: HashSet<&'static str>,
+    bookmarks: HashSet<String>,
+    slugs: markdown::Slugs,
+    drawings: u32,
+    missing: Vec<String>,
+}
+
+/// Relationships every document has: styles, numbering, footnotes and
+/// settings.
+const FIXED_RELATIONSHIPS: usize = 4;
+
+impl<'a> Writer<'a> {
+    fn new(base: Option<&'a Path>, access: ImageAccess<'a>) -> Self {
+        Self {
+            base,
+            access,
+            body: String::new(),
+            footnotes: BTreeMap::new(),
+            footnote_ids: HashMap::new(),
+            footnote: None,
+            paragraph: None,
+            bold: 0,
+            italic: 0,
+            strike: 0,
+            links: Vec::new(),
+            quotes: 0,
+            lists: Vec::new(),
+            ordered: Vec::new(),
+            item_started: false,
+            heading: None,
+            code: None,
+            table: None,
+            image: None,
+            relationships: Vec::new(),
+            hyperlinks: HashMap::new(),
+            media: HashMap::new(),
+            files: Vec::new(),
+            extensions: HashSet::new(),
+            bookmarks: HashSet::new(),
+            slugs: markdown::Slugs::default(),
+            drawings: 0,
+            missing: Vec::new(),
+        }
+    }
+
+    /// Where blocks are written: the open footnote or the body.
+    fn out(&mut self) -> &mut String {
+        match self.footnote {
+            Some((id, _)) => self.footnotes.entry(id).or_default(),
+            None => &mut self.body,
+        }
+    }
+
+    fn relationship(&mut self, kind: &str, target: &str, external: bool) -> String {
+        let id = format!("rId{}", FIXED_RELATIONSHIPS + self.relationships.len() + 1);
+        let mode = if external {
+            " TargetMode=\"External\""
+        } else {
+            ""
+        };
+        self.relationships.push(format!(
+            "<Relationship Id=\"{id}\" Type=\"{RELATIONSHIPS_NS}/{kind}\" Target=\"{}\"{mode}/>",
+            xml(target)
+        ));
+        id
+    }
+
+    fn footnote_id(&mut self, label: &str) -> i32 {
+        let next = self.footnote_ids.len() as i32 + 1;
+        *self.footnote_ids.entry(label.to_string()).or_insert(next)
+    }
+
+    /// Left indent of paragraphs nested in lists and quotes.
+    fn indent(&self) -> u32 {
+        (self.lists.len() + self.quotes) as u32 * INDENT
+    }
+
+    /// Starts a paragraph for inline content if none is open, styled for
+    /// where it is: a table cell, list item, footnote or quote.
+    fn open(&mut self) {
+        if self.paragraph.is_some() {
+            return;
+        }
+        let properties = if let Some(table) = &self.table {
+            let alignment = match table.alignments.get(table.cell) {
+                Some(Alignment::Center) => "center",
+                Some(Alignment::Right) => "right",
+                _ => "left",
+            };
+            format!("<w:pStyle w:val=\"Compact\"/><w:jc w:val=\"{alignment}\"/>")
+        } else if let (Some(&num), false) = (self.lists.last(), self.item_started) {
+            self.item_started = true;
+            let level = self.lists.len() - 1;
+            let indent = self.indent();
+            format!(
+                "<w:pStyle w:val=\"ListParagraph\"/><w:numPr><w:ilvl w:val=\"{level}\"/>\
+                 <w:numId w:val=\"{num}\"/></w:numPr><w:ind w:left=\"{indent}\" w:hanging=\"360\"/>"
+            )
+        } else if self.footnote.is_some() {
+            "<w:pStyle w:val=\"FootnoteText\"/>".to_string()
+        } else if self.quotes > 0 {
+            format!(
+                "<w:pStyle w:val=\"Quote\"/><w:ind w:left=\"{}\"/>",
+                self.indent()
+            )
+        } else if !self.lists.is_empty() {
+            format!(
+                "<w:pStyle w:val=\"ListParagraph\"/><w:ind w:left=\"{}\"/>",
+                self.indent()
+            )
+        } else {
+            String::new()
+        };
+        let mut runs = String::new();
+        if let Some((_, mark @ true)) = &mut self.footnote {
+            *mark = false;
+            runs.push_str(
+                "<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr><w:footnoteRef/></w:r>\
+                 <w:r><w:t xml:space=\"preserve\"> </w:t></w:r>",
+            );
+        }
+        self.paragraph = Some(Paragraph {
+            properties,
+            runs,
+            bookmark: None,
+        });
+    }
+
+    fn close(&mut self) {
+        let Some(paragraph) = self.paragraph.take() else {
+            return;
+        };
+        let mut xml = format!("<w:p><w:pPr>{}</w:pPr>", paragraph.properties);
+        if let Some(name) = paragraph.bookmark {
+            let id = self.bookmarks.len();
+            xml.push_str(&format!(
+                "<w:bookmarkStart w:id=\"{id}\" w:name=\"{name}\"/><w:bookmarkEnd w:id=\"{id}\"/>"
+            ));
+        }
+        xml.push_str(&paragraph.runs);
+        xml.push_str("</w:p>");
+        self.out().push_str(&xml);
+    }
+
+    fn runs(&mut self) -> &mut String {
+        self.open();
+        &mut self.paragraph.as_mut().unwrap().runs
+    }
+
+    /// The run properties for the current formatting, in the order the
+    /// schema requires.
+    fn run_properties(&self, code: bool) -> String {
+        let mut properties = String::new();
+        let link = self.links.iter().any(|&link| link);
+        if link {
+            properties.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+            // Code in a link keeps its font, though not its shading.
+            if code {
+                properties.push_str("<w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\"/>");
+            }
+        } else if code {
+            properties.push_str("<w:rStyle w:val=\"CodeChar\"/>");
+        }
+        if self.bold > 0 {
+            properties.push_str("<w:b/>");
+        }
+        if self.italic > 0 {
+            properties.push_str("<w:i/>");
+        }
+        if self.strike > 0 {
+            properties.push_str("<w:strike/>");
+        }
+        properties
+    }
+
+    fn text(&mut self, text: &str, code: bool) {
+        if let Some(heading) = &mut self.heading {
+            heading.push_str(text);
+        }
+        if let Some(image) = &mut self.image {
+            image.alt.push_str(text);
+            return;
+        }
+        let properties = self.run_properties(code);
+        let text = xml(text).replace('\t', "</w:t><w:tab/><w:t xml:space=\"preserve\">");
+        self.runs().push_str(&format!(
+            "<w:r><w:rPr>{properties}</w:rPr><w:t xml:space=\"preserve\">{text}</w:t></w:r>"
+        ));
+    }
+
+    fn code_block(&mut self, code: &str) {
+        let indent = self.indent();
+        let mut xml = String::new();
+        for line in code.trim_end_matches('\n').split('\n') {
+            xml.push_str(&format!(
+                "<w:p><w:pPr><w:pStyle w:val=\"Code\"/><w:ind w:left=\"{indent}\"/></w:pPr>\
+                 <w:r><w:t xml:space=\"preserve\">{}</w:t></w:r></w:p>",
+                self::xml(&line.replace('\t', "    "))
+            ));
+        }
+        self.out().push_str(&xml);
+    }
+
+    /// Adds the image file once and returns its relationship id and size in
+    /// pixels, or `None` if it isn't a local image Word can show or may not
+    /// be read.
+    fn media(&mut self, path: PathBuf) -> Option<(String, u32, u32)> {
+        if let Some(media) = self.media.get(&path) {
+            return Some(media.clone());
+        }
+        if !self.access.allows(&path) {
+            return None;
+        }
+        let bytes = fs::read(&path).ok()?;
+        let (extension, width, height) = image_info(&bytes)?;
+        let name = format!("media/image{}.{extension}", self.files.len() + 1);
+        let id = self.relationship("image", &name, false);
+        self.files.push((format!("word/{name}"), bytes));
+        self.extensions.insert(extension);
+        let media = (id, width, height);
+        self.media.insert(path, media.clone());
+        Some(media)
+    }
+
+    fn image(&mut self, image: Image) {
+        let local = resolve_local(self.base, &image.url);
+        let media = local.clone().and_then(|path| self.media(path));
+        let Some((id, width, height)) = media else {
+            if local.is_some() || !image.url.contains("://") {
+                self.missing.push(image.url.clone());
+            }
+            // Images Word can't embed stay readable as their alt text.
+            let alt = if image.alt.is_empty() {
+                image.url
+            } else {
+                image.alt
+            };
+            self.italic += 1;
+            self.text(&format!("[{alt}]"), false);
+            self.italic -= 1;
+            return;
+        };
+
+        let max_width = u64::from(TEXT_WIDTH) * EMU_PER_TWIP;
+        let mut cx = u64::from(width.max(1)) * EMU_PER_PIXEL;
+        let mut cy = u64::from(height.max(1)) * EMU_PER_PIXEL;
+        if cx > max_width {
+            cy = cy * max_width / cx;
+            cx = max_width;
+        }
+        self.drawings += 1;
+        let n = self.drawings;
+        let description = xml(if image.title.is_empty() {
+            &image.alt
+        } else {
+            &image.title
+        });
+        let drawing = format!(
+            "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\">\
+             <wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{n}\" name=\"Picture {n}\" \
+             descr=\"{description}\"/><wp:cNvGraphicFramePr><a:graphicFrameLocks \
+             xmlns:a=\"{GRAPHIC_NS}\" noChangeAspect=\"1\"/></wp:cNvGraphicFramePr>\
+             <a:graphic xmlns:a=\"{GRAPHIC_NS}\"><a:graphicData uri=\"{PICTURE_NS}\">\
+             <pic:pic xmlns:pic=\"{PICTURE_NS}\"><pic:nvPicPr><pic:cNvPr id=\"{n}\" \
+             name=\"Picture {n}\"/><pic:cNvPicPr/></pic:nvPicPr><pic:blipFill>\
+             <a:blip r:embed=\"{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill>\
+             <pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm>\
+             <a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic>\
+             </a:graphicData></a:graphic></wp:inline></w:drawing></w:r>"
+        );
+        self.runs().push_str(&drawing);
+    }
+
+    fn event(&mut self, event: Event) {
+        if let Some(code) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let code = self.code.take().unwrap_or_default();
+                    self.code_block(&code);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(Tag::Paragraph) => self.close(),
+            Event::End(TagEnd::Paragraph) => self.close(),
+            Event::Start(Tag::Heading { level, .. }) => {
+                self.close();
+                let level = level as u8;
+                self.paragraph = Some(Paragraph {
+                    properties: format!("<w:pStyle w:val=\"Heading{level}\"/>"),
+                    runs: String::new(),
+                    bookmark: None,
+                });
+                self.heading = Some(String::new());
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                let text = self.heading.take().unwrap_or_default();
+                let name = bookmark_name(&self.slugs.next(&text));
+                // Long ids can be cut to the same name; the first keeps it.
+                if self.bookmarks.insert(name.clone()) {
+                    if let Some(paragraph) = &mut self.paragraph {
+                        paragraph.bookmark = Some(name);
+                    }
+                }
+                self.close();
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.close();
+                self.code = Some(String::new());
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.close();
+                self.quotes += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.close();
+                self.quotes -= 1;
+            }
+            Event::Start(Tag::List(start)) => {
+                self.close();
+                let num = match start {
+                    Some(start) => {
+                        self.ordered.push((self.lists.len(), start));
+                        self.ordered.len() as u32 + 1
+                    }
+                    None => 1,
+                };
+                self.lists.push(num);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.close();
+                self.lists.pop();
+                self.item_started = true;
+            }
+            Event::Start(Tag::Item) => {
+                self.close();
+                self.item_started = false;
+            }
+            Event::End(TagEnd::Item) => {
+                // An empty item still gets its bullet.
+                if !self.item_started {
+                    self.open();
+                }
+                self.close();
+            }
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                self.close();
+                let id = self.footnote_id(&label);
+                self.footnote = Some((id, true));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => {
+                self.close();
+                self.footnote = None;
+            }
+            Event::Start(Tag::Table(alignments)) => {
+                self.close();
+                let width = TEXT_WIDTH / alignments.len().max(1) as u32;
+                let mut xml = String::from(
+                    "<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/>\
+                     <w:tblW w:w=\"5000\" w:type=\"pct\"/></w:tblPr><w:tblGrid>",
+                );
+                for _ in &alignments {
+                    xml.push_str(&format!("<w:gridCol w:w=\"{width}\"/>"));
+                }
+                xml.push_str("</w:tblGrid>");
+                self.out().push_str(&xml);
+                self.table = Some(Table {
+                    alignments,
+                    cell: 0,
+                    head: false,
+                });
+            }
+            Event::End(TagEnd::Table) => {
+                self.table = None;
+                self.out().push_str("</w:tbl>");
+                // Word merges tables that touch, so keep a paragraph after.
+                self.out().push_str("<w:p/>");
+            }
+            Event::Start(Tag::TableHead) => {
+                if let Some(table) = &mut self.table {
+                    table.head = true;
+                    table.cell = 0;
+                }
+                self.out().push_str("<w:tr><w:trPr><w:tblHeader/></w:trPr>");
+            }
+            Event::Start(Tag::TableRow) => {
+                if let Some(table) = &mut self.table {
+                    table.head = false;
+                    table.cell = 0;
+                }
+                self.out().push_str("<w:tr>");
+            }
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => self.out().push_str("</w:tr>"),
+            Event::Start(Tag::TableCell) => {
+                let head = self.table.as_ref().is_some_and(|table| table.head);
+                if head {
+                    self.bold += 1;
+                }
+                self.out().push_str("<w:tc>");
+            }
+            Event::End(TagEnd::TableCell) => {
+                // A cell must hold a paragraph, even an empty one.
+                self.open();
+                self.close();
+                self.out().push_str("</w:tc>");
+                if let Some(table) = &mut self.table {
+                    table.cell += 1;
+                    if table.head {
+                        self.bold -= 1;
+                    }
+                }
+            }
+            Event::Start(Tag::Strong) => self.bold += 1,
+            Event::End(TagEnd::Strong) => self.bold -= 1,
+            Event::Start(Tag::Emphasis) => self.italic += 1,
+            Event::End(TagEnd::Emphasis) => self.italic -= 1,
+            Event::Start(Tag::Strikethrough) => self.strike += 1,
+            Event::End(TagEnd::Strikethrough) => self.strike -= 1,
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => {
+                if self.image.is_some() {
+                    self.links.push(false);
+                    return;
+                }
+                let dest_url = markdown::link_target(link_type, &dest_url);
+                let element = match dest_url.strip_prefix('#') {
+                    Some(fragment) => {
+                        format!("<w:hyperlink w:anchor=\"{}\">", bookmark_name(fragment))
+                    }
+                    None => {
+                        let id = match self.hyperlinks.get(&dest_url) {
+                            Some(id) => id.clone(),
+                            None => {
+                                let id = self.relationship("hyperlink", &dest_url, true);
+                                self.hyperlinks.insert(dest_url, id.clone());
+                                id
+                            }
+                        };
+                        format!("<w:hyperlink r:id=\"{id}\">")
+                    }
+                };
+                self.runs().push_str(&element);
+                self.links.push(true);
+            }
+            Event::End(TagEnd::Link) => {
+                let hyperlink = self.links.pop().unwrap_or(false);
+                if hyperlink {
+                    self.runs().push_str("</w:hyperlink>");
+                }
+            }
+            Event::Start(Tag::Image {
+                dest_url, title, ..
+            }) => {
+                self.image = Some(Image {
+                    url: dest_url.to_string(),
+                    title: title.to_string(),
+                    alt: String::new(),
+                });
+            }
+            Event::End(TagEnd::Image) => {
+                if let Some(image) = self.image.take() {
+                    self.image(image);
+                }
+            }
+            Event::Text(text) => self.text(&text, false),
+            Event::Code(code) => self.text(&code, true),
+            Event::FootnoteReference(label) => {
+                let id = self.footnote_id(&label);
+                self.runs().push_str(&format!(
+                    "<w:r><w:rPr><w:rStyle w:val=\"FootnoteReference\"/></w:rPr>\
+                     <w:footnoteReference w:id=\"{id}\"/></w:r>"
+                ));
+            }
+            Event::TaskListMarker(done) => {
+                self.text(if done { "\u{2612} " } else { "\u{2610} " }, false)
+            }
+            Event::SoftBreak => self.text(" ", false),
+            Event::HardBreak => self.runs().push_str("<w:r><w:br/></w:r>"),
+            Event::InlineHtml(html) if html.trim().to_ascii_lowercase().starts_with("<br") => {
+                self.runs().push_str("<w:r><w:br/></w:r>");
+            }
+            Event::Rule => {
+                self.close();
+                self.out().push_str(
+                    "<w:p><w:pPr><w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" \
+                     w:color=\"auto\"/></w:pBdr></w:pPr></w:p>",
+                );
+            }
+            _ => {}
+        }
+    }
+
+    fn document(&self) -> String {
+        format!(
+            "{XML_DECLARATION}<w:document xmlns:w=\"{MAIN_NS}\" xmlns:r=\"{RELATIONSHIPS_NS}\" \
+             xmlns:wp=\"{DRAWING_NS}\"><w:body>{}<w:sectPr><w:pgSz w:w=\"{PAGE_WIDTH}\" \
+             w:h=\"{PAGE_HEIGHT}\"/><w:pgMar w:top=\"{PAGE_MARGIN}\" w:right=\"{PAGE_MARGIN}\" \
+             w:bottom=\"{PAGE_MARGIN}\" w:left=\"{PAGE_MARGIN}\" w:header=\"708\" \
+             w:footer=\"708\" w:gutter=\"0\"/></w:sectPr></w:body></w:document>",
+            self.body
+        )
+    }
+
+    fn footnotes(&self) -> String {
+        let mut xml = format!(
+            "{XML_DECLARATION}<w:footnotes xmlns:w=\"{MAIN_NS}\" xmlns:r=\"{RELATIONSHIPS_NS}\" \
+             xmlns:wp=\"{DRAWING_NS}\">\
+             <w:footnote w:type=\"separator\" w:id=\"-1\"><w:p><w:pPr><w:spacing w:after=\"0\" \
+             w:line=\"240\" w:lineRule=\"auto\"/></w:pPr><w:r><w:separator/></w:r></w:p></w:footnote>\
+             <w:footnote w:type=\"continuationSeparator\" w:id=\"0\"><w:p><w:pPr><w:spacing \
+             w:after=\"0\" w:line=\"240\" w:lineRule=\"auto\"/></w:pPr><w:r><w:continuationSeparator/>\
+             </w:r></w:p></w:footnote>"
+        );
+        // References to footnotes that were never defined get an empty one.
+        let mut ids: Vec<i32> = self.footnote_ids.values().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            let body = self.footnotes.get(&id).map_or(
+                "<w:p><w:pPr><w:pStyle w:val=\"FootnoteText\"/></w:pPr></w:p>",
+                String::as_str,
+            );
+            xml.push_str(&format!("<w:footnote w:id=\"{id}\">{body}</w:footnote>"));
+        }
+        xml.push_str("</w:footnotes>");
+        xml
+    }
+
+    fn numbering(&self) -> String {
+        const BULLETS: [&str; 3] = ["\u{2022}", "\u{25e6}", "\u{25aa}"];
+        let level = |index: usize, format: &str, text: &str| {
+            let indent = (index as u32 + 1) * INDENT;
+            format!(
+                "<w:lvl w:ilvl=\"{index}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{format}\"/>\
+                 <w:lvlText w:val=\"{text}\"/><w:lvlJc w:val=\"left\"/><w:pPr><w:ind \
+                 w:left=\"{indent}\" w:hanging=\"360\"/></w:pPr></w:lvl>"
+            )
+        };
+        let mut xml = format!(
+            "{XML_DECLARATION}<w:numbering xmlns:w=\"{MAIN_NS}\"><w:abstractNum w:abstractNumId=\"0\">"
+        );
+        for index in 0..9 {
+            xml.push_str(&level(index, "bullet", BULLETS[index % BULLETS.len()]));
+        }
+        xml.push_str("</w:abstractNum><w:abstractNum w:abstractNumId=\"1\">");
+        for index in 0..9 {
+            xml.push_str(&level(index, "decimal", &format!("%{}.", index + 1)));
+        }
+        xml.push_str("</w:abstractNum><w:num w:numId=\"1\"><w:abstractNumId w:val=\"0\"/></w:num>");
+        // Each ordered list restarts at its own start number.
+        for (index, (level, start)) in self.ordered.iter().enumerate() {
+            xml.push_str(&format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"1\"/><w:lvlOverride \
+                 w:ilvl=\"{level}\"><w:startOverride w:val=\"{start}\"/></w:lvlOverride></w:num>",
+                index + 2
+            ));
+        }
+        xml.push_str("</w:numbering>");
+        xml
+    }
+
+    fn relationships(&self) -> String {
+        let fixed = ["styles", "numbering", "footnotes", "settings"]
+            .iter()
+            .enumerate()
+            .map(|(index, kind)| {
+                format!(
+                    "<Relationship Id=\"rId{}\" Type=\"{RELATIONSHIPS_NS}/{kind}\" \
+                     Target=\"{kind}.xml\"/>",
+                    index + 1
+                )
+            })
+            .collect::<String>();
+        format!(
+            "{XML_DECLARATION}<Relationships xmlns=\"{PACKAGE_RELATIONSHIPS_NS}\">{fixed}{}\
+             </Relationships>",
+            self.relationships.concat()
+        )
+    }
+
+    fn content_types(&self) -> String {
+        let mut extensions: Vec<&str> = self.extensions.iter().copied().collect();
+        extensions.sort_unstable();
+        let images: String = extensions
+            .into_iter()
+            .map(|ext| {
+                format!(
+                    "<Default Extension=\"{ext}\" ContentType=\"{}\"/>",
+                    content_type(ext)
+                )
+            })
+            .collect();
+        let part = |name: &str, kind: &str| {
+            format!(
+                "<Override PartName=\"/word/{name}.xml\" ContentType=\"application/\
+                 vnd.openxmlformats-officedocument.wordprocessingml.{kind}+xml\"/>"
+            )
+        };
+        format!(
+            "{XML_DECLARATION}<Types xmlns=\"http://schemas.openxmlformats.org/package/2006/\
+             content-types\"><Default Extension=\"rels\" ContentType=\"application/\
+             vnd.openxmlformats-package.relationships+xml\"/><Default Extension=\"xml\" \
+             ContentType=\"application/xml\"/>{images}{}{}{}{}{}<Override \
+             PartName=\"/docProps/core.xml\" ContentType=\"application/\
+             vnd.openxmlformats-package.core-properties+xml\"/></Types>",
+            part("document", "document.main"),
+            part("styles", "styles"),
+            part("numbering", "numbering"),
+            part("footnotes", "footnotes"),
+            part("settings", "settings"),
+        )
+    }
+}
+
+/// Word's built-in styles the document uses. Headings keep Word's own
+/// names and outline levels, so the navigation pane and generated tables of
+/// contents pick them up.
+fn styles(family: FontFamily) -> String {
+    const HEADING_SIZES: [u32; 6] = [40, 32, 28, 26, 24, 22];
+    let font = font_name(family);
+    let mut xml = format!(
+        "{XML_DECLARATION}<w:styles xmlns:w=\"{MAIN_NS}\"><w:docDefaults><w:rPrDefault><w:rPr>\
+         <w:rFonts w:ascii=\"{font}\" w:hAnsi=\"{font}\" w:eastAsia=\"{font}\" w:cs=\"{font}\"/>\
+         <w:sz w:val=\"22\"/><w:szCs w:val=\"22\"/><w:lang w:val=\"en-US\"/></w:rPr></w:rPrDefault>\
+         <w:pPrDefault><w:pPr><w:spacing w:after=\"160\" w:line=\"276\" w:lineRule=\"auto\"/>\
+         </w:pPr></w:pPrDefault></w:docDefaults>\
+         <w:style w:type=\"paragraph\" w:default=\"1\" w:styleId=\"Normal\"><w:name w:val=\"Normal\"/>\
+         <w:qFormat/></w:style>"
+    );
+    for (index, size) in HEADING_SIZES.iter().enumerate() {
+        let level = index + 1;
+        xml.push_str(&format!(
+            "<w:style w:type=\"paragraph\" w:styleId=\"Heading{level}\"><w:name \
+             w:val=\"heading {level}\"/><w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/>\
+             <w:uiPriority w:val=\"9\"/><w:qFormat/><w:pPr><w:keepNext/><w:keepLines/><w:spacing \
+             w:before=\"{}\" w:after=\"80\"/><w:outlineLvl w:val=\"{index}\"/></w:pPr><w:rPr><w:b/>\
+             <w:sz w:val=\"{size}\"/><w:szCs w:val=\"{size}\"/></w:rPr></w:style>",
+            if level <= 2 { 360 } else { 240 }
+        ));
+    }
+    xml.push_str(
+        "<w:style w:type=\"paragraph\" w:styleId=\"ListParagraph\"><w:name w:val=\"List Paragraph\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:uiPriority w:val=\"34\"/><w:qFormat/><w:pPr>\
+         <w:spacing w:after=\"60\"/><w:ind w:left=\"720\"/></w:pPr></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Compact\"><w:name w:val=\"Compact\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:before=\"40\" w:after=\"40\"/></w:pPr>\
+         </w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:next w:val=\"Normal\"/><w:uiPriority w:val=\"29\"/>\
+         <w:qFormat/><w:pPr><w:pBdr><w:left w:val=\"single\" w:sz=\"18\" w:space=\"8\" \
+         w:color=\"BFBFBF\"/></w:pBdr><w:ind w:left=\"720\"/></w:pPr><w:rPr><w:i/>\
+         <w:color w:val=\"595959\"/></w:rPr></w:style>\
+         <w:style w:type=\"paragraph\" w:customStyle=\"1\" w:styleId=\"Code\"><w:name w:val=\"Code\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:qFormat/><w:pPr><w:shd w:val=\"clear\" w:color=\"auto\" \
+         w:fill=\"F2F2F2\"/><w:spacing w:after=\"0\" w:line=\"240\" w:lineRule=\"auto\"/></w:pPr>\
+         <w:rPr><w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\" w:cs=\"Consolas\"/>\
+         <w:sz w:val=\"19\"/><w:szCs w:val=\"19\"/></w:rPr></w:style>\
+         <w:style w:type=\"character\" w:customStyle=\"1\" w:styleId=\"CodeChar\"><w:name \
+         w:val=\"Code Char\"/><w:rPr><w:rFonts w:ascii=\"Consolas\" w:hAnsi=\"Consolas\" \
+         w:cs=\"Consolas\"/><w:sz w:val=\"20\"/><w:shd w:val=\"clear\" w:color=\"auto\" \
+         w:fill=\"F2F2F2\"/></w:rPr></w:style>\
+         <w:style w:type=\"character\" w:styleId=\"Hyperlink\"><w:name w:val=\"Hyperlink\"/>\
+         <w:uiPriority w:val=\"99\"/><w:rPr><w:color w:val=\"0563C1\"/><w:u w:val=\"single\"/>\
+         </w:rPr></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"FootnoteText\"><w:name w:val=\"footnote text\"/>\
+         <w:basedOn w:val=\"Normal\"/><w:pPr><w:spacing w:after=\"0\" w:line=\"240\" \
+         w:lineRule=\"auto\"/></w:pPr><w:rPr><w:sz w:val=\"20\"/><w:szCs w:val=\"20\"/></w:rPr>\
+         </w:style>\
+         <w:style w:type=\"character\" w:styleId=\"FootnoteReference\"><w:name \
+         w:val=\"footnote reference\"/><w:rPr><w:vertAlign w:val=\"superscript\"/></w:rPr>\
+         </w:style>\
+         <w:style w:type=\"table\" w:styleId=\"TableGrid\"><w:name w:val=\"Table Grid\"/>\
+         <w:tblPr><w:tblBorders><w:top w:val=\"single\" w:sz=\"4\" w:space=\"0\" \
+         w:color=\"BFBFBF\"/><w:left w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"BFBFBF\"/>\
+         <w:bottom w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"BFBFBF\"/><w:right \
+         w:val=\"single\" w:sz=\"4\" w:space=\"0\" w:color=\"BFBFBF\"/><w:insideH w:val=\"single\" \
+         w:sz=\"4\" w:space=\"0\" w:color=\"BFBFBF\"/><w:insideV w:val=\"single\" w:sz=\"4\" \
+         w:space=\"0\" w:color=\"BFBFBF\"/></w:tblBorders><w:tblCellMar><w:left w:w=\"108\" \
+         w:type=\"dxa\"/><w:right w:w=\"108\" w:type=\"dxa\"/></w:tblCellMar></w:tblPr></w:style>\
+         </w:styles>",
+    );
+    xml
+}
+
+fn core_properties(title: Option<&str>) -> String {
+    let title = title
+        .map(|title| format!("<dc:title>{}</dc:title>", xml(title)))
+        .unwrap_or_default();
+    format!(
+        "{XML_DECLARATION}<cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/\
+         package/2006/metadata/core-properties\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+         xmlns:dcterms=\"http://purl.org/dc/terms/\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\">{title}</cp:coreProperties>"
+    )
+}
+
+/// A Word rendering of a document.
+pub struct DocxExport {
+    pub bytes: Vec<u8>,
+    /// Local images that could not or may not be read, or aren't PNG, JPEG,
+    /// GIF or BMP.
+    pub missing: Vec<String>,
+}
+
+/// Renders `source` as a Word document. `base` is the folder relative image
+/// paths are resolved against, and `access` says which images may be read.
+pub fn render(
+    source: &str,
+    base: Option<&Path>,
+    access: ImageAccess,
+    title: Option<&str>,
+    family: FontFamily,
+) -> DocxExport {
+    let mut writer = Writer::new(base, access);
+    for event in markdown::events(source) {
+        writer.event(event);
+    }
+    writer.close();
+
+    let mut zip = ZipWriter::new();
+    zip.add("[Content_Types].xml", writer.content_types().as_bytes());
+    zip.add(
+        "_rels/.rels",
+        format!(
+            "{XML_DECLARATION}<Relationships xmlns=\"{PACKAGE_RELATIONSHIPS_NS}\">\
+             <Relationship Id=\"rId1\" Type=\"{RELATIONSHIPS_NS}/officeDocument\" \
+             Target=\"word/document.xml\"/><Relationship Id=\"rId2\" \
+             Type=\"{PACKAGE_RELATIONSHIPS_NS}/metadata/core-properties\" \
+             Target=\"docProps/core.xml\"/></Relationships>"
+        )
+        .as_bytes(),
+    );
+    zip.add("docProps/core.xml", core_properties(title).as_bytes());
+    zip.add("word/document.xml", writer.document().as_bytes());
+    zip.add(
+        "word/_rels/document.xml.rels",
+        writer.relationships().as_bytes(),
+    );
+    zip.add("word/styles.xml", styles(family).as_bytes());
+    zip.add("word/numbering.xml", writer.numbering().as_bytes());
+    zip.add("word/footnotes.xml", writer.footnotes().as_bytes());
+    zip.add(
+        "word/settings.xml",
+        format!(
+            "{XML_DECLARATION}<w:settings xmlns:w=\"{MAIN_NS}\"><w:footnotePr>\
+             <w:footnote w:id=\"-1\"/><w:footnote w:id=\"0\"/></w:footnotePr></w:settings>"
+        )
+        .as_bytes(),
+    );
+    for (name, bytes) in &writer.files {
+        zip.add(name, bytes);
+    }
+    DocxExport {
+        bytes: zip.finish(),
+        missing: writer.missing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::ZipReader;
+
+    fn export(source: &str) -> Vec<u8> {
+        render(source, None, ImageAccess::Unscoped, None, FontFamily::Sans).bytes
+    }
+
+    fn part(docx: &[u8], name: &str) -> String {
+        let bytes = ZipReader::new(docx).unwrap().read(name).unwrap();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    /// The values of every `w:<attribute>` on `w:<element>` elements, in
+    /// document order.
+    fn values(xml: &str, element: &str, attribute: &str) -> Vec<String> {
+        let document = roxmltree::Document::parse(xml).unwrap();
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name((MAIN_NS, element)))
+            .filter_map(|node| node.attribute((MAIN_NS, attribute)))
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn package_parts() {
+        let docx = export("Hello");
+        let reader = ZipReader::new(&docx).unwrap();
+        for name in [
+            "[Content_Types].xml",
+            "_rels/.rels",
+            "docProps/core.xml",
+            "word/document.xml",
+            "word/_rels/document.xml.rels",
+            "word/styles.xml",
+            "word/numbering.xml",
+            "word/footnotes.xml",
+            "word/settings.xml",
+        ] {
+            let bytes = reader.read(name).unwrap();
+            roxmltree::Document::parse(std::str::from_utf8(&bytes).unwrap()).unwrap();
+        }
+        assert!(part(&docx, "word/document.xml").contains(">Hello</w:t>"));
+    }
+
+    #[test]
+    fn headings() {
+        let docx = export("# Intro\n\ntext\n\n## Café au lait\n\n# Intro\n\n[back](#intro-1)");
+        let document = part(&docx, "word/document.xml");
+        assert_eq!(
+            values(&document, "pStyle", "val"),
+            ["Heading1", "Heading2", "Heading1"]
+        );
+        assert_eq!(
+            values(&document, "bookmarkStart", "name"),
+            ["_intro", "_caf_au_lait", "_intro_1"]
+        );
+        assert_eq!(values(&document, "hyperlink", "anchor"), ["_intro_1"]);
+        let styles = part(&docx, "word/styles.xml");
+        assert!(styles.contains("<w:name w:val=\"heading 2\"/>"));
+        assert!(styles.contains("<w:outlineLvl w:val=\"1\"/>"));
+    }
+
+    #[test]
+    fn list_numbering() {
+        let docx = export("- a\n  1. b\n  2. c\n- d\n\ntext\n\n5. e\n6. f\n");
+        let document = part(&docx, "word/document.xml");
+        assert_eq!(
+            values(&document, "numId", "val"),
+            ["1", "2", "2", "1", "3", "3"]
+        );
+        assert_eq!(
+            values(&document, "ilvl", "val"),
+            ["0", "1", "1", "0", "0", "0"]
+        );
+        let numbering = part(&docx, "word/numbering.xml");
+        let document = roxmltree::Document::parse(&numbering).unwrap();
+        let starts: Vec<(&str, &str, &str)> = document
+            .descendants()
+            .filter(|node| node.has_tag_name((MAIN_NS, "num")))
+            .filter_map(|num| {
+                let id = num.attribute((MAIN_NS, "numId"))?;
+                let level = num
+                    .descendants()
+                    .find(|node| node.has_tag_name((MAIN_NS, "lvlOverride")))?;
+                let start = level
+                    .descendants()
+                    .find(|node| node.has_tag_name((MAIN_NS, "startOverride")))?;
+                Some((
+                    id,
+                    level.attribute((MAIN_NS, "ilvl"))?,
+                    start.attribute((MAIN_NS, "val"))?,
+                ))
+            })
+            .collect();
+        assert_eq!(starts, [("2", "1", "1"), ("3", "0", "5")]);
+    }
+
+    #[test]
+    fn footnotes() {
+        let docx = export("Claim[^a] and more[^b].\n\n[^b]: Second.\n[^a]: First.\n");
+        let document = part(&docx, "word/document.xml");
+        assert_eq!(values(&document, "footnoteReference", "id"), ["1", "2"]);
+        let footnotes = part(&docx, "word/footnotes.xml");
+        assert_eq!(values(&footnotes, "footnote", "id"), ["-1", "0", "1", "2"]);
+        let first = footnotes.find("w:id=\"1\"").unwrap();
+        let second = footnotes.find("w:id=\"2\"").unwrap();
+        assert!(footnotes[first..second].contains("First."));
+        assert!(footnotes[second..].contains("Second."));
+    }
+
+    #[test]
+    fn links() {
+        let docx = export(
+            "<me@example.com> and [site](https://example.com) twice [again](https://example.com)",
+        );
+        let relationships = part(&docx, "word/_rels/document.xml.rels");
+        assert!(relationships.contains("Target=\"mailto:me@example.com\" TargetMode=\"External\""));
+        assert_eq!(relationships.matches("https://example.com").count(), 1);
+        let document = part(&docx, "word/document.xml");
+        assert_eq!(
+            values(&document, "rStyle", "val"),
+            ["Hyperlink", "Hyperlink", "Hyperlink"]
+        );
+    }
+
+    #[test]
+    fn image_sizes() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend_from_slice(&640u32.to_be_bytes());
+        png.extend_from_slice(&480u32.to_be_bytes());
+        assert_eq!(image_info(&png), Some(("png", 640, 480)));
+
+        let mut gif = b"GIF89a".to_vec();
+        gif.extend_from_slice(&300u16.to_le_bytes());
+        gif.extend_from_slice(&200u16.to_le_bytes());
+        assert_eq!(image_info(&gif), Some(("gif", 300, 200)));
+
+        // Bottom-up bitmaps have a negative height.
+        let mut bmp = b"BM".to_vec();
+        bmp.resize(18, 0);
+        bmp.extend_from_slice(&120i32.to_le_bytes());
+        bmp.extend_from_slice(&(-90i32).to_le_bytes());
+        assert_eq!(image_info(&bmp), Some(("bmp", 120, 90)));
+
+        // The start of frame comes after an APP0 segment.
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0];
+        jpeg.extend_from_slice(&[0xff, 0xc0, 0, 17, 8]);
+        jpeg.extend_from_slice(&768u16.to_be_bytes());
+        jpeg.extend_from_slice(&1024u16.to_be_bytes());
+        jpeg.extend_from_slice(&[3, 0, 0]);
+        assert_eq!(image_info(&jpeg), Some(("jpeg", 1024, 768)));
+
+        assert_eq!(image_info(b"<svg/>"), None);
+        assert_eq!(image_info(&png[..20]), None);
+    }
+}
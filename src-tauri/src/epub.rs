@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use pulldown_cmark::{html, CowStr, Event, Tag, TagEnd};
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+use crate::export::{mime_type, resolve_local, ImageAccess};
+use crate::markdown;
+use crate::zip::ZipWriter;
+
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\
+<rootfiles><rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\
+</rootfiles></container>\n";
+
+/// Kept plain so e-readers can apply their own fonts, sizes and themes.
+const STYLESHEET: &str = "\
+body { line-height: 1.5; }
+h1, h2, h3, h4, h5, h6 { line-height: 1.25; page-break-after: avoid; }
+pre { white-space: pre-wrap; font-size: 0.85em; background: #f3f3f3; padding: 0.6em; }
+code { font-family: monospace; }
+blockquote { margin-left: 1em; padding-left: 1em; border-left: 3px solid #ccc; color: #555; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.5em; }
+img { max-width: 100%; }
+aside.footnote { font-size: 0.9em; }
+.cover { text-align: center; margin: 0; padding: 0; }
+.cover img { max-height: 100%; }
+";
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct EpubOptions {
+    /// Headings at this level or above start a new chapter.
+    pub chapter_level: u8,
+}
+
+impl Default for EpubOptions {
+    fn default() -> Self {
+        Self { chapter_level: 1 }
+    }
+}
+
+impl EpubOptions {
+    pub fn validate(&self) -> Result<()> {
+        if !(1..=6).contains(&self.chapter_level) {
+            return Err(Error::Invalid(format!(
+                "chapterLevel must be between 1 and 6, got {}",
+                self.chapter_level
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Book metadata from the document's YAML front matter.
+#[derive(Debug, Default)]
+struct Metadata {
+    title: Option<String>,
+    authors: Vec<String>,
+    language: Option<String>,
+    identifier: Option<String>,
+    description: Option<String>,
+    publisher: Option<String>,
+    date: Option<String>,
+    cover: Option<String>,
+}
+
+/// Reads the book metadata from the front matter of `source`, and returns
+/// the rest of it.
+fn front_matter(source: &str) -> (Metadata, &str) {
+    let mut metadata = Metadata::default();
+    let (fields, rest) = markdown::front_matter(source);
+    for (key, mut values) in fields {
+        let first = values.first().cloned();
+        match key.as_str() {
+            "title" => metadata.title = first,
+            "author" | "authors" | "creator" => metadata.authors.append(&mut values),
+            "lang" | "language" => metadata.language = first,
+            "identifier" | "isbn" => metadata.identifier = first,
+            "description" | "subtitle" => metadata.description = first,
+            "publisher" => metadata.publisher = first,
+            "date" => metadata.date = first,
+            "cover" | "cover-image" | "cover_image" => metadata.cover = first,
+            _ => {}
+        }
+    }
+    (metadata, rest)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Formats seconds since the epoch as an ISO 8601 UTC timestamp, for
+/// `dcterms:modified`.
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let time = secs % 86_400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// A heading, as listed in the preview's table of contents, and the
+/// chapter it ended up in.
+struct Heading {
+    level: u8,
+    text: String,
+    id: String,
+    chapter: usize,
+}
+
+struct Chapter<'a> {
+    title: Option<String>,
+    events: Vec<Event<'a>>,
+    /// Whether it shows images from the web, which readers must be told.
+    remote: bool,
+}
+
+impl Chapter<'_> {
+    fn new() -> Self {
+        Self {
+            title: None,
+            events: Vec::new(),
+            remote: false,
+        }
+    }
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{}.xhtml", index + 1)
+}
+
+/// A file copied into the book.
+struct Resource {
+    href: String,
+    media_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+/// Local images, each copied in once.
+struct Images<'a> {
+    base: Option<&'a Path>,
+    access: ImageAccess<'a>,
+    hrefs: HashMap<PathBuf, String>,
+    resources: Vec<Resource>,
+    missing: Vec<String>,
+    unsupported: Vec<String>,
+}
+
+/// Image types every EPUB 3 reader must show. Others are left out of the
+/// book rather than listed with a type readers may not know.
+const CORE_IMAGE_TYPES: [&str; 5] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/svg+xml",
+    "image/webp",
+];
+
+impl Images<'_> {
+    /// Copies the image at `url` into the book and returns its path there,
+    /// or `None` if it isn't local, can't or may not be read, or isn't a core
+    /// media type.
+    fn add(&mut self, url: &str) -> Option<String> {
+        let path = resolve_local(self.base, url)?;
+        if let Some(href) = self.hrefs.get(&path) {
+            return Some(href.clone());
+        }
+        let media_type = mime_type(&path);
+        if !CORE_IMAGE_TYPES.contains(&media_type) {
+            if !self.unsupported.iter().any(|seen| seen == url) {
+                self.unsupported.push(url.to_string());
+            }
+            return None;
+        }
+        let bytes = self.access.allows(&path).then(|| fs::read(&path));
+        let Some(Ok(bytes)) = bytes else {
+            self.missing.push(url.to_string());
+            return None;
+        };
+        let extension = path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy().to_ascii_lowercase()))
+            .unwrap_or_default();
+        let href = format!("images/image-{}{extension}", self.resources.len() + 1);
+        self.resources.push(Resource {
+            href: href.clone(),
+            media_type,
+            bytes,
+        });
+        self.hrefs.insert(path, href.clone());
+        Some(href)
+    }
+}
+
+fn xhtml_page(title: &str, language: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" \
+         xml:lang=\"{language}\" lang=\"{language}\">\n<head>\n<meta charset=\"utf-8\"/>\n\
+         <title>{}</title>\n<link rel=\"stylesheet\" type=\"text/css\" href=\"styles.css\"/>\n\
+         </head>\n<body>\n{body}</body>\n</html>\n",
+        escape(title)
+    )
+}
+
+/// Nests the headings into the ordered lists of the nav document, even
+/// when levels are skipped.
+fn nav_list(entries: &[(u8, String, String)]) -> String {
+    let mut out = String::from("<ol>\n");
+    let mut open: Vec<u8> = Vec::new();
+    for (level, text, href) in entries {
+        let mut closed = 0;
+        while open.last().is_some_and(|top| top >= level) {
+            open.pop();
+            out.push_str(if closed > 0 {
+                "</ol></li>\n"
+            } else {
+                "</li>\n"
+            });
+            closed += 1;
+        }
+        if closed == 0 && !open.is_empty() {
+            out.push_str("<ol>\n");
+        }
+        out.push_str(&format!(
+            "<li><a href=\"{}\">{}</a>",
+            escape(href),
+            escape(text)
+        ));
+        open.push(*level);
+    }
+    for index in 0..open.len() {
+        out.push_str(if index > 0 { "</ol></li>\n" } else { "</li>\n" });
+    }
+    out.push_str("</ol>\n");
+    out
+}
+
+/// An EPUB rendering of a document.
+pub struct EpubExport {
+    pub bytes: Vec<u8>,
+    pub missing: Vec<String>,
+    /// Images left out because EPUB readers need not support their type.
+    pub unsupported: Vec<String>,
+}
+
+/// Renders `source` as an EPUB 3 book, one chapter per heading at
+/// `options.chapter_level` or above. `base` is the folder relative image
+/// paths are resolved against, `access` says which images may be read and
+/// `fallback_title` is used when neither the front matter nor a heading
+/// names the book.
+pub fn render(
+    source: &str,
+    base: Option<&Path>,
+    access: ImageAccess,
+    fallback_title: Option<&str>,
+    options: &EpubOptions,
+) -> EpubExport {
+    let (metadata, body) = front_matter(source);
+    let title = metadata
+        .title
+        .clone()
+        .or_else(|| markdown::title(body))
+        .or_else(|| fallback_title.map(str::to_string))
+        .unwrap_or_else(|| "Untitled".into());
+    let language = escape(metadata.language.as_deref().unwrap_or("en"));
+    let mut images = Images {
+        base,
+        access,
+        hrefs: HashMap::new(),
+        resources: Vec::new(),
+        missing: Vec::new(),
+        unsupported: Vec::new(),
+    };
+
+    // First pass: split into chapters, give headings the preview's ids (see
+    // `markdown::headings`, which reads them the same way) and
+    // note where each heading and footnote lands, so links between
+    // chapters can point at the right file.
+    let mut chapters = vec![Chapter::new()];
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut slugs = markdown::Slugs::default();
+    let mut footnotes: HashMap<String, usize> = HashMap::new();
+    // The heading being read: where it starts, its text and whether it
+    // starts a chapter.
+    let mut heading: Option<(usize, String, bool)> = None;
+    // Open tags. Only headings outside any block quote, list or footnote
+    // start a chapter, so no element is split between two files.
+    let mut depth = 0usize;
+    for event in markdown::events(body) {
+        let top_level = depth == 0;
+        match event {
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        let event = match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let splits = top_level && level as u8 <= options.chapter_level;
+                if splits && !chapters.last().unwrap().events.is_empty() {
+                    chapters.push(Chapter::new());
+                }
+                heading = Some((chapters.last().unwrap().events.len(), String::new(), splits));
+                event
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                if let Some((start, text, splits)) = heading.take() {
+                    let id = slugs.next(&text);
+                    let chapter = chapters.last_mut().unwrap();
+                    if let Event::Start(Tag::Heading { id: slot, .. }) = &mut chapter.events[start]
+                    {
+                        *slot = Some(CowStr::from(id.clone()));
+                    }
+                    if splits && chapter.title.is_none() {
+                        chapter.title = Some(text.clone());
+                    }
+                    headings.push(Heading {
+                        level: level as u8,
+                        text,
+                        id,
+                        chapter: chapters.len() - 1,
+                    });
+                }
+                event
+            }
+            Event::Text(ref text) | Event::Code(ref text) => {
+                if let Some((_, heading, _)) = &mut heading {
+                    heading.push_str(text);
+                }
+                event
+            }
+            Event::Start(Tag::FootnoteDefinition(ref label)) => {
+                footnotes.insert(label.to_string(), chapters.len() - 1);
+                event
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let dest_url = match images.add(&dest_url) {
+                    Some(href) => CowStr::from(href),
+                    None => {
+                        if dest_url.contains("://") {
+                            chapters.last_mut().unwrap().remote = true;
+                        }
+                        dest_url
+                    }
+                };
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                })
+            }
+            _ => event,
+        };
+        chapters.last_mut().unwrap().events.push(event);
+    }
+    let anchors: HashMap<&str, usize> = headings
+        .iter()
+        .map(|heading| (heading.id.as_str(), heading.chapter))
+        .collect();
+
+    // Second pass: render each chapter, pointing fragment links and
+    // footnote references at the chapter that holds their target.
+    let mut numbers: HashMap<String, usize> = HashMap::new();
+    let mut pages = Vec::new();
+    for chapter in &chapters {
+        let mut events = Vec::with_capacity(chapter.events.len());
+        for event in chapter.events.iter().cloned() {
+            events.push(match event {
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                }) => {
+                    let dest_url = match dest_url.strip_prefix('#').and_then(|f| anchors.get(f)) {
+                        Some(&target) => {
+                            CowStr::from(format!("{}{dest_url}", chapter_file(target)))
+                        }
+                        None => dest_url,
+                    };
+                    Event::Start(Tag::Link {
+                        link_type,
+                        dest_url,
+                        title,
+                        id,
+                    })
+                }
+                Event::FootnoteReference(label) => match footnotes.get(label.as_ref()) {
+                    Some(&target) => {
+                        let next = numbers.len() + 1;
+                        let first = !numbers.contains_key(label.as_ref());
+                        let number = *numbers.entry(label.to_string()).or_insert(next);
+                        let id = if first {
+                            format!(" id=\"fnref-{number}\"")
+                        } else {
+                            String::new()
+                        };
+                        Event::Html(CowStr::from(format!(
+                            "<sup><a epub:type=\"noteref\"{id} href=\"{}#fn-{number}\">{number}</a></sup>",
+                            chapter_file(target)
+                        )))
+                    }
+                    None => Event::Text(CowStr::from(format!("[^{label}]"))),
+                },
+                Event::Start(Tag::FootnoteDefinition(label)) => {
+                    let next = numbers.len() + 1;
+                    let number = *numbers.entry(label.to_string()).or_insert(next);
+                    Event::Html(CowStr::from(format!(
+                        "<aside epub:type=\"footnote\" class=\"footnote\" id=\"fn-{number}\">\
+                         <p><strong>{number}.</strong></p>\n"
+                    )))
+                }
+                Event::End(TagEnd::FootnoteDefinition) => Event::Html(CowStr::from("</aside>\n")),
+                // Raw HTML is rarely well-formed XHTML, which readers
+                // require, so only line breaks are kept.
+                Event::Html(raw) | Event::InlineHtml(raw) => {
+                    if raw.trim().to_ascii_lowercase().starts_with("<br") {
+                        Event::Html(CowStr::from("<br />"))
+                    } else {
+                        continue;
+                    }
+                }
+                event => event,
+            });
+        }
+        let mut body = String::new();
+        html::push_html(&mut body, events.into_iter());
+        let page_title = chapter.title.as_deref().unwrap_or(&title);
+        pages.push(xhtml_page(page_title, &language, &body));
+    }
+
+    let cover = metadata.cover.as_deref().and_then(|cover| {
+        let href = images.add(cover)?;
+        images
+            .resources
+            .iter()
+            .position(|resource| resource.href == href)
+    });
+
+    // The nav lists the headings like the preview's table of contents, or
+    // just the chapters when there are none.
+    let entries: Vec<(u8, String, String)> = if headings.is_empty() {
+        chapters
+            .iter()
+            .enumerate()
+            .map(|(index, chapter)| {
+                let text = chapter.title.clone().unwrap_or_else(|| title.clone());
+                (1, text, chapter_file(index))
+            })
+            .collect()
+    } else {
+        headings
+            .iter()
+            .map(|heading| {
+                let href = format!("{}#{}", chapter_file(heading.chapter), heading.id);
+                (heading.level, heading.text.clone(), href)
+            })
+            .collect()
+    };
+    let nav = xhtml_page(
+        &title,
+        &language,
+        &format!(
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n{}</nav>\n",
+            nav_list(&entries)
+        ),
+    );
+
+    let identifier = metadata
+        .identifier
+        .clone()
+        .unwrap_or_else(|| format!("urn:uuid:{}", uuid::Uuid::new_v4()));
+    let modified = timestamp(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default(),
+    );
+    let mut meta = format!(
+        "<dc:identifier id=\"book-id\">{}</dc:identifier>\n<dc:title>{}</dc:title>\n\
+         <dc:language>{language}</dc:language>\n",
+        escape(&identifier),
+        escape(&title)
+    );
+    for author in &metadata.authors {
+        meta.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(author)));
+    }
+    let optional = [
+        ("description", &metadata.description),
+        ("publisher", &metadata.publisher),
+        ("date", &metadata.date),
+    ];
+    for (element, value) in optional {
+        if let Some(value) = value {
+            meta.push_str(&format!("<dc:{element}>{}</dc:{element}>\n", escape(value)));
+        }
+    }
+    meta.push_str(&format!(
+        "<meta property=\"dcterms:modified\">{modified}</meta>\n"
+    ));
+
+    let mut manifest = String::from(
+        "<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n\
+         <item id=\"css\" href=\"styles.css\" media-type=\"text/css\"/>\n",
+    );
+    let mut spine = String::new();
+    if let Some(cover) = cover {
+        // Older readers find the cover through this meta instead.
+        meta.push_str(&format!(
+            "<meta name=\"cover\" content=\"image-{}\"/>\n",
+            cover + 1
+        ));
+        manifest.push_str(
+            "<item id=\"cover\" href=\"cover.xhtml\" media-type=\"application/xhtml+xml\"/>\n",
+        );
+        spine.push_str("<itemref idref=\"cover\"/>\n");
+    }
+    for (index, chapter) in chapters.iter().enumerate() {
+        let properties = if chapter.remote {
+            " properties=\"remote-resources\""
+        } else {
+            ""
+        };
+        manifest.push_str(&format!(
+            "<item id=\"chapter-{0}\" href=\"{1}\" media-type=\"application/xhtml+xml\"{properties}/>\n",
+            index + 1,
+            chapter_file(index)
+        ));
+        spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", index + 1));
+    }
+    for (index, resource) in images.resources.iter().enumerate() {
+        let properties = if Some(index) == cover {
+            " properties=\"cover-image\""
+        } else {
+            ""
+        };
+        manifest.push_str(&format!(
+            "<item id=\"image-{}\" href=\"{}\" media-type=\"{}\"{properties}/>\n",
+            index + 1,
+            escape(&resource.href),
+            resource.media_type
+        ));
+    }
+    let package = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" \
+         unique-identifier=\"book-id\" xml:lang=\"{language}\">\n\
+         <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{meta}</metadata>\n\
+         <manifest>\n{manifest}</manifest>\n<spine>\n{spine}</spine>\n</package>\n"
+    );
+
+    let mut zip = ZipWriter::new();
+    // The mimetype must come first and uncompressed, so the file can be
+    // recognised by its first bytes.
+    zip.add_stored("mimetype", b"application/epub+zip");
+    zip.add("META-INF/container.xml", CONTAINER.as_bytes());
+    zip.add("OEBPS/content.opf", package.as_bytes());
+    zip.add("OEBPS/nav.xhtml", nav.as_bytes());
+    zip.add("OEBPS/styles.css", STYLESHEET.as_bytes());
+    if let Some(cover) = cover {
+        let page = xhtml_page(
+            &title,
+            &language,
+            &format!(
+                "<section class=\"cover\" epub:type=\"cover\"><img src=\"{}\" alt=\"{}\"/></section>\n",
+                escape(&images.resources[cover].href),
+                escape(&title)
+            ),
+        );
+        zip.add("OEBPS/cover.xhtml", page.as_bytes());
+    }
+    for (index, page) in pages.iter().enumerate() {
+        zip.add(&format!("OEBPS/{}", chapter_file(index)), page.as_bytes());
+    }
+    for resource in &images.resources {
+        zip.add(&format!("OEBPS/{}", resource.href), &resource.bytes);
+    }
+    EpubExport {
+        bytes: zip.finish(),
+        missing: images.missing,
+        unsupported: images.unsupported,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::ZipReader;
+
+    fn entry(book: &EpubExport, name: &str) -> String {
+        let zip = ZipReader::new(&book.bytes).unwrap();
+        String::from_utf8(zip.read(name).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn heading_ids() {
+        let source = "# Intro\n\n## Intro\n\n## ***\n\n## `x`\n";
+        let book = render(
+            source,
+            None,
+            ImageAccess::Unscoped,
+            None,
+            &EpubOptions::default(),
+        );
+        let chapter = entry(&book, &format!("OEBPS/{}", chapter_file(0)));
+        let expected: Vec<String> = markdown::headings(source)
+            .into_iter()
+            .map(|heading| format!("id=\"{}\"", heading.id))
+            .collect();
+        assert_eq!(
+            expected,
+            [
+                "id=\"intro\"",
+                "id=\"intro-1\"",
+                "id=\"section-1\"",
+                "id=\"x\""
+            ]
+        );
+        for id in &expected {
+            assert!(chapter.contains(id.as_str()), "{id} missing from {chapter}");
+        }
+        assert!(!chapter.contains("id=\"\""));
+    }
+
+    #[test]
+    fn chapters_split_at_top_level_headings() {
+        let source = "# One\n\ntext\n\n> # Quoted\n> more\n\n- # Listed\n\n# Two\n";
+        let book = render(
+            source,
+            None,
+            ImageAccess::Unscoped,
+            None,
+            &EpubOptions::default(),
+        );
+        let zip = ZipReader::new(&book.bytes).unwrap();
+        assert!(zip.read(&format!("OEBPS/{}", chapter_file(2))).is_none());
+        for index in 0..2 {
+            let chapter = entry(&book, &format!("OEBPS/{}", chapter_file(index)));
+            let options = roxmltree::ParsingOptions {
+                allow_dtd: true,
+                ..Default::default()
+            };
+            let parsed = roxmltree::Document::parse_with_options(&chapter, options);
+            assert!(parsed.is_ok(), "{chapter}");
+        }
+        assert!(entry(&book, &format!("OEBPS/{}", chapter_file(0))).contains("Quoted"));
+    }
+
+    #[test]
+    fn unsupported_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("a.png"), b"png").unwrap();
+        fs::write(dir.join("b.bmp"), b"bmp").unwrap();
+        let source = "# Pictures\n\n![a](a.png) ![b](b.bmp) ![c](c.png)\n";
+        let book = render(
+            source,
+            Some(dir),
+            ImageAccess::Unscoped,
+            None,
+            &EpubOptions::default(),
+        );
+        let package = entry(&book, "OEBPS/content.opf");
+        assert!(package.contains("image/png"));
+        assert!(!package.contains("image/bmp"));
+        assert!(!package.contains("octet-stream"));
+        assert_eq!(book.unsupported, ["b.bmp"]);
+        assert_eq!(book.missing, ["c.png"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refused_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("id_rsa"), b"key").unwrap();
+        std::os::unix::fs::symlink(dir.join("id_rsa"), dir.join("secret.png")).unwrap();
+        let source = "# Pictures\n\n![](secret.png)\n";
+        let book = render(
+            source,
+            Some(dir),
+            ImageAccess::Unscoped,
+            None,
+            &EpubOptions::default(),
+        );
+        assert!(!entry(&book, "OEBPS/content.opf").contains("images/"));
+        assert_eq!(book.missing, ["secret.png"]);
+    }
+}
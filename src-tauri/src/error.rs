@@ -0,0 +1,44 @@
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Errors returned to the frontend. Serialised as `{ kind, message }` so the
+/// UI can react to the kind without parsing the message.
+#[derive(Debug, thiserror::Error, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "camelCase")]
+pub enum Error {
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    DiskFull(String),
+    #[error("{0}")]
+    ReadOnly(String),
+    #[error("{0}")]
+    Encoding(String),
+    #[error("{0}")]
+    AlreadyOpen(String),
+    /// A value sent by the frontend is out of range.
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Io(String),
+}
+
+impl Error {
+    /// Classifies an I/O error that happened while accessing `path`.
+    pub fn io(err: io::Error, path: &Path) -> Self {
+        let message = format!("{}: {}", path.display(), err);
+        match err.kind() {
+            io::ErrorKind::NotFound => Error::NotFound(message),
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied(message),
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => Error::DiskFull(message),
+            io::ErrorKind::ReadOnlyFilesystem => Error::ReadOnly(message),
+            _ => Error::Io(message),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use pulldown_cmark::{html, CowStr, Event, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, State};
+
+use crate::atomic;
+use crate::docx;
+use crate::epub::{self, EpubOptions};
+use crate::error::{Error, Result};
+use crate::images;
+use crate::links::{self, encode_segment};
+use crate::markdown;
+use crate::pdf::{self, PdfOptions};
+use crate::scope;
+use crate::settings::{AccentColor, FontFamily, Settings, SettingsStore, Theme};
+
+/// The formats a document can be rendered to outside the editor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Html,
+    Text,
+    Pdf,
+    Docx,
+}
+
+impl Format {
+    /// Parses a format name as given on the command line or taken from a
+    /// file extension.
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "html" | "htm" => Ok(Format::Html),
+            "txt" | "text" => Ok(Format::Text),
+            "pdf" => Ok(Format::Pdf),
+            "docx" => Ok(Format::Docx),
+            _ => Err(Error::Invalid(format!(
+                "unknown format \"{name}\", expected html, txt, pdf or docx"
+            ))),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Text => "txt",
+            Format::Pdf => "pdf",
+            Format::Docx => "docx",
+        }
+    }
+}
+
+/// Where images referenced by an exported HTML file end up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AssetMode {
+    /// Inlined as `data:` URIs, so the HTML file stands on its own.
+    #[default]
+    Embed,
+    /// Copied into a `<name>_files` folder next to the HTML file.
+    Folder,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportReport {
+    pub path: PathBuf,
+    /// Images that could not be read, or were refused because they aren't
+    /// images or are outside the folders the app may use, and were left
+    /// pointing at the original.
+    pub missing_assets: Vec<String>,
+    /// Images left out because the format can't hold their type.
+    pub unsupported_assets: Vec<String>,
+}
+
+/// Resolves an image or link target the way the preview does: URLs other
+/// than `file:` are not local, `%XX` escapes are decoded and any `?query` or
+/// `#fragment` is dropped, absolute paths are kept, and relative paths are
+/// relative to the document's folder. Returns `None` for unsaved documents.
+pub fn resolve_local(base: Option<&Path>, href: &str) -> Option<PathBuf> {
+    let href = links::file_url_path(href).unwrap_or(href);
+    if !links::is_local(href) {
+        return None;
+    }
+    let (path, _) = links::split_suffix(href);
+    if path.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(links::percent_decode(path));
+    if path.is_absolute() {
+        Some(path)
+    } else {
+        base.map(|base| links::normalize(&base.join(path)))
+    }
+}
+
+/// Which local files an export may read images from.
+#[derive(Clone, Copy)]
+pub enum ImageAccess<'a> {
+    /// Files inside the folders the app may use, for exports from a window.
+    Scoped(&'a AppHandle),
+    /// Any file, for the command line, which reads with its user's rights.
+    Unscoped,
+}
+
+impl ImageAccess<'_> {
+    /// Whether `path` may be read into an export: it must be an image, and
+    /// so must the file a symlink points at, so `a.png` linking to a key
+    /// file is refused.
+    pub fn allows(self, path: &Path) -> bool {
+        let Ok(target) = dunce::canonicalize(path) else {
+            return false;
+        };
+        if !target.is_file() || !images::is_image(path) || !images::is_image(&target) {
+            return false;
+        }
+        match self {
+            ImageAccess::Scoped(app) => scope::check(app, &target).is_ok(),
+            ImageAccess::Unscoped => true,
+        }
+    }
+}
+
+pub fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Rewrites local image references while the document is rendered.
+struct Assets<'a> {
+    base: Option<&'a Path>,
+    access: ImageAccess<'a>,
+    mode: AssetMode,
+    folder: String,
+    /// Rewritten URL of each image already seen.
+    urls: HashMap<PathBuf, String>,
+    /// Files to copy into the folder, with their names there.
+    copies: Vec<(PathBuf, String)>,
+    missing: Vec<String>,
+}
+
+impl Assets<'_> {
+    fn rewrite(&mut self, href: &str) -> Option<String> {
+        let path = resolve_local(self.base, href)?;
+        if let Some(url) = self.urls.get(&path) {
+            return Some(url.clone());
+        }
+        let url = match self.mode {
+            _ if !self.access.allows(&path) => None,
+            AssetMode::Embed => fs::read(&path)
+                .ok()
+                .map(|bytes| format!("data:{};base64,{}", mime_type(&path), BASE64.encode(bytes))),
+            AssetMode::Folder => {
+                let name = self.unique_name(&path);
+                let url = format!("{}/{}", encode_segment(&self.folder), encode_segment(&name));
+                self.copies.push((path.clone(), name));
+                Some(url)
+            }
+        };
+        match url {
+            Some(url) => {
+                self.urls.insert(path, url.clone());
+                Some(url)
+            }
+            None => {
+                self.missing.push(href.to_string());
+                None
+            }
+        }
+    }
+
+    /// The file's name, numbered if another image already took it.
+    fn unique_name(&self, path: &Path) -> String {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "image".into());
+        let taken = |candidate: &str| self.copies.iter().any(|(_, used)| used == candidate);
+        if !taken(&name) {
+            return name;
+        }
+        let stem = Path::new(&name)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = Path::new(&name)
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+        (2..)
+            .map(|n| format!("{stem}-{n}{extension}"))
+            .find(|candidate| !taken(candidate))
+            .unwrap()
+    }
+}
+
+struct Palette {
+    background: &'static str,
+    text: &'static str,
+    border: &'static str,
+}
+
+fn palette(theme: Theme) -> Palette {
+    match theme {
+        Theme::Light => Palette {
+            background: "#eff1f5",
+            text: "#4c4f69",
+            border: "#dce0e8",
+        },
+        Theme::Dark => Palette {
+            background: "#1e1e2e",
+            text: "#cdd6f4",
+            border: "#313244",
+        },
+        Theme::Dim => Palette {
+            background: "#3a3a3a",
+            text: "#f2f2f2",
+            border: "#5a5a5a",
+        },
+    }
+}
+
+/// The accent color as the app shows it: darker shades on the light theme.
+fn accent(color: AccentColor, theme: Theme) -> &'static str {
+    let (light, dark) = match color {
+        AccentColor::Blue => ("#1e66f5", "#89b4fa"),
+        AccentColor::Green => ("#40a02b", "#a6e3a1"),
+        AccentColor::Mauve => ("#8839ef", "#cba6f7"),
+        AccentColor::Flamingo => ("#dd7878", "#f2cdcd"),
+        AccentColor::Peach => ("#fe640b", "#fab387"),
+    };
+    if theme == Theme::Light {
+        light
+    } else {
+        dark
+    }
+}
+
+fn font_stack(family: FontFamily) -> &'static str {
+    match family {
+        FontFamily::Sans => "ui-sans-serif, system-ui, -apple-system, \"Segoe UI\", Roboto, Helvetica, Arial, sans-serif",
+        FontFamily::Serif => "ui-serif, Georgia, Cambria, \"Times New Roman\", Times, serif",
+        FontFamily::Mono => "ui-monospace, SFMono-Regular, Menlo, Monaco, Consolas, \"Liberation Mono\", \"Courier New\", monospace",
+    }
+}
+
+/// The reading view's styles, kept in step with `.markdown-body` in App.css.
+const STYLESHEET: &str = r#"
+*, ::before, ::after { box-sizing: border-box; }
+body { margin: 0; background: var(--background); color: var(--text); font-family: var(--font); font-size: var(--font-size); line-height: 1.625; -webkit-font-smoothing: antialiased; }
+.markdown-body { max-width: 720px; margin: 0 auto; padding: 2.5rem; }
+.markdown-body h1 { margin: 3rem 0 2rem; font-size: 2.25em; font-weight: 800; letter-spacing: -.025em; color: var(--accent-color); }
+.markdown-body h2 { margin: 3rem 0 1rem; font-size: 1.5em; font-weight: 700; opacity: .9; }
+.markdown-body h3 { margin: 2.5rem 0 .75rem; font-size: 1.25em; font-weight: 700; opacity: .8; }
+.markdown-body h4, .markdown-body h5, .markdown-body h6 { margin: 2rem 0 .5rem; font-size: 1em; font-weight: 700; opacity: .8; }
+.markdown-body p { margin: 0 0 1.5rem; opacity: .8; }
+.markdown-body a { color: var(--accent-color); font-weight: 500; text-decoration: none; border-bottom: 1px solid color-mix(in srgb, currentColor, transparent 70%); }
+.markdown-body blockquote { margin: 2rem 0; padding: 1rem 0 1rem 1.5rem; border-left: 4px solid var(--accent-color); font-style: italic; opacity: .6; }
+.markdown-body blockquote p { margin-bottom: 0; }
+.markdown-body pre { margin: 2rem 0; padding: 1.5rem; overflow-x: auto; border: 1px solid #64748b1a; border-radius: 1rem; box-shadow: 0 1px 2px 0 rgb(0 0 0 / .05); }
+.markdown-body code { font-family: ui-monospace, SFMono-Regular, Menlo, Monaco, Consolas, "Liberation Mono", "Courier New", monospace; }
+.markdown-body code:not(pre code) { padding: .125rem .375rem; border-radius: .375rem; background-color: #64748b1a; font-size: .9em; }
+.markdown-body ul, .markdown-body ol { margin: 0 0 1.5rem; padding-left: 1.5rem; }
+.markdown-body ul { list-style-type: disc; }
+.markdown-body ol { list-style-type: decimal; }
+.markdown-body li { opacity: .9; }
+.markdown-body li > ul, .markdown-body li > ol { margin: 0; }
+.markdown-body li::marker { color: #64748b80; }
+.markdown-body li p { margin-bottom: 0; }
+.markdown-body table { width: 100%; margin-bottom: 1.5rem; border-collapse: collapse; border: 1px solid var(--border-color); }
+.markdown-body th, .markdown-body td { padding: .75rem; border-bottom: 1px solid #64748b1a; text-align: left; }
+.markdown-body th { background-color: #64748b0d; font-weight: 700; }
+.markdown-body img { display: block; max-width: 100%; height: auto; margin: 1.5rem auto; border-radius: .5rem; }
+.markdown-body hr { height: 0; margin: 2rem 0; border: 0; border-top: 1px solid var(--border-color); }
+.markdown-body .footnote-definition { font-size: .9em; opacity: .8; }
+"#;
+
+/// The stylesheet for exported HTML, in the user's theme, accent color and
+/// font.
+pub fn stylesheet(settings: &Settings) -> String {
+    let palette = palette(settings.theme);
+    let color_scheme = if settings.theme == Theme::Light {
+        "light"
+    } else {
+        "dark"
+    };
+    format!(
+        ":root {{ color-scheme: {color_scheme}; --background: {}; --text: {}; --border-color: {}; \
+         --accent-color: {}; --font: {}; --font-size: {}px; }}{STYLESHEET}",
+        palette.background,
+        palette.text,
+        palette.border,
+        accent(settings.accent_color, settings.theme),
+        font_stack(settings.font_family),
+        settings.font_size,
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Wraps rendered markdown in a standalone HTML document.
+fn html_document(body: &str, title: Option<&str>, css: &str) -> String {
+    let title = title.map(escape).unwrap_or_default();
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{title}</title>\n<style>{css}</style>\n</head>\n<body>\n\
+         <article class=\"markdown-body\">\n{body}</article>\n</body>\n</html>\n"
+    )
+}
+
+/// An HTML rendering of a document and the images it needs copied next to
+/// it.
+pub struct HtmlExport {
+    pub html: String,
+    pub copies: Vec<(PathBuf, String)>,
+    pub folder: String,
+    pub missing: Vec<String>,
+}
+
+/// Renders `source` to a standalone HTML document. `base` is the folder of
+/// the document, which relative image paths are resolved against, and
+/// `name` is the file stem of the HTML file, which names the asset folder.
+pub fn to_html(
+    source: &str,
+    base: Option<&Path>,
+    access: ImageAccess,
+    name: &str,
+    title: Option<&str>,
+    settings: &Settings,
+    mode: AssetMode,
+) -> HtmlExport {
+    let mut assets = Assets {
+        base,
+        access,
+        mode,
+        folder: format!("{name}_files"),
+        urls: HashMap::new(),
+        copies: Vec::new(),
+        missing: Vec::new(),
+    };
+    // Headings get the preview's ids (see `markdown::headings`), so links to
+    // them keep working.
+    let mut events = Vec::new();
+    let mut slugs = markdown::Slugs::default();
+    let mut heading: Option<(usize, String)> = None;
+    for event in markdown::events(source) {
+        let event = match event {
+            Event::Start(Tag::Heading { .. }) => {
+                heading = Some((events.len(), String::new()));
+                event
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((start, text)) = heading.take() {
+                    if let Event::Start(Tag::Heading { id, .. }) = &mut events[start] {
+                        *id = Some(CowStr::from(slugs.next(&text)));
+                    }
+                }
+                event
+            }
+            Event::Text(ref text) | Event::Code(ref text) => {
+                if let Some((_, heading)) = &mut heading {
+                    heading.push_str(text);
+                }
+                event
+            }
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            }) => {
+                let dest_url = match assets.rewrite(&dest_url) {
+                    Some(url) => url.into(),
+                    None => dest_url,
+                };
+                Event::Start(Tag::Image {
+                    link_type,
+                    dest_url,
+                    title,
+                    id,
+                })
+            }
+            event => event,
+        };
+        events.push(event);
+    }
+    let mut body = String::with_capacity(source.len() * 3 / 2);
+    html::push_html(&mut body, events.into_iter());
+    HtmlExport {
+        html: html_document(&body, title, &stylesheet(settings)),
+        copies: assets.copies,
+        folder: assets.folder,
+        missing: assets.missing,
+    }
+}
+
+fn file_stem(path: &Path) -> Option<String> {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+}
+
+/// Splits the front matter off `source` and returns the rest with the
+/// document's title: the front matter's, then the first heading, then the
+/// file name of `path`.
+fn body_and_title<'a>(source: &'a str, path: Option<&Path>) -> (&'a str, Option<String>) {
+    let (fields, body) = markdown::front_matter(source);
+    let title = fields
+        .into_iter()
+        .find(|(key, _)| key == "title")
+        .and_then(|(_, values)| values.into_iter().next())
+        .or_else(|| markdown::title(body))
+        .or_else(|| path.and_then(file_stem));
+    (body, title)
+}
+
+/// Renders `source` to `format` with the default look, for the command
+/// line. `path` is the source file, if any. Front matter is left out, and
+/// the title of HTML, PDF and Word output is its title or the first
+/// heading, falling back to the file name.
+pub fn render(source: &str, format: Format, path: Option<&Path>) -> Vec<u8> {
+    let (source, title) = body_and_title(source, path);
+    match format {
+        Format::Html => {
+            let base = path.and_then(Path::parent);
+            to_html(
+                source,
+                base,
+                ImageAccess::Unscoped,
+                "",
+                title.as_deref(),
+                &Settings::default(),
+                AssetMode::Embed,
+            )
+            .html
+            .into_bytes()
+        }
+        Format::Text => markdown::to_text(source).into_bytes(),
+        Format::Pdf => {
+            pdf::render(
+                source,
+                path.and_then(Path::parent),
+                ImageAccess::Unscoped,
+                &PdfOptions::default(),
+                title.as_deref(),
+            )
+            .bytes
+        }
+        Format::Docx => {
+            let base = path.and_then(Path::parent);
+            docx::render(
+                source,
+                base,
+                ImageAccess::Unscoped,
+                title.as_deref(),
+                FontFamily::default(),
+            )
+            .bytes
+        }
+    }
+}
+
+/// Exports `source` as a standalone HTML file at `target`, styled with the
+/// current settings. `document_path` is where the document is saved, for
+/// resolving relative image paths.
+#[tauri::command]
+pub async fn export_html(
+    app: AppHandle,
+    source: String,
+    document_path: Option<PathBuf>,
+    target: PathBuf,
+    assets: AssetMode,
+    settings: State<'_, SettingsStore>,
+) -> Result<ExportReport> {
+    check_paths(&app, document_path.as_deref(), &target)?;
+    let name = file_stem(&target).unwrap_or_else(|| "document".into());
+    let (source, title) = body_and_title(&source, document_path.as_deref());
+    let export = to_html(
+        source,
+        document_path.as_deref().and_then(Path::parent),
+        ImageAccess::Scoped(&app),
+        &name,
+        title.as_deref(),
+        &settings.get(),
+        assets,
+    );
+
+    if !export.copies.is_empty() {
+        let folder = target.with_file_name(&export.folder);
+        fs::create_dir_all(&folder).map_err(|e| Error::io(e, &folder))?;
+        for (source, name) in &export.copies {
+            fs::copy(source, folder.join(name)).map_err(|e| Error::io(e, source))?;
+        }
+    }
+    atomic::write(&target, export.html.as_bytes()).map_err(|e| Error::io(e, &target))?;
+    Ok(ExportReport {
+        path: target,
+        missing_assets: export.missing,
+        unsupported_assets: Vec::new(),
+    })
+}
+
+/// Exports `source` as a PDF at `target`, laid out with `options`.
+#[tauri::command]
+pub async fn export_pdf(
+    app: AppHandle,
+    source: String,
+    document_path: Option<PathBuf>,
+    target: PathBuf,
+    options: PdfOptions,
+) -> Result<ExportReport> {
+    check_paths(&app, document_path.as_deref(), &target)?;
+    options.validate()?;
+    let (source, title) = body_and_title(&source, document_path.as_deref());
+    let export = pdf::render(
+        source,
+        document_path.as_deref().and_then(Path::parent),
+        ImageAccess::Scoped(&app),
+        &options,
+        title.as_deref(),
+    );
+    atomic::write(&target, &export.bytes).map_err(|e| Error::io(e, &target))?;
+    Ok(ExportReport {
+        path: target,
+        missing_assets: export.missing,
+        unsupported_assets: export.unsupported,
+    })
+}
+
+/// Exports `source` as a Word document at `target`, in the font family of
+/// the current settings.
+#[tauri::command]
+pub async fn export_docx(
+    app: AppHandle,
+    source: String,
+    document_path: Option<PathBuf>,
+    target: PathBuf,
+    settings: State<'_, SettingsStore>,
+) -> Result<ExportReport> {
+    check_paths(&app, document_path.as_deref(), &target)?;
+    let (source, title) = body_and_title(&source, document_path.as_deref());
+    let export = docx::render(
+        source,
+        document_path.as_deref().and_then(Path::parent),
+        ImageAccess::Scoped(&app),
+        title.as_deref(),
+        settings.get().font_family,
+    );
+    atomic::write(&target, &export.bytes).map_err(|e| Error::io(e, &target))?;
+    Ok(ExportReport {
+        path: target,
+        missing_assets: export.missing,
+        unsupported_assets: Vec::new(),
+    })
+}
+
+/// Exports `source` as an EPUB book at `target`.
+#[tauri::command]
+pub async fn export_epub(
+    app: AppHandle,
+    source: String,
+    document_path: Option<PathBuf>,
+    target: PathBuf,
+    options: EpubOptions,
+) -> Result<ExportReport> {
+    check_paths(&app, document_path.as_deref(), &target)?;
+    options.validate()?;
+    let export = epub::render(
+        &source,
+        document_path.as_deref().and_then(Path::parent),
+        ImageAccess::Scoped(&app),
+        document_path.as_deref().and_then(file_stem).as_deref(),
+        &options,
+    );
+    atomic::write(&target, &export.bytes).map_err(|e| Error::io(e, &target))?;
+    Ok(ExportReport {
+        path: target,
+        missing_assets: export.missing,
+        unsupported_assets: export.unsupported,
+    })
+}
+
+/// Refuses an export whose document, which images are read next to, or
+/// target is outside the folders the app may use.
+fn check_paths(app: &AppHandle, document_path: Option<&Path>, target: &Path) -> Result<()> {
+    if let Some(document_path) = document_path {
+        scope::check(app, document_path)?;
+    }
+    scope::check(app, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_targets() {
+        let base = Path::new("/notes");
+        let resolve = |href| resolve_local(Some(base), href);
+        assert_eq!(
+            resolve("img/a.png"),
+            Some(PathBuf::from("/notes/img/a.png"))
+        );
+        assert_eq!(
+            resolve("../my%20pics/a.png?v=2#x"),
+            Some(PathBuf::from("/my pics/a.png"))
+        );
+        assert_eq!(resolve("/abs/a.png"), Some(PathBuf::from("/abs/a.png")));
+        assert_eq!(
+            resolve("file:///abs/b%20c.png"),
+            Some(PathBuf::from("/abs/b c.png"))
+        );
+        assert_eq!(resolve("https://example.com/a.png"), None);
+        assert_eq!(resolve("data:image/png;base64,AAAA"), None);
+        assert_eq!(resolve("#top"), None);
+        assert_eq!(resolve_local(None, "a.png"), None);
+    }
+
+    #[test]
+    fn refused_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        fs::write(dir.join("a.png"), b"png").unwrap();
+        fs::write(dir.join("id_rsa"), b"key").unwrap();
+        let source = "![](a.png) ![](id_rsa) ![](../id_rsa)";
+        let export = to_html(
+            source,
+            Some(dir),
+            ImageAccess::Unscoped,
+            "out",
+            None,
+            &Settings::default(),
+            AssetMode::Folder,
+        );
+        assert_eq!(export.copies, [(dir.join("a.png"), "a.png".to_string())]);
+        assert_eq!(export.missing, ["id_rsa", "../id_rsa"]);
+        assert!(export.html.contains("src=\"out_files/a.png\""));
+    }
+
+    #[test]
+    fn heading_ids() {
+        let source = "# Intro\n\n## Intro\n\n## ***\n\n## `x`\n\n[see](#intro-1)\n";
+        let export = to_html(
+            source,
+            None,
+            ImageAccess::Unscoped,
+            "out",
+            None,
+            &Settings::default(),
+            AssetMode::Embed,
+        );
+        for heading in markdown::headings(source) {
+            let id = format!("id=\"{}\"", heading.id);
+            assert!(
+                export.html.contains(&id),
+                "{id} missing from {}",
+                export.html
+            );
+        }
+        assert!(export.html.contains("href=\"#intro-1\""));
+        assert!(!export.html.contains("id=\"\""));
+    }
+
+    #[test]
+    fn front_matter_is_left_out() {
+        let source = "---\ntitle: Report\nauthor: Me\n---\n# Intro\n";
+        let html = String::from_utf8(render(source, Format::Html, None)).unwrap();
+        assert!(html.contains("<title>Report</title>"));
+        assert!(!html.contains("author"));
+        assert!(!html.contains("<hr"));
+        let (body, title) = body_and_title("---\nlang: en\n---\n# Intro\n", None);
+        assert_eq!((body, title.as_deref()), ("# Intro\n", Some("Intro")));
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use encoding_rs::WINDOWS_1252;
+use fontdb::{Database, Family, Query, Style, Weight};
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::types::{CidFontType, FontFlags, SystemInfo, UnicodeCmap};
+use pdf_writer::{Filter, Finish, Name, Pdf, Rect, Ref, Str};
+
+use crate::settings::FontFamily;
+
+/// Families tried for each font setting, in order, before the generic one.
+/// They match the stacks the preview uses on each platform.
+const SANS_FAMILIES: [&str; 8] = [
+    "Helvetica Neue",
+    "Helvetica",
+    "Segoe UI",
+    "Arial",
+    "Roboto",
+    "Noto Sans",
+    "DejaVu Sans",
+    "Liberation Sans",
+];
+const SERIF_FAMILIES: [&str; 7] = [
+    "Georgia",
+    "Cambria",
+    "Times New Roman",
+    "Times",
+    "Noto Serif",
+    "DejaVu Serif",
+    "Liberation Serif",
+];
+const MONO_FAMILIES: [&str; 8] = [
+    "SF Mono",
+    "Menlo",
+    "Monaco",
+    "Consolas",
+    "Liberation Mono",
+    "DejaVu Sans Mono",
+    "Noto Sans Mono",
+    "Courier New",
+];
+
+const IDENTITY: SystemInfo = SystemInfo {
+    registry: Str(b"Adobe"),
+    ordering: Str(b"Identity"),
+    supplement: 0,
+};
+
+/// Advance widths of ASCII 32..=126 in Helvetica, in thousandths of an em.
+const HELVETICA_WIDTHS: [u16; 95] = [
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556, 556, 556,
+    556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667, 667, 722, 722, 667,
+    611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722, 667, 611, 722, 667, 944, 667,
+    667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500,
+    222, 833, 556, 556, 556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+/// The styles a PDF is drawn with. Code is always monospaced; the rest use
+/// the family chosen in the settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Font {
+    Regular,
+    Bold,
+    Italic,
+    BoldItalic,
+    Mono,
+}
+
+impl Font {
+    const ALL: [Font; 5] = [
+        Font::Regular,
+        Font::Bold,
+        Font::Italic,
+        Font::BoldItalic,
+        Font::Mono,
+    ];
+
+    pub fn styled(bold: bool, italic: bool) -> Font {
+        match (bold, italic) {
+            (false, false) => Font::Regular,
+            (true, false) => Font::Bold,
+            (false, true) => Font::Italic,
+            (true, true) => Font::BoldItalic,
+        }
+    }
+
+    /// The font's name in page resources.
+    pub fn resource(self) -> Name<'static> {
+        Name(match self {
+            Font::Regular => b"F1",
+            Font::Bold => b"F2",
+            Font::Italic => b"F3",
+            Font::BoldItalic => b"F4",
+            Font::Mono => b"F5",
+        })
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    fn is_bold(self) -> bool {
+        matches!(self, Font::Bold | Font::BoldItalic)
+    }
+
+    fn is_italic(self) -> bool {
+        matches!(self, Font::Italic | Font::BoldItalic)
+    }
+}
+
+/// Font-wide values for the font descriptor, in thousandths of an em.
+struct Metrics {
+    bbox: Rect,
+    italic_angle: f32,
+    ascent: f32,
+    descent: f32,
+    cap_height: f32,
+    monospaced: bool,
+    italic: bool,
+}
+
+/// A TrueType or OpenType font from the system, embedded with only the
+/// glyphs that were drawn.
+struct Embedded {
+    data: Vec<u8>,
+    index: u32,
+    postscript_name: String,
+    units_per_em: f32,
+    cff: bool,
+    serif: bool,
+    metrics: Metrics,
+    /// Glyph id and advance (in ems) of each character looked up so far.
+    glyphs: HashMap<char, (u16, f32)>,
+    /// Glyphs drawn, with the character each stands for.
+    used: BTreeMap<u16, (char, f32)>,
+}
+
+impl Embedded {
+    fn new(data: Vec<u8>, index: u32, serif: bool) -> Option<Self> {
+        let face = ttf_parser::Face::parse(&data, index).ok()?;
+        let cff = face.tables().cff.is_some();
+        if !cff && face.tables().glyf.is_none() {
+            return None;
+        }
+        let postscript_name = face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == ttf_parser::name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .unwrap_or_else(|| "Font".into())
+            .replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "");
+        let units_per_em = f32::from(face.units_per_em());
+        let scale = |value: i16| f32::from(value) * 1000.0 / units_per_em;
+        let bbox = face.global_bounding_box();
+        let metrics = Metrics {
+            bbox: Rect::new(
+                scale(bbox.x_min),
+                scale(bbox.y_min),
+                scale(bbox.x_max),
+                scale(bbox.y_max),
+            ),
+            italic_angle: face.italic_angle(),
+            ascent: scale(face.ascender()),
+            descent: scale(face.descender()),
+            cap_height: scale(face.capital_height().unwrap_or(face.ascender())),
+            monospaced: face.is_monospaced(),
+            italic: face.is_italic(),
+        };
+        Some(Self {
+            data,
+            index,
+            postscript_name,
+            units_per_em,
+            cff,
+            serif,
+            metrics,
+            glyphs: HashMap::new(),
+            used: BTreeMap::new(),
+        })
+    }
+
+    fn glyph(&mut self, ch: char) -> (u16, f32) {
+        if let Some(glyph) = self.glyphs.get(&ch) {
+            return *glyph;
+        }
+        let glyph = ttf_parser::Face::parse(&self.data, self.index)
+            .ok()
+            .and_then(|face| {
+                let id = face.glyph_index(ch)?;
+                let advance = face.glyph_hor_advance(id).unwrap_or(0);
+                Some((id.0, f32::from(advance) / self.units_per_em))
+            })
+            .unwrap_or((0, 0.5));
+        self.glyphs.insert(ch, glyph);
+        glyph
+    }
+}
+
+/// One of the standard PDF fonts every reader has, used when the system
+/// has none of the families above.
+struct Standard {
+    base_font: &'static [u8],
+    mono: bool,
+    bold: bool,
+}
+
+enum Face {
+    Embedded(Embedded),
+    Standard(Standard),
+}
+
+fn database() -> &'static Database {
+    static DATABASE: OnceLock<Database> = OnceLock::new();
+    DATABASE.get_or_init(|| {
+        let mut database = Database::new();
+        database.load_system_fonts();
+        database
+    })
+}
+
+fn find(names: &[&str], generic: Family, font: Font, serif: bool) -> Option<Embedded> {
+    let database = database();
+    let families: Vec<Family> = names
+        .iter()
+        .map(|name| Family::Name(name))
+        .chain([generic])
+        .collect();
+    let id = database.query(&Query {
+        families: &families,
+        weight: if font.is_bold() {
+            Weight::BOLD
+        } else {
+            Weight::NORMAL
+        },
+        style: if font.is_italic() {
+            Style::Italic
+        } else {
+            Style::Normal
+        },
+        ..Query::default()
+    })?;
+    let (data, index) = database.with_face_data(id, |data, index| (data.to_vec(), index))?;
+    Embedded::new(data, index, serif)
+}
+
+fn standard(font: Font) -> Standard {
+    let base_font: &'static [u8] = match font {
+        Font::Regular => b"Helvetica",
+        Font::Bold => b"Helvetica-Bold",
+        Font::Italic => b"Helvetica-Oblique",
+        Font::BoldItalic => b"Helvetica-BoldOblique",
+        Font::Mono => b"Courier",
+    };
+    Standard {
+        base_font,
+        mono: font == Font::Mono,
+        bold: font.is_bold(),
+    }
+}
+
+/// The fonts of one PDF document, which measure text for layout, encode it
+/// for content streams and are finally written into the file.
+pub struct Fonts {
+    faces: Vec<Face>,
+}
+
+impl Fonts {
+    pub fn load(family: FontFamily) -> Self {
+        let (names, generic): (&[&str], Family) = match family {
+            FontFamily::Sans => (&SANS_FAMILIES, Family::SansSerif),
+            FontFamily::Serif => (&SERIF_FAMILIES, Family::Serif),
+            FontFamily::Mono => (&MONO_FAMILIES, Family::Monospace),
+        };
+        let faces = Font::ALL
+            .iter()
+            .map(|&font| {
+                let found = if font == Font::Mono {
+                    find(&MONO_FAMILIES, Family::Monospace, font, false)
+                } else {
+                    find(names, generic, font, family == FontFamily::Serif)
+                };
+                match found {
+                    Some(embedded) => Face::Embedded(embedded),
+                    None => Face::Standard(standard(font)),
+                }
+            })
+            .collect();
+        Self { faces }
+    }
+
+    /// Width of `text` in ems.
+    pub fn measure(&mut self, font: Font, text: &str) -> f32 {
+        match &mut self.faces[font.index()] {
+            Face::Embedded(face) => text.chars().map(|ch| face.glyph(ch).1).sum(),
+            Face::Standard(face) => {
+                let width: f32 = text
+                    .chars()
+                    .map(|ch| match ch {
+                        _ if face.mono => 600.0,
+                        ' '..='~' => f32::from(HELVETICA_WIDTHS[ch as usize - 32]),
+                        _ => 556.0,
+                    })
+                    .sum();
+                // Bold widths are close enough to regular ones scaled up.
+                let scale = if face.bold && !face.mono { 1.06 } else { 1.0 };
+                width / 1000.0 * scale
+            }
+        }
+    }
+
+    /// Encodes `text` for a `Tj` operator in `font`, remembering the glyphs
+    /// so they are embedded.
+    pub fn encode(&mut self, font: Font, text: &str) -> Vec<u8> {
+        match &mut self.faces[font.index()] {
+            Face::Embedded(face) => {
+                let mut bytes = Vec::with_capacity(text.len() * 2);
+                for ch in text.chars() {
+                    let (id, advance) = face.glyph(ch);
+                    face.used.entry(id).or_insert((ch, advance));
+                    bytes.extend_from_slice(&id.to_be_bytes());
+                }
+                bytes
+            }
+            // Standard fonts use WinAnsi; anything outside it becomes `?`.
+            Face::Standard(_) => {
+                let mut buf = [0u8; 4];
+                text.chars()
+                    .map(|ch| {
+                        let (encoded, _, had_errors) =
+                            WINDOWS_1252.encode(ch.encode_utf8(&mut buf));
+                        match (had_errors, encoded.as_ref()) {
+                            (false, [byte]) => *byte,
+                            _ => b'?',
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Writes every font into `pdf`, taking object ids from `next_id`, and
+    /// returns the resource name and id of each.
+    pub fn write(self, pdf: &mut Pdf, next_id: &mut impl FnMut() -> Ref) -> Vec<(Font, Ref)> {
+        let mut written = Vec::new();
+        for (font, face) in Font::ALL.into_iter().zip(self.faces) {
+            let id = next_id();
+            match face {
+                Face::Standard(face) => {
+                    pdf.type1_font(id)
+                        .base_font(Name(face.base_font))
+                        .encoding_predefined(Name(b"WinAnsiEncoding"));
+                }
+                Face::Embedded(face) => write_embedded(pdf, id, font, face, next_id),
+            }
+            written.push((font, id));
+        }
+        written
+    }
+}
+
+fn write_embedded(
+    pdf: &mut Pdf,
+    id: Ref,
+    font: Font,
+    face: Embedded,
+    next_id: &mut impl FnMut() -> Ref,
+) {
+    let cid_id = next_id();
+    let descriptor_id = next_id();
+    let file_id = next_id();
+    let cmap_id = next_id();
+
+    // Subsets are named with a tag unique within the document.
+    let base_font = format!(
+        "MKDWN{}+{}",
+        (b'A' + font.index() as u8) as char,
+        face.postscript_name
+    );
+    let base_font = Name(base_font.as_bytes());
+
+    pdf.type0_font(id)
+        .base_font(base_font)
+        .encoding_predefined(Name(b"Identity-H"))
+        .descendant_font(cid_id)
+        .to_unicode(cmap_id);
+
+    let mut cid_font = pdf.cid_font(cid_id);
+    cid_font
+        .subtype(if face.cff {
+            CidFontType::Type0
+        } else {
+            CidFontType::Type2
+        })
+        .base_font(base_font)
+        .system_info(IDENTITY)
+        .font_descriptor(descriptor_id)
+        .default_width(0.0);
+    if !face.cff {
+        cid_font.cid_to_gid_map_predefined(Name(b"Identity"));
+    }
+    let mut widths = cid_font.widths();
+    for (glyph, (_, advance)) in &face.used {
+        widths.consecutive(*glyph, [advance * 1000.0]);
+    }
+    widths.finish();
+    cid_font.finish();
+
+    let metrics = &face.metrics;
+    let mut flags = FontFlags::NON_SYMBOLIC;
+    if font == Font::Mono || metrics.monospaced {
+        flags |= FontFlags::FIXED_PITCH;
+    }
+    if face.serif {
+        flags |= FontFlags::SERIF;
+    }
+    if metrics.italic {
+        flags |= FontFlags::ITALIC;
+    }
+    let mut descriptor = pdf.font_descriptor(descriptor_id);
+    descriptor
+        .name(base_font)
+        .flags(flags)
+        .bbox(metrics.bbox)
+        .italic_angle(metrics.italic_angle)
+        .ascent(metrics.ascent)
+        .descent(metrics.descent)
+        .cap_height(metrics.cap_height)
+        .stem_v(if font.is_bold() { 120.0 } else { 80.0 });
+    if face.cff {
+        descriptor.font_file3(file_id);
+    } else {
+        descriptor.font_file2(file_id);
+    }
+    descriptor.finish();
+
+    // Glyph 0 (.notdef) must always be kept.
+    let glyphs: Vec<u16> = std::iter::once(0)
+        .chain(face.used.keys().copied())
+        .collect();
+    let program = subsetter::subset(&face.data, face.index, subsetter::Profile::pdf(&glyphs))
+        .unwrap_or_else(|_| face.data.clone());
+    let compressed = compress_to_vec_zlib(&program, 6);
+    let mut stream = pdf.stream(file_id, &compressed);
+    stream.filter(Filter::FlateDecode);
+    if face.cff {
+        stream.pair(Name(b"Subtype"), Name(b"OpenType"));
+    }
+    stream.finish();
+
+    let mut cmap = UnicodeCmap::new(Name(b"Custom"), IDENTITY);
+    for (glyph, (ch, _)) in &face.used {
+        cmap.pair(*glyph, *ch);
+    }
+    pdf.cmap(cmap_id, &cmap.finish());
+}
@@ -0,0 +1,683 @@
+use crate::markdown::Align;
+
+/// A block of a document converted from another format, rendered to GFM by
+/// [`render`]. Inline content is already markdown, made with the helpers at
+/// the end of this file.
+pub enum Block {
+    Heading(u8, String),
+    Paragraph(String),
+    Code {
+        language: String,
+        text: String,
+    },
+    Quote(Vec<Block>),
+    List {
+        ordered: bool,
+        start: u64,
+        items: Vec<Vec<Block>>,
+    },
+    /// The first row is the header.
+    Table {
+        rows: Vec<Vec<String>>,
+        align: Vec<Align>,
+    },
+    Footnote(String, Vec<Block>),
+    Rule,
+}
+
+/// Renders blocks as a markdown document ending in a newline.
+pub fn render(blocks: &[Block]) -> String {
+    let mut out = render_blocks(blocks);
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    out
+}
+
+fn render_blocks(blocks: &[Block]) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    let mut previous_list: Option<(bool, bool)> = None;
+    for block in blocks {
+        // Two lists of the same kind in a row would read back as one, so
+        // the second switches to the other marker.
+        let alternate = match (block, previous_list) {
+            (Block::List { ordered, .. }, Some((kind, alternate))) if *ordered == kind => {
+                !alternate
+            }
+            _ => false,
+        };
+        previous_list = match block {
+            Block::List { ordered, .. } => Some((*ordered, alternate)),
+            _ => None,
+        };
+        let text = render_block(block, alternate);
+        if !text.is_empty() {
+            parts.push(text);
+        }
+    }
+    parts.join("\n\n")
+}
+
+fn render_block(block: &Block, alternate: bool) -> String {
+    match block {
+        Block::Heading(level, text) => {
+            let text = text.replace("\\\n", " ").replace('\n', " ");
+            let text = text.trim();
+            if text.is_empty() {
+                return String::new();
+            }
+            // A trailing `#` would be read as a closing sequence.
+            let text = match text.strip_suffix('#') {
+                Some(rest) if !rest.ends_with('\\') => format!("{rest}\\#"),
+                _ => text.to_string(),
+            };
+            format!("{} {}", "#".repeat((*level).clamp(1, 6) as usize), text)
+        }
+        Block::Paragraph(text) => text
+            .trim()
+            .lines()
+            .map(|line| escape_line_start(line.trim_start()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Block::Code { language, text } => {
+            let fence = "`".repeat(longest_run(text, '`').max(2) + 1);
+            let text = text.trim_end_matches('\n');
+            format!("{fence}{language}\n{text}\n{fence}")
+        }
+        Block::Quote(blocks) => prefix_lines(&render_blocks(blocks), "> ", ">"),
+        Block::List {
+            ordered,
+            start,
+            items,
+        } => render_list(*ordered, *start, items, alternate),
+        Block::Table { rows, align } => render_table(rows, align),
+        Block::Footnote(label, blocks) => {
+            let body = render_blocks(blocks);
+            let mut lines = body.lines();
+            let mut out = format!("[^{label}]: {}", lines.next().unwrap_or_default());
+            for line in lines {
+                out.push('\n');
+                if !line.is_empty() {
+                    out.push_str("    ");
+                    out.push_str(line);
+                }
+            }
+            out
+        }
+        Block::Rule => "---".to_string(),
+    }
+}
+
+fn render_list(ordered: bool, start: u64, items: &[Vec<Block>], alternate: bool) -> String {
+    // Tight when every item is at most one paragraph, plus nested lists.
+    let tight = items.iter().all(|item| {
+        item.iter().enumerate().all(|(index, block)| match block {
+            Block::Paragraph(_) => index == 0,
+            Block::List { .. } => true,
+            _ => false,
+        })
+    });
+    let mut out = Vec::new();
+    for (index, item) in items.iter().enumerate() {
+        let marker = match (ordered, alternate) {
+            (false, false) => "-".to_string(),
+            (false, true) => "*".to_string(),
+            (true, false) => format!("{}.", start + index as u64),
+            (true, true) => format!("{})", start + index as u64),
+        };
+        let body = if tight {
+            item.iter()
+                .map(|block| render_block(block, false))
+                .filter(|text| !text.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            render_blocks(item)
+        };
+        let indent = " ".repeat(marker.len() + 1);
+        let mut lines = body.lines();
+        let mut text = match lines.next() {
+            Some(first) => format!("{marker} {first}"),
+            None => marker,
+        };
+        for line in lines {
+            text.push('\n');
+            if !line.is_empty() {
+                text.push_str(&indent);
+                text.push_str(line);
+            }
+        }
+        out.push(text);
+    }
+    out.join(if tight { "\n" } else { "\n\n" })
+}
+
+fn render_table(rows: &[Vec<String>], align: &[Align]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+    let row = |cells: &[String]| {
+        let mut line = String::from("|");
+        for column in 0..columns {
+            let cell = cells.get(column).map(String::as_str).unwrap_or_default();
+            let cell = cell
+                .replace("\\\n", "<br>")
+                .replace('\n', " ")
+                .replace('|', "\\|");
+            line.push(' ');
+            line.push_str(cell.trim());
+            line.push_str(" |");
+        }
+        line
+    };
+    // Header cells are bold already.
+    let header: Vec<String> = rows[0]
+        .iter()
+        .map(|cell| {
+            cell.strip_prefix("**")
+                .and_then(|cell| cell.strip_suffix("**"))
+                .filter(|inner| !inner.is_empty() && !inner.contains("**"))
+                .unwrap_or(cell)
+                .to_string()
+        })
+        .collect();
+    let mut out = vec![row(&header)];
+    let mut delimiter = String::from("|");
+    for column in 0..columns {
+        delimiter.push_str(match align.get(column) {
+            Some(Align::Left) => " :--- |",
+            Some(Align::Center) => " :---: |",
+            Some(Align::Right) => " ---: |",
+            _ => " --- |",
+        });
+    }
+    out.push(delimiter);
+    out.extend(rows[1..].iter().map(|cells| row(cells)));
+    out.join("\n")
+}
+
+/// Renders blocks to fit in one table cell, their lines joined by hard
+/// breaks.
+pub fn cell(blocks: &[Block]) -> String {
+    render_blocks(blocks)
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.strip_suffix('\\').unwrap_or(line))
+        .collect::<Vec<_>>()
+        .join("\\\n")
+}
+
+fn prefix_lines(text: &str, prefix: &str, blank: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                blank.to_string()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn longest_run(text: &str, ch: char) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for c in text.chars() {
+        run = if c == ch { run + 1 } else { 0 };
+        longest = longest.max(run);
+    }
+    longest
+}
+
+/// A paragraph or table read from a document, before runs of code, quote
+/// and list paragraphs are grouped into blocks.
+pub enum Part {
+    Block(Block),
+    Code(String),
+    Quote(String),
+    Item(ListItem),
+}
+
+/// A list paragraph, with its nesting level and the number it shows.
+pub struct ListItem {
+    pub level: usize,
+    pub ordered: bool,
+    pub number: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Format {
+    pub bold: bool,
+    pub italic: bool,
+    pub strike: bool,
+    pub code: bool,
+}
+
+/// A run of text in a paragraph, or markup already rendered from one.
+pub enum Piece {
+    Text(String, Format),
+    /// Markdown that is already rendered, like a link or an image.
+    Markup(String),
+}
+
+/// Renders the pieces of a paragraph, merging neighbouring runs with the
+/// same formatting. Code paragraphs keep their text as it is.
+pub fn inline(pieces: &[Piece], raw: bool) -> String {
+    let mut out = String::new();
+    let mut index = 0;
+    while index < pieces.len() {
+        let (first, format) = match &pieces[index] {
+            Piece::Markup(markup) => {
+                out.push_str(markup);
+                index += 1;
+                continue;
+            }
+            Piece::Text(text, format) => (text, *format),
+        };
+        let mut text = first.clone();
+        index += 1;
+        while let Some(Piece::Text(next, next_format)) = pieces.get(index) {
+            if raw || *next_format == format {
+                text.push_str(next);
+                index += 1;
+            } else {
+                break;
+            }
+        }
+        if raw {
+            out.push_str(&text);
+            continue;
+        }
+        text = if format.code {
+            // Keep surrounding spaces outside the code span.
+            let start = text.len() - text.trim_start().len();
+            let end = text.trim_end().len();
+            format!(
+                "{}{}{}",
+                &text[..start],
+                code(&text[start..end]),
+                &text[end..]
+            )
+        } else {
+            escape(&text)
+        };
+        if format.strike {
+            text = emphasis(&text, "~~");
+        }
+        if format.italic {
+            text = emphasis(&text, "*");
+        }
+        if format.bold {
+            text = emphasis(&text, "**");
+        }
+        out.push_str(&text);
+    }
+    out
+}
+
+/// Groups consecutive code, quote and list paragraphs into blocks.
+pub fn assemble(parts: Vec<Part>) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut parts = parts.into_iter().peekable();
+    while let Some(part) = parts.next() {
+        match part {
+            Part::Block(block) => blocks.push(block),
+            Part::Code(line) => {
+                let mut text = line;
+                while let Some(Part::Code(line)) =
+                    parts.next_if(|part| matches!(part, Part::Code(_)))
+                {
+                    text.push('\n');
+                    text.push_str(&line);
+                }
+                if !text.trim().is_empty() {
+                    blocks.push(Block::Code {
+                        language: String::new(),
+                        text,
+                    });
+                }
+            }
+            Part::Quote(text) => {
+                let mut quoted = vec![Block::Paragraph(text)];
+                while let Some(Part::Quote(text)) =
+                    parts.next_if(|part| matches!(part, Part::Quote(_)))
+                {
+                    quoted.push(Block::Paragraph(text));
+                }
+                blocks.push(Block::Quote(quoted));
+            }
+            Part::Item(item) => {
+                let mut items = vec![item];
+                while let Some(Part::Item(item)) =
+                    parts.next_if(|part| matches!(part, Part::Item(_)))
+                {
+                    items.push(item);
+                }
+                blocks.extend(nest(&items));
+            }
+        }
+    }
+    blocks
+}
+
+/// Builds nested lists from list paragraphs and their levels.
+pub fn nest(items: &[ListItem]) -> Vec<Block> {
+    let base = items.iter().map(|item| item.level).min().unwrap_or(0);
+    let mut lists: Vec<Block> = Vec::new();
+    let mut index = 0;
+    while index < items.len() {
+        let item = &items[index];
+        if item.level > base {
+            let end = items[index..]
+                .iter()
+                .position(|item| item.level == base)
+                .map_or(items.len(), |offset| index + offset);
+            let nested = nest(&items[index..end]);
+            match lists.last_mut() {
+                Some(Block::List { items, .. }) if !items.is_empty() => {
+                    items.last_mut().unwrap().extend(nested);
+                }
+                _ => lists.extend(nested),
+            }
+            index = end;
+            continue;
+        }
+        let content = if item.text.trim().is_empty() {
+            Vec::new()
+        } else {
+            vec![Block::Paragraph(item.text.clone())]
+        };
+        match lists.last_mut() {
+            Some(Block::List { ordered, items, .. }) if *ordered == item.ordered => {
+                items.push(content);
+            }
+            _ => lists.push(Block::List {
+                ordered: item.ordered,
+                start: item.number,
+                items: vec![content],
+            }),
+        }
+        index += 1;
+    }
+    lists
+}
+
+/// Escapes what would turn a paragraph line into another block: headings,
+/// quotes, list markers and setext underlines.
+fn escape_line_start(line: &str) -> String {
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    let after_marker = |marker: usize| rest[marker..].is_empty() || rest[marker..].starts_with(' ');
+    if digits > 0 && (rest.starts_with('.') || rest.starts_with(')')) && after_marker(1) {
+        return format!("{}\\{}", &line[..digits], rest);
+    }
+    if digits == 0
+        && (line.starts_with('#')
+            || line.starts_with('>')
+            || line.starts_with('=')
+            || ((line.starts_with('-') || line.starts_with('+'))
+                && (after_marker(1) || line.chars().all(|c| c == '-'))))
+    {
+        return format!("\\{line}");
+    }
+    line.to_string()
+}
+
+/// Escapes characters that markdown would read as markup. Underscores
+/// inside words are left alone, as GFM does not treat them as emphasis.
+pub fn escape(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    for (index, &c) in chars.iter().enumerate() {
+        let escape = match c {
+            '\\' | '*' | '`' | '[' | ']' | '<' | '~' => true,
+            '_' => {
+                let before = index > 0 && chars[index - 1].is_alphanumeric();
+                let after = chars.get(index + 1).is_some_and(|c| c.is_alphanumeric());
+                !(before && after)
+            }
+            _ => false,
+        };
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Wraps already rendered inline markdown in an emphasis marker, keeping
+/// surrounding whitespace outside where the marker still works.
+pub fn emphasis(text: &str, marker: &str) -> String {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return text.to_string();
+    }
+    let start = text.len() - text.trim_start().len();
+    let end = text.trim_end().len();
+    format!(
+        "{}{marker}{trimmed}{marker}{}",
+        &text[..start],
+        &text[end..]
+    )
+}
+
+pub fn code(text: &str) -> String {
+    if text.is_empty() {
+        return String::new();
+    }
+    let fence = "`".repeat(longest_run(text, '`') + 1);
+    if text.starts_with('`') || text.ends_with('`') {
+        format!("{fence} {text} {fence}")
+    } else {
+        format!("{fence}{text}{fence}")
+    }
+}
+
+fn destination(url: &str) -> String {
+    let url = url.trim().replace(['\n', '\r'], "");
+    if url.contains([' ', '(', ')']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url
+    }
+}
+
+pub fn link(text: &str, url: &str, title: Option<&str>) -> String {
+    let autolink = (url.contains("://") || url.starts_with("mailto:"))
+        && !url.contains([' ', '<', '>'])
+        && title.is_none_or(str::is_empty);
+    if autolink && (text == url || text == escape(url)) {
+        return format!("<{url}>");
+    }
+    let text = if text.trim().is_empty() {
+        escape(url)
+    } else {
+        text.to_string()
+    };
+    match title.filter(|title| !title.is_empty()) {
+        Some(title) => format!(
+            "[{text}]({} \"{}\")",
+            destination(url),
+            title.replace('"', "\\\"")
+        ),
+        None => format!("[{text}]({})", destination(url)),
+    }
+}
+
+pub fn image(alt: &str, url: &str) -> String {
+    format!("![{}]({})", escape(alt.trim()), destination(url))
+}
+
+#[cfg(test)]
+mod tests {
+    use pulldown_cmark::{Event, Parser, Tag};
+
+    use super::*;
+    use crate::markdown;
+
+    /// The text markdown shows for `source`, and whether it is all one
+    /// paragraph.
+    fn read_back(source: &str) -> (String, bool) {
+        let mut text = String::new();
+        let mut blocks = 0;
+        for event in Parser::new_ext(source, markdown::options()) {
+            match event {
+                Event::Text(chunk) | Event::Code(chunk) => text.push_str(&chunk),
+                Event::SoftBreak | Event::HardBreak => text.push('\n'),
+                Event::Start(Tag::Paragraph) => blocks += 1,
+                Event::Start(_) => blocks += 2,
+                _ => {}
+            }
+        }
+        (text, blocks == 1)
+    }
+
+    fn item(level: usize, ordered: bool, number: u64, text: &str) -> ListItem {
+        ListItem {
+            level,
+            ordered,
+            number,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn escaped_text_reads_back() {
+        assert_eq!(escape("a*b_c d_ [x] <y>"), "a\\*b_c d\\_ \\[x\\] \\<y>");
+        for text in [
+            "snake_case_name",
+            "_lead and trail_",
+            "2 * 3 `x` ~~no~~",
+            "\\path\\",
+        ] {
+            assert_eq!(read_back(&escape(text)), (text.to_string(), true));
+        }
+    }
+
+    #[test]
+    fn paragraph_lines_stay_paragraphs() {
+        let lines = [
+            "# not a heading",
+            "1. not a list",
+            "2) nor this",
+            "- nor this",
+            "+ nor",
+            "> no quote",
+            "===",
+        ];
+        let markdown = render(&[Block::Paragraph(lines.join("\n"))]);
+        assert_eq!(read_back(&markdown), (lines.join("\n"), true));
+        let underline = render(&[Block::Paragraph("Title\n---".into())]);
+        assert_eq!(read_back(&underline), ("Title\n---".to_string(), true));
+        assert_eq!(escape_line_start("1.5 litres"), "1.5 litres");
+        assert_eq!(escape_line_start("-1 degrees"), "-1 degrees");
+    }
+
+    #[test]
+    fn headings() {
+        assert_eq!(render(&[Block::Heading(2, "C#".into())]), "## C\\#\n");
+        assert_eq!(
+            render(&[Block::Heading(9, "a\\\nb".into())]),
+            "###### a b\n"
+        );
+        assert_eq!(render(&[Block::Heading(1, "  ".into())]), "");
+    }
+
+    #[test]
+    fn nested_lists() {
+        let items = [
+            item(0, false, 1, "a"),
+            item(1, true, 1, "b"),
+            item(1, true, 2, "c"),
+            item(0, false, 1, "d"),
+            item(2, false, 1, "deep"),
+        ];
+        assert_eq!(
+            render(&nest(&items)),
+            "- a\n  1. b\n  2. c\n- d\n  - deep\n"
+        );
+        // Items that start below the first level nest under nothing.
+        let items = [item(1, false, 1, "x"), item(2, false, 1, "y")];
+        assert_eq!(render(&nest(&items)), "- x\n  - y\n");
+    }
+
+    #[test]
+    fn lists_keep_their_numbers_and_stay_apart() {
+        let items = [
+            item(0, true, 3, "three"),
+            item(0, true, 4, "four"),
+            item(0, false, 1, "bullet"),
+        ];
+        assert_eq!(render(&nest(&items)), "3. three\n4. four\n\n- bullet\n");
+        let first = nest(&[item(0, false, 1, "a")]);
+        let second = nest(&[item(0, false, 1, "b")]);
+        let blocks: Vec<Block> = first.into_iter().chain(second).collect();
+        assert_eq!(render(&blocks), "- a\n\n* b\n");
+    }
+
+    #[test]
+    fn tables() {
+        let rows = vec![
+            vec!["**Name**".to_string(), "Value".to_string()],
+            vec!["a|b".to_string(), "one\\\ntwo".to_string()],
+            vec!["short".to_string()],
+        ];
+        let table = render(&[Block::Table {
+            rows,
+            align: vec![Align::Left, Align::Right],
+        }]);
+        assert_eq!(
+            table,
+            "| Name | Value |\n| :--- | ---: |\n| a\\|b | one<br>two |\n| short |  |\n"
+        );
+    }
+
+    #[test]
+    fn code_links_and_images() {
+        assert_eq!(code("a`b"), "``a`b``");
+        assert_eq!(code("`tick"), "`` `tick ``");
+        assert_eq!(
+            render(&[Block::Code {
+                language: "rust".into(),
+                text: "```\nfn x() {}\n".into(),
+            }]),
+            "````rust\n```\nfn x() {}\n````\n"
+        );
+        assert_eq!(
+            link("https://x.org", "https://x.org", None),
+            "<https://x.org>"
+        );
+        assert_eq!(link("x", "a (1).md", None), "[x](<a (1).md>)");
+        assert_eq!(
+            link("x", "a.md", Some("say \"hi\"")),
+            "[x](a.md \"say \\\"hi\\\"\")"
+        );
+        assert_eq!(link("", "a.md", None), "[a.md](a.md)");
+        assert_eq!(image("a [b]", "img/a b.png"), "![a \\[b\\]](<img/a b.png>)");
+    }
+
+    #[test]
+    fn quotes_and_footnotes() {
+        let blocks = [
+            Block::Quote(vec![
+                Block::Paragraph("one".into()),
+                Block::Paragraph("two".into()),
+            ]),
+            Block::Footnote(
+                "1".into(),
+                vec![
+                    Block::Paragraph("note".into()),
+                    Block::Paragraph("more".into()),
+                ],
+            ),
+        ];
+        assert_eq!(
+            render(&blocks),
+            "> one\n>\n> two\n\n[^1]: note\n\n    more\n"
+        );
+    }
+}
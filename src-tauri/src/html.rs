@@ -0,0 +1,574 @@
+use kuchikiki::traits::TendrilSink;
+use kuchikiki::{ElementData, NodeRef};
+
+use crate::gfm::{self, Block, ListItem};
+use crate::links;
+use crate::markdown::Align;
+
+const BLOCKS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "center",
+    "dd",
+    "details",
+    "dialog",
+    "div",
+    "dl",
+    "dt",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "li",
+    "main",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+/// Elements whose content is never part of the text.
+const SKIPPED: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "title", "meta", "link", "iframe", "object",
+    "svg", "button", "select", "textarea",
+];
+
+/// Converts an HTML document or fragment to markdown, for imported web
+/// pages and pasted rich text. `image` maps each image source
+/// to the destination written in the markdown; `None` drops the image and
+/// keeps its alt text.
+pub fn to_markdown(html: &str, image: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    let document = kuchikiki::parse_html().one(html).document_node;
+    let body = document
+        .descendants()
+        .find(|node| tag(node) == Some("body"))
+        .unwrap_or(document);
+    let mut converter = Converter {
+        image,
+        style: Style::default(),
+    };
+    let blocks = converter.blocks(&body);
+    gfm::render(&blocks)
+}
+
+/// Inline formatting in effect, so nested tags don't repeat markers.
+#[derive(Debug, Clone, Copy, Default)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+}
+
+struct Converter<'a> {
+    image: &'a mut dyn FnMut(&str) -> Option<String>,
+    style: Style,
+}
+
+impl Converter<'_> {
+    /// Converts the children of `node`, gathering loose inline content into
+    /// paragraphs.
+    fn blocks(&mut self, node: &NodeRef) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let mut inline = String::new();
+        // Word list paragraphs, nested once the list ends.
+        let mut items = Vec::new();
+        for child in node.children() {
+            if let Some(level) = word_list_level(&child) {
+                flush(&mut inline, &mut blocks);
+                items.push(self.word_list_item(&child, level));
+                continue;
+            }
+            let blank = child
+                .as_text()
+                .is_some_and(|text| text.borrow().trim().is_empty());
+            if !items.is_empty() && !blank {
+                blocks.extend(gfm::nest(&items));
+                items.clear();
+            }
+            if is_block(&child) {
+                flush(&mut inline, &mut blocks);
+                self.block(&child, &mut blocks);
+            } else {
+                self.inline(&child, &mut inline);
+            }
+        }
+        flush(&mut inline, &mut blocks);
+        blocks.extend(gfm::nest(&items));
+        blocks
+    }
+
+    /// Converts a paragraph Word marked as a list item. Its bullet or number
+    /// is text in a `mso-list:Ignore` span, which tells the kind of list.
+    fn word_list_item(&mut self, node: &NodeRef, level: usize) -> ListItem {
+        let marker = node
+            .descendants()
+            .find(is_word_list_marker)
+            .map(|marker| collapse(&marker.text_contents()).trim().to_string())
+            .unwrap_or_default();
+        let numbered = marker
+            .strip_suffix(['.', ')'])
+            .filter(|number| !number.is_empty() && number.chars().all(char::is_alphanumeric));
+        let mut text = String::new();
+        self.inline_children(node, &mut text);
+        ListItem {
+            level,
+            ordered: numbered.is_some(),
+            number: numbered.and_then(|number| number.parse().ok()).unwrap_or(1),
+            text: clean(&text),
+        }
+    }
+
+    fn block(&mut self, node: &NodeRef, blocks: &mut Vec<Block>) {
+        let Some(name) = tag(node) else {
+            return;
+        };
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse().unwrap_or(1);
+                let mut text = String::new();
+                self.inline_children(node, &mut text);
+                blocks.push(Block::Heading(level, clean(&text)));
+            }
+            "ul" | "ol" => {
+                let start = node
+                    .as_element()
+                    .and_then(|element| attribute(element, "start"))
+                    .and_then(|start| start.trim().parse().ok())
+                    .unwrap_or(1);
+                let mut items: Vec<Vec<Block>> = Vec::new();
+                for child in node.children() {
+                    if tag(&child) == Some("li") {
+                        items.push(self.blocks(&child));
+                        continue;
+                    }
+                    // Lists nested without an `li` belong to the item before.
+                    let mut nested = Vec::new();
+                    if is_block(&child) {
+                        self.block(&child, &mut nested);
+                    } else {
+                        let mut text = String::new();
+                        self.inline(&child, &mut text);
+                        flush(&mut text, &mut nested);
+                    }
+                    match items.last_mut() {
+                        Some(item) => item.extend(nested),
+                        None if !nested.is_empty() => items.push(nested),
+                        None => {}
+                    }
+                }
+                if !items.is_empty() {
+                    blocks.push(Block::List {
+                        ordered: name == "ol",
+                        start,
+                        items,
+                    });
+                }
+            }
+            "blockquote" => {
+                let quoted = self.blocks(node);
+                if !quoted.is_empty() {
+                    blocks.push(Block::Quote(quoted));
+                }
+            }
+            "pre" => {
+                let mut text = node.text_contents();
+                if text.starts_with('\n') {
+                    text.remove(0);
+                }
+                let language = node
+                    .inclusive_descendants()
+                    .filter_map(|node| node.as_element().and_then(|e| attribute(e, "class")))
+                    .find_map(|class| language(&class))
+                    .unwrap_or_default();
+                blocks.push(Block::Code { language, text });
+            }
+            "table" => self.table(node, blocks),
+            "hr" => blocks.push(Block::Rule),
+            "dt" => {
+                let mut text = String::new();
+                self.inline_children(node, &mut text);
+                let text = clean(&text);
+                if !text.is_empty() {
+                    blocks.push(Block::Paragraph(gfm::emphasis(&text, "**")));
+                }
+            }
+            _ => blocks.extend(self.blocks(node)),
+        }
+    }
+
+    fn table(&mut self, node: &NodeRef, blocks: &mut Vec<Block>) {
+        let mut rows = Vec::new();
+        let mut align = Vec::new();
+        let table_rows = node.descendants().filter(|row| {
+            tag(row) == Some("tr")
+                && row
+                    .ancestors()
+                    .find(|ancestor| tag(ancestor) == Some("table"))
+                    .is_some_and(|table| &table == node)
+        });
+        for row in table_rows {
+            let mut cells = Vec::new();
+            for cell in row.children() {
+                if !matches!(tag(&cell), Some("td" | "th")) {
+                    continue;
+                }
+                let element = cell.as_element();
+                if rows.is_empty() {
+                    align.push(element.map(alignment).unwrap_or(Align::None));
+                }
+                let blocks = self.blocks(&cell);
+                cells.push(gfm::cell(&blocks));
+                let span = element
+                    .and_then(|element| attribute(element, "colspan"))
+                    .and_then(|span| span.trim().parse::<usize>().ok())
+                    .unwrap_or(1);
+                for _ in 1..span.min(64) {
+                    cells.push(String::new());
+                    if rows.is_empty() {
+                        align.push(Align::None);
+                    }
+                }
+            }
+            rows.push(cells);
+        }
+        if !rows.is_empty() {
+            blocks.push(Block::Table { rows, align });
+        }
+    }
+
+    fn inline_children(&mut self, node: &NodeRef, out: &mut String) {
+        for child in node.children() {
+            self.inline(&child, out);
+        }
+    }
+
+    fn inline(&mut self, node: &NodeRef, out: &mut String) {
+        if let Some(text) = node.as_text() {
+            push_text(out, &gfm::escape(&collapse(&text.borrow())));
+            return;
+        }
+        let (Some(name), Some(data)) = (tag(node), node.as_element()) else {
+            return;
+        };
+        if SKIPPED.contains(&name) {
+            return;
+        }
+        match name {
+            "br" => {
+                out.push_str("\\\n");
+                return;
+            }
+            "img" => {
+                let alt = attribute(data, "alt").unwrap_or_default();
+                let src = attribute(data, "src").unwrap_or_default();
+                match (self.image)(&src) {
+                    Some(destination) => push_text(out, &gfm::image(&alt, &destination)),
+                    None => push_text(out, &gfm::escape(&collapse(&alt))),
+                }
+                return;
+            }
+            "input" => {
+                if attribute(data, "type").is_some_and(|kind| kind.eq_ignore_ascii_case("checkbox"))
+                {
+                    let checked = attribute(data, "checked").is_some();
+                    push_text(out, if checked { "[x] " } else { "[ ] " });
+                }
+                return;
+            }
+            _ => {}
+        }
+
+        if is_word_list_marker(node) {
+            return;
+        }
+        let css = attribute(data, "style")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let declared = |property: &str| declaration(&css, property);
+        let weight = declared("font-weight");
+        let bold = match weight.as_deref() {
+            Some("normal" | "lighter") => false,
+            Some(weight) if weight.parse::<u32>().is_ok_and(|weight| weight < 600) => false,
+            Some(_) => true,
+            None => matches!(name, "b" | "strong"),
+        };
+        let italic = matches!(name, "em" | "i" | "cite" | "dfn" | "var")
+            || declared("font-style").is_some_and(|style| style != "normal");
+        let strike = matches!(name, "del" | "s" | "strike")
+            || declared("text-decoration").is_some_and(|line| line.contains("line-through"))
+            || declared("text-decoration-line").is_some_and(|line| line.contains("line-through"));
+        let code = matches!(name, "code" | "kbd" | "samp" | "tt")
+            || declared("font-family").is_some_and(|family| {
+                ["monospace", "courier", "consolas", "menlo", "monaco"]
+                    .iter()
+                    .any(|mono| family.contains(mono))
+            });
+
+        let outer = self.style;
+        let mut text = String::new();
+        if code {
+            text = gfm::code(&collapse(&node.text_contents()));
+        } else {
+            self.style = Style {
+                bold: outer.bold || bold,
+                italic: outer.italic || italic,
+                strike: outer.strike || strike,
+            };
+            self.inline_children(node, &mut text);
+            self.style = outer;
+        }
+        if strike && !outer.strike {
+            text = gfm::emphasis(&text, "~~");
+        }
+        if italic && !outer.italic {
+            text = gfm::emphasis(&text, "*");
+        }
+        if bold && !outer.bold {
+            text = gfm::emphasis(&text, "**");
+        }
+        if name == "a" {
+            let href = attribute(data, "href").unwrap_or_default();
+            let href = href.trim();
+            if !href.is_empty() && links::is_safe(href) && !text.trim().is_empty() {
+                let title = attribute(data, "title");
+                text = gfm::link(&text, href, title.as_deref());
+            }
+        }
+        // Block content inside inline elements still breaks the line.
+        if is_block(node) && !out.is_empty() && !out.ends_with('\n') {
+            out.push_str("\\\n");
+        }
+        push_text(out, &text);
+    }
+}
+
+fn tag(node: &NodeRef) -> Option<&str> {
+    node.as_element().map(|element| element.name.local.as_ref())
+}
+
+/// The language of a code block from a `language-x` or `lang-x` class.
+fn language(class: &str) -> Option<String> {
+    class
+        .split_whitespace()
+        .find_map(|class| {
+            class
+                .strip_prefix("language-")
+                .or_else(|| class.strip_prefix("lang-"))
+        })
+        .map(str::to_string)
+}
+
+fn attribute(element: &ElementData, name: &str) -> Option<String> {
+    element.attributes.borrow().get(name).map(str::to_string)
+}
+
+fn declaration(css: &str, property: &str) -> Option<String> {
+    css.split(';').find_map(|declaration| {
+        let (name, value) = declaration.split_once(':')?;
+        (name.trim() == property).then(|| value.trim().to_string())
+    })
+}
+
+fn alignment(element: &ElementData) -> Align {
+    let css = attribute(element, "style")
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let value = declaration(&css, "text-align")
+        .or_else(|| attribute(element, "align"))
+        .unwrap_or_default();
+    match value.to_ascii_lowercase().as_str() {
+        "left" => Align::Left,
+        "center" => Align::Center,
+        "right" => Align::Right,
+        _ => Align::None,
+    }
+}
+
+/// The level of a paragraph pasted from Word as a list item, from its
+/// `mso-list: l0 level2 lfo1` style.
+fn word_list_level(node: &NodeRef) -> Option<usize> {
+    if tag(node) != Some("p") {
+        return None;
+    }
+    let css = attribute(node.as_element()?, "style")?.to_ascii_lowercase();
+    let list = declaration(&css, "mso-list")?;
+    let level = list
+        .split_whitespace()
+        .find_map(|part| part.strip_prefix("level"))?
+        .parse::<usize>()
+        .ok()?;
+    Some(level.saturating_sub(1))
+}
+
+fn is_word_list_marker(node: &NodeRef) -> bool {
+    node.as_element()
+        .and_then(|element| attribute(element, "style"))
+        .and_then(|css| declaration(&css.to_ascii_lowercase(), "mso-list"))
+        .is_some_and(|list| list == "ignore")
+}
+
+/// Block elements, and inline elements wrapping them, like the `<b>` that
+/// Google Docs puts around a whole copied selection.
+fn is_block(node: &NodeRef) -> bool {
+    match tag(node) {
+        Some(name) if BLOCKS.contains(&name) => true,
+        Some(name) if SKIPPED.contains(&name) => false,
+        Some(_) => node
+            .descendants()
+            .any(|node| tag(&node).is_some_and(|name| BLOCKS.contains(&name))),
+        None => false,
+    }
+}
+
+/// Collapses whitespace the way a browser renders it.
+fn collapse(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space {
+            out.push(' ');
+            space = false;
+        }
+        out.push(c);
+    }
+    if space {
+        out.push(' ');
+    }
+    out
+}
+
+/// Appends inline markdown, dropping a space where one already ends the
+/// line so far.
+fn push_text(out: &mut String, text: &str) {
+    if text.starts_with(' ') && (out.is_empty() || out.ends_with([' ', '\n'])) {
+        out.push_str(&text[1..]);
+    } else {
+        out.push_str(text);
+    }
+}
+
+/// Tidies collected inline markdown into a paragraph: no surrounding
+/// whitespace and no hard break at the end.
+fn clean(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(str::trim).collect();
+    let mut text = lines.join("\n").trim().to_string();
+    while text.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1 {
+        text.pop();
+        text.truncate(text.trim_end().len());
+    }
+    text
+}
+
+fn flush(inline: &mut String, blocks: &mut Vec<Block>) {
+    let text = clean(inline);
+    if !text.is_empty() {
+        blocks.push(Block::Paragraph(text));
+    }
+    inline.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        to_markdown(html, &mut |src| Some(src.to_string()))
+    }
+
+    #[test]
+    fn blocks() {
+        let html =
+            "<h2>Title <em>here</em></h2><p>One\n  two</p><blockquote><p>quoted</p></blockquote>\
+                    <pre><code class=\"language-rust\">fn x() {}\n</code></pre><hr><p>end</p>";
+        assert_eq!(
+            convert(html),
+            "## Title *here*\n\nOne two\n\n> quoted\n\n```rust\nfn x() {}\n```\n\n---\n\nend\n"
+        );
+    }
+
+    #[test]
+    fn inline_formatting() {
+        let html = "<p><b>bold <i>both</i></b> <s>gone</s> <code>a*b</code> 2*3<br>next</p>";
+        assert_eq!(
+            convert(html),
+            "**bold *both*** ~~gone~~ `a*b` 2\\*3\\\nnext\n"
+        );
+        // Google Docs wraps the whole selection in a bold element.
+        let wrapped = "<b style=\"font-weight:normal\"><p>one</p><p>two</p></b>";
+        assert_eq!(convert(wrapped), "one\n\ntwo\n");
+    }
+
+    #[test]
+    fn links_and_images() {
+        let html = "<p><a href=\"https://x.org\" title=\"X\">site</a> \
+                    <a href=\"JavaScript:alert(1)\">bad</a> \
+                    <img src=\"a.png\" alt=\"pic\"> <img src=\"gone.png\" alt=\"alt\"></p>";
+        let markdown = to_markdown(html, &mut |src| {
+            (src != "gone.png").then(|| src.to_string())
+        });
+        assert_eq!(
+            markdown,
+            "[site](https://x.org \"X\") bad ![pic](a.png) alt\n"
+        );
+    }
+
+    #[test]
+    fn lists() {
+        let html = "<ol start=\"3\"><li>three<ul><li>nested</li></ul></li><li><p>four</p></li></ol>\
+                    <ul><li><input type=\"checkbox\" checked> done</li><li><input type=\"checkbox\"> todo</li></ul>";
+        assert_eq!(
+            convert(html),
+            "3. three\n   - nested\n4. four\n\n- [x] done\n- [ ] todo\n"
+        );
+    }
+
+    #[test]
+    fn word_lists() {
+        let html = "<p class=MsoListParagraph style='mso-list:l0 level1 lfo1'>\
+                    <span style='mso-list:Ignore'>1.</span>First</p>\
+                    <p class=MsoListParagraph style='mso-list:l0 level2 lfo1'>\
+                    <span style='mso-list:Ignore'>o</span>Inner</p>\
+                    <p class=MsoListParagraph style='mso-list:l0 level1 lfo1'>\
+                    <span style='mso-list:Ignore'>2.</span>Second</p>\
+                    <p>After</p>";
+        assert_eq!(convert(html), "1. First\n   - Inner\n2. Second\n\nAfter\n");
+    }
+
+    #[test]
+    fn tables() {
+        let html = "<table><tr><th>Name</th><th style=\"text-align:right\">Qty</th></tr>\
+                    <tr><td colspan=\"2\">a | b</td></tr><tr><td><p>x</p><p>y</p></td><td>1</td></tr></table>";
+        assert_eq!(
+            convert(html),
+            "| Name | Qty |\n| --- | ---: |\n| a \\| b |  |\n| x<br>y | 1 |\n"
+        );
+    }
+
+    #[test]
+    fn skipped_content() {
+        let html = "<html><head><title>T</title><style>p{}</style></head>\
+                    <body><script>alert(1)</script><p>kept</p></body></html>";
+        assert_eq!(convert(html), "kept\n");
+    }
+}
@@ -0,0 +1,400 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, State};
+
+use crate::atomic;
+use crate::error::{Error, Result};
+use crate::gfm;
+use crate::scope;
+use crate::settings::SettingsStore;
+
+/// Images that markdown can show.
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "bmp", "svg", "webp", "avif"];
+
+const JPEG_QUALITY: u8 = 85;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImageConfig {
+    /// Where pasted and dropped images are saved, relative to the document.
+    /// `{name}` stands for the document's file name without its extension.
+    pub folder: String,
+    /// Images larger than this many pixels on a side are scaled down to fit;
+    /// 0 keeps them as they are.
+    pub max_dimension: u32,
+}
+
+impl Default for ImageConfig {
+    fn default() -> Self {
+        Self {
+            folder: "assets".to_string(),
+            max_dimension: 0,
+        }
+    }
+}
+
+impl ImageConfig {
+    pub fn validate(&self) -> Result<()> {
+        let folder = Path::new(self.folder.trim());
+        let relative = folder
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+        if !relative {
+            return Err(Error::Invalid(format!(
+                "images.folder must be a folder inside the document's, got {}",
+                self.folder
+            )));
+        }
+        if self.max_dimension != 0 && !(256..=8192).contains(&self.max_dimension) {
+            return Err(Error::Invalid(format!(
+                "images.maxDimension must be 0 or between 256 and 8192, got {}",
+                self.max_dimension
+            )));
+        }
+        Ok(())
+    }
+
+    fn folder_for(&self, document: &Path) -> String {
+        let name = document
+            .file_stem()
+            .map(|stem| file_name(&stem.to_string_lossy()))
+            .unwrap_or_else(|| "document".to_string());
+        let folder = self.folder.trim().replace("{name}", &name);
+        folder.trim_matches(['/', '\\']).replace('\\', "/")
+    }
+}
+
+/// An image to add to a document.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ImageSource {
+    /// Image data pasted from the clipboard, as a `data:` URL.
+    #[serde(rename_all = "camelCase")]
+    Data { name: Option<String>, url: String },
+    /// A file dropped on the editor.
+    File { path: PathBuf },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedImage {
+    /// Where the image was saved.
+    pub path: PathBuf,
+    /// The image relative to the document, with `/` separators.
+    pub link: String,
+    /// The `![](...)` to insert.
+    pub markdown: String,
+}
+
+/// Whether `path` has one of the image extensions.
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|image| ext.eq_ignore_ascii_case(image))
+        })
+}
+
+/// The extensions of the files the frontend treats as images, so dropped
+/// files and links are told apart the same way everywhere.
+#[tauri::command]
+pub async fn image_extensions() -> &'static [&'static str] {
+    IMAGE_EXTENSIONS
+}
+
+/// Saves an image into the configured folder next to `document_path` and
+/// returns how to link it. An identical image already in the folder is
+/// reused instead of being written again.
+#[tauri::command]
+pub async fn save_image(
+    app: AppHandle,
+    document_path: PathBuf,
+    source: ImageSource,
+    store: State<'_, SettingsStore>,
+) -> Result<SavedImage> {
+    scope::check(&app, &document_path)?;
+    let config = store.get().images;
+    let (name, bytes) = match source {
+        ImageSource::Data { name, url } => {
+            let (mime, bytes) = decode_data_url(&url)
+                .ok_or_else(|| Error::Invalid("The pasted image could not be read".to_string()))?;
+            // `image/svg+xml` is an `.svg`.
+            let extension = mime
+                .split_once('/')
+                .and_then(|(_, kind)| kind.split('+').next())
+                .unwrap_or("png");
+            // Screenshots have no name worth keeping, so they are told
+            // apart by their contents.
+            let name = name.unwrap_or_else(|| {
+                let hash = Sha256::digest(&bytes);
+                let short: String = hash[..4].iter().map(|byte| format!("{byte:02x}")).collect();
+                format!("image-{short}.{extension}")
+            });
+            (name, bytes)
+        }
+        ImageSource::File { path } => {
+            scope::check(&app, &path)?;
+            let bytes = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            (name, bytes)
+        }
+    };
+    write_image(&document_path, &name, bytes, &config)
+}
+
+/// Writes the image `bytes`, called `name`, into the folder `config` gives
+/// for `document_path`, unless the folder already has it.
+fn write_image(
+    document_path: &Path,
+    name: &str,
+    bytes: Vec<u8>,
+    config: &ImageConfig,
+) -> Result<SavedImage> {
+    let format = image::guess_format(&bytes).ok();
+    let extension = format
+        .and_then(|format| format.extensions_str().first().copied())
+        .filter(|extension| IMAGE_EXTENSIONS.contains(extension))
+        .or_else(|| {
+            let (_, extension) = name.rsplit_once('.')?;
+            let extension = extension.to_ascii_lowercase();
+            IMAGE_EXTENSIONS
+                .iter()
+                .find(|known| **known == extension)
+                .copied()
+        })
+        .ok_or_else(|| Error::Invalid(format!("{name} is not an image")))?;
+    let bytes = match format {
+        Some(format) if config.max_dimension > 0 => {
+            downsize(&bytes, format, config.max_dimension).unwrap_or(bytes)
+        }
+        _ => bytes,
+    };
+
+    let folder = config.folder_for(document_path);
+    let dir = document_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(&folder);
+    fs::create_dir_all(&dir).map_err(|e| Error::io(e, &dir))?;
+    let file = match find_duplicate(&dir, &bytes) {
+        Some(existing) => existing,
+        None => {
+            let stem = name.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(name);
+            let file = free_name(&dir, &file_name(stem), extension);
+            let path = dir.join(&file);
+            atomic::write(&path, &bytes).map_err(|e| Error::io(e, &path))?;
+            file
+        }
+    };
+    let link = if folder.is_empty() || folder == "." {
+        file.clone()
+    } else {
+        format!("{folder}/{file}")
+    };
+    Ok(SavedImage {
+        path: dir.join(&file),
+        markdown: gfm::image("", &link),
+        link,
+    })
+}
+
+/// A name safe on every platform and in a markdown link: letters, digits,
+/// `-`, `_` and `.`, with anything else turned into `-`.
+pub fn file_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for c in name.chars() {
+        if c.is_alphanumeric() || matches!(c, '_' | '.') {
+            out.push(c);
+        } else if !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    let out = out.trim_matches(['-', '.']);
+    if out.is_empty() {
+        "image".to_string()
+    } else {
+        out.to_string()
+    }
+}
+
+/// `stem.extension`, or `stem-N.extension` for the first N not taken yet.
+fn free_name(dir: &Path, stem: &str, extension: &str) -> String {
+    let mut candidate = format!("{stem}.{extension}");
+    let mut number = 2;
+    while dir.join(&candidate).exists() {
+        candidate = format!("{stem}-{number}.{extension}");
+        number += 1;
+    }
+    candidate
+}
+
+/// The name of a file in `dir` with the same contents as `bytes`, compared
+/// by hash among the files of the same size.
+fn find_duplicate(dir: &Path, bytes: &[u8]) -> Option<String> {
+    let hash = Sha256::digest(bytes);
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .metadata()
+                .is_ok_and(|metadata| metadata.is_file() && metadata.len() == bytes.len() as u64)
+        })
+        .find(|entry| fs::read(entry.path()).is_ok_and(|existing| Sha256::digest(existing) == hash))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
+
+/// Scales a PNG or JPEG image down to fit `max` pixels on its longest side.
+/// Returns `None` when it already fits or can't be re-encoded.
+fn downsize(bytes: &[u8], format: ImageFormat, max: u32) -> Option<Vec<u8>> {
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+        return None;
+    }
+    let image = image::load_from_memory_with_format(bytes, format).ok()?;
+    if image.width() <= max && image.height() <= max {
+        return None;
+    }
+    let image = image.resize(max, max, FilterType::Lanczos3);
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            let encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
+            image.to_rgb8().write_with_encoder(encoder).ok()?;
+        }
+        _ => image.write_to(&mut Cursor::new(&mut out), format).ok()?,
+    }
+    Some(out)
+}
+
+fn decode_data_url(url: &str) -> Option<(String, Vec<u8>)> {
+    let (header, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let mime = header.split(';').next().unwrap_or_default();
+    if !header.ends_with(";base64") {
+        return None;
+    }
+    let bytes = BASE64.decode(data.trim()).ok()?;
+    Some((mime.to_ascii_lowercase(), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    fn encoded(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut out), format)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn data_urls() {
+        assert_eq!(
+            decode_data_url("data:Image/PNG;base64,aGk="),
+            Some(("image/png".to_string(), b"hi".to_vec()))
+        );
+        assert_eq!(
+            decode_data_url("data:image/svg+xml;charset=utf-8;base64, aGk=\n"),
+            Some(("image/svg+xml".to_string(), b"hi".to_vec()))
+        );
+        for url in [
+            "",
+            "aGk=",
+            "data:image/png;base64",
+            "data:image/png,hi",
+            "data:image/png;base64,not base64!",
+            "https://x.org/a.png",
+        ] {
+            assert_eq!(decode_data_url(url), None, "{url} was decoded");
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(
+            file_name("Screen Shot 2024/01/02 at 10:00"),
+            "Screen-Shot-2024-01-02-at-10-00"
+        );
+        assert_eq!(file_name("..//"), "image");
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        assert_eq!(free_name(dir, "pic", "png"), "pic.png");
+        fs::write(dir.join("pic.png"), b"1").unwrap();
+        fs::write(dir.join("pic-2.png"), b"2").unwrap();
+        assert_eq!(free_name(dir, "pic", "png"), "pic-3.png");
+        assert_eq!(free_name(dir, "pic", "jpg"), "pic.jpg");
+    }
+
+    #[test]
+    fn folders() {
+        let config = |folder: &str| ImageConfig {
+            folder: folder.to_string(),
+            ..ImageConfig::default()
+        };
+        let document = Path::new("/notes/My Trip.md");
+        assert_eq!(config("assets").folder_for(document), "assets");
+        assert_eq!(
+            config(" {name}_files/ ").folder_for(document),
+            "My-Trip_files"
+        );
+        assert_eq!(
+            config("media\\{name}").folder_for(document),
+            "media/My-Trip"
+        );
+        assert_eq!(config(".").folder_for(document), ".");
+        assert!(config("../up").validate().is_err());
+        assert!(config("/abs").validate().is_err());
+    }
+
+    #[test]
+    fn identical_images_are_saved_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let document = temp.path().join("doc.md");
+        let config = ImageConfig::default();
+        let png = encoded(2, 2, ImageFormat::Png);
+
+        let first = write_image(&document, "shot.png", png.clone(), &config).unwrap();
+        assert_eq!(first.link, "assets/shot.png");
+        assert_eq!(first.markdown, "![](assets/shot.png)");
+        let again = write_image(&document, "other name.png", png, &config).unwrap();
+        assert_eq!(again.link, "assets/shot.png");
+
+        let different = encoded(3, 3, ImageFormat::Png);
+        let second = write_image(&document, "shot.png", different, &config).unwrap();
+        assert_eq!(second.link, "assets/shot-2.png");
+        assert_eq!(fs::read_dir(temp.path().join("assets")).unwrap().count(), 2);
+
+        let refused = write_image(&document, "notes.txt", b"text".to_vec(), &config);
+        assert!(matches!(refused, Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn large_images_are_scaled_down() {
+        for format in [ImageFormat::Png, ImageFormat::Jpeg] {
+            let bytes = encoded(600, 300, format);
+            let smaller = downsize(&bytes, format, 256).unwrap();
+            let image = image::load_from_memory_with_format(&smaller, format).unwrap();
+            assert_eq!((image.width(), image.height()), (256, 128));
+            assert_eq!(downsize(&bytes, format, 600), None);
+        }
+        // Only PNG and JPEG are re-encoded.
+        let png = encoded(600, 300, ImageFormat::Png);
+        assert_eq!(downsize(&png, ImageFormat::Gif, 256), None);
+    }
+}
@@ -0,0 +1,1305 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use roxmltree::{Document, Node};
+use serde::Serialize;
+use tauri::AppHandle;
+
+use crate::atomic;
+use crate::document;
+use crate::error::{Error, Result};
+use crate::export::{resolve_local, ImageAccess};
+use crate::gfm::{self, Block, Format, ListItem, Part, Piece};
+use crate::html;
+use crate::images::{self, IMAGE_EXTENSIONS};
+use crate::links;
+use crate::markdown::{self, Align};
+use crate::scope;
+use crate::zip::ZipReader;
+
+/// Images taken out of an imported document, written to a `<name>_assets`
+/// folder next to where its markdown is meant to be saved.
+struct Assets {
+    dir: PathBuf,
+    folder: String,
+}
+
+impl Assets {
+    fn new(markdown_path: &Path) -> Self {
+        let stem = markdown_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "document".to_string());
+        let folder = format!("{stem}_assets");
+        Self {
+            dir: markdown_path.with_file_name(&folder),
+            folder,
+        }
+    }
+
+    /// Writes an image under a name based on `name` and returns the link to
+    /// it from the markdown file. A file already there with the same bytes
+    /// is reused, so importing twice doesn't duplicate images.
+    fn add(&mut self, name: &str, bytes: &[u8]) -> Result<String> {
+        fs::create_dir_all(&self.dir).map_err(|e| Error::io(e, &self.dir))?;
+        let name = Path::new(name)
+            .file_name()
+            .map(|name| images::file_name(&name.to_string_lossy()))
+            .unwrap_or_else(|| "image".to_string());
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+            _ => (name.as_str(), String::new()),
+        };
+        for number in 1.. {
+            let candidate = if number == 1 {
+                format!("{stem}{extension}")
+            } else {
+                format!("{stem}-{number}{extension}")
+            };
+            let path = self.dir.join(&candidate);
+            if path.exists() {
+                if fs::read(&path).is_ok_and(|existing| existing == bytes) {
+                    return Ok(format!("{}/{candidate}", self.folder));
+                }
+                continue;
+            }
+            atomic::write(&path, bytes).map_err(|e| Error::io(e, &path))?;
+            return Ok(format!("{}/{candidate}", self.folder));
+        }
+        unreachable!()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedDocument {
+    pub markdown: String,
+    /// Where the markdown is meant to be saved: next to the imported file.
+    /// Extracted images are linked relative to it.
+    pub path: PathBuf,
+    /// Images that could not be extracted and were left as alt text or
+    /// pointing at the original.
+    pub missing_assets: Vec<String>,
+}
+
+/// Converts a `.docx` or `.html` file to markdown, for opening as a new
+/// document. Images go to an assets folder next to the imported file.
+#[tauri::command]
+pub async fn import_document(app: AppHandle, path: PathBuf) -> Result<ImportedDocument> {
+    scope::check(&app, &path)?;
+    let bytes = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+    let target = path.with_extension("md");
+    let mut assets = Assets::new(&target);
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let (markdown, missing_assets) = match extension.as_str() {
+        "docx" => docx_to_markdown(&bytes, &mut assets).ok_or_else(|| {
+            Error::Invalid(format!("{} is not a Word document", path.display()))
+        })??,
+        "html" | "htm" | "xhtml" => {
+            html_to_markdown(&bytes, &path, ImageAccess::Scoped(&app), &mut assets)?
+        }
+        _ => {
+            return Err(Error::Invalid(format!(
+                "can't import {}, expected a .docx or .html file",
+                path.display()
+            )))
+        }
+    };
+    Ok(ImportedDocument {
+        markdown,
+        path: target,
+        missing_assets,
+    })
+}
+
+fn html_to_markdown(
+    bytes: &[u8],
+    path: &Path,
+    access: ImageAccess,
+    assets: &mut Assets,
+) -> Result<(String, Vec<String>)> {
+    let (text, _) = document::decode(bytes);
+    let base = path.parent();
+    let mut missing = Vec::new();
+    let mut error = None;
+    let markdown = html::to_markdown(&text, &mut |src| {
+        let src = src.trim();
+        if let Some(data) = src.strip_prefix("data:") {
+            let Some((extension, bytes)) = decode_data_url(data) else {
+                // Too long to keep in the markdown, so only its type is
+                // reported.
+                let header = data.split([',', ';']).next().unwrap_or_default();
+                missing.push(format!("data:{header}"));
+                return None;
+            };
+            return match assets.add(&format!("image.{extension}"), &bytes) {
+                Ok(link) => Some(link),
+                Err(e) => {
+                    error.get_or_insert(e);
+                    None
+                }
+            };
+        }
+        if !links::is_local(src) && links::file_url_path(src).is_none() {
+            return Some(src.to_string());
+        }
+        let local = resolve_local(base, src)?;
+        match access.allows(&local).then(|| fs::read(&local)) {
+            Some(Ok(bytes)) => match assets.add(&local.to_string_lossy(), &bytes) {
+                Ok(link) => Some(link),
+                Err(e) => {
+                    error.get_or_insert(e);
+                    Some(src.to_string())
+                }
+            },
+            _ => {
+                missing.push(src.to_string());
+                Some(src.to_string())
+            }
+        }
+    });
+    match error {
+        Some(error) => Err(error),
+        None => Ok((markdown, missing)),
+    }
+}
+
+/// The file extension and contents of an image in a `data:` URL, given
+/// without the `data:`, whether it is base64 or percent-encoded.
+fn decode_data_url(data: &str) -> Option<(&str, Vec<u8>)> {
+    let (header, payload) = data.split_once(',')?;
+    let mut parameters = header.split(';');
+    let extension = parameters
+        .next()
+        .and_then(|mime| mime.strip_prefix("image/"))
+        .map(|kind| if kind == "svg+xml" { "svg" } else { kind })
+        .filter(|extension| !extension.is_empty())?;
+    let bytes = if parameters.any(|parameter| parameter.eq_ignore_ascii_case("base64")) {
+        let payload: String = payload.split_whitespace().collect();
+        BASE64.decode(links::percent_decode(&payload)).ok()?
+    } else {
+        links::percent_decode_bytes(payload)
+    };
+    Some((extension, bytes))
+}
+
+/// Converts a DOCX file. `None` if it isn't a zip archive with a document
+/// in it.
+fn docx_to_markdown(bytes: &[u8], assets: &mut Assets) -> Option<Result<(String, Vec<String>)>> {
+    let zip = ZipReader::new(bytes)?;
+    let main = relationships(&zip, "", "_rels/.rels")
+        .into_values()
+        .find(|relationship| relationship.kind == "officeDocument")
+        .map(|relationship| relationship.target)
+        .unwrap_or_else(|| "word/document.xml".to_string());
+    let text = String::from_utf8(zip.read(&main)?).ok()?;
+    let document = Document::parse(&text).ok()?;
+    let body = document.descendants().find(|node| is(*node, "body"))?;
+
+    let (folder, file) = main.rsplit_once('/').unwrap_or(("", &main));
+    let relationships = relationships(&zip, folder, &format!("{folder}/_rels/{file}.rels"));
+    let part = |kind: &str| {
+        relationships
+            .values()
+            .find(|relationship| relationship.kind == kind)
+            .and_then(|relationship| zip.read(&relationship.target))
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+    };
+    let styles = part("styles");
+    let numbering = part("numbering");
+    let footnotes = part("footnotes");
+    let endnotes = part("endnotes");
+
+    let mut reader = DocxReader {
+        zip: &zip,
+        styles: styles.as_deref().map(parse_styles).unwrap_or_default(),
+        numbering: numbering
+            .as_deref()
+            .map(parse_numbering)
+            .unwrap_or_default(),
+        relationships,
+        counters: HashMap::new(),
+        bookmarks: HashMap::new(),
+        notes: Vec::new(),
+        assets,
+        missing: Vec::new(),
+    };
+    reader.collect_bookmarks(body);
+    Some(reader.convert(body, footnotes.as_deref(), endnotes.as_deref()))
+}
+
+struct Relationship {
+    /// The last segment of the relationship type, like `styles`.
+    kind: String,
+    /// A path inside the package, or a URL when external.
+    target: String,
+    external: bool,
+}
+
+/// Reads a `.rels` part, resolving internal targets against `folder`.
+fn relationships(zip: &ZipReader, folder: &str, name: &str) -> HashMap<String, Relationship> {
+    let Some(text) = zip
+        .read(name)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+    else {
+        return HashMap::new();
+    };
+    let Ok(document) = Document::parse(&text) else {
+        return HashMap::new();
+    };
+    document
+        .descendants()
+        .filter(|node| is(*node, "Relationship"))
+        .filter_map(|node| {
+            let id = attribute(node, "Id")?;
+            let target = attribute(node, "Target")?;
+            let external = attribute(node, "TargetMode") == Some("External");
+            let kind = attribute(node, "Type")?.rsplit('/').next()?.to_string();
+            let target = if external {
+                target.to_string()
+            } else {
+                package_path(folder, target)
+            };
+            Some((
+                id.to_string(),
+                Relationship {
+                    kind,
+                    target,
+                    external,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Resolves a relative part name, with `..` segments, against `folder`.
+fn package_path(folder: &str, target: &str) -> String {
+    let mut segments: Vec<&str> = if target.starts_with('/') {
+        Vec::new()
+    } else {
+        folder
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect()
+    };
+    for segment in target.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+#[derive(Default)]
+struct Style {
+    /// Lowercase, as Word names built-in styles like `heading 1`.
+    name: String,
+    based_on: Option<String>,
+    outline: Option<u8>,
+    numbering: Option<(String, usize)>,
+    bold: bool,
+    italic: bool,
+    monospace: bool,
+}
+
+fn parse_styles(text: &str) -> HashMap<String, Style> {
+    let Ok(document) = Document::parse(text) else {
+        return HashMap::new();
+    };
+    document
+        .descendants()
+        .filter(|node| is(*node, "style"))
+        .filter_map(|node| {
+            let id = attribute(node, "styleId")?.to_string();
+            let name = child(node, "name")
+                .and_then(value)
+                .unwrap_or(&id)
+                .to_lowercase();
+            let paragraph = child(node, "pPr");
+            let run = child(node, "rPr");
+            let character = attribute(node, "type") == Some("character");
+            let monospace = ["code", "verbatim", "preformatted", "source"]
+                .iter()
+                .any(|word| name.contains(word))
+                || (character && run.is_some_and(|run| monospace_font(run)));
+            Some((
+                id,
+                Style {
+                    based_on: child(node, "basedOn").and_then(value).map(str::to_string),
+                    outline: paragraph
+                        .and_then(|paragraph| child(paragraph, "outlineLvl"))
+                        .and_then(value)
+                        .and_then(|level| level.parse().ok()),
+                    numbering: paragraph
+                        .and_then(|paragraph| child(paragraph, "numPr"))
+                        .and_then(numbering_reference),
+                    bold: run.is_some_and(|run| toggle(child(run, "b"))),
+                    italic: run.is_some_and(|run| toggle(child(run, "i"))),
+                    monospace,
+                    name,
+                },
+            ))
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+struct Level {
+    ordered: bool,
+    /// `false` for levels numbered with `none`, which Word shows as plain
+    /// indented paragraphs.
+    numbered: bool,
+    start: u64,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            ordered: false,
+            numbered: true,
+            start: 1,
+        }
+    }
+}
+
+/// Reads numbering definitions into the levels of each `numId`.
+fn parse_numbering(text: &str) -> HashMap<String, Vec<Level>> {
+    let Ok(document) = Document::parse(text) else {
+        return HashMap::new();
+    };
+    let levels = |node: Node, levels: &mut Vec<Level>| {
+        for level in node.children().filter(|node| is(*node, "lvl")) {
+            let Some(index) = attribute(level, "ilvl").and_then(|ilvl| ilvl.parse::<usize>().ok())
+            else {
+                continue;
+            };
+            if index >= 9 {
+                continue;
+            }
+            if levels.len() <= index {
+                levels.resize(index + 1, Level::default());
+            }
+            let format = child(level, "numFmt").and_then(value).unwrap_or("decimal");
+            levels[index] = Level {
+                ordered: format != "bullet",
+                numbered: format != "none",
+                start: child(level, "start")
+                    .and_then(value)
+                    .and_then(|start| start.parse().ok())
+                    .unwrap_or(1),
+            };
+        }
+    };
+    let mut abstracts = HashMap::new();
+    for node in document
+        .descendants()
+        .filter(|node| is(*node, "abstractNum"))
+    {
+        let mut list = Vec::new();
+        levels(node, &mut list);
+        if let Some(id) = attribute(node, "abstractNumId") {
+            abstracts.insert(id.to_string(), list);
+        }
+    }
+    let mut numbering = HashMap::new();
+    for node in document.descendants().filter(|node| is(*node, "num")) {
+        let (Some(id), Some(abstract_id)) = (
+            attribute(node, "numId"),
+            child(node, "abstractNumId").and_then(value),
+        ) else {
+            continue;
+        };
+        let mut list = abstracts.get(abstract_id).cloned().unwrap_or_default();
+        for level in node.children().filter(|node| is(*node, "lvlOverride")) {
+            let index = attribute(level, "ilvl").and_then(|ilvl| ilvl.parse::<usize>().ok());
+            let start = child(level, "startOverride")
+                .and_then(value)
+                .and_then(|start| start.parse().ok());
+            if let (Some(index), Some(start)) = (index, start) {
+                if index < 9 {
+                    if list.len() <= index {
+                        list.resize(index + 1, Level::default());
+                    }
+                    list[index].start = start;
+                }
+            }
+        }
+        numbering.insert(id.to_string(), list);
+    }
+    numbering
+}
+
+fn numbering_reference(properties: Node) -> Option<(String, usize)> {
+    let id = child(properties, "numId").and_then(value)?;
+    let level = child(properties, "ilvl")
+        .and_then(value)
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(0);
+    Some((id.to_string(), level))
+}
+
+enum Kind {
+    Normal,
+    Heading(u8),
+    Code,
+    Quote,
+    Item(String, usize),
+    /// Paragraphs of a generated table of contents.
+    Skip,
+}
+
+/// A complex field, `w:fldChar` begin to end, with the piece its result
+/// starts at once the instruction is complete.
+struct Field {
+    instruction: String,
+    result: Option<usize>,
+}
+
+#[derive(Default)]
+struct Inline {
+    pieces: Vec<Piece>,
+    fields: Vec<Field>,
+}
+
+struct DocxReader<'a> {
+    zip: &'a ZipReader<'a>,
+    relationships: HashMap<String, Relationship>,
+    styles: HashMap<String, Style>,
+    numbering: HashMap<String, Vec<Level>>,
+    /// The last number used at each level of each list.
+    counters: HashMap<String, Vec<u64>>,
+    /// Bookmarks on headings, by name, with the slug of the heading.
+    bookmarks: HashMap<String, String>,
+    /// Footnotes and endnotes in the order they are referenced.
+    notes: Vec<(&'static str, String)>,
+    assets: &'a mut Assets,
+    missing: Vec<String>,
+}
+
+impl DocxReader<'_> {
+    fn convert(
+        mut self,
+        body: Node,
+        footnotes: Option<&str>,
+        endnotes: Option<&str>,
+    ) -> Result<(String, Vec<String>)> {
+        let mut blocks = gfm::assemble(self.collect(body)?);
+
+        let footnotes = footnotes.and_then(|text| Document::parse(text).ok());
+        let endnotes = endnotes.and_then(|text| Document::parse(text).ok());
+        // Notes can reference further notes, so the list may grow.
+        let mut index = 0;
+        while index < self.notes.len() {
+            let (kind, id) = self.notes[index].clone();
+            let (document, tag) = match kind {
+                "footnote" => (&footnotes, "footnote"),
+                _ => (&endnotes, "endnote"),
+            };
+            let note = document.as_ref().and_then(|document| {
+                document
+                    .descendants()
+                    .find(|node| is(*node, tag) && attribute(*node, "id") == Some(id.as_str()))
+            });
+            let content = match note {
+                Some(note) => gfm::assemble(self.collect(note)?),
+                None => Vec::new(),
+            };
+            index += 1;
+            blocks.push(Block::Footnote(index.to_string(), content));
+        }
+        Ok((gfm::render(&blocks), self.missing))
+    }
+
+    /// Maps the bookmarks placed on headings to the headings' ids, so links
+    /// to them can point at the heading in the markdown. The ids are handed
+    /// out in document order as the preview does, so a repeated heading
+    /// gets its numbered id.
+    fn collect_bookmarks(&mut self, body: Node) {
+        let mut slugs = markdown::Slugs::default();
+        for paragraph in body.descendants().filter(|node| is(*node, "p")) {
+            // Empty headings are left out and table cells hold no headings.
+            if !matches!(self.kind(paragraph), Kind::Heading(_))
+                || paragraph.ancestors().any(|node| is(node, "tbl"))
+            {
+                continue;
+            }
+            let text: String = paragraph
+                .descendants()
+                .filter(|node| is(*node, "t"))
+                .filter_map(|node| node.text())
+                .collect();
+            if text.trim().is_empty() {
+                continue;
+            }
+            let id = slugs.next(&text);
+            for bookmark in paragraph
+                .descendants()
+                .filter(|node| is(*node, "bookmarkStart"))
+            {
+                if let Some(name) = attribute(bookmark, "name") {
+                    self.bookmarks.insert(name.to_string(), id.clone());
+                }
+            }
+        }
+    }
+
+    fn collect(&mut self, container: Node) -> Result<Vec<Part>> {
+        let mut parts = Vec::new();
+        for node in container.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "p" => self.paragraph(node, &mut parts)?,
+                "tbl" => {
+                    if let Some(table) = self.table(node)? {
+                        parts.push(Part::Block(table));
+                    }
+                }
+                "sdt" => {
+                    if let Some(content) = child(node, "sdtContent") {
+                        parts.extend(self.collect(content)?);
+                    }
+                }
+                "customXml" | "ins" | "moveTo" => parts.extend(self.collect(node)?),
+                _ => {}
+            }
+        }
+        Ok(parts)
+    }
+
+    /// The paragraph's style and its `basedOn` ancestors, nearest first.
+    fn style_chain(&self, id: Option<&str>) -> Vec<&Style> {
+        let mut chain = Vec::new();
+        let mut id = id;
+        while let Some(style) = id.and_then(|id| self.styles.get(id)) {
+            if chain.len() == 10 {
+                break;
+            }
+            chain.push(style);
+            id = style.based_on.as_deref();
+        }
+        chain
+    }
+
+    fn level(&self, id: &str, level: usize) -> Level {
+        self.numbering
+            .get(id)
+            .and_then(|levels| levels.get(level))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn kind(&self, paragraph: Node) -> Kind {
+        let properties = child(paragraph, "pPr");
+        let style = properties
+            .and_then(|properties| child(properties, "pStyle"))
+            .and_then(value);
+        let chain = self.style_chain(style);
+        if chain
+            .first()
+            .is_some_and(|style| style.name.starts_with("toc"))
+        {
+            return Kind::Skip;
+        }
+        for style in &chain {
+            if style.name == "title" {
+                return Kind::Heading(1);
+            }
+            if let Some(level) = style
+                .name
+                .strip_prefix("heading ")
+                .and_then(|level| level.parse::<u8>().ok())
+                .filter(|level| (1..=6).contains(level))
+            {
+                return Kind::Heading(level);
+            }
+        }
+        let outline = properties
+            .and_then(|properties| child(properties, "outlineLvl"))
+            .and_then(value)
+            .and_then(|level| level.parse::<u8>().ok())
+            .or_else(|| chain.iter().find_map(|style| style.outline));
+        if let Some(level) = outline.filter(|level| *level < 6) {
+            return Kind::Heading(level + 1);
+        }
+        let numbering = properties
+            .and_then(|properties| child(properties, "numPr"))
+            .and_then(numbering_reference)
+            .or_else(|| chain.iter().find_map(|style| style.numbering.clone()));
+        if let Some((id, level)) = numbering {
+            if id != "0" && self.level(&id, level).numbered {
+                return Kind::Item(id, level);
+            }
+        }
+        if chain.iter().any(|style| style.name.contains("quote")) {
+            return Kind::Quote;
+        }
+        if chain.iter().any(|style| style.monospace) || self.monospace_runs(paragraph) {
+            return Kind::Code;
+        }
+        Kind::Normal
+    }
+
+    /// Whether all the text in a paragraph is set in a monospaced font.
+    fn monospace_runs(&self, paragraph: Node) -> bool {
+        let mut runs = paragraph
+            .descendants()
+            .filter(|node| is(*node, "r"))
+            .filter(|run| {
+                run.children()
+                    .any(|node| is(node, "t") && node.text().is_some_and(|t| !t.trim().is_empty()))
+            })
+            .peekable();
+        runs.peek().is_some() && runs.all(|run| self.format(child(run, "rPr")).code)
+    }
+
+    fn format(&self, properties: Option<Node>) -> Format {
+        let mut format = Format::default();
+        let Some(properties) = properties else {
+            return format;
+        };
+        let style = child(properties, "rStyle").and_then(value);
+        for style in self.style_chain(style) {
+            format.bold |= style.bold;
+            format.italic |= style.italic;
+            format.code |= style.monospace;
+        }
+        if let Some(bold) = child(properties, "b") {
+            format.bold = toggle(Some(bold));
+        }
+        if let Some(italic) = child(properties, "i") {
+            format.italic = toggle(Some(italic));
+        }
+        format.strike = toggle(child(properties, "strike")) || toggle(child(properties, "dstrike"));
+        format.code |= monospace_font(properties);
+        format
+    }
+
+    /// The number a list item gets, following Word: each level counts on
+    /// from its last item and restarts after a shallower level.
+    fn number(&mut self, id: &str, level: usize) -> u64 {
+        let start = self.level(id, level).start;
+        let counters = self.counters.entry(id.to_string()).or_default();
+        counters.resize(level + 1, 0);
+        counters[level] = if counters[level] == 0 {
+            start
+        } else {
+            counters[level] + 1
+        };
+        counters[level]
+    }
+
+    fn paragraph(&mut self, paragraph: Node, parts: &mut Vec<Part>) -> Result<()> {
+        let kind = self.kind(paragraph);
+        if matches!(kind, Kind::Skip) {
+            return Ok(());
+        }
+        let raw = matches!(kind, Kind::Code);
+        let mut inline = Inline::default();
+        self.inline(paragraph, raw, &mut inline)?;
+        let text = gfm::inline(&inline.pieces, raw);
+        let empty = text.trim().is_empty();
+        match kind {
+            Kind::Heading(level) if !empty => parts.push(Part::Block(Block::Heading(level, text))),
+            Kind::Normal if !empty => parts.push(Part::Block(Block::Paragraph(text))),
+            Kind::Code => parts.push(Part::Code(text)),
+            Kind::Quote if !empty => parts.push(Part::Quote(text)),
+            Kind::Item(id, level) => {
+                let ordered = self.level(&id, level).ordered;
+                let number = self.number(&id, level);
+                parts.push(Part::Item(ListItem {
+                    level,
+                    ordered,
+                    number,
+                    text,
+                }));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn table(&mut self, table: Node) -> Result<Option<Block>> {
+        let mut rows = Vec::new();
+        let mut align = Vec::new();
+        for row in table.children().filter(|node| is(*node, "tr")) {
+            let mut cells = Vec::new();
+            for cell in row.children().filter(|node| is(*node, "tc")) {
+                if rows.is_empty() {
+                    let justification = cell
+                        .descendants()
+                        .find(|node| is(*node, "jc"))
+                        .and_then(value);
+                    align.push(match justification {
+                        Some("center") => Align::Center,
+                        Some("right" | "end") => Align::Right,
+                        _ => Align::None,
+                    });
+                }
+                let blocks = gfm::assemble(self.collect(cell)?);
+                cells.push(gfm::cell(&blocks));
+                let span = child(cell, "tcPr")
+                    .and_then(|properties| child(properties, "gridSpan"))
+                    .and_then(value)
+                    .and_then(|span| span.parse::<usize>().ok())
+                    .unwrap_or(1);
+                for _ in 1..span.min(64) {
+                    cells.push(String::new());
+                    if rows.is_empty() {
+                        align.push(Align::None);
+                    }
+                }
+            }
+            rows.push(cells);
+        }
+        Ok((!rows.is_empty()).then_some(Block::Table { rows, align }))
+    }
+
+    fn inline(&mut self, node: Node, raw: bool, out: &mut Inline) -> Result<()> {
+        for node in node.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "r" => self.run(node, raw, out)?,
+                "hyperlink" => {
+                    let target = match attribute(node, "id") {
+                        Some(id) => self
+                            .relationships
+                            .get(id)
+                            .filter(|relationship| relationship.external)
+                            .map(|relationship| match attribute(node, "anchor") {
+                                Some(anchor) => format!("{}#{anchor}", relationship.target),
+                                None => relationship.target.clone(),
+                            }),
+                        None => attribute(node, "anchor").and_then(|anchor| self.anchor(anchor)),
+                    };
+                    let start = out.pieces.len();
+                    self.inline(node, raw, out)?;
+                    link(out, start, target, raw);
+                }
+                "fldSimple" => {
+                    let target =
+                        attribute(node, "instr").and_then(|field| self.field_target(field));
+                    let start = out.pieces.len();
+                    self.inline(node, raw, out)?;
+                    link(out, start, target, raw);
+                }
+                "ins" | "smartTag" | "customXml" | "moveTo" | "dir" | "bdo" => {
+                    self.inline(node, raw, out)?
+                }
+                "sdt" => {
+                    if let Some(content) = child(node, "sdtContent") {
+                        self.inline(content, raw, out)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn run(&mut self, run: Node, raw: bool, out: &mut Inline) -> Result<()> {
+        let format = self.format(child(run, "rPr"));
+        for node in run.children().filter(Node::is_element) {
+            match node.tag_name().name() {
+                "t" => text(out, node.text().unwrap_or_default(), format),
+                "tab" | "ptab" => text(out, if raw { "\t" } else { " " }, format),
+                "noBreakHyphen" => text(out, "-", format),
+                "br" | "cr" if !matches!(attribute(node, "type"), Some("page" | "column")) => {
+                    out.pieces
+                        .push(Piece::Markup(if raw { "\n" } else { "\\\n" }.to_string()));
+                }
+                "drawing" | "pict" | "object" => {
+                    if let Some(image) = self.image(node)? {
+                        out.pieces.push(Piece::Markup(image));
+                    }
+                }
+                name @ ("footnoteReference" | "endnoteReference") => {
+                    let kind = if name == "footnoteReference" {
+                        "footnote"
+                    } else {
+                        "endnote"
+                    };
+                    if let Some(id) = attribute(node, "id") {
+                        let note = (kind, id.to_string());
+                        let number = match self.notes.iter().position(|known| *known == note) {
+                            Some(index) => index + 1,
+                            None => {
+                                self.notes.push(note);
+                                self.notes.len()
+                            }
+                        };
+                        out.pieces.push(Piece::Markup(format!("[^{number}]")));
+                    }
+                }
+                "fldChar" => match attribute(node, "fldCharType") {
+                    Some("begin") => out.fields.push(Field {
+                        instruction: String::new(),
+                        result: None,
+                    }),
+                    Some("separate") => {
+                        if let Some(field) = out.fields.last_mut() {
+                            field.result = Some(out.pieces.len());
+                        }
+                    }
+                    Some("end") => {
+                        if let Some(Field {
+                            instruction,
+                            result: Some(start),
+                        }) = out.fields.pop()
+                        {
+                            let target = self.field_target(&instruction);
+                            link(out, start, target, raw);
+                        }
+                    }
+                    _ => {}
+                },
+                "instrText" => {
+                    if let Some(field) = out.fields.last_mut() {
+                        field.instruction.push_str(node.text().unwrap_or_default());
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Extracts a picture to the assets folder and returns its markdown.
+    /// Pictures that can't be extracted leave their alt text behind.
+    fn image(&mut self, node: Node) -> Result<Option<String>> {
+        let alt = node
+            .descendants()
+            .find(|node| is(*node, "docPr"))
+            .and_then(|properties| {
+                attribute(properties, "descr")
+                    .filter(|alt| !alt.is_empty())
+                    .or_else(|| attribute(properties, "title"))
+            })
+            .or_else(|| {
+                node.descendants()
+                    .find(|node| is(*node, "imagedata"))
+                    .and_then(|data| attribute(data, "title"))
+            })
+            .unwrap_or_default()
+            .to_string();
+        let relationship = node.descendants().find_map(|node| {
+            let id = if is(node, "blip") {
+                attribute(node, "embed").or_else(|| attribute(node, "link"))
+            } else if is(node, "imagedata") {
+                attribute(node, "id")
+            } else {
+                None
+            }?;
+            self.relationships.get(id)
+        });
+        let Some(relationship) = relationship else {
+            return Ok(None);
+        };
+        if relationship.external {
+            return Ok(Some(gfm::image(&alt, &relationship.target)));
+        }
+        let name = relationship.target.rsplit('/').next().unwrap_or_default();
+        let supported = name
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        match self.zip.read(&relationship.target).filter(|_| supported) {
+            Some(bytes) => {
+                let link = self.assets.add(name, &bytes)?;
+                Ok(Some(gfm::image(&alt, &link)))
+            }
+            None => {
+                self.missing.push(name.to_string());
+                Ok((!alt.is_empty()).then(|| gfm::escape(&alt)))
+            }
+        }
+    }
+
+    /// A link to a bookmark, if it marks a heading.
+    fn anchor(&self, name: &str) -> Option<String> {
+        self.bookmarks.get(name).map(|slug| format!("#{slug}"))
+    }
+
+    /// The target of a `HYPERLINK` field.
+    fn field_target(&self, instruction: &str) -> Option<String> {
+        match hyperlink_field(instruction)? {
+            (Some(url), Some(anchor)) => Some(format!("{url}#{anchor}")),
+            (Some(url), None) => Some(url),
+            (None, Some(anchor)) => self.anchor(&anchor),
+            (None, None) => None,
+        }
+    }
+}
+
+/// The URL and bookmark of a `HYPERLINK` field instruction, as used by
+/// Word documents and RTF, like `HYPERLINK "https://example.com" \o "tip"`
+/// or `HYPERLINK \l "bookmark"`.
+pub fn hyperlink_field(instruction: &str) -> Option<(Option<String>, Option<String>)> {
+    let arguments = instruction.trim().strip_prefix("HYPERLINK")?;
+    let mut url = None;
+    let mut anchor = None;
+    let mut arguments = field_arguments(arguments).into_iter();
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "\\l" => anchor = arguments.next(),
+            "\\o" | "\\t" => {
+                arguments.next();
+            }
+            _ if argument.starts_with('\\') => {}
+            _ if url.is_none() => url = Some(argument),
+            _ => {}
+        }
+    }
+    Some((url, anchor))
+}
+
+/// Splits field instruction arguments, keeping quoted ones together.
+fn field_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                if quoted {
+                    arguments.push(std::mem::take(&mut current));
+                }
+                quoted = !quoted;
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+    arguments
+}
+
+fn text(out: &mut Inline, text: &str, format: Format) {
+    out.pieces.push(Piece::Text(text.to_string(), format));
+}
+
+/// Turns the pieces from `start` on into a link to `target`. Without a
+/// target, with one that isn't safe to follow, or inside code, the text
+/// stays as it is.
+fn link(out: &mut Inline, start: usize, target: Option<String>, raw: bool) {
+    let Some(target) = target.filter(|target| !raw && links::is_safe(target)) else {
+        return;
+    };
+    let pieces = out.pieces.split_off(start.min(out.pieces.len()));
+    let text = gfm::inline(&pieces, raw);
+    if text.trim().is_empty() {
+        return;
+    }
+    out.pieces
+        .push(Piece::Markup(gfm::link(&text, &target, None)));
+}
+
+/// Matches a WordprocessingML element by its local name.
+fn is(node: Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|node| is(*node, name))
+}
+
+/// An attribute by its local name, whatever its namespace prefix.
+fn attribute<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|attribute| attribute.name() == name)
+        .map(|attribute| attribute.value())
+}
+
+fn value<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    attribute(node, "val")
+}
+
+/// Reads an on/off property like `<w:b/>` or `<w:b w:val="false"/>`.
+fn toggle(node: Option<Node>) -> bool {
+    node.is_some_and(|node| !matches!(value(node), Some("0" | "false" | "off" | "none")))
+}
+
+fn monospace_font(properties: Node) -> bool {
+    child(properties, "rFonts")
+        .and_then(|fonts| attribute(fonts, "ascii").or_else(|| attribute(fonts, "hAnsi")))
+        .is_some_and(|font| {
+            let font = font.to_ascii_lowercase();
+            [
+                "mono", "courier", "consolas", "menlo", "monaco", "code", "console",
+            ]
+            .iter()
+            .any(|name| font.contains(name))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip::ZipWriter;
+
+    const NAMESPACES: &str =
+        "xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" \
+         xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\"";
+    const RELATIONSHIPS: &str =
+        "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+    const STYLES: &str = "<w:style w:type=\"paragraph\" w:styleId=\"Heading1\"><w:name w:val=\"heading 1\"/></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Sub\"><w:name w:val=\"Subsection\"/><w:basedOn w:val=\"Heading2\"/></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Heading2\"><w:name w:val=\"heading 2\"/></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Quote\"><w:name w:val=\"Quote\"/></w:style>\
+         <w:style w:type=\"paragraph\" w:styleId=\"Code\"><w:name w:val=\"Source Code\"/></w:style>";
+
+    const NUMBERING: &str = "<w:abstractNum w:abstractNumId=\"0\">\
+         <w:lvl w:ilvl=\"0\"><w:start w:val=\"1\"/><w:numFmt w:val=\"decimal\"/></w:lvl>\
+         <w:lvl w:ilvl=\"1\"><w:numFmt w:val=\"bullet\"/></w:lvl></w:abstractNum>\
+         <w:num w:numId=\"1\"><w:abstractNumId w:val=\"0\"/></w:num>\
+         <w:num w:numId=\"2\"><w:abstractNumId w:val=\"0\"/>\
+         <w:lvlOverride w:ilvl=\"0\"><w:startOverride w:val=\"5\"/></w:lvlOverride></w:num>";
+
+    /// A Word package with `body` as its document, the styles and numbering
+    /// above and `footnotes`. `rId10` links to https://example.com and
+    /// `rId11` to a script.
+    fn docx(body: &str, footnotes: &str) -> Vec<u8> {
+        let mut zip = ZipWriter::new();
+        zip.add(
+            "_rels/.rels",
+            format!(
+                "<Relationships><Relationship Id=\"rId1\" Type=\"{RELATIONSHIPS}/officeDocument\" \
+                 Target=\"word/document.xml\"/></Relationships>"
+            )
+            .as_bytes(),
+        );
+        zip.add(
+            "word/_rels/document.xml.rels",
+            format!(
+                "<Relationships>\
+                 <Relationship Id=\"rId1\" Type=\"{RELATIONSHIPS}/styles\" Target=\"styles.xml\"/>\
+                 <Relationship Id=\"rId2\" Type=\"{RELATIONSHIPS}/numbering\" Target=\"numbering.xml\"/>\
+                 <Relationship Id=\"rId3\" Type=\"{RELATIONSHIPS}/footnotes\" Target=\"footnotes.xml\"/>\
+                 <Relationship Id=\"rId10\" Type=\"{RELATIONSHIPS}/hyperlink\" \
+                 Target=\"https://example.com\" TargetMode=\"External\"/>\
+                 <Relationship Id=\"rId11\" Type=\"{RELATIONSHIPS}/hyperlink\" \
+                 Target=\"javascript:alert(1)\" TargetMode=\"External\"/>\
+                 </Relationships>"
+            )
+            .as_bytes(),
+        );
+        let part =
+            |root: &str, content: &str| format!("<w:{root} {NAMESPACES}>{content}</w:{root}>");
+        zip.add(
+            "word/document.xml",
+            part("document", &format!("<w:body>{body}</w:body>")).as_bytes(),
+        );
+        zip.add("word/styles.xml", part("styles", STYLES).as_bytes());
+        zip.add(
+            "word/numbering.xml",
+            part("numbering", NUMBERING).as_bytes(),
+        );
+        zip.add(
+            "word/footnotes.xml",
+            part("footnotes", footnotes).as_bytes(),
+        );
+        zip.finish()
+    }
+
+    fn paragraph(style: &str, content: &str) -> String {
+        format!("<w:p><w:pPr><w:pStyle w:val=\"{style}\"/></w:pPr>{content}</w:p>")
+    }
+
+    fn item(list: u32, level: u32, text: &str) -> String {
+        format!(
+            "<w:p><w:pPr><w:numPr><w:ilvl w:val=\"{level}\"/><w:numId w:val=\"{list}\"/></w:numPr></w:pPr>{}</w:p>",
+            run("", text)
+        )
+    }
+
+    fn run(properties: &str, text: &str) -> String {
+        format!("<w:r><w:rPr>{properties}</w:rPr><w:t xml:space=\"preserve\">{text}</w:t></w:r>")
+    }
+
+    fn convert(body: &str, footnotes: &str) -> String {
+        let mut assets = Assets::new(Path::new("/nonexistent/imported.md"));
+        let (markdown, missing) = docx_to_markdown(&docx(body, footnotes), &mut assets)
+            .unwrap()
+            .unwrap();
+        assert!(missing.is_empty());
+        markdown
+    }
+
+    #[test]
+    fn headings_and_bookmarks() {
+        let heading = |name: &str| {
+            paragraph(
+                "Heading1",
+                &format!(
+                    "<w:bookmarkStart w:id=\"0\" w:name=\"{name}\"/>{}<w:bookmarkEnd w:id=\"0\"/>",
+                    run("", "Intro")
+                ),
+            )
+        };
+        let body = [
+            heading("_Toc1"),
+            heading("_Toc2"),
+            paragraph("Sub", &run("", "Based on heading 2")),
+            format!(
+                "<w:p><w:hyperlink w:anchor=\"_Toc2\">{}</w:hyperlink>{}\
+                 <w:r><w:fldChar w:fldCharType=\"begin\"/></w:r>\
+                 <w:r><w:instrText> HYPERLINK \\l \"_Toc1\" </w:instrText></w:r>\
+                 <w:r><w:fldChar w:fldCharType=\"separate\"/></w:r>{}\
+                 <w:r><w:fldChar w:fldCharType=\"end\"/></w:r></w:p>",
+                run("", "second"),
+                run("", " "),
+                run("", "first")
+            ),
+        ]
+        .concat();
+        assert_eq!(
+            convert(&body, ""),
+            "# Intro\n\n# Intro\n\n## Based on heading 2\n\n[second](#intro-1) [first](#intro)\n"
+        );
+    }
+
+    #[test]
+    fn list_numbering() {
+        let body = [
+            item(1, 0, "one"),
+            item(1, 1, "inner"),
+            item(1, 1, "inner too"),
+            item(1, 0, "two"),
+            paragraph("Normal", &run("", "between")),
+            item(1, 0, "three"),
+            paragraph("Normal", &run("", "and")),
+            item(2, 0, "five"),
+        ]
+        .concat();
+        assert_eq!(
+            convert(&body, ""),
+            "1. one\n   - inner\n   - inner too\n2. two\n\nbetween\n\n3. three\n\nand\n\n5. five\n"
+        );
+    }
+
+    #[test]
+    fn inline_formatting_and_links() {
+        let body = format!(
+            "<w:p>{}{}{}{}{space}<w:hyperlink r:id=\"rId10\">{}</w:hyperlink>\
+             {space}<w:hyperlink r:id=\"rId11\">{}</w:hyperlink>{space}\
+             <w:fldSimple w:instr=\" HYPERLINK &quot;https://x.org/a b&quot; \\o &quot;tip&quot;\">{}</w:fldSimple></w:p>",
+            run("<w:b/>", "bold"),
+            run("<w:i/>", " italic"),
+            run("<w:strike/>", " gone "),
+            run("<w:rFonts w:ascii=\"Consolas\"/>", "a*b"),
+            run("", "site"),
+            run("", "script"),
+            run("", "spaced"),
+            space = run("", " "),
+        );
+        assert_eq!(
+            convert(&body, ""),
+            "**bold** *italic* ~~gone~~ `a*b` [site](https://example.com) script [spaced](<https://x.org/a b>)\n"
+        );
+    }
+
+    #[test]
+    fn quotes_code_and_footnotes() {
+        let body = [
+            paragraph("Quote", &run("", "quoted")),
+            paragraph("Code", &run("", "let a = 1;")),
+            paragraph("Code", &run("", "let b = *a;")),
+            format!(
+                "<w:p>{}<w:r><w:footnoteReference w:id=\"7\"/></w:r></w:p>",
+                run("", "Claim")
+            ),
+        ]
+        .concat();
+        let footnotes = format!(
+            "<w:footnote w:id=\"7\">{}</w:footnote>",
+            paragraph("Normal", &run("", "Source"))
+        );
+        assert_eq!(
+            convert(&body, &footnotes),
+            "> quoted\n\n```\nlet a = 1;\nlet b = *a;\n```\n\nClaim[^1]\n\n[^1]: Source\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let cell = |content: &str| format!("<w:tc>{content}</w:tc>");
+        let body = format!(
+            "<w:tbl><w:tr>{}{}</w:tr><w:tr>{}{}</w:tr></w:tbl>",
+            cell(&paragraph("Normal", &run("<w:b/>", "Name"))),
+            cell(&format!(
+                "<w:p><w:pPr><w:jc w:val=\"right\"/></w:pPr>{}</w:p>",
+                run("", "Qty")
+            )),
+            cell(
+                &[
+                    paragraph("Normal", &run("", "a|b")),
+                    paragraph("Normal", &run("", "c"))
+                ]
+                .concat()
+            ),
+            cell(&paragraph("Normal", &run("", "1"))),
+        );
+        assert_eq!(
+            convert(&body, ""),
+            "| Name | Qty |\n| --- | ---: |\n| a\\|b<br>c | 1 |\n"
+        );
+    }
+
+    #[test]
+    fn not_a_word_document() {
+        let mut assets = Assets::new(Path::new("/nonexistent/imported.md"));
+        assert!(docx_to_markdown(b"<html></html>", &mut assets).is_none());
+    }
+
+    #[test]
+    fn hyperlink_fields() {
+        assert_eq!(
+            hyperlink_field(" HYPERLINK \"https://x.org\" \\o \"tip\" "),
+            Some((Some("https://x.org".to_string()), None))
+        );
+        assert_eq!(
+            hyperlink_field("HYPERLINK \\l \"_Toc1\""),
+            Some((None, Some("_Toc1".to_string())))
+        );
+        assert_eq!(hyperlink_field("PAGEREF _Toc1"), None);
+    }
+
+    #[test]
+    fn data_images() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut assets = Assets::new(&temp.path().join("page.md"));
+        let html = "<p><img alt=\"a\" src=\"data:image/png;base64,aGk=\">\
+             <img alt=\"b\" src=\"data:image/svg+xml,%3Csvg%2F%3E\">\
+             <img alt=\"c\" src=\"data:image/gif;base64,***\">\
+             <img alt=\"d\" src=\"data:text/html,%3Cp%3E\"></p>";
+        let (markdown, missing) = html_to_markdown(
+            html.as_bytes(),
+            &temp.path().join("page.html"),
+            ImageAccess::Unscoped,
+            &mut assets,
+        )
+        .unwrap();
+        // Images that can't be read are left as their alt text.
+        assert_eq!(
+            markdown,
+            "![a](page_assets/image.png)![b](page_assets/image.svg)cd\n"
+        );
+        assert_eq!(missing, ["data:image/gif", "data:text/html"]);
+        let read = |name: &str| fs::read(temp.path().join("page_assets").join(name)).unwrap();
+        assert_eq!(read("image.png"), b"hi");
+        assert_eq!(read("image.svg"), b"<svg/>");
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager, State, Url, WebviewWindow};
+
+use crate::document::TextFormat;
+use crate::registry::{self, DocumentId, DocumentRegistry, DocumentSnapshot};
+use crate::reload::ViewState;
+use crate::scope;
+
+pub const INCOMING_EVENT: &str = "incoming-document";
+
+pub const MAIN_WINDOW: &str = "main";
+
+/// Something a window should open.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Incoming {
+    /// A file from the command line, "Open With" or another launch.
+    File { path: PathBuf },
+    /// A document moved over from another window, unsaved edits included.
+    Document {
+        id: DocumentId,
+        path: Option<PathBuf>,
+        snapshot: DocumentSnapshot,
+    },
+    /// An unsaved buffer restored from the crash recovery journal.
+    Recovered {
+        path: Option<PathBuf>,
+        text: String,
+        format: Option<TextFormat>,
+    },
+    /// A file from the last session, shown the way it was left.
+    Restored { path: PathBuf, view: ViewState },
+}
+
+impl Incoming {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Incoming::File { path } | Incoming::Restored { path, .. } => Some(path),
+            Incoming::Document { path, .. } | Incoming::Recovered { path, .. } => path.as_deref(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Inbox {
+    items: Vec<Incoming>,
+    frontend_ready: bool,
+}
+
+/// Per-window queue of documents the frontend hasn't picked up yet. Events
+/// emitted before a webview has loaded would be lost, so items are held
+/// until the window asks for them.
+#[derive(Default)]
+pub struct PendingDocuments(Mutex<HashMap<String, Inbox>>);
+
+impl PendingDocuments {
+    /// Queues the files passed at startup for the main window.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        let items = paths
+            .into_iter()
+            .map(|path| Incoming::File { path })
+            .collect();
+        let inbox = Inbox {
+            items,
+            frontend_ready: false,
+        };
+        Self(Mutex::new(HashMap::from([(
+            MAIN_WINDOW.to_string(),
+            inbox,
+        )])))
+    }
+
+    /// Drops the queue of a window that has been closed.
+    pub fn remove_window(&self, label: &str) {
+        self.0.lock().unwrap().remove(label);
+    }
+
+    /// Marks a window as not listening, e.g. while its webview reloads.
+    pub fn reset_window(&self, label: &str) {
+        if let Some(inbox) = self.0.lock().unwrap().get_mut(label) {
+            inbox.frontend_ready = false;
+        }
+    }
+
+    fn is_waiting(&self, label: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .get(label)
+            .is_some_and(|inbox| !inbox.frontend_ready)
+    }
+}
+
+/// Extracts the files to open from command-line arguments. Options are
+/// skipped (macOS, for one, may pass `-psn_*`), `file://` URLs are
+/// converted and relative paths are resolved against `cwd`. Arguments that
+/// don't point at an existing file are ignored.
+pub fn paths_from_args<I>(args: I, cwd: &Path) -> Vec<PathBuf>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options_done = false;
+    let mut paths = Vec::new();
+    for arg in args {
+        if !options_done && arg == "--" {
+            options_done = true;
+        } else if !options_done && arg.starts_with('-') {
+            continue;
+        } else if let Some(path) = path_from_arg(&arg, cwd) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+fn path_from_arg(arg: &str, cwd: &Path) -> Option<PathBuf> {
+    let path = if arg.starts_with("file://") {
+        Url::parse(arg).ok()?.to_file_path().ok()?
+    } else {
+        cwd.join(arg)
+    };
+    path.is_file().then_some(path)
+}
+
+/// Hands `items` to the window labelled `label`, or queues them if its
+/// frontend isn't listening yet.
+pub fn deliver(app: &AppHandle, label: &str, items: Vec<Incoming>) {
+    let pending = app.state::<PendingDocuments>();
+    let mut inboxes = pending.0.lock().unwrap();
+    let inbox = inboxes.entry(label.to_string()).or_default();
+    if !inbox.frontend_ready {
+        inbox.items.extend(items);
+        return;
+    }
+    drop(inboxes);
+
+    allow_paths(app, &items);
+    for item in items {
+        let _ = app.emit_to(label, INCOMING_EVENT, item);
+    }
+}
+
+/// Lets the window open the files in `items`, which didn't come through a
+/// dialog.
+fn allow_paths(app: &AppHandle, items: &[Incoming]) {
+    for path in items.iter().filter_map(Incoming::path) {
+        scope::allow_file(app, path);
+    }
+}
+
+/// Opens files that arrive while the app is running. Files that are already
+/// open get their window focused; the rest open in new windows, except
+/// during startup when the main window takes them.
+pub fn open_files(app: &AppHandle, paths: Vec<PathBuf>) {
+    let registry = app.state::<DocumentRegistry>();
+    let pending = app.state::<PendingDocuments>();
+
+    for path in paths {
+        if registry.focus_path(app, &path) {
+            continue;
+        }
+        let item = Incoming::File { path };
+        if pending.is_waiting(MAIN_WINDOW) {
+            deliver(app, MAIN_WINDOW, vec![item]);
+        } else {
+            let _ = registry::open_window(app, vec![item]);
+        }
+    }
+}
+
+/// Brings the app to the front for a launch without files: the focused
+/// window, or any, or a new one if every window was closed.
+pub fn focus_app(app: &AppHandle) {
+    let windows = app.webview_windows();
+    let window = windows
+        .values()
+        .find(|window| window.is_focused().unwrap_or(false))
+        .or_else(|| windows.get(MAIN_WINDOW))
+        .or_else(|| windows.values().next());
+    match window {
+        Some(window) => {
+            let _ = window.unminimize();
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        None => {
+            let _ = registry::open_window(app, Vec::new());
+        }
+    }
+}
+
+/// Called by each window once its `incoming-document` listener is
+/// registered. Returns what was queued for it; later items arrive as events.
+#[tauri::command]
+pub fn take_pending_documents(
+    window: WebviewWindow,
+    pending: State<'_, PendingDocuments>,
+) -> Vec<Incoming> {
+    let mut inboxes = pending.0.lock().unwrap();
+    let inbox = inboxes.entry(window.label().to_string()).or_default();
+    inbox.frontend_ready = true;
+    let items = std::mem::take(&mut inbox.items);
+    allow_paths(window.app_handle(), &items);
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_from_arguments() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        for name in ["a.md", "sub/b.md", "my notes.md", "-dash.md"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        let url = Url::from_file_path(dir.join("my notes.md")).unwrap();
+        assert!(url.as_str().contains("my%20notes.md"));
+        let args = [
+            "-psn_0_12345".to_string(),
+            "--flag".to_string(),
+            "a.md".to_string(),
+            "sub/b.md".to_string(),
+            url.to_string(),
+            dir.join("a.md").to_string_lossy().into_owned(),
+            "missing.md".to_string(),
+            "sub".to_string(),
+            "--".to_string(),
+            "-dash.md".to_string(),
+        ];
+        assert_eq!(
+            paths_from_args(args, dir),
+            [
+                dir.join("a.md"),
+                dir.join("sub/b.md"),
+                dir.join("my notes.md"),
+                dir.join("a.md"),
+                dir.join("-dash.md"),
+            ]
+        );
+    }
+
+    #[test]
+    fn options_are_skipped_until_the_separator() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::write(dir.join("-psn_0_1"), "").unwrap();
+        let args = ["-psn_0_1".to_string()];
+        assert!(paths_from_args(args.clone(), dir).is_empty());
+        let args = ["--".to_string(), args[0].clone()];
+        assert_eq!(paths_from_args(args, dir), [dir.join("-psn_0_1")]);
+    }
+
+    #[test]
+    fn file_urls_that_are_not_paths() {
+        let temp = tempfile::tempdir().unwrap();
+        let args = [
+            "file://server/share/a.md".to_string(),
+            "file://%".to_string(),
+        ];
+        assert!(paths_from_args(args, temp.path()).is_empty());
+    }
+}
@@ -1,4 +1,38 @@
-use tauri::WebviewWindow;
+use tauri::{Manager, WindowEvent};
+
+mod atomic;
+mod cli;
+mod clipboard;
+mod close_guard;
+mod document;
+mod docx;
+mod epub;
+mod error;
+mod export;
+mod fonts;
+mod gfm;
+mod html;
+mod images;
+mod import;
+mod launch;
+mod links;
+mod markdown;
+mod menu;
+mod merge;
+mod pdf;
+mod recent;
+mod recovery;
+mod registry;
+mod reload;
+mod replace;
+mod rtf;
+mod scope;
+mod search;
+mod settings;
+mod single_instance;
+mod watcher;
+mod workspace;
+mod zip;
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 #[tauri::command]
@@ -6,19 +40,167 @@ fn greet(name: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", name)
 }
 
-#[tauri::command]
-fn clear_cache_and_reload(window: WebviewWindow) {
-    let _ = window.clear_all_browsing_data();
-    let _ = window.reload();
+/// Handles `mark-it-down render ...` without starting the app. `args` are
+/// the arguments after the subcommand name; returns the exit code.
+pub fn render(args: &[String]) -> i32 {
+    cli::render(args)
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // If the lock can't be taken at all, just run without single-instance.
+    let instance = match single_instance::acquire(&cwd, &args) {
+        Ok(single_instance::Instance::Forwarded) => return,
+        Ok(single_instance::Instance::Primary(primary)) => Some(primary),
+        Err(_) => None,
+    };
+    let initial_files = launch::paths_from_args(args, &cwd);
+    let launched_with_files = !initial_files.is_empty();
+
     tauri::Builder::default()
         .plugin(tauri_plugin_fs::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
-        .invoke_handler(tauri::generate_handler![greet, clear_cache_and_reload])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .manage(launch::PendingDocuments::new(initial_files))
+        .manage(registry::DocumentRegistry::default())
+        .manage(close_guard::CloseGuard::default())
+        .manage(reload::ReloadSnapshots::default())
+        .manage(replace::ReplaceHistory::default())
+        .setup(move |app| {
+            if let Some(primary) = instance {
+                app.manage(primary.listen(app.handle().clone()));
+            }
+            app.manage(watcher::DocumentWatcher::new(app.handle().clone())?);
+            let settings = settings::SettingsStore::load(app.handle())?;
+            app.manage(recovery::Recovery::new(
+                app.handle(),
+                settings.get().recovery,
+            )?);
+            let restore_session = settings.get().restore_session && !launched_with_files;
+            app.manage(settings);
+            recovery::start(app.handle().clone());
+
+            app.manage(recent::RecentFiles::load(app.handle())?);
+            app.manage(search::SearchIndex::new(app.handle())?);
+            menu::init(app.handle())?;
+            if restore_session {
+                recent::restore_session(app.handle());
+            }
+            Ok(())
+        })
+        .on_menu_event(menu::on_menu_event)
+        .on_window_event(|window, event| match event {
+            WindowEvent::CloseRequested { api, .. } => {
+                close_guard::on_close_requested(window, api);
+            }
+            WindowEvent::Focused(true) => {
+                menu::window_focused(window.app_handle(), window.label());
+            }
+            WindowEvent::Destroyed => {
+                let app = window.app_handle();
+                app.state::<reload::ReloadSnapshots>()
+                    .remove_window(window.label());
+                app.state::<registry::DocumentRegistry>()
+                    .remove_window(window.label());
+                app.state::<launch::PendingDocuments>()
+                    .remove_window(window.label());
+                app.state::<recent::RecentFiles>()
+                    .remove_window(window.label());
+                app.state::<search::SearchIndex>()
+                    .remove_window(window.label());
+                if let Some(menu) = app.try_state::<menu::AppMenu>() {
+                    menu.remove_window(window.label());
+                }
+            }
+            _ => {}
+        })
+        .invoke_handler(tauri::generate_handler![
+            greet,
+            document::open_document,
+            document::save_document,
+            watcher::watch_document,
+            watcher::unwatch_document,
+            watcher::merge_document,
+            launch::take_pending_documents,
+            registry::register_document,
+            registry::update_document,
+            registry::close_document,
+            registry::list_documents,
+            registry::create_window,
+            registry::move_document,
+            close_guard::finish_close,
+            close_guard::cancel_close,
+            recovery::journal_document,
+            recovery::take_recovered_documents,
+            recovery::restore_recovered_documents,
+            recovery::discard_recovered_documents,
+            reload::reload_window,
+            reload::take_reload_snapshot,
+            reload::reset_app_data,
+            settings::get_settings,
+            settings::set_settings,
+            settings::reset_settings,
+            recent::list_recent_files,
+            recent::remove_recent_file,
+            recent::clear_recent_files,
+            recent::update_session_view,
+            menu::update_menu_state,
+            markdown::parse_markdown,
+            markdown::render_markdown,
+            markdown::markdown_source_map,
+            markdown::document_headings,
+            export::export_html,
+            export::export_pdf,
+            export::export_docx,
+            export::export_epub,
+            import::import_document,
+            clipboard::paste_markdown,
+            clipboard::clipboard_text,
+            images::image_extensions,
+            images::save_image,
+            workspace::list_workspace,
+            workspace::create_workspace_entry,
+            workspace::rename_workspace_entry,
+            workspace::move_workspace_entry,
+            workspace::delete_workspace_entry,
+            search::open_search_index,
+            search::close_search_index,
+            search::search_workspace,
+            replace::preview_replace,
+            replace::apply_replace,
+            replace::undo_replace,
+            links::open_linked_file,
+            links::resolve_link
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| match event {
+            tauri::RunEvent::ExitRequested { api, .. } => {
+                close_guard::on_exit_requested(app, &api);
+            }
+            tauri::RunEvent::Exit => {
+                if let Some(recent) = app.try_state::<recent::RecentFiles>() {
+                    recent.save_session();
+                }
+                if let Some(recovery) = app.try_state::<recovery::Recovery>() {
+                    recovery.clear_session();
+                }
+                if let Some(lock) = app.try_state::<single_instance::LockFile>() {
+                    lock.release();
+                }
+            }
+            // "Open With" on macOS delivers files as an event, not as argv.
+            #[cfg(any(target_os = "macos", target_os = "ios"))]
+            tauri::RunEvent::Opened { urls } => {
+                let paths = urls
+                    .iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .collect();
+                launch::open_files(app, paths);
+            }
+            _ => {}
+        });
 }
@@ -0,0 +1,614 @@
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use serde::Serialize;
+use tauri::AppHandle;
+use tauri_plugin_opener::OpenerExt;
+
+use crate::document;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::scope;
+
+/// A link or image destination in a markdown source.
+#[derive(Debug, Clone)]
+pub struct Destination {
+    /// The destination as the parser reads it, escapes resolved.
+    pub url: String,
+    /// Where it is written in the source, without any `<` `>` around it.
+    pub range: Range<usize>,
+    /// Whether it is written between `<` and `>`.
+    pub bracketed: bool,
+}
+
+/// Where a link in a document leads.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedLink {
+    /// The target, canonical when it exists.
+    pub path: PathBuf,
+    /// The `#fragment` after the path, decoded and without its `#`.
+    pub fragment: Option<String>,
+    pub exists: bool,
+    pub is_markdown: bool,
+}
+
+/// The destinations of the inline links, images and link reference
+/// definitions in `source`, in source order. Autolinks and reference links
+/// have no destination of their own.
+pub fn destinations(source: &str) -> Vec<Destination> {
+    let parser = Parser::new_ext(source, markdown::options());
+    let mut out: Vec<Destination> = parser
+        .reference_definitions()
+        .iter()
+        .filter_map(|(_, definition)| {
+            let span = definition.span.clone();
+            let start = span.start + source.get(span.clone())?.find("]:")? + 2;
+            locate(source, start..span.end, &definition.dest)
+        })
+        .collect();
+    for (event, range) in parser.into_offset_iter() {
+        let dest_url = match event {
+            Event::Start(Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            })
+            | Event::Start(Tag::Image {
+                link_type: LinkType::Inline,
+                dest_url,
+                ..
+            }) => dest_url,
+            _ => continue,
+        };
+        // The text may hold `](` too, e.g. an image inside a link, so the
+        // destination is the last one that reads back as `dest_url`.
+        let found = source[range.clone()]
+            .match_indices("](")
+            .filter_map(|(at, _)| locate(source, range.start + at + 2..range.end, &dest_url))
+            .last();
+        out.extend(found);
+    }
+    out.sort_by_key(|destination| destination.range.start);
+    out
+}
+
+/// Reads the destination written at the start of `range`, if it is `url`.
+fn locate(source: &str, range: Range<usize>, url: &str) -> Option<Destination> {
+    if url.is_empty() {
+        return None;
+    }
+    let text = source.get(range.clone())?;
+    let start = range.start + text.len() - text.trim_start().len();
+    let text = text.trim_start();
+    let (range, bracketed) = if let Some(inner) = text.strip_prefix('<') {
+        let end = inner.find(['>', '\n'])?;
+        (start + 1..start + 1 + end, true)
+    } else {
+        let mut depth = 0usize;
+        let mut end = text.len();
+        let mut chars = text.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    chars.next();
+                }
+                '(' => depth += 1,
+                ')' if depth == 0 => {
+                    end = index;
+                    break;
+                }
+                ')' => depth -= 1,
+                c if c.is_whitespace() => {
+                    end = index;
+                    break;
+                }
+                _ => {}
+            }
+        }
+        (start..start + end, false)
+    };
+    (unescape(&source[range.clone()]) == url).then(|| Destination {
+        url: url.to_string(),
+        range,
+        bracketed,
+    })
+}
+
+/// Drops the backslashes escaping ASCII punctuation.
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(next) if c == '\\' && next.is_ascii_punctuation() => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Whether `url` points at a file relative to the document or on disk,
+/// rather than a web page, an email or a heading in the same document.
+pub fn is_local(url: &str) -> bool {
+    if url.is_empty() || url.starts_with('#') || url.starts_with("//") {
+        return false;
+    }
+    scheme(url).is_none()
+}
+
+/// The scheme `url` starts with, like `https`, if it has one.
+fn scheme(url: &str) -> Option<&str> {
+    let (scheme, _) = url.split_once(':')?;
+    // A scheme is at least two letters, so `C:` is a Windows drive.
+    let valid = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then_some(scheme)
+}
+
+/// Schemes that links from pasted or imported documents may keep.
+const SAFE_SCHEMES: &[&str] = &["http", "https", "mailto"];
+
+/// Extensions of programs, installers, scripts and shortcuts, which a link
+/// may only reveal, never open.
+const LAUNCHABLE_EXTENSIONS: &[&str] = &[
+    "app",
+    "appimage",
+    "apk",
+    "application",
+    "bash",
+    "bat",
+    "cmd",
+    "com",
+    "command",
+    "cpl",
+    "csh",
+    "deb",
+    "desktop",
+    "dmg",
+    "exe",
+    "fish",
+    "gadget",
+    "hta",
+    "inetloc",
+    "inf",
+    "jar",
+    "js",
+    "jse",
+    "ksh",
+    "lnk",
+    "msc",
+    "msi",
+    "msp",
+    "pif",
+    "pkg",
+    "pl",
+    "ps1",
+    "psm1",
+    "py",
+    "pyw",
+    "rb",
+    "reg",
+    "rpm",
+    "run",
+    "scf",
+    "scpt",
+    "scr",
+    "sh",
+    "terminal",
+    "tool",
+    "url",
+    "vb",
+    "vbe",
+    "vbs",
+    "webloc",
+    "workflow",
+    "ws",
+    "wsf",
+    "wsh",
+    "zsh",
+];
+
+/// Whether a link from pasted or imported content may be kept: a web page,
+/// an email address or a relative path. Anything else, like `javascript:`
+/// or `data:` in any case, could run or load something when followed.
+pub fn is_safe(url: &str) -> bool {
+    // Browsers drop tabs and line breaks anywhere in a URL.
+    let url: String = url
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    scheme(&url).is_none_or(|scheme| {
+        SAFE_SCHEMES
+            .iter()
+            .any(|safe| scheme.eq_ignore_ascii_case(safe))
+    })
+}
+
+/// Splits `url` into its path and the `?query` or `#fragment` after it,
+/// which keeps its leading `?` or `#`.
+pub fn split_suffix(url: &str) -> (&str, &str) {
+    let at = url.find(['?', '#']).unwrap_or(url.len());
+    url.split_at(at)
+}
+
+/// Decodes `%XX` escapes in a URL path, leaving anything else as it is.
+pub fn percent_decode(text: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(text)).into_owned()
+}
+
+/// Decodes `%XX` escapes into the bytes they stand for, like the contents
+/// of a `data:` URL that isn't base64.
+pub fn percent_decode_bytes(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let hex = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Escapes the characters that would end or break a URL path segment.
+pub fn encode_segment(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for ch in name.chars() {
+        match ch {
+            '%' => out.push_str("%25"),
+            ' ' => out.push_str("%20"),
+            '#' => out.push_str("%23"),
+            '?' => out.push_str("%3F"),
+            '"' => out.push_str("%22"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Resolves `.` and `..` without touching the disk, so paths that don't
+/// exist (yet) can be compared.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
+
+/// `to` relative to the folder `from`, both absolute and normalized, or `.`
+/// when they are the same folder. Paths on different drives have no
+/// relative form, so `to` is kept.
+pub fn relative(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to_components: Vec<Component> = to.components().collect();
+    if from.first() != to_components.first() {
+        return to.to_path_buf();
+    }
+    let common = from
+        .iter()
+        .zip(&to_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut out = PathBuf::new();
+    for _ in common..from.len() {
+        out.push("..");
+    }
+    for component in &to_components[common..] {
+        out.push(component);
+    }
+    if out.as_os_str().is_empty() {
+        out.push(".");
+    }
+    out
+}
+
+/// A path as written in a link: `/` separators, and each segment escaped
+/// unless the link is bracketed, where only `<` `>` need escaping.
+pub fn to_url(path: &Path, bracketed: bool) -> String {
+    let mut segments: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                segments.push(prefix.as_os_str().to_string_lossy().into_owned())
+            }
+            // Keeps the leading `/` of a Unix path.
+            Component::RootDir if segments.is_empty() => segments.push(String::new()),
+            Component::RootDir => {}
+            component => {
+                let segment = component.as_os_str().to_string_lossy();
+                segments.push(if bracketed {
+                    segment.replace('<', "%3C").replace('>', "%3E")
+                } else {
+                    encode_segment(&segment)
+                        .replace('(', "%28")
+                        .replace(')', "%29")
+                });
+            }
+        }
+    }
+    segments.join("/")
+}
+
+/// A `file:` URL as a path. On Windows `file:///C:/notes` is `C:/notes`.
+pub fn file_url_path(url: &str) -> Option<&str> {
+    let path = url
+        .strip_prefix("file://")
+        .or_else(|| url.strip_prefix("file:"))?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let drive = path.as_bytes();
+    if drive.len() > 2 && drive[0] == b'/' && drive[1].is_ascii_alphabetic() && drive[2] == b':' {
+        Some(&path[1..])
+    } else {
+        Some(path)
+    }
+}
+
+/// Resolves the link `url` in the document at `document_path`: the path is
+/// decoded and taken relative to the document's folder, and the fragment is
+/// split off. A link without an extension finds the `.md` file of that name.
+/// Targets outside the folders the app may read are refused.
+#[tauri::command]
+pub async fn resolve_link(
+    app: AppHandle,
+    document_path: Option<PathBuf>,
+    url: String,
+) -> Result<ResolvedLink> {
+    let url = url.trim();
+    let url = file_url_path(url).unwrap_or(url);
+    if !is_local(url) {
+        return Err(Error::Invalid(format!("{url} is not a link to a file")));
+    }
+    let (path, suffix) = split_suffix(url);
+    let fragment = suffix
+        .split_once('#')
+        .map(|(_, fragment)| percent_decode(fragment))
+        .filter(|fragment| !fragment.is_empty());
+    let written = PathBuf::from(percent_decode(path));
+    let target = if written.is_absolute() {
+        written
+    } else {
+        let Some(dir) = document_path.as_deref().and_then(Path::parent) else {
+            return Err(Error::Invalid(
+                "Save the document first to follow relative links".to_string(),
+            ));
+        };
+        dir.join(written)
+    };
+    let mut target = normalize(&target);
+    if !target.exists() && target.extension().is_none() {
+        let markdown = target.with_extension("md");
+        if markdown.is_file() {
+            target = markdown;
+        }
+    }
+    let (path, exists) = match dunce::canonicalize(&target) {
+        Ok(path) => (path, true),
+        Err(_) => (target, false),
+    };
+
+    scope::check(&app, &path)?;
+    Ok(ResolvedLink {
+        is_markdown: document::is_markdown(&path),
+        path,
+        fragment,
+        exists,
+    })
+}
+
+/// Opens a linked file that isn't markdown, such as a PDF or a picture, in
+/// the app the system opens it with. Programs, scripts, shortcuts and
+/// folders are shown in the file manager instead, so a link in a document
+/// can't launch anything.
+#[tauri::command]
+pub async fn open_linked_file(app: AppHandle, path: PathBuf) -> Result<()> {
+    scope::check(&app, &path)?;
+    if !path.exists() {
+        return Err(Error::NotFound(format!(
+            "{} does not exist",
+            path.display()
+        )));
+    }
+    let opener = app.opener();
+    let opened = if is_launchable(&path) {
+        opener.reveal_item_in_dir(&path)
+    } else {
+        opener.open_path(path.to_string_lossy(), None::<&str>)
+    };
+    opened.map_err(|e| Error::Io(e.to_string()))
+}
+
+/// Whether opening `path` could run a program: a folder, which may be an
+/// app bundle, a file with an executable or shortcut extension, or one
+/// marked executable.
+fn is_launchable(path: &Path) -> bool {
+    let Ok(metadata) = path.metadata() else {
+        return true;
+    };
+    if metadata.is_dir() {
+        return true;
+    }
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    if extension.is_some_and(|extension| LAUNCHABLE_EXTENSIONS.contains(&extension.as_str())) {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_links() {
+        assert!(is_local("notes/todo.md"));
+        assert!(is_local("../todo.md#today"));
+        assert!(is_local("/home/me/todo.md"));
+        assert!(is_local("C:/notes/todo.md"));
+        assert!(!is_local(""));
+        assert!(!is_local("#heading"));
+        assert!(!is_local("//example.com/page"));
+        assert!(!is_local("https://example.com"));
+        assert!(!is_local("mailto:me@example.com"));
+    }
+
+    #[test]
+    fn suffix_is_split_at_query_or_fragment() {
+        assert_eq!(split_suffix("a.md#top"), ("a.md", "#top"));
+        assert_eq!(split_suffix("a.png?v=2#x"), ("a.png", "?v=2#x"));
+        assert_eq!(split_suffix("a.md"), ("a.md", ""));
+    }
+
+    #[test]
+    fn percent_escapes_are_decoded() {
+        assert_eq!(
+            percent_decode("my%20notes/%C3%A9t%C3%A9.md"),
+            "my notes/été.md"
+        );
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%2"), "%zz%2");
+    }
+
+    #[test]
+    fn dots_are_resolved() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize(Path::new("../a/b/..")), Path::new("../a"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            relative(Path::new("/a/b"), Path::new("/a/b/c.md")),
+            Path::new("c.md")
+        );
+        assert_eq!(
+            relative(Path::new("/a/b"), Path::new("/a/d/c.md")),
+            Path::new("../d/c.md")
+        );
+        assert_eq!(relative(Path::new("/a"), Path::new("/a")), Path::new("."));
+    }
+
+    #[test]
+    fn destinations_are_located() {
+        let source = "[one](a.md) ![two](<my image.png> \"title\")\n[![x](in.png)](out.md)\n\n[ref]: b%20c.md\n";
+        let destinations = destinations(source);
+        let found: Vec<(&str, bool)> = destinations
+            .iter()
+            .map(|destination| {
+                assert_eq!(
+                    source[destination.range.clone()].replace('\\', ""),
+                    destination.url
+                );
+                (destination.url.as_str(), destination.bracketed)
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("a.md", false),
+                ("my image.png", true),
+                ("in.png", false),
+                ("out.md", false),
+                ("b%20c.md", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_destinations() {
+        let source = r"[a](notes\(1\).md)";
+        let found = destinations(source);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].url, "notes(1).md");
+        assert_eq!(&source[found[0].range.clone()], r"notes\(1\).md");
+    }
+
+    #[test]
+    fn safe_links() {
+        for url in [
+            "https://x.org",
+            "HTTP://x.org",
+            "mailto:me@x.org",
+            "notes/a.md",
+            "#top",
+            "a/b:c",
+        ] {
+            assert!(is_safe(url), "{url} was refused");
+        }
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:alert(1)",
+            " java\tscript:x",
+            "vbscript:x",
+            "data:text/html,x",
+            "file:///etc",
+        ] {
+            assert!(!is_safe(url), "{url} was kept");
+        }
+    }
+
+    #[test]
+    fn programs_are_not_opened() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for name in [
+            "report.pdf",
+            "photo.PNG",
+            "setup.exe",
+            "run.SH",
+            "link.lnk",
+            "a.desktop",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        std::fs::create_dir(dir.join("Tool.app")).unwrap();
+        assert!(!is_launchable(&dir.join("report.pdf")));
+        assert!(!is_launchable(&dir.join("photo.PNG")));
+        for name in ["setup.exe", "run.SH", "link.lnk", "a.desktop", "Tool.app"] {
+            assert!(is_launchable(&dir.join(name)), "{name} would be opened");
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let script = dir.join("script");
+            std::fs::write(&script, b"#!/bin/sh\n").unwrap();
+            assert!(!is_launchable(&script));
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+            assert!(is_launchable(&script));
+        }
+    }
+}
@@ -2,5 +2,25 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // `render` converts files headlessly: no window, no single-instance lock.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("render") {
+        attach_console();
+        std::process::exit(mark_it_down_lib::render(&args[1..]));
+    }
     mark_it_down_lib::run()
 }
+
+/// Release builds on Windows have no console of their own, so `render`
+/// writes to the one of the shell it was started from.
+#[cfg(windows)]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // Fails harmlessly when there is no parent console or one is attached.
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
@@ -0,0 +1,1105 @@
+use std::collections::{HashSet, VecDeque};
+use std::ops::Range;
+
+use pulldown_cmark::{
+    html, Alignment, CodeBlockKind, CowStr, Event, LinkType, Options, Parser, Tag, TagEnd,
+    TextMergeWithOffset,
+};
+use serde::Serialize;
+
+/// CommonMark plus the GitHub extensions the preview renders with
+/// remark-gfm, so every output agrees with what the editor shows.
+pub fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_GFM
+}
+
+/// Parses `source`, yielding each event with the byte range of the source
+/// it came from. Bare URLs and email addresses are turned into links.
+pub fn events_with_offsets(source: &str) -> impl Iterator<Item = (Event<'_>, Range<usize>)> {
+    let events = TextMergeWithOffset::new(Parser::new_ext(source, options()).into_offset_iter());
+    Autolinks {
+        source,
+        events,
+        opaque: 0,
+        pending: VecDeque::new(),
+    }
+}
+
+/// The parser every renderer shares: the preview's dialect of markdown,
+/// without source positions.
+pub fn events(source: &str) -> impl Iterator<Item = Event<'_>> {
+    events_with_offsets(source).map(|(event, _)| event)
+}
+
+/// Renders `source` to an HTML fragment.
+pub fn to_html(source: &str) -> String {
+    let mut out = String::with_capacity(source.len() * 3 / 2);
+    html::push_html(&mut out, events(source));
+    out
+}
+
+/// Links bare `http(s)://` and `www.` URLs and email addresses in text,
+/// like GitHub's extended autolinks. pulldown-cmark only links the
+/// `<...>` form.
+struct Autolinks<'a, I> {
+    source: &'a str,
+    events: I,
+    /// Open links, images and code blocks, whose text is left alone.
+    opaque: usize,
+    pending: VecDeque<(Event<'a>, Range<usize>)>,
+}
+
+impl<'a, I> Iterator for Autolinks<'a, I>
+where
+    I: Iterator<Item = (Event<'a>, Range<usize>)>,
+{
+    type Item = (Event<'a>, Range<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(event);
+        }
+        let (event, range) = self.events.next()?;
+        match &event {
+            Event::Start(Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_)) => {
+                self.opaque += 1
+            }
+            Event::End(TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock) => self.opaque -= 1,
+            Event::Text(text) if self.opaque == 0 && find_autolink(text, 0).is_some() => {
+                self.pending = split_autolinks(self.source, text, &range);
+                return self.pending.pop_front();
+            }
+            _ => {}
+        }
+        Some((event, range))
+    }
+}
+
+/// Replaces a text event with text and link events. When the text matches
+/// the source exactly, each piece gets its own range; otherwise (escapes,
+/// entities) they all share the range of the whole text.
+fn split_autolinks<'a>(
+    source: &str,
+    text: &str,
+    range: &Range<usize>,
+) -> VecDeque<(Event<'a>, Range<usize>)> {
+    let exact = source.get(range.clone()) == Some(text);
+    let piece = |start: usize, end: usize| {
+        if exact {
+            range.start + start..range.start + end
+        } else {
+            range.clone()
+        }
+    };
+    let mut out = VecDeque::new();
+    let mut last = 0;
+    while let Some((start, end, link_type)) = find_autolink(text, last) {
+        if start > last {
+            out.push_back((
+                Event::Text(text[last..start].to_string().into()),
+                piece(last, start),
+            ));
+        }
+        let label = &text[start..end];
+        let dest_url = match link_type {
+            // The HTML writer adds `mailto:` to email links itself.
+            LinkType::Email => label.to_string(),
+            _ if starts_with_ignore_case(label, "www.") => {
+                format!("http://{label}")
+            }
+            _ => label.to_string(),
+        };
+        let tag = Tag::Link {
+            link_type,
+            dest_url: dest_url.into(),
+            title: CowStr::Borrowed(""),
+            id: CowStr::Borrowed(""),
+        };
+        out.push_back((Event::Start(tag), piece(start, end)));
+        out.push_back((Event::Text(label.to_string().into()), piece(start, end)));
+        out.push_back((Event::End(TagEnd::Link), piece(start, end)));
+        last = end;
+    }
+    if last < text.len() {
+        out.push_back((
+            Event::Text(text[last..].to_string().into()),
+            piece(last, text.len()),
+        ));
+    }
+    out
+}
+
+/// Finds the first autolink in `text` at or after `from`, returning its
+/// byte range and whether it is a URL or an email address.
+fn find_autolink(text: &str, from: usize) -> Option<(usize, usize, LinkType)> {
+    let url = find_url(text, from).map(|(start, end)| (start, end, LinkType::Autolink));
+    let email = find_email(text, from).map(|(start, end)| (start, end, LinkType::Email));
+    match (url, email) {
+        (Some(url), Some(email)) => Some(if email.0 < url.0 { email } else { url }),
+        (url, email) => url.or(email),
+    }
+}
+
+const URL_PREFIXES: [&str; 3] = ["https://", "http://", "www."];
+
+fn find_url(text: &str, from: usize) -> Option<(usize, usize)> {
+    let mut previous = text[..from].chars().next_back();
+    for (index, ch) in text[from..].char_indices() {
+        let start = from + index;
+        let boundary = previous.is_none_or(|p| p.is_whitespace() || "*_~(".contains(p));
+        previous = Some(ch);
+        if !boundary {
+            continue;
+        }
+        let rest = &text[start..];
+        let Some(prefix) = URL_PREFIXES
+            .iter()
+            .find(|prefix| starts_with_ignore_case(rest, prefix))
+        else {
+            continue;
+        };
+        let length = rest
+            .find(|c: char| c.is_whitespace() || c == '<')
+            .unwrap_or(rest.len());
+        let url = trim_url(&rest[..length]);
+        let domain_start = if prefix.starts_with("http") {
+            prefix.len()
+        } else {
+            0
+        };
+        let domain = url[domain_start..]
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or_default();
+        if is_domain(domain) {
+            return Some((start, start + url.len()));
+        }
+    }
+    None
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.as_bytes()
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix.as_bytes()))
+}
+
+/// Drops trailing punctuation, and closing parentheses that have no
+/// opening one in the URL, so "(see https://a.b/c)." links just the URL.
+fn trim_url(mut url: &str) -> &str {
+    loop {
+        let Some(last) = url.chars().next_back() else {
+            return url;
+        };
+        let unbalanced = last == ')' && url.matches(')').count() > url.matches('(').count();
+        if "?!.,:*_~'\"".contains(last) || unbalanced {
+            url = &url[..url.len() - 1];
+        } else {
+            return url;
+        }
+    }
+}
+
+/// A domain has at least two dot-separated parts of letters, digits,
+/// hyphens and underscores, with no underscores in the last two.
+fn is_domain(domain: &str) -> bool {
+    let parts: Vec<&str> = domain.split('.').collect();
+    parts.len() >= 2
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        })
+        && parts[parts.len() - 2..]
+            .iter()
+            .all(|part| !part.contains('_'))
+}
+
+fn find_email(text: &str, from: usize) -> Option<(usize, usize)> {
+    let is_local = |c: char| c.is_ascii_alphanumeric() || ".+-_".contains(c);
+    let is_host = |c: char| c.is_ascii_alphanumeric() || ".-_".contains(c);
+    for (index, _) in text[from..].match_indices('@') {
+        let at = from + index;
+        let start = text[from..at]
+            .char_indices()
+            .rev()
+            .take_while(|(_, c)| is_local(*c))
+            .last()
+            .map_or(at, |(i, _)| from + i);
+        let host_length = text[at + 1..]
+            .find(|c: char| !is_host(c))
+            .unwrap_or(text.len() - at - 1);
+        let host = text[at + 1..at + 1 + host_length].trim_end_matches('.');
+        let valid_end = host.ends_with(|c: char| c.is_ascii_alphanumeric());
+        if start < at && valid_end && host.contains('.') {
+            return Some((start, at + 1 + host.len()));
+        }
+    }
+    None
+}
+
+/// The text of the first heading, used as the title of exported documents.
+pub fn title(source: &str) -> Option<String> {
+    let mut title: Option<String> = None;
+    for event in events(source) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => title = Some(String::new()),
+            Event::End(TagEnd::Heading(_)) => break,
+            Event::Text(text) | Event::Code(text) => {
+                if let Some(title) = &mut title {
+                    title.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    title.filter(|title| !title.trim().is_empty())
+}
+
+/// Where a link goes. Email autolinks have no `mailto:` in their
+/// destination, which pulldown-cmark leaves to its HTML writer to add.
+pub fn link_target(link_type: LinkType, dest_url: &str) -> String {
+    match link_type {
+        LinkType::Email => format!("mailto:{dest_url}"),
+        _ => dest_url.to_string(),
+    }
+}
+
+/// Splits a `---` delimited front matter block off `source`, returning its
+/// fields with lowercase keys. Only the simple `key: value` and list forms
+/// documents use for metadata are read; anything else in the block is
+/// ignored.
+pub fn front_matter(source: &str) -> (Vec<(String, Vec<String>)>, &str) {
+    let Some(rest) = source
+        .strip_prefix("---\n")
+        .or_else(|| source.strip_prefix("---\r\n"))
+    else {
+        return (Vec::new(), source);
+    };
+    let mut offset = 0;
+    let mut end = None;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            end = Some(offset + line.len());
+            break;
+        }
+        offset += line.len();
+    }
+    let Some(end) = end else {
+        return (Vec::new(), source);
+    };
+
+    let mut fields: Vec<(String, Vec<String>)> = Vec::new();
+    for line in rest[..offset].lines() {
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        if let Some(item) = line.trim_start().strip_prefix("- ") {
+            if line.starts_with(char::is_whitespace) || line.starts_with('-') {
+                if let Some((_, values)) = fields.last_mut() {
+                    values.push(unquote(item));
+                }
+            }
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(list) => list
+                .split(',')
+                .map(unquote)
+                .filter(|v| !v.is_empty())
+                .collect(),
+            None if value.is_empty() => Vec::new(),
+            None => vec![unquote(value)],
+        };
+        fields.push((key.trim().to_ascii_lowercase(), values));
+    }
+    (fields, &rest[end..])
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|rest| rest.strip_suffix(quote))
+        {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// The id the preview gives a heading with this text: lowercased, without
+/// punctuation, and with runs of whitespace turned into hyphens.
+pub fn slug(text: &str) -> String {
+    text.to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-' || c.is_whitespace())
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Hands out the ids of a document's headings: the slug of each, numbered
+/// when it repeats, or `section-N` for a heading without one.
+#[derive(Default)]
+pub struct Slugs(HashSet<String>);
+
+impl Slugs {
+    pub fn next(&mut self, text: &str) -> String {
+        let slug = slug(text);
+        let id = if slug.is_empty() {
+            (1..)
+                .map(|n| format!("section-{n}"))
+                .find(|id| !self.0.contains(id))
+        } else {
+            std::iter::once(slug.clone())
+                .chain((1..).map(|n| format!("{slug}-{n}")))
+                .find(|id| !self.0.contains(id))
+        }
+        .unwrap();
+        self.0.insert(id.clone());
+        id
+    }
+}
+
+/// A heading in the outline of a document.
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentHeading {
+    pub level: u8,
+    pub text: String,
+    /// The id the preview and the exports give it.
+    pub id: String,
+    /// 1-based line it starts on.
+    pub line: usize,
+}
+
+/// The headings of `source` after its front matter, in order. Every outline
+/// (the preview's table of contents, the EPUB nav, PDF bookmarks) is built
+/// from these same rules.
+pub fn headings(source: &str) -> Vec<DocumentHeading> {
+    let (_, body) = front_matter(source);
+    let offset = source.len() - body.len();
+    let mut slugs = Slugs::default();
+    let mut headings = Vec::new();
+    let mut current: Option<(u8, String, usize)> = None;
+    for (event, range) in events_with_offsets(body) {
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                let line = source[..offset + range.start].matches('\n').count() + 1;
+                current = Some((level as u8, String::new(), line));
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, heading, _)) = &mut current {
+                    heading.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((level, text, line)) = current.take() {
+                    headings.push(DocumentHeading {
+                        level,
+                        id: slugs.next(&text),
+                        text,
+                        line,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    headings
+}
+
+/// Renders `source` as plain text: markup is dropped, blocks are separated
+/// by blank lines and list items keep a marker.
+pub fn to_text(source: &str) -> String {
+    let mut out = String::new();
+    // Next number of each open list; `None` for bullet lists.
+    let mut lists: Vec<Option<u64>> = Vec::new();
+    for event in events(source) {
+        match event {
+            Event::Start(Tag::List(start)) => {
+                if lists.is_empty() {
+                    end_block(&mut out);
+                } else {
+                    end_line(&mut out);
+                }
+                lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                lists.pop();
+                if lists.is_empty() {
+                    end_block(&mut out);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                end_line(&mut out);
+                out.push_str(&"  ".repeat(lists.len().saturating_sub(1)));
+                match lists.last_mut() {
+                    Some(Some(number)) => {
+                        out.push_str(&format!("{number}. "));
+                        *number += 1;
+                    }
+                    _ => out.push_str("- "),
+                }
+            }
+            Event::Start(Tag::TableCell) if !out.is_empty() && !out.ends_with('\n') => {
+                out.push('\t');
+            }
+            Event::End(TagEnd::TableHead | TagEnd::TableRow) => out.push('\n'),
+            Event::End(
+                TagEnd::Paragraph
+                | TagEnd::Heading(_)
+                | TagEnd::CodeBlock
+                | TagEnd::Table
+                | TagEnd::FootnoteDefinition
+                | TagEnd::HtmlBlock,
+            ) if lists.is_empty() => end_block(&mut out),
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                out.push_str(&format!("[^{label}]: "));
+            }
+            Event::Text(text) | Event::Code(text) => out.push_str(&text),
+            Event::FootnoteReference(label) => out.push_str(&format!("[^{label}]")),
+            Event::TaskListMarker(done) => out.push_str(if done { "[x] " } else { "[ ] " }),
+            Event::SoftBreak => out.push(' '),
+            Event::HardBreak => out.push('\n'),
+            Event::Rule => end_block(&mut out),
+            _ => {}
+        }
+    }
+    let mut out = out.trim_end().to_string();
+    out.push('\n');
+    out
+}
+
+fn end_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn end_block(out: &mut String) {
+    end_line(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+/// A point in the source, in the form remark uses: 1-based line and column,
+/// and an offset in UTF-16 code units like a textarea's selection.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Point {
+    pub line: usize,
+    pub column: usize,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Position {
+    pub start: Point,
+    pub end: Point,
+}
+
+/// Converts byte offsets into `Point`s.
+struct Positions<'a> {
+    source: &'a str,
+    /// Byte offset and UTF-16 offset of the start of each line.
+    lines: Vec<(usize, usize)>,
+}
+
+impl<'a> Positions<'a> {
+    fn new(source: &'a str) -> Self {
+        let mut lines = vec![(0, 0)];
+        let mut utf16 = 0;
+        for (index, ch) in source.char_indices() {
+            utf16 += ch.len_utf16();
+            if ch == '\n' {
+                lines.push((index + 1, utf16));
+            }
+        }
+        Self { source, lines }
+    }
+
+    fn point(&self, offset: usize) -> Point {
+        let line = self.lines.partition_point(|(start, _)| *start <= offset) - 1;
+        let (line_start, line_utf16) = self.lines[line];
+        let column = self
+            .source
+            .get(line_start..offset)
+            .map_or(0, |text| text.encode_utf16().count());
+        Point {
+            line: line + 1,
+            column: column + 1,
+            offset: line_utf16 + column,
+        }
+    }
+
+    fn position(&self, range: &Range<usize>) -> Position {
+        Position {
+            start: self.point(range.start),
+            end: self.point(range.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+impl From<Alignment> for Align {
+    fn from(alignment: Alignment) -> Self {
+        match alignment {
+            Alignment::None => Align::None,
+            Alignment::Left => Align::Left,
+            Alignment::Center => Align::Center,
+            Alignment::Right => Align::Right,
+        }
+    }
+}
+
+/// The kinds of syntax tree node, named after their mdast counterparts so
+/// the frontend can treat both trees alike.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum NodeKind {
+    Root,
+    Paragraph,
+    Heading {
+        depth: u8,
+        id: Option<String>,
+    },
+    Blockquote,
+    Code {
+        lang: Option<String>,
+        value: String,
+    },
+    Html {
+        value: String,
+    },
+    List {
+        ordered: bool,
+        start: Option<u64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    ListItem {
+        checked: Option<bool>,
+    },
+    FootnoteDefinition {
+        label: String,
+    },
+    Table {
+        align: Vec<Align>,
+    },
+    /// `head` is set on the header row.
+    TableRow {
+        head: bool,
+    },
+    TableCell,
+    Emphasis,
+    Strong,
+    Delete,
+    Link {
+        url: String,
+        title: String,
+    },
+    Image {
+        url: String,
+        title: String,
+    },
+    Text {
+        value: String,
+    },
+    InlineCode {
+        value: String,
+    },
+    FootnoteReference {
+        label: String,
+    },
+    SoftBreak,
+    Break,
+    ThematicBreak,
+}
+
+impl NodeKind {
+    fn from_tag(tag: &Tag) -> Option<Self> {
+        Some(match tag {
+            Tag::Paragraph => NodeKind::Paragraph,
+            Tag::Heading { level, id, .. } => NodeKind::Heading {
+                depth: *level as u8,
+                id: id.as_ref().map(|id| id.to_string()),
+            },
+            Tag::BlockQuote(_) => NodeKind::Blockquote,
+            Tag::CodeBlock(kind) => NodeKind::Code {
+                lang: match kind {
+                    CodeBlockKind::Fenced(info) => {
+                        info.split_whitespace().next().map(str::to_string)
+                    }
+                    CodeBlockKind::Indented => None,
+                },
+                value: String::new(),
+            },
+            Tag::HtmlBlock => NodeKind::Html {
+                value: String::new(),
+            },
+            Tag::List(start) => NodeKind::List {
+                ordered: start.is_some(),
+                start: *start,
+            },
+            Tag::Item => NodeKind::ListItem { checked: None },
+            Tag::FootnoteDefinition(label) => NodeKind::FootnoteDefinition {
+                label: label.to_string(),
+            },
+            Tag::Table(alignments) => NodeKind::Table {
+                align: alignments.iter().map(|a| Align::from(*a)).collect(),
+            },
+            Tag::TableHead => NodeKind::TableRow { head: true },
+            Tag::TableRow => NodeKind::TableRow { head: false },
+            Tag::TableCell => NodeKind::TableCell,
+            Tag::Emphasis => NodeKind::Emphasis,
+            Tag::Strong => NodeKind::Strong,
+            Tag::Strikethrough => NodeKind::Delete,
+            Tag::Link {
+                link_type,
+                dest_url,
+                title,
+                ..
+            } => NodeKind::Link {
+                url: link_target(*link_type, dest_url),
+                title: title.to_string(),
+            },
+            Tag::Image {
+                dest_url, title, ..
+            } => NodeKind::Image {
+                url: dest_url.to_string(),
+                title: title.to_string(),
+            },
+            // Not enabled by `options`.
+            _ => return None,
+        })
+    }
+
+    fn is_block(&self) -> bool {
+        matches!(
+            self,
+            NodeKind::Paragraph
+                | NodeKind::Heading { .. }
+                | NodeKind::Blockquote
+                | NodeKind::Code { .. }
+                | NodeKind::Html { .. }
+                | NodeKind::List { .. }
+                | NodeKind::ListItem { .. }
+                | NodeKind::FootnoteDefinition { .. }
+                | NodeKind::Table { .. }
+                | NodeKind::ThematicBreak
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    #[serde(flatten)]
+    pub kind: NodeKind,
+    pub position: Position,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+}
+
+/// Parses `source` into a syntax tree whose nodes carry their source
+/// positions.
+pub fn parse(source: &str) -> Node {
+    let positions = Positions::new(source);
+    let leaf = |kind, range: &Range<usize>| Node {
+        kind,
+        position: positions.position(range),
+        children: Vec::new(),
+    };
+    let mut stack = vec![leaf(NodeKind::Root, &(0..source.len()))];
+    // Tags without a node, whose contents go to the enclosing node.
+    let mut skipped = Vec::new();
+
+    for (event, range) in events_with_offsets(source) {
+        let parent = stack.last_mut().unwrap();
+        let child = match event {
+            Event::Start(tag) => {
+                match NodeKind::from_tag(&tag) {
+                    Some(kind) => stack.push(leaf(kind, &range)),
+                    None => skipped.push(tag.to_end()),
+                }
+                continue;
+            }
+            Event::End(end) => {
+                if skipped.last() == Some(&end) {
+                    skipped.pop();
+                } else if stack.len() > 1 {
+                    let node = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(node);
+                }
+                continue;
+            }
+            // Code and HTML blocks keep their contents as a value.
+            Event::Text(text) | Event::Html(text)
+                if matches!(parent.kind, NodeKind::Code { .. } | NodeKind::Html { .. }) =>
+            {
+                if let NodeKind::Code { value, .. } | NodeKind::Html { value } = &mut parent.kind {
+                    value.push_str(&text);
+                }
+                continue;
+            }
+            Event::TaskListMarker(done) => {
+                if let NodeKind::ListItem { checked } = &mut parent.kind {
+                    *checked = Some(done);
+                }
+                continue;
+            }
+            Event::Text(text) => NodeKind::Text {
+                value: text.to_string(),
+            },
+            Event::Code(text) => NodeKind::InlineCode {
+                value: text.to_string(),
+            },
+            Event::Html(html) | Event::InlineHtml(html) => NodeKind::Html {
+                value: html.to_string(),
+            },
+            Event::FootnoteReference(label) => NodeKind::FootnoteReference {
+                label: label.to_string(),
+            },
+            Event::SoftBreak => NodeKind::SoftBreak,
+            Event::HardBreak => NodeKind::Break,
+            Event::Rule => NodeKind::ThematicBreak,
+            // Math is not enabled by `options`.
+            Event::InlineMath(_) | Event::DisplayMath(_) => continue,
+        };
+        parent.children.push(leaf(child, &range));
+    }
+
+    while stack.len() > 1 {
+        let node = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(node);
+    }
+    stack.pop().unwrap()
+}
+
+/// A block-level element and where it is in the source. Blocks are listed
+/// in document order, parents before their children, which is also the
+/// order their elements appear in the rendered HTML.
+#[derive(Debug, Clone, Serialize)]
+pub struct SourceBlock {
+    #[serde(flatten)]
+    pub kind: NodeKind,
+    pub position: Position,
+    /// How many blocks this one is nested in.
+    pub nesting: usize,
+}
+
+pub fn source_map(source: &str) -> Vec<SourceBlock> {
+    fn walk(node: &Node, nesting: usize, out: &mut Vec<SourceBlock>) {
+        for child in &node.children {
+            if child.kind.is_block() {
+                out.push(SourceBlock {
+                    kind: child.kind.clone(),
+                    position: child.position,
+                    nesting,
+                });
+                walk(child, nesting + 1, out);
+            }
+        }
+    }
+    let mut out = Vec::new();
+    walk(&parse(source), 0, &mut out);
+    out
+}
+
+/// The syntax tree of `source`, for features that need to understand the
+/// document (table of contents, linting, search).
+#[tauri::command]
+pub async fn parse_markdown(source: String) -> Node {
+    parse(&source)
+}
+
+/// Renders `source` to HTML with the same parser as the exports.
+#[tauri::command]
+pub async fn render_markdown(source: String) -> String {
+    to_html(&source)
+}
+
+/// The outline of `source`, for the table of contents and the ids of the
+/// preview's headings.
+#[tauri::command]
+pub async fn document_headings(source: String) -> Vec<DocumentHeading> {
+    headings(&source)
+}
+
+/// The position of every block in `source`, to map between the editor and
+/// the rendered document.
+#[tauri::command]
+pub async fn markdown_source_map(source: String) -> Vec<SourceBlock> {
+    source_map(&source)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Getting Started"), "getting-started");
+        assert_eq!(slug("  What's new?  "), "whats-new");
+        assert_eq!(slug("snake_case and-dashes"), "snake_case-and-dashes");
+        assert_eq!(slug("A -- B"), "a----b");
+        assert_eq!(slug("!!!"), "");
+    }
+
+    #[test]
+    fn outline() {
+        let source = "---\ntitle: Book\n---\n# Intro\n\n```\n# not a heading\n```\n\nSetext `code`\n---\n\n### Intro\n#\n";
+        let headings = headings(source);
+        let found: Vec<(u8, &str, &str, usize)> = headings
+            .iter()
+            .map(|h| (h.level, h.text.as_str(), h.id.as_str(), h.line))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (1, "Intro", "intro", 4),
+                (2, "Setext code", "setext-code", 10),
+                (3, "Intro", "intro-1", 13),
+                (1, "", "section-1", 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn unique_slugs() {
+        let mut slugs = Slugs::default();
+        let ids: Vec<String> = ["Intro", "Intro", "", "Intro-1", "?", "Intro"]
+            .iter()
+            .map(|text| slugs.next(text))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                "intro",
+                "intro-1",
+                "section-1",
+                "intro-1-1",
+                "section-2",
+                "intro-2"
+            ]
+        );
+    }
+
+    /// The links in `source`'s events, as label and destination.
+    fn links(source: &str) -> Vec<(String, String)> {
+        let mut links = Vec::new();
+        let mut label = None;
+        for event in events(source) {
+            match event {
+                Event::Start(Tag::Link {
+                    link_type,
+                    dest_url,
+                    ..
+                }) => label = Some((String::new(), link_target(link_type, &dest_url))),
+                Event::Text(text) | Event::Code(text) => {
+                    if let Some((label, _)) = &mut label {
+                        label.push_str(&text);
+                    }
+                }
+                Event::End(TagEnd::Link) => links.extend(label.take()),
+                _ => {}
+            }
+        }
+        links
+    }
+
+    fn link(label: &str, url: &str) -> (String, String) {
+        (label.to_string(), url.to_string())
+    }
+
+    #[test]
+    fn autolinks_drop_trailing_punctuation() {
+        assert_eq!(
+            links("See https://example.com/a. Or https://example.com/b?, or *https://x.org*!"),
+            [
+                link("https://example.com/a", "https://example.com/a"),
+                link("https://example.com/b", "https://example.com/b"),
+                link("https://x.org", "https://x.org"),
+            ]
+        );
+        assert_eq!(
+            links("https://example.com/?q=1&r=2#top"),
+            [link(
+                "https://example.com/?q=1&r=2#top",
+                "https://example.com/?q=1&r=2#top"
+            )]
+        );
+    }
+
+    #[test]
+    fn autolinks_balance_parentheses() {
+        assert_eq!(
+            links("(see https://en.wikipedia.org/wiki/Rust_(language))"),
+            [link(
+                "https://en.wikipedia.org/wiki/Rust_(language)",
+                "https://en.wikipedia.org/wiki/Rust_(language)"
+            )]
+        );
+        assert_eq!(
+            links("(https://example.com/a)."),
+            [link("https://example.com/a", "https://example.com/a")]
+        );
+    }
+
+    #[test]
+    fn www_autolinks() {
+        assert_eq!(
+            links("Visit www.example.com/docs, or WWW.Example.org."),
+            [
+                link("www.example.com/docs", "http://www.example.com/docs"),
+                link("WWW.Example.org", "http://WWW.Example.org"),
+            ]
+        );
+        // Not a domain, or not at a word boundary.
+        assert!(links("www. xhttps://example.com www.a_b.com").is_empty());
+    }
+
+    #[test]
+    fn email_autolinks() {
+        assert_eq!(
+            links("Mail first.last+tag@mail.example.com. Or <me@example.org>"),
+            [
+                link(
+                    "first.last+tag@mail.example.com",
+                    "mailto:first.last+tag@mail.example.com"
+                ),
+                link("me@example.org", "mailto:me@example.org"),
+            ]
+        );
+        assert!(links("user@localhost and @example.com").is_empty());
+        assert_eq!(
+            to_html("a@b.co"),
+            "<p><a href=\"mailto:a@b.co\">a@b.co</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn no_autolinks_in_code_or_links() {
+        assert_eq!(
+            links("`https://a.example.com` [https://b.example.com](https://c.example.com)"),
+            [link("https://b.example.com", "https://c.example.com")]
+        );
+        assert!(links("```\nhttps://example.com\n```\n\n    www.example.com\n").is_empty());
+        assert!(links("![www.example.com](pic.png)").is_empty());
+    }
+
+    #[test]
+    fn autolink_ranges() {
+        let source = "go https://example.com now";
+        let ranges: Vec<(String, Range<usize>)> = events_with_offsets(source)
+            .filter_map(|(event, range)| match event {
+                Event::Text(text) => Some((text.to_string(), range)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                ("go ".to_string(), 0..3),
+                ("https://example.com".to_string(), 3..22),
+                (" now".to_string(), 22..26),
+            ]
+        );
+        // Text with an entity doesn't match the source, so the pieces share
+        // its range.
+        let source = "&amp; www.example.com";
+        let ranges: Vec<Range<usize>> = events_with_offsets(source)
+            .filter_map(|(event, range)| matches!(event, Event::Text(_)).then_some(range))
+            .collect();
+        assert_eq!(ranges, [0..21, 0..21]);
+    }
+
+    #[test]
+    fn syntax_tree() {
+        let tree = parse("# Title\n\n- [x] é😀 **b**\n\n```rs\nlet a;\n```\n");
+        let value = serde_json::to_value(&tree).unwrap();
+        assert_eq!(value["type"], "root");
+        let children = value["children"].as_array().unwrap();
+        let types: Vec<&str> = children
+            .iter()
+            .map(|child| child["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, ["heading", "list", "code"]);
+        assert_eq!(children[0]["depth"], 1);
+        assert_eq!(children[0]["children"][0]["value"], "Title");
+        assert_eq!(children[2]["lang"], "rs");
+        assert_eq!(children[2]["value"], "let a;\n");
+        assert_eq!(children[2]["position"]["start"]["line"], 5);
+
+        let item = &children[1]["children"][0];
+        assert_eq!(item["type"], "listItem");
+        assert_eq!(item["checked"], true);
+        // Items of a tight list hold their inline content directly.
+        let strong = &item["children"][1];
+        assert_eq!(strong["type"], "strong");
+        // Columns and offsets count UTF-16 code units.
+        assert_eq!(
+            strong["position"]["start"],
+            serde_json::json!({ "line": 3, "column": 11, "offset": 19 })
+        );
+    }
+
+    #[test]
+    fn source_blocks() {
+        let blocks = source_map("> quote\n\n- a\n  - b\n\n| x |\n| - |\n| 1 |\n\n---\n");
+        let found: Vec<(String, usize, usize)> = blocks
+            .iter()
+            .map(|block| {
+                let kind = serde_json::to_value(&block.kind).unwrap()["type"]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
+                (kind, block.nesting, block.position.start.line)
+            })
+            .collect();
+        let expected = [
+            ("blockquote", 0, 1),
+            ("paragraph", 1, 1),
+            ("list", 0, 3),
+            ("listItem", 1, 3),
+            ("list", 2, 4),
+            ("listItem", 3, 4),
+            ("table", 0, 6),
+            ("thematicBreak", 0, 10),
+        ];
+        assert_eq!(
+            found,
+            expected.map(|(kind, nesting, line)| (kind.to_string(), nesting, line))
+        );
+    }
+
+    #[test]
+    fn plain_text() {
+        let source = "# Title\n\nSome *em* and `code`\nwrapped.[^1]\n\n3. three\n4. four\n   - inner\n   - [ ] task\n\n| a | b |\n| - | - |\n| 1 | 2 |\n\n> quoted\n\n[^1]: Note.\n";
+        assert_eq!(
+            to_text(source),
+            "Title\n\nSome em and code wrapped.[^1]\n\n3. three\n4. four\n  - inner\n  - [ ] task\n\na\tb\n1\t2\n\nquoted\n\n[^1]: Note.\n"
+        );
+    }
+}
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use tauri::menu::{
+    CheckMenuItem, CheckMenuItemBuilder, Menu, MenuBuilder, MenuEvent, MenuItem, MenuItemBuilder,
+    PredefinedMenuItem, Submenu, SubmenuBuilder,
+};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewWindow, Wry};
+
+use crate::recent::{self, RecentFiles};
+use crate::registry;
+use crate::reload::ViewMode;
+use crate::settings::{self, Settings, Theme};
+
+/// Sent to the focused window with the id of the chosen menu item, for
+/// actions the frontend carries out.
+pub const MENU_ACTION_EVENT: &str = "menu-action";
+
+const OPEN_RECENT_PREFIX: &str = "open-recent:";
+const CLEAR_RECENT_ID: &str = "clear-recent";
+const NEW_WINDOW_ID: &str = "new-window";
+const SYNC_SCROLL_ID: &str = "sync-scroll";
+const SAVE_ID: &str = "save";
+const REVERT_ID: &str = "revert";
+
+const VIEW_MODES: [(ViewMode, &str, &str, &str); 3] = [
+    (ViewMode::Reading, "view-reading", "Reading", "CmdOrCtrl+1"),
+    (ViewMode::Editing, "view-editing", "Editing", "CmdOrCtrl+2"),
+    (ViewMode::Split, "view-split", "Split", "CmdOrCtrl+3"),
+];
+
+const THEMES: [(Theme, &str, &str); 3] = [
+    (Theme::Light, "theme-light", "Light"),
+    (Theme::Dark, "theme-dark", "Dark"),
+    (Theme::Dim, "theme-dim", "Dim"),
+];
+
+/// Items of the Format menu, only enabled while editing.
+const FORMATS: [(&str, &str, Option<&str>); 9] = [
+    ("format-bold", "Bold", Some("CmdOrCtrl+B")),
+    ("format-italic", "Italic", Some("CmdOrCtrl+I")),
+    ("format-heading-1", "Heading 1", Some("CmdOrCtrl+Alt+1")),
+    ("format-heading-2", "Heading 2", Some("CmdOrCtrl+Alt+2")),
+    ("format-heading-3", "Heading 3", Some("CmdOrCtrl+Alt+3")),
+    ("format-bullet-list", "Bullet List", None),
+    ("format-code", "Code Block", None),
+    ("format-link", "Link", Some("CmdOrCtrl+K")),
+    ("format-table", "Table", None),
+];
+
+/// Format items that wrap the selection, so they also need text selected.
+const WRAPPING_FORMATS: [&str; 3] = ["format-bold", "format-italic", "format-link"];
+
+/// What the menu should reflect about a window, reported by its frontend.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MenuState {
+    pub view_mode: ViewMode,
+    /// The document has changes that aren't saved.
+    pub dirty: bool,
+    /// The document has never been saved to a file.
+    pub untitled: bool,
+    /// The document has no text to export.
+    pub empty: bool,
+    /// Text is selected in the editor.
+    pub selection: bool,
+}
+
+/// Handles to the menu items whose state changes while the app runs. The
+/// menu is shared by all windows, so it shows the state of the focused one.
+pub struct AppMenu {
+    recent: Submenu<Wry>,
+    save: MenuItem<Wry>,
+    revert: MenuItem<Wry>,
+    export: Submenu<Wry>,
+    view_modes: Vec<(ViewMode, CheckMenuItem<Wry>)>,
+    themes: Vec<(Theme, CheckMenuItem<Wry>)>,
+    sync_scroll: CheckMenuItem<Wry>,
+    formats: Vec<(bool, MenuItem<Wry>)>,
+    windows: Mutex<HashMap<String, MenuState>>,
+}
+
+impl AppMenu {
+    /// Forgets a window that has been closed.
+    pub fn remove_window(&self, label: &str) {
+        self.windows.lock().unwrap().remove(label);
+    }
+
+    fn show_window_state(&self, state: MenuState) {
+        for (mode, item) in &self.view_modes {
+            let _ = item.set_checked(*mode == state.view_mode);
+        }
+        // Untitled documents can always be saved, so Ctrl+S asks where.
+        let _ = self.save.set_enabled(state.dirty || state.untitled);
+        let _ = self.revert.set_enabled(state.dirty && !state.untitled);
+        let _ = self.export.set_enabled(!state.empty);
+        let editing = state.view_mode != ViewMode::Reading;
+        for (wraps, item) in &self.formats {
+            let _ = item.set_enabled(editing && (state.selection || !wraps));
+        }
+    }
+
+    fn show_settings(&self, settings: &Settings) {
+        for (theme, item) in &self.themes {
+            let _ = item.set_checked(*theme == settings.theme);
+        }
+        let _ = self.sync_scroll.set_checked(settings.sync_scroll);
+    }
+}
+
+fn item(
+    app: &AppHandle,
+    id: &str,
+    title: &str,
+    accelerator: Option<&str>,
+) -> tauri::Result<MenuItem<Wry>> {
+    let builder = MenuItemBuilder::with_id(id, title);
+    match accelerator {
+        Some(accelerator) => builder.accelerator(accelerator),
+        None => builder,
+    }
+    .build(app)
+}
+
+/// Builds the menu bar and sets it for the whole app.
+pub fn init(app: &AppHandle) -> tauri::Result<()> {
+    let settings = app.state::<settings::SettingsStore>().get();
+
+    let recent = SubmenuBuilder::new(app, "Open Recent").build()?;
+    let save = item(app, SAVE_ID, "Save", Some("CmdOrCtrl+S"))?;
+    let revert = item(app, REVERT_ID, "Revert to Saved", None)?;
+    revert.set_enabled(false)?;
+    let export = SubmenuBuilder::new(app, "Export")
+        .item(&item(app, "export-html", "HTML…", None)?)
+        .item(&item(
+            app,
+            "export-html-folder",
+            "HTML with Assets Folder…",
+            None,
+        )?)
+        .item(&item(app, "export-pdf", "PDF…", None)?)
+        .item(&item(app, "export-docx", "Word Document…", None)?)
+        .item(&item(app, "export-epub", "EPUB…", None)?)
+        .build()?;
+    let file = SubmenuBuilder::new(app, "File")
+        .item(&item(app, "new", "New", Some("CmdOrCtrl+N"))?)
+        .item(&item(
+            app,
+            NEW_WINDOW_ID,
+            "New Window",
+            Some("CmdOrCtrl+Shift+N"),
+        )?)
+        .item(&item(app, "open", "Open…", Some("CmdOrCtrl+O"))?)
+        .item(&item(
+            app,
+            "open-folder",
+            "Open Folder…",
+            Some("CmdOrCtrl+Shift+O"),
+        )?)
+        .item(&recent)
+        .item(&item(app, "import", "Import…", None)?)
+        .separator()
+        .item(&save)
+        .item(&item(
+            app,
+            "save-as",
+            "Save As…",
+            Some("CmdOrCtrl+Shift+S"),
+        )?)
+        .item(&revert)
+        .item(&export)
+        .separator()
+        .item(&item(
+            app,
+            "move-to-new-window",
+            "Move to New Window",
+            Some("CmdOrCtrl+Shift+M"),
+        )?)
+        .separator()
+        .close_window();
+    #[cfg(not(target_os = "macos"))]
+    let file = file.quit();
+    let file = file.build()?;
+
+    let edit = SubmenuBuilder::new(app, "Edit")
+        .undo()
+        .redo()
+        .separator()
+        .cut()
+        .copy()
+        .paste()
+        .item(&item(
+            app,
+            "paste-plain",
+            "Paste as Plain Text",
+            Some("CmdOrCtrl+Shift+V"),
+        )?)
+        .select_all()
+        .separator()
+        .item(&item(app, "find", "Find…", Some("CmdOrCtrl+F"))?);
+    // macOS keeps Settings in the application menu.
+    #[cfg(not(target_os = "macos"))]
+    let edit = edit
+        .separator()
+        .item(&item(app, "settings", "Settings…", Some("CmdOrCtrl+,"))?);
+    let edit = edit.build()?;
+
+    let mut view = SubmenuBuilder::new(app, "View");
+    let mut view_modes = Vec::new();
+    for (mode, id, title, accelerator) in VIEW_MODES {
+        let check = CheckMenuItemBuilder::with_id(id, title)
+            .accelerator(accelerator)
+            .checked(mode == ViewMode::default())
+            .build(app)?;
+        view = view.item(&check);
+        view_modes.push((mode, check));
+    }
+    let sync_scroll = CheckMenuItemBuilder::with_id(SYNC_SCROLL_ID, "Sync Scroll")
+        .checked(settings.sync_scroll)
+        .build(app)?;
+    let mut theme = SubmenuBuilder::new(app, "Theme");
+    let mut themes = Vec::new();
+    for (value, id, title) in THEMES {
+        let check = CheckMenuItemBuilder::with_id(id, title)
+            .checked(value == settings.theme)
+            .build(app)?;
+        theme = theme.item(&check);
+        themes.push((value, check));
+    }
+    let view = view
+        .item(&item(
+            app,
+            "toggle-mode",
+            "Toggle Reading",
+            Some("CmdOrCtrl+Enter"),
+        )?)
+        .separator()
+        .item(&sync_scroll)
+        .item(&item(app, "toggle-toc", "Table of Contents", None)?)
+        .item(&theme.build()?)
+        .separator()
+        .item(&item(app, "go-back", "Back", Some("CmdOrCtrl+["))?)
+        .item(&item(app, "go-forward", "Forward", Some("CmdOrCtrl+]"))?)
+        .separator()
+        .item(&item(app, "reload", "Reload", Some("CmdOrCtrl+R"))?)
+        .fullscreen()
+        .build()?;
+
+    let mut format = SubmenuBuilder::new(app, "Format");
+    let mut formats = Vec::new();
+    for (id, title, accelerator) in FORMATS {
+        let entry = item(app, id, title, accelerator)?;
+        entry.set_enabled(false)?;
+        format = format.item(&entry);
+        formats.push((WRAPPING_FORMATS.contains(&id), entry));
+    }
+    let format = format.build()?;
+
+    let window = SubmenuBuilder::new(app, "Window")
+        .minimize()
+        .maximize()
+        .separator()
+        .close_window()
+        .build()?;
+
+    let help = SubmenuBuilder::new(app, "Help").item(&item(
+        app,
+        "markdown-guide",
+        "Markdown Guide",
+        None,
+    )?);
+    #[cfg(not(target_os = "macos"))]
+    let help = help.separator().about(None);
+    let help = help.build()?;
+
+    let menu = MenuBuilder::new(app);
+    #[cfg(target_os = "macos")]
+    let menu = menu.item(&app_menu(app)?);
+    let menu: Menu<Wry> = menu
+        .items(&[&file, &edit, &view, &format, &window, &help])
+        .build()?;
+
+    app.set_menu(menu)?;
+    app.manage(AppMenu {
+        recent,
+        save,
+        revert,
+        export,
+        view_modes,
+        themes,
+        sync_scroll,
+        formats,
+        windows: Mutex::new(HashMap::new()),
+    });
+    refresh_recent(app);
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn app_menu(app: &AppHandle) -> tauri::Result<Submenu<Wry>> {
+    SubmenuBuilder::new(app, app.package_info().name.clone())
+        .about(None)
+        .separator()
+        .item(&item(app, "settings", "Settings…", Some("CmdOrCtrl+,"))?)
+        .separator()
+        .services()
+        .separator()
+        .hide()
+        .hide_others()
+        .separator()
+        .quit()
+        .build()
+}
+
+/// Fills "Open Recent" with the current recent files.
+pub fn refresh_recent(app: &AppHandle) {
+    let Some(menu) = app.try_state::<AppMenu>() else {
+        return;
+    };
+    let _ = fill_recent(app, &menu.recent);
+}
+
+fn fill_recent(app: &AppHandle, submenu: &Submenu<Wry>) -> tauri::Result<()> {
+    for item in submenu.items()? {
+        submenu.remove(&item)?;
+    }
+    let files = app.state::<RecentFiles>().files();
+    for path in &files {
+        let label = path.display().to_string();
+        let item =
+            MenuItemBuilder::with_id(format!("{OPEN_RECENT_PREFIX}{label}"), &label).build(app)?;
+        submenu.append(&item)?;
+    }
+    if !files.is_empty() {
+        submenu.append(&PredefinedMenuItem::separator(app)?)?;
+    }
+    let clear = MenuItemBuilder::with_id(CLEAR_RECENT_ID, "Clear Menu")
+        .enabled(!files.is_empty())
+        .build(app)?;
+    submenu.append(&clear)
+}
+
+/// Checks the theme and sync scroll items after the settings changed.
+pub fn sync_settings(app: &AppHandle, settings: &Settings) {
+    if let Some(menu) = app.try_state::<AppMenu>() {
+        menu.show_settings(settings);
+    }
+}
+
+/// Shows the state of the window that just gained focus.
+pub fn window_focused(app: &AppHandle, label: &str) {
+    let Some(menu) = app.try_state::<AppMenu>() else {
+        return;
+    };
+    let state = menu
+        .windows
+        .lock()
+        .unwrap()
+        .get(label)
+        .copied()
+        .unwrap_or_default();
+    menu.show_window_state(state);
+}
+
+fn focused_window(app: &AppHandle) -> Option<WebviewWindow> {
+    let windows = app.webview_windows();
+    windows
+        .values()
+        .find(|window| window.is_focused().unwrap_or(false))
+        .or_else(|| windows.values().next())
+        .cloned()
+}
+
+/// Handles menu items the backend owns and forwards the rest to the focused
+/// window as `menu-action`.
+pub fn on_menu_event(app: &AppHandle, event: MenuEvent) {
+    let id = event.id().as_ref();
+    if let Some(path) = id.strip_prefix(OPEN_RECENT_PREFIX) {
+        // Opening may create a window, which deadlocks on Windows if done
+        // on the event loop that is running this handler. The same goes for
+        // "New Window" below.
+        let app = app.clone();
+        let path = PathBuf::from(path);
+        tauri::async_runtime::spawn(async move {
+            let _ = recent::open_recent(&app, path);
+        });
+        return;
+    }
+    if let Some((theme, _, _)) = THEMES.iter().find(|(_, theme_id, _)| *theme_id == id) {
+        let _ = settings::update(app, |settings| settings.theme = *theme);
+        return;
+    }
+    match id {
+        CLEAR_RECENT_ID => recent::clear(app),
+        NEW_WINDOW_ID => {
+            let app = app.clone();
+            tauri::async_runtime::spawn(async move {
+                let _ = registry::open_window(&app, Vec::new());
+            });
+        }
+        SYNC_SCROLL_ID => {
+            let _ = settings::update(app, |settings| settings.sync_scroll = !settings.sync_scroll);
+        }
+        _ => {
+            if let Some(window) = focused_window(app) {
+                let _ = app.emit_to(window.label(), MENU_ACTION_EVENT, id);
+            }
+        }
+    }
+}
+
+/// Records the calling window's view mode and document state, and shows
+/// them in the menu if the window has focus.
+#[tauri::command]
+pub fn update_menu_state(window: WebviewWindow, state: MenuState, menu: State<'_, AppMenu>) {
+    menu.windows
+        .lock()
+        .unwrap()
+        .insert(window.label().to_string(), state);
+    if window.is_focused().unwrap_or(false) {
+        menu.show_window_state(state);
+    }
+}
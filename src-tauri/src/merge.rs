@@ -0,0 +1,196 @@
+use similar::{capture_diff_slices, Algorithm, DiffOp};
+
+const OURS_MARKER: &str = "<<<<<<< unsaved changes\n";
+const SEPARATOR: &str = "=======\n";
+const THEIRS_MARKER: &str = ">>>>>>> on disk\n";
+
+pub struct Merged {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// Line-based three-way merge in the style of diff3.
+///
+/// Changes made on only one side are applied cleanly; regions both sides
+/// changed differently are wrapped in conflict markers.
+pub fn three_way(base: &str, ours: &str, theirs: &str) -> Merged {
+    // Every last line is merged with a newline, so that adding a line after
+    // one that had none doesn't count as changing it. The result ends with a
+    // newline if the side that changed that says so.
+    let final_newline = if ends_line(ours) == ends_line(base) {
+        ends_line(theirs)
+    } else {
+        ends_line(ours)
+    };
+    let (base, ours, theirs) = (with_newline(base), with_newline(ours), with_newline(theirs));
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+
+    let ours_at = matching_lines(&base, &ours);
+    let theirs_at = matching_lines(&base, &theirs);
+
+    let mut merged = Merged {
+        text: String::new(),
+        conflicts: 0,
+    };
+    let (mut b, mut o, mut t) = (0, 0, 0);
+
+    while b < base.len() || o < ours.len() || t < theirs.len() {
+        // The next base line both sides kept is where the current chunk ends.
+        let stable = (b..base.len())
+            .find(|&i| ours_at[i].is_some() && theirs_at[i].is_some())
+            .unwrap_or(base.len());
+        let (o_end, t_end) = if stable < base.len() {
+            (ours_at[stable].unwrap(), theirs_at[stable].unwrap())
+        } else {
+            (ours.len(), theirs.len())
+        };
+
+        if stable == b && o_end == o && t_end == t {
+            merged.text.push_str(base[b]);
+            b += 1;
+            o += 1;
+            t += 1;
+            continue;
+        }
+
+        merge_chunk(
+            &mut merged,
+            &base[b..stable],
+            &ours[o..o_end],
+            &theirs[t..t_end],
+        );
+        b = stable;
+        o = o_end;
+        t = t_end;
+    }
+
+    if !final_newline && merged.text.ends_with('\n') {
+        merged.text.pop();
+    }
+    merged
+}
+
+fn ends_line(text: &str) -> bool {
+    text.is_empty() || text.ends_with('\n')
+}
+
+fn with_newline(text: &str) -> String {
+    let mut text = text.to_string();
+    if !ends_line(&text) {
+        text.push('\n');
+    }
+    text
+}
+
+fn merge_chunk(merged: &mut Merged, base: &[&str], ours: &[&str], theirs: &[&str]) {
+    if ours == base || ours == theirs {
+        merged.text.extend(theirs.iter().copied());
+    } else if theirs == base {
+        merged.text.extend(ours.iter().copied());
+    } else {
+        merged.conflicts += 1;
+        merged.text.push_str(OURS_MARKER);
+        merged.text.extend(ours.iter().copied());
+        merged.text.push_str(SEPARATOR);
+        merged.text.extend(theirs.iter().copied());
+        merged.text.push_str(THEIRS_MARKER);
+    }
+}
+
+/// For every line of `base`, the index of the line it was kept as in
+/// `other`, if any.
+fn matching_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut at = vec![None; base.len()];
+    for op in capture_diff_slices(Algorithm::Myers, base, other) {
+        if let DiffOp::Equal {
+            old_index,
+            new_index,
+            len,
+        } = op
+        {
+            for i in 0..len {
+                at[old_index + i] = Some(new_index + i);
+            }
+        }
+    }
+    at
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let merged = three_way(base, ours, theirs);
+        (merged.text, merged.conflicts)
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(merge("a\nb\n", "a\nb\n", "a\nb\n"), ("a\nb\n".into(), 0));
+        assert_eq!(merge("", "", ""), (String::new(), 0));
+    }
+
+    #[test]
+    fn one_sided_edits() {
+        let base = "one\ntwo\nthree\n";
+        assert_eq!(
+            merge(base, "one\n2\nthree\n", base),
+            ("one\n2\nthree\n".into(), 0)
+        );
+        assert_eq!(
+            merge(base, base, "zero\none\ntwo\nthree\n"),
+            ("zero\none\ntwo\nthree\n".into(), 0)
+        );
+        assert_eq!(
+            merge(base, base, "one\nthree\n"),
+            ("one\nthree\n".into(), 0)
+        );
+    }
+
+    #[test]
+    fn edits_on_both_sides_in_different_places() {
+        assert_eq!(
+            merge("a\nb\nc\nd\n", "A\nb\nc\nd\n", "a\nb\nc\nD\n"),
+            ("A\nb\nc\nD\n".into(), 0)
+        );
+    }
+
+    #[test]
+    fn identical_changes_on_both_sides() {
+        assert_eq!(
+            merge("a\nb\nc\n", "a\nB\nc\nd\n", "a\nB\nc\nd\n"),
+            ("a\nB\nc\nd\n".into(), 0)
+        );
+    }
+
+    #[test]
+    fn overlapping_changes_conflict() {
+        assert_eq!(
+            merge("a\nb\nc\n", "a\nmine\nc\n", "a\ntheirs\nc\n"),
+            (
+                "a\n<<<<<<< unsaved changes\nmine\n=======\ntheirs\n>>>>>>> on disk\nc\n".into(),
+                1
+            )
+        );
+        assert_eq!(merge("a\nb\n", "a\nx\n", "a\ny\nb\nz\n").1, 1);
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        // A line added after one without a newline, and an edit elsewhere.
+        assert_eq!(merge("a\nb", "a\nb\nc", "A\nb"), ("A\nb\nc".into(), 0));
+        assert_eq!(merge("a\nb", "a\nb", "a\nb\n"), ("a\nb\n".into(), 0));
+        assert_eq!(merge("a\nb\n", "a\nb", "A\nb\n"), ("A\nb".into(), 0));
+        // Conflict markers still start on their own lines.
+        assert_eq!(
+            merge("a", "b", "c"),
+            (
+                "<<<<<<< unsaved changes\nb\n=======\nc\n>>>>>>> on disk".into(),
+                1
+            )
+        );
+    }
+}
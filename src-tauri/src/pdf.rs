@@ -0,0 +1,1406 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use pdf_writer::types::{ActionType, AnnotationType, PageMode};
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str, TextStr};
+use pulldown_cmark::{Event, HeadingLevel, Tag, TagEnd};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::export::{resolve_local, ImageAccess};
+use crate::fonts::{Font, Fonts};
+use crate::markdown;
+use crate::settings::FontFamily;
+
+const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+const LINE_HEIGHT: f32 = 1.4;
+const INDENT: f32 = 18.0;
+const LINK_COLOR: (f32, f32, f32) = (0.12, 0.4, 0.96);
+/// Images are sized as if they were 96 dpi, like a browser does.
+const POINTS_PER_PIXEL: f32 = 0.75;
+
+const MARGINS: RangeInclusive<f32> = 0.0..=60.0;
+const FONT_SIZES: RangeInclusive<f32> = 6.0..=24.0;
+/// Narrowest text column accepted, in points.
+const MIN_TEXT_WIDTH: f32 = 144.0;
+/// Headings down to this level are listed in the table of contents.
+const CONTENTS_DEPTH: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSize {
+    #[default]
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl PageSize {
+    /// Width and height in points, portrait.
+    fn dimensions(self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (595.28, 841.89),
+            PageSize::A5 => (419.53, 595.28),
+            PageSize::Letter => (612.0, 792.0),
+            PageSize::Legal => (612.0, 1008.0),
+        }
+    }
+}
+
+/// Page margins in millimetres.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Margins {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Default for Margins {
+    fn default() -> Self {
+        Self {
+            top: 20.0,
+            right: 20.0,
+            bottom: 20.0,
+            left: 20.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PdfOptions {
+    pub page_size: PageSize,
+    pub landscape: bool,
+    pub margins: Margins,
+    /// Text centred in the top and bottom margins of every page, in which
+    /// `{page}`, `{pages}` and `{title}` are replaced. Empty for none.
+    pub header: String,
+    pub footer: String,
+    pub table_of_contents: bool,
+    pub font_family: FontFamily,
+    /// Body text size in points; headings are scaled from it.
+    pub font_size: f32,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            page_size: PageSize::default(),
+            landscape: false,
+            margins: Margins::default(),
+            header: String::new(),
+            footer: "{page} / {pages}".into(),
+            table_of_contents: false,
+            font_family: FontFamily::default(),
+            font_size: 11.0,
+        }
+    }
+}
+
+impl PdfOptions {
+    pub fn validate(&self) -> Result<()> {
+        let margins = [
+            ("top", self.margins.top),
+            ("right", self.margins.right),
+            ("bottom", self.margins.bottom),
+            ("left", self.margins.left),
+        ];
+        for (side, value) in margins {
+            if !MARGINS.contains(&value) {
+                return Err(Error::Invalid(format!(
+                    "the {side} margin must be between {} and {} mm, got {value}",
+                    MARGINS.start(),
+                    MARGINS.end()
+                )));
+            }
+        }
+        if !FONT_SIZES.contains(&self.font_size) {
+            return Err(Error::Invalid(format!(
+                "fontSize must be between {} and {}, got {}",
+                FONT_SIZES.start(),
+                FONT_SIZES.end(),
+                self.font_size
+            )));
+        }
+        let geometry = Geometry::new(self);
+        if geometry.right - geometry.left < MIN_TEXT_WIDTH
+            || geometry.top - geometry.bottom < MIN_TEXT_WIDTH
+        {
+            return Err(Error::Invalid(
+                "the margins leave too little room for text on this page size".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// The page and its text area, in points from the bottom left.
+#[derive(Debug, Clone, Copy)]
+struct Geometry {
+    width: f32,
+    height: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+    left: f32,
+}
+
+impl Geometry {
+    fn new(options: &PdfOptions) -> Self {
+        let (width, height) = options.page_size.dimensions();
+        let (width, height) = if options.landscape {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let margins = options.margins;
+        Self {
+            width,
+            height,
+            top: height - margins.top * POINTS_PER_MM,
+            right: width - margins.right * POINTS_PER_MM,
+            bottom: margins.bottom * POINTS_PER_MM,
+            left: margins.left * POINTS_PER_MM,
+        }
+    }
+}
+
+/// Where a link goes: a URL, or a heading or footnote in the document.
+#[derive(Debug, Clone)]
+enum Target {
+    Uri(String),
+    Anchor(String),
+}
+
+/// A run of text in one style.
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    font: Font,
+    link: Option<Target>,
+}
+
+impl Span {
+    fn plain(text: impl Into<String>, font: Font) -> Self {
+        Self {
+            text: text.into(),
+            font,
+            link: None,
+        }
+    }
+}
+
+/// A span placed on a line, `x` from the line's left edge.
+struct Placed {
+    span: Span,
+    x: f32,
+}
+
+/// Breaks `spans` into lines no wider than `width`. Lines only break after
+/// whitespace or at a `\n` span, so a word split across styles stays whole,
+/// unless the word is wider than a line; then it breaks between characters.
+fn wrap(fonts: &mut Fonts, spans: &[Span], size: f32, width: f32) -> Vec<Vec<Placed>> {
+    // Words are runs of pieces up to and including trailing whitespace.
+    let mut words: Vec<Vec<Span>> = vec![Vec::new()];
+    for span in spans {
+        for piece in span.text.split_inclusive(char::is_whitespace) {
+            words.last_mut().unwrap().push(Span {
+                text: piece.to_string(),
+                ..span.clone()
+            });
+            if piece.ends_with(char::is_whitespace) {
+                words.push(Vec::new());
+            }
+        }
+    }
+
+    let mut lines = vec![Vec::new()];
+    let mut x = 0.0;
+    for word in words.into_iter().filter(|word| !word.is_empty()) {
+        let visible: f32 = word
+            .iter()
+            .map(|piece| fonts.measure(piece.font, piece.text.trim_end()) * size)
+            .sum();
+        if x > 0.0 && x + visible > width {
+            lines.push(Vec::new());
+            x = 0.0;
+        }
+        let split = visible > width;
+        for piece in word {
+            if piece.text == "\n" {
+                lines.push(Vec::new());
+                x = 0.0;
+                continue;
+            }
+            if x == 0.0 && piece.text.trim().is_empty() {
+                continue;
+            }
+            if split {
+                let mut chunk = String::new();
+                let mut start = x;
+                for ch in piece.text.chars() {
+                    let advance = fonts.measure(piece.font, ch.encode_utf8(&mut [0; 4])) * size;
+                    if x > 0.0 && x + advance > width && !ch.is_whitespace() {
+                        if !chunk.is_empty() {
+                            let text = std::mem::take(&mut chunk);
+                            let span = Span {
+                                text,
+                                ..piece.clone()
+                            };
+                            lines.last_mut().unwrap().push(Placed { span, x: start });
+                        }
+                        lines.push(Vec::new());
+                        x = 0.0;
+                        start = 0.0;
+                    }
+                    chunk.push(ch);
+                    x += advance;
+                }
+                if !chunk.is_empty() {
+                    let span = Span {
+                        text: chunk,
+                        ..piece
+                    };
+                    lines.last_mut().unwrap().push(Placed { span, x: start });
+                }
+                continue;
+            }
+            let advance = fonts.measure(piece.font, &piece.text) * size;
+            lines.last_mut().unwrap().push(Placed { span: piece, x });
+            x += advance;
+        }
+    }
+    lines
+}
+
+/// A clickable area on a page.
+struct Link {
+    rect: Rect,
+    target: Target,
+}
+
+struct Page {
+    content: Content,
+    links: Vec<Link>,
+    /// The pictures drawn on the page, by index.
+    pictures: Vec<usize>,
+}
+
+impl Page {
+    fn new() -> Self {
+        Self {
+            content: Content::new(),
+            links: Vec::new(),
+            pictures: Vec::new(),
+        }
+    }
+}
+
+fn picture_name(index: usize) -> String {
+    format!("Im{}", index + 1)
+}
+
+/// A PNG or JPEG, ready to be written as an image XObject.
+struct Picture {
+    width: u32,
+    height: u32,
+    /// JPEG data as it is, which PDF readers decode themselves, or deflated
+    /// RGB samples.
+    data: Vec<u8>,
+    filter: Filter,
+    gray: bool,
+    /// Deflated alpha samples, for images with transparency.
+    alpha: Option<Vec<u8>>,
+}
+
+impl Picture {
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let format = image::guess_format(bytes).ok()?;
+        match format {
+            ImageFormat::Jpeg => {
+                // CMYK JPEGs are converted, since readers show them inverted
+                // unless told how Adobe wrote them.
+                if let Some((width, height, components @ (1 | 3))) = jpeg_header(bytes) {
+                    return Some(Self {
+                        width,
+                        height,
+                        data: bytes.to_vec(),
+                        filter: Filter::DctDecode,
+                        gray: components == 1,
+                        alpha: None,
+                    });
+                }
+            }
+            ImageFormat::Png => {}
+            _ => return None,
+        }
+        let image = image::load_from_memory_with_format(bytes, format).ok()?;
+        let alpha = image.color().has_alpha().then(|| {
+            let samples: Vec<u8> = image.to_rgba8().pixels().map(|pixel| pixel[3]).collect();
+            compress_to_vec_zlib(&samples, 6)
+        });
+        Some(Self {
+            width: image.width(),
+            height: image.height(),
+            data: compress_to_vec_zlib(image.to_rgb8().as_raw(), 6),
+            filter: Filter::FlateDecode,
+            gray: false,
+            alpha,
+        })
+    }
+}
+
+/// The size and number of colour components of a JPEG, from its start of
+/// frame.
+fn jpeg_header(bytes: &[u8]) -> Option<(u32, u32, u8)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?));
+    let mut at = 2;
+    while at + 4 <= bytes.len() {
+        if bytes[at] != 0xff {
+            return None;
+        }
+        let marker = bytes[at + 1];
+        match marker {
+            0xff => at += 1,
+            0xd0..=0xd9 | 0x01 => at += 2,
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = be16(at + 5)?.into();
+                let width = be16(at + 7)?.into();
+                return Some((width, height, *bytes.get(at + 9)?));
+            }
+            _ => at += 2 + usize::from(be16(at + 2)?),
+        }
+    }
+    None
+}
+
+/// A point in the laid out document: the page index and the height on it.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    page: usize,
+    y: f32,
+}
+
+/// Places blocks top to bottom, starting a new page when one is full.
+struct Layout<'a> {
+    fonts: &'a mut Fonts,
+    geometry: Geometry,
+    pages: Vec<Page>,
+    y: f32,
+}
+
+impl<'a> Layout<'a> {
+    fn new(fonts: &'a mut Fonts, geometry: Geometry) -> Self {
+        Self {
+            fonts,
+            geometry,
+            pages: vec![Page::new()],
+            y: geometry.top,
+        }
+    }
+
+    fn page(&mut self) -> &mut Page {
+        self.pages.last_mut().unwrap()
+    }
+
+    fn location(&self) -> Location {
+        Location {
+            page: self.pages.len() - 1,
+            y: self.y,
+        }
+    }
+
+    fn at_top(&self) -> bool {
+        self.y >= self.geometry.top
+    }
+
+    /// Makes room for `height`, moving to a new page if needed.
+    fn ensure(&mut self, height: f32) {
+        if self.y - height < self.geometry.bottom && !self.at_top() {
+            self.pages.push(Page::new());
+            self.y = self.geometry.top;
+        }
+    }
+
+    fn space(&mut self, height: f32) {
+        if !self.at_top() {
+            self.y -= height;
+        }
+    }
+
+    fn show(&mut self, x: f32, baseline: f32, size: f32, span: &Span) {
+        let (r, g, b) = if span.link.is_some() {
+            LINK_COLOR
+        } else {
+            (0.0, 0.0, 0.0)
+        };
+        let text = self.fonts.encode(span.font, &span.text);
+        self.page()
+            .content
+            .begin_text()
+            .set_fill_rgb(r, g, b)
+            .set_font(span.font.resource(), size)
+            .set_text_matrix([1.0, 0.0, 0.0, 1.0, x, baseline])
+            .show(Str(&text))
+            .end_text();
+        if let Some(target) = &span.link {
+            let width = self.fonts.measure(span.font, span.text.trim_end()) * size;
+            let rect = Rect::new(x, baseline - size * 0.25, x + width, baseline + size * 0.85);
+            self.page().links.push(Link {
+                rect,
+                target: target.clone(),
+            });
+        }
+    }
+
+    /// Draws wrapped text at `left`, with an optional marker (a bullet or
+    /// number) hanging to the left of the first line. Returns where the
+    /// top of the first line is.
+    fn paragraph(
+        &mut self,
+        spans: &[Span],
+        size: f32,
+        left: f32,
+        marker: Option<&str>,
+    ) -> Location {
+        let width = self.geometry.right - left;
+        let height = size * LINE_HEIGHT;
+        let mut first = None;
+        for (index, line) in wrap(self.fonts, spans, size, width).iter().enumerate() {
+            self.ensure(height);
+            first.get_or_insert(self.location());
+            self.y -= height;
+            let baseline = self.y + height * 0.25;
+            if let (0, Some(marker)) = (index, marker) {
+                let x = left - self.fonts.measure(Font::Regular, marker) * size - 4.0;
+                self.show(x, baseline, size, &Span::plain(marker, Font::Regular));
+            }
+            for placed in line {
+                self.show(left + placed.x, baseline, size, &placed.span);
+            }
+        }
+        first.unwrap_or_else(|| self.location())
+    }
+
+    fn code_block(&mut self, code: &str, left: f32, size: f32) {
+        let width = self.geometry.right - left;
+        let height = size * LINE_HEIGHT;
+        let columns = ((width - 8.0) / (self.fonts.measure(Font::Mono, " ") * size)).max(1.0);
+        for line in code.trim_end_matches('\n').split('\n') {
+            let chars: Vec<char> = line.replace('\t', "    ").chars().collect();
+            let chunks: Vec<String> = if chars.is_empty() {
+                vec![String::new()]
+            } else {
+                chars
+                    .chunks(columns as usize)
+                    .map(|chunk| chunk.iter().collect())
+                    .collect()
+            };
+            for chunk in chunks {
+                self.ensure(height);
+                self.y -= height;
+                let y = self.y;
+                self.page()
+                    .content
+                    .set_fill_gray(0.95)
+                    .rect(left, y, width, height)
+                    .fill_nonzero();
+                self.show(
+                    left + 4.0,
+                    y + height * 0.25,
+                    size,
+                    &Span::plain(chunk, Font::Mono),
+                );
+            }
+        }
+    }
+
+    fn rule(&mut self, left: f32) {
+        self.ensure(12.0);
+        self.y -= 6.0;
+        let (y, right) = (self.y, self.geometry.right);
+        self.page()
+            .content
+            .set_stroke_gray(0.75)
+            .set_line_width(0.5)
+            .move_to(left, y)
+            .line_to(right, y)
+            .stroke();
+        self.y -= 6.0;
+    }
+
+    /// Draws picture `index` at `left`, scaled down to fit the text area.
+    fn picture(&mut self, index: usize, picture: &Picture, left: f32) {
+        let mut width = picture.width.max(1) as f32 * POINTS_PER_PIXEL;
+        let mut height = picture.height.max(1) as f32 * POINTS_PER_PIXEL;
+        let scale = ((self.geometry.right - left) / width)
+            .min((self.geometry.top - self.geometry.bottom) / height)
+            .min(1.0);
+        width *= scale;
+        height *= scale;
+        self.ensure(height);
+        self.y -= height;
+        let y = self.y;
+        let name = picture_name(index);
+        let page = self.page();
+        page.content
+            .save_state()
+            .transform([width, 0.0, 0.0, height, left, y])
+            .x_object(Name(name.as_bytes()))
+            .restore_state();
+        if !page.pictures.contains(&index) {
+            page.pictures.push(index);
+        }
+    }
+
+    fn table(&mut self, rows: &[(bool, Vec<Vec<Span>>)], left: f32, size: f32) {
+        let columns = rows.iter().map(|(_, cells)| cells.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let height = size * LINE_HEIGHT;
+        let column_width = (self.geometry.right - left) / columns as f32;
+        for (header, cells) in rows {
+            let wrapped: Vec<Vec<Vec<Placed>>> = cells
+                .iter()
+                .map(|cell| {
+                    let spans: Vec<Span> = cell
+                        .iter()
+                        .map(|span| Span {
+                            font: if *header { Font::Bold } else { span.font },
+                            ..span.clone()
+                        })
+                        .collect();
+                    wrap(self.fonts, &spans, size, column_width - 8.0)
+                })
+                .collect();
+            let lines = wrapped.iter().map(Vec::len).max().unwrap_or(1);
+            let row_height = lines as f32 * height + 4.0;
+            self.ensure(row_height);
+            let top = self.y;
+            for (column, cell) in wrapped.iter().enumerate() {
+                let x = left + column as f32 * column_width + 4.0;
+                for (index, line) in cell.iter().enumerate() {
+                    let baseline = top - 2.0 - (index + 1) as f32 * height + height * 0.25;
+                    for placed in line {
+                        self.show(x + placed.x, baseline, size, &placed.span);
+                    }
+                }
+            }
+            self.y -= row_height;
+            let (y, right) = (self.y, self.geometry.right);
+            self.page()
+                .content
+                .set_stroke_gray(if *header { 0.5 } else { 0.85 })
+                .set_line_width(0.5)
+                .move_to(left, y)
+                .line_to(right, y)
+                .stroke();
+        }
+    }
+}
+
+fn heading_scale(level: HeadingLevel) -> f32 {
+    match level {
+        HeadingLevel::H1 => 2.0,
+        HeadingLevel::H2 => 1.64,
+        HeadingLevel::H3 => 1.36,
+        HeadingLevel::H4 => 1.18,
+        HeadingLevel::H5 => 1.09,
+        HeadingLevel::H6 => 1.0,
+    }
+}
+
+/// A heading, for the bookmarks and the table of contents.
+struct Heading {
+    level: u8,
+    text: String,
+    anchor: String,
+}
+
+fn footnote_anchor(label: &str) -> String {
+    format!("fn:{label}")
+}
+
+/// An image whose alt text is being read.
+struct Image {
+    url: String,
+    alt: String,
+}
+
+/// Walks the markdown events, collecting inline text into spans and laying
+/// out each block once it is complete.
+struct Renderer<'a> {
+    base: Option<&'a Path>,
+    access: ImageAccess<'a>,
+    size: f32,
+    spans: Vec<Span>,
+    bold: usize,
+    italic: usize,
+    /// Targets of the open links; `None` for links that can't be followed
+    /// from a PDF.
+    links: Vec<Option<Target>>,
+    quotes: usize,
+    /// Next number of each open list; `None` for bullet lists.
+    lists: Vec<Option<u64>>,
+    marker: Option<String>,
+    code: Option<String>,
+    table: Option<Vec<(bool, Vec<Vec<Span>>)>>,
+    /// Text of the heading being read.
+    heading: Option<String>,
+    /// Anchor for the next block drawn.
+    anchor: Option<String>,
+    anchors: HashMap<String, Location>,
+    slugs: markdown::Slugs,
+    headings: Vec<Heading>,
+    image: Option<Image>,
+    pictures: Vec<Picture>,
+    /// Index of each image file in `pictures`.
+    loaded: HashMap<PathBuf, usize>,
+    missing: Vec<String>,
+    unsupported: Vec<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(size: f32, base: Option<&'a Path>, access: ImageAccess<'a>) -> Self {
+        Self {
+            base,
+            access,
+            size,
+            spans: Vec::new(),
+            bold: 0,
+            italic: 0,
+            links: Vec::new(),
+            quotes: 0,
+            lists: Vec::new(),
+            marker: None,
+            code: None,
+            table: None,
+            heading: None,
+            anchor: None,
+            anchors: HashMap::new(),
+            slugs: markdown::Slugs::default(),
+            headings: Vec::new(),
+            image: None,
+            pictures: Vec::new(),
+            loaded: HashMap::new(),
+            missing: Vec::new(),
+            unsupported: Vec::new(),
+        }
+    }
+
+    fn left(&self, layout: &Layout) -> f32 {
+        layout.geometry.left + INDENT * (self.lists.len() + self.quotes) as f32
+    }
+
+    fn push(&mut self, text: &str, font: Font) {
+        if let Some(heading) = &mut self.heading {
+            heading.push_str(text);
+        }
+        if let Some(image) = &mut self.image {
+            image.alt.push_str(text);
+            return;
+        }
+        let span = Span {
+            text: text.to_string(),
+            font,
+            link: self.links.last().cloned().flatten(),
+        };
+        match self.table.as_mut().and_then(|rows| rows.last_mut()) {
+            Some((_, cells)) => {
+                if let Some(cell) = cells.last_mut() {
+                    cell.push(span);
+                }
+            }
+            None => self.spans.push(span),
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        let font = Font::styled(self.bold > 0, self.italic > 0 || self.quotes > 0);
+        self.push(text, font);
+    }
+
+    /// Loads the image at `url` once, returning its index in `pictures`, or
+    /// `None` if it isn't a local PNG or JPEG that may be read.
+    fn load(&mut self, url: &str) -> Option<usize> {
+        let path = resolve_local(self.base, url);
+        if let Some(index) = path.as_ref().and_then(|path| self.loaded.get(path)) {
+            return Some(*index);
+        }
+        let Some(path) = path else {
+            if !url.contains("://") {
+                self.missing.push(url.to_string());
+            }
+            return None;
+        };
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        if !["png", "jpg", "jpeg"].contains(&extension.as_str()) {
+            if !self.unsupported.iter().any(|seen| seen == url) {
+                self.unsupported.push(url.to_string());
+            }
+            return None;
+        }
+        let picture = self
+            .access
+            .allows(&path)
+            .then(|| fs::read(&path).ok())
+            .flatten()
+            .and_then(|bytes| Picture::decode(&bytes));
+        let Some(picture) = picture else {
+            self.missing.push(url.to_string());
+            return None;
+        };
+        self.pictures.push(picture);
+        self.loaded.insert(path, self.pictures.len() - 1);
+        Some(self.pictures.len() - 1)
+    }
+
+    /// Draws an image as a block of its own, ending the paragraph before it.
+    /// Images in headings and tables, and ones that can't be drawn, stay
+    /// readable as their alt text.
+    fn image(&mut self, layout: &mut Layout, image: Image) {
+        let inline = self.heading.is_some() || self.table.is_some();
+        let index = if inline { None } else { self.load(&image.url) };
+        let Some(index) = index else {
+            let alt = if image.alt.is_empty() {
+                image.url
+            } else {
+                image.alt
+            };
+            self.italic += 1;
+            self.text(&format!("[{alt}]"));
+            self.italic -= 1;
+            return;
+        };
+        let size = self.size;
+        if !self.spans.is_empty() || self.marker.is_some() {
+            self.flush(layout, size);
+        }
+        let left = self.left(layout);
+        layout.picture(index, &self.pictures[index], left);
+        layout.space(size * 0.3);
+    }
+
+    fn flush(&mut self, layout: &mut Layout, size: f32) {
+        if self.spans.is_empty() && self.marker.is_none() {
+            // An empty heading has nothing to point at; its anchor mustn't
+            // move to the next block.
+            self.anchor = None;
+            return;
+        }
+        let spans = std::mem::take(&mut self.spans);
+        let marker = self.marker.take();
+        let left = self.left(layout);
+        let location = layout.paragraph(&spans, size, left, marker.as_deref());
+        if let Some(anchor) = self.anchor.take() {
+            self.anchors.entry(anchor).or_insert(location);
+        }
+    }
+
+    fn event(&mut self, layout: &mut Layout, event: Event) {
+        let size = self.size;
+        if let Some(code) = &mut self.code {
+            match event {
+                Event::Text(text) => code.push_str(&text),
+                Event::End(TagEnd::CodeBlock) => {
+                    let code = self.code.take().unwrap_or_default();
+                    let left = self.left(layout);
+                    layout.code_block(&code, left, size * 0.86);
+                    layout.space(size * 0.6);
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match event {
+            Event::Start(Tag::Heading { level, .. }) => {
+                self.flush(layout, size);
+                layout.space(size * heading_scale(level) * 0.6);
+                self.heading = Some(String::new());
+                self.bold += 1;
+            }
+            Event::End(TagEnd::Heading(level)) => {
+                self.bold -= 1;
+                let text = self.heading.take().unwrap_or_default();
+                let anchor = self.slugs.next(&text);
+                self.anchor = Some(anchor.clone());
+                self.flush(layout, size * heading_scale(level));
+                self.headings.push(Heading {
+                    level: level as u8,
+                    text,
+                    anchor,
+                });
+                layout.space(4.0);
+            }
+            Event::End(TagEnd::Paragraph) => {
+                self.flush(layout, size);
+                layout.space(size * 0.6);
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                self.flush(layout, size);
+                self.code = Some(String::new());
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.flush(layout, size);
+                self.quotes += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush(layout, size);
+                self.quotes -= 1;
+            }
+            Event::Start(Tag::List(start)) => {
+                self.flush(layout, size);
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.flush(layout, size);
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    layout.space(size * 0.6);
+                }
+            }
+            Event::Start(Tag::Item) => {
+                self.flush(layout, size);
+                self.marker = Some(match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}.", *number - 1)
+                    }
+                    _ => "\u{2022}".to_string(),
+                });
+            }
+            Event::End(TagEnd::Item) => self.flush(layout, size),
+            Event::Start(Tag::FootnoteDefinition(label)) => {
+                self.flush(layout, size);
+                self.marker = Some(format!("[{label}]"));
+                self.anchor = Some(footnote_anchor(&label));
+            }
+            Event::End(TagEnd::FootnoteDefinition) => self.flush(layout, size),
+            Event::Start(Tag::Table(_)) => {
+                self.flush(layout, size);
+                self.table = Some(Vec::new());
+            }
+            Event::Start(Tag::TableHead) => {
+                if let Some(rows) = &mut self.table {
+                    rows.push((true, Vec::new()));
+                }
+            }
+            Event::Start(Tag::TableRow) => {
+                if let Some(rows) = &mut self.table {
+                    rows.push((false, Vec::new()));
+                }
+            }
+            Event::Start(Tag::TableCell) => {
+                if let Some((_, cells)) = self.table.as_mut().and_then(|rows| rows.last_mut()) {
+                    cells.push(Vec::new());
+                }
+            }
+            Event::End(TagEnd::Table) => {
+                let rows = self.table.take().unwrap_or_default();
+                let left = self.left(layout);
+                layout.table(&rows, left, size * 0.9);
+                layout.space(size * 0.6);
+            }
+            Event::Start(Tag::Strong) => self.bold += 1,
+            Event::End(TagEnd::Strong) => self.bold -= 1,
+            Event::Start(Tag::Emphasis) => self.italic += 1,
+            Event::End(TagEnd::Emphasis) => self.italic -= 1,
+            Event::Start(Tag::Link {
+                link_type,
+                dest_url,
+                ..
+            }) => {
+                // Relative paths mean nothing once the PDF is shared, so
+                // only fragments and absolute URLs stay clickable.
+                let dest_url = markdown::link_target(link_type, &dest_url);
+                let target = match dest_url.strip_prefix('#') {
+                    Some(fragment) => Some(Target::Anchor(fragment.to_string())),
+                    None if dest_url.contains(':') => Some(Target::Uri(dest_url.to_string())),
+                    None => None,
+                };
+                self.links.push(target);
+            }
+            Event::End(TagEnd::Link) => {
+                self.links.pop();
+            }
+            Event::Start(Tag::Image { dest_url, .. }) => {
+                self.image = Some(Image {
+                    url: dest_url.to_string(),
+                    alt: String::new(),
+                });
+            }
+            Event::End(TagEnd::Image) => {
+                if let Some(image) = self.image.take() {
+                    self.image(layout, image);
+                }
+            }
+            Event::Text(text) => self.text(&text),
+            Event::Code(code) => self.push(&code, Font::Mono),
+            Event::FootnoteReference(label) => {
+                self.links
+                    .push(Some(Target::Anchor(footnote_anchor(&label))));
+                self.text(&format!("[{label}]"));
+                self.links.pop();
+            }
+            Event::TaskListMarker(done) => self.text(if done { "[x] " } else { "[ ] " }),
+            Event::SoftBreak => self.text(" "),
+            Event::HardBreak => self.text("\n"),
+            Event::Rule => {
+                self.flush(layout, size);
+                let left = self.left(layout);
+                layout.rule(left);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Lays out the table of contents, numbering the document's pages from
+/// `first_page`.
+fn table_of_contents(
+    fonts: &mut Fonts,
+    geometry: Geometry,
+    size: f32,
+    headings: &[Heading],
+    anchors: &HashMap<String, Location>,
+    first_page: usize,
+) -> Vec<Page> {
+    let mut layout = Layout::new(fonts, geometry);
+    let title = Span::plain("Contents", Font::Bold);
+    let title_size = size * heading_scale(HeadingLevel::H2);
+    layout.paragraph(&[title], title_size, geometry.left, None);
+    layout.space(size);
+
+    let height = size * LINE_HEIGHT;
+    let number_width = size * 3.0;
+    for heading in headings {
+        let Some(location) = anchors.get(&heading.anchor) else {
+            continue;
+        };
+        if heading.level > CONTENTS_DEPTH {
+            continue;
+        }
+        let left = geometry.left + INDENT * f32::from(heading.level - 1);
+        let font = if heading.level == 1 {
+            Font::Bold
+        } else {
+            Font::Regular
+        };
+        let text = Span::plain(heading.text.as_str(), font);
+        let lines = wrap(
+            layout.fonts,
+            &[text],
+            size,
+            geometry.right - number_width - left,
+        );
+        for (index, line) in lines.iter().enumerate() {
+            layout.ensure(height);
+            layout.y -= height;
+            let baseline = layout.y + height * 0.25;
+            for placed in line {
+                layout.show(left + placed.x, baseline, size, &placed.span);
+            }
+            if index + 1 == lines.len() {
+                let number = (first_page + location.page).to_string();
+                let width = layout.fonts.measure(Font::Regular, &number) * size;
+                let number = Span::plain(number, Font::Regular);
+                layout.show(geometry.right - width, baseline, size, &number);
+            }
+            // The whole line, page number included, goes to the heading.
+            let y = layout.y;
+            layout.page().links.push(Link {
+                rect: Rect::new(left, y, geometry.right, y + height),
+                target: Target::Anchor(heading.anchor.clone()),
+            });
+        }
+        layout.space(size * 0.2);
+    }
+    layout.pages
+}
+
+/// Draws the header and footer of every page, now that the page count is
+/// known.
+fn decorate(
+    fonts: &mut Fonts,
+    geometry: Geometry,
+    options: &PdfOptions,
+    pages: &mut [Page],
+    title: &str,
+) {
+    let size = options.font_size * 0.8;
+    let count = pages.len();
+    let lines = [
+        (&options.header, (geometry.height + geometry.top) / 2.0),
+        (&options.footer, geometry.bottom / 2.0 - size / 2.0),
+    ];
+    for (index, page) in pages.iter_mut().enumerate() {
+        for (template, baseline) in lines {
+            let text = template
+                .replace("{pages}", &count.to_string())
+                .replace("{page}", &(index + 1).to_string())
+                .replace("{title}", title);
+            if text.trim().is_empty() {
+                continue;
+            }
+            let width = fonts.measure(Font::Regular, &text) * size;
+            let x = (geometry.left + geometry.right - width) / 2.0;
+            let encoded = fonts.encode(Font::Regular, &text);
+            page.content
+                .begin_text()
+                .set_fill_gray(0.45)
+                .set_font(Font::Regular.resource(), size)
+                .set_text_matrix([1.0, 0.0, 0.0, 1.0, x, baseline])
+                .show(Str(&encoded))
+                .end_text();
+        }
+    }
+}
+
+/// Writes the bookmarks, nesting each heading under the closest heading
+/// before it with a lower level.
+fn write_outline(
+    pdf: &mut Pdf,
+    outline_id: Ref,
+    items: &[(&Heading, Ref, Location)],
+    page_ids: &[Ref],
+    left: f32,
+) {
+    let mut parents: Vec<Option<usize>> = Vec::with_capacity(items.len());
+    let mut open: Vec<usize> = Vec::new();
+    for (index, (heading, ..)) in items.iter().enumerate() {
+        while open
+            .last()
+            .is_some_and(|&last| items[last].0.level >= heading.level)
+        {
+            open.pop();
+        }
+        parents.push(open.last().copied());
+        open.push(index);
+    }
+    let children = |parent: Option<usize>| -> Vec<usize> {
+        (0..items.len())
+            .filter(|&index| parents[index] == parent)
+            .collect()
+    };
+
+    let top = children(None);
+    pdf.outline(outline_id)
+        .first(items[top[0]].1)
+        .last(items[top[top.len() - 1]].1)
+        // Only the top level shows until an item is expanded.
+        .count(top.len() as i32);
+
+    for (index, (heading, id, location)) in items.iter().enumerate() {
+        let siblings = children(parents[index]);
+        let position = siblings.iter().position(|&s| s == index).unwrap_or(0);
+        let mut item = pdf.outline_item(*id);
+        item.title(TextStr(&heading.text))
+            .parent(parents[index].map_or(outline_id, |parent| items[parent].1));
+        if position > 0 {
+            item.prev(items[siblings[position - 1]].1);
+        }
+        if let Some(&next) = siblings.get(position + 1) {
+            item.next(items[next].1);
+        }
+        let own = children(Some(index));
+        if let (Some(&first), Some(&last)) = (own.first(), own.last()) {
+            // Descendants are the headings that follow until one at the
+            // same level or above; they start closed.
+            let descendants = items[index + 1..]
+                .iter()
+                .take_while(|(other, ..)| other.level > heading.level)
+                .count();
+            item.first(items[first].1)
+                .last(items[last].1)
+                .count(-(descendants as i32));
+        }
+        item.dest()
+            .page(page_ids[location.page])
+            .xyz(left, location.y, None);
+    }
+}
+
+/// A PDF rendering of a document.
+pub struct PdfExport {
+    pub bytes: Vec<u8>,
+    /// Local images that could not or may not be read.
+    pub missing: Vec<String>,
+    /// Local images left out because they aren't PNG or JPEG.
+    pub unsupported: Vec<String>,
+}
+
+/// Renders `source` as a PDF laid out with `options`, with `title` in the
+/// document info and available to the header and footer. `base` is the
+/// folder relative image paths are resolved against, and `access` says
+/// which images may be read.
+pub fn render(
+    source: &str,
+    base: Option<&Path>,
+    access: ImageAccess,
+    options: &PdfOptions,
+    title: Option<&str>,
+) -> PdfExport {
+    let geometry = Geometry::new(options);
+    let size = options.font_size;
+    let mut fonts = Fonts::load(options.font_family);
+
+    let mut renderer = Renderer::new(size, base, access);
+    let mut layout = Layout::new(&mut fonts, geometry);
+    for event in markdown::events(source) {
+        renderer.event(&mut layout, event);
+    }
+    renderer.flush(&mut layout, size);
+    let mut body = layout.pages;
+    let Renderer {
+        mut anchors,
+        headings,
+        pictures,
+        missing,
+        unsupported,
+        ..
+    } = renderer;
+
+    // The contents come first, so they are laid out once to count their
+    // pages and again with the body's final page numbers.
+    let mut pages = Vec::new();
+    if options.table_of_contents && !headings.is_empty() {
+        let count = table_of_contents(&mut fonts, geometry, size, &headings, &anchors, 1).len();
+        pages = table_of_contents(&mut fonts, geometry, size, &headings, &anchors, count + 1);
+        for location in anchors.values_mut() {
+            location.page += pages.len();
+        }
+    }
+    pages.append(&mut body);
+
+    let title = title.unwrap_or_default();
+    decorate(&mut fonts, geometry, options, &mut pages, title);
+
+    let mut pdf = Pdf::new();
+    let mut next = 0;
+    let mut next_id = || {
+        next += 1;
+        Ref::new(next)
+    };
+    let catalog_id = next_id();
+    let tree_id = next_id();
+    let info_id = next_id();
+    let outline_id = next_id();
+    let page_ids: Vec<Ref> = pages.iter().map(|_| next_id()).collect();
+    let content_ids: Vec<Ref> = pages.iter().map(|_| next_id()).collect();
+    let outline: Vec<(&Heading, Ref, Location)> = headings
+        .iter()
+        .filter_map(|heading| Some((heading, next_id(), *anchors.get(&heading.anchor)?)))
+        .collect();
+
+    let mut catalog = pdf.catalog(catalog_id);
+    catalog.pages(tree_id);
+    if !outline.is_empty() {
+        catalog
+            .outlines(outline_id)
+            .page_mode(PageMode::UseOutlines);
+    }
+    catalog.finish();
+    pdf.pages(tree_id)
+        .kids(page_ids.iter().copied())
+        .count(pages.len() as i32);
+    if !outline.is_empty() {
+        write_outline(&mut pdf, outline_id, &outline, &page_ids, geometry.left);
+    }
+
+    let font_ids = fonts.write(&mut pdf, &mut next_id);
+    let mut picture_ids = Vec::with_capacity(pictures.len());
+    for picture in &pictures {
+        let id = next_id();
+        let mask_id = picture.alpha.as_ref().map(|_| next_id());
+        let mut image = pdf.image_xobject(id, &picture.data);
+        image
+            .width(picture.width as i32)
+            .height(picture.height as i32)
+            .bits_per_component(8)
+            .filter(picture.filter);
+        if picture.gray {
+            image.color_space().device_gray();
+        } else {
+            image.color_space().device_rgb();
+        }
+        if let Some(mask_id) = mask_id {
+            image.s_mask(mask_id);
+        }
+        image.finish();
+        if let (Some(mask_id), Some(alpha)) = (mask_id, &picture.alpha) {
+            let mut mask = pdf.image_xobject(mask_id, alpha);
+            mask.width(picture.width as i32)
+                .height(picture.height as i32)
+                .bits_per_component(8)
+                .filter(Filter::FlateDecode);
+            mask.color_space().device_gray();
+        }
+        picture_ids.push(id);
+    }
+    for (index, page) in pages.into_iter().enumerate() {
+        let mut writer = pdf.page(page_ids[index]);
+        writer
+            .media_box(Rect::new(0.0, 0.0, geometry.width, geometry.height))
+            .parent(tree_id)
+            .contents(content_ids[index]);
+        let mut resources = writer.resources();
+        let mut font_resources = resources.fonts();
+        for (font, id) in &font_ids {
+            font_resources.pair(font.resource(), *id);
+        }
+        font_resources.finish();
+        if !page.pictures.is_empty() {
+            let mut x_objects = resources.x_objects();
+            for &index in &page.pictures {
+                x_objects.pair(Name(picture_name(index).as_bytes()), picture_ids[index]);
+            }
+        }
+        resources.finish();
+
+        let mut annotations = writer.annotations();
+        for link in &page.links {
+            // Links to headings that don't exist are left as plain text.
+            if let Target::Anchor(anchor) = &link.target {
+                if !anchors.contains_key(anchor) {
+                    continue;
+                }
+            }
+            let mut annotation = annotations.push();
+            annotation
+                .subtype(AnnotationType::Link)
+                .rect(link.rect)
+                .border(0.0, 0.0, 0.0, None);
+            let mut action = annotation.action();
+            match &link.target {
+                Target::Uri(uri) => {
+                    action.action_type(ActionType::Uri).uri(Str(uri.as_bytes()));
+                }
+                Target::Anchor(anchor) => {
+                    let location = anchors[anchor];
+                    action
+                        .action_type(ActionType::GoTo)
+                        .destination()
+                        .page(page_ids[location.page])
+                        .xyz(geometry.left, location.y, None);
+                }
+            }
+        }
+        annotations.finish();
+        writer.finish();
+
+        let content = compress_to_vec_zlib(&page.content.finish(), 6);
+        pdf.stream(content_ids[index], &content)
+            .filter(Filter::FlateDecode);
+    }
+
+    if !title.is_empty() {
+        pdf.document_info(info_id).title(TextStr(title));
+    }
+    PdfExport {
+        bytes: pdf.finish(),
+        missing,
+        unsupported,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb, Rgba};
+
+    fn encode(image: image::DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image
+            .write_to(&mut std::io::Cursor::new(&mut bytes), format)
+            .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn long_words_break_between_characters() {
+        let mut fonts = Fonts::load(FontFamily::Sans);
+        let word = "https://example.com/".repeat(8);
+        let spans = [
+            Span::plain("See ", Font::Regular),
+            Span::plain(word.as_str(), Font::Mono),
+            Span::plain(" now", Font::Regular),
+        ];
+        let width = 150.0;
+        let lines = wrap(&mut fonts, &spans, 10.0, width);
+        assert!(lines.len() > 3);
+        let mut text = String::new();
+        for line in &lines {
+            let last = line.last().unwrap();
+            let end = last.x + fonts.measure(last.span.font, last.span.text.trim_end()) * 10.0;
+            assert!(end <= width + 0.01, "{end} is wider than {width}");
+            for placed in line {
+                text.push_str(&placed.span.text);
+            }
+        }
+        assert_eq!(text, format!("See {word} now"));
+        // Words that fit still move to the next line whole.
+        assert_eq!(lines[0].len(), 1);
+        assert_eq!(lines[0][0].span.text, "See ");
+    }
+
+    #[test]
+    fn pictures() {
+        let png = encode(
+            ImageBuffer::from_pixel(3, 2, Rgba([255u8, 0, 0, 128])).into(),
+            ImageFormat::Png,
+        );
+        let picture = Picture::decode(&png).unwrap();
+        assert_eq!((picture.width, picture.height), (3, 2));
+        assert!(matches!(picture.filter, Filter::FlateDecode));
+        assert!(picture.alpha.is_some());
+
+        let opaque = encode(
+            ImageBuffer::from_pixel(4, 4, Rgb([0u8, 0, 255])).into(),
+            ImageFormat::Png,
+        );
+        assert!(Picture::decode(&opaque).unwrap().alpha.is_none());
+
+        // JPEGs are embedded as they are.
+        let jpeg = encode(
+            ImageBuffer::from_pixel(5, 7, Rgb([0u8, 128, 0])).into(),
+            ImageFormat::Jpeg,
+        );
+        let picture = Picture::decode(&jpeg).unwrap();
+        assert_eq!((picture.width, picture.height), (5, 7));
+        assert!(matches!(picture.filter, Filter::DctDecode));
+        assert!(!picture.gray);
+        assert_eq!(picture.data, jpeg);
+
+        assert!(Picture::decode(b"GIF89a").is_none());
+        assert!(Picture::decode(b"not an image").is_none());
+    }
+
+    #[test]
+    fn images_that_cannot_be_drawn() {
+        let source = "![logo](/nonexistent/logo.png) ![anim](/nonexistent/a.gif) \
+                      ![remote](https://example.com/r.png) ![](relative.jpg)";
+        let export = render(
+            source,
+            None,
+            ImageAccess::Unscoped,
+            &PdfOptions::default(),
+            None,
+        );
+        assert!(export.bytes.starts_with(b"%PDF-"));
+        assert_eq!(export.missing, ["/nonexistent/logo.png", "relative.jpg"]);
+        assert_eq!(export.unsupported, ["/nonexistent/a.gif"]);
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, State, WebviewWindow};
+
+use crate::atomic;
+use crate::error::{Error, Result};
+use crate::launch::{self, Incoming, MAIN_WINDOW};
+use crate::menu;
+use crate::registry::{self, DocumentRegistry};
+use crate::reload::ViewState;
+
+/// Number of files kept in the "Open Recent" list.
+const RECENT_LIMIT: usize = 10;
+
+const RECENT_FILE: &str = "recent.json";
+
+/// A document that was open when the app last quit, and how it was shown.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionDocument {
+    pub path: PathBuf,
+    pub view: ViewState,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct History {
+    /// Most recently used first.
+    files: Vec<PathBuf>,
+    session: Vec<SessionDocument>,
+}
+
+/// What a window currently shows, as last reported by its frontend.
+struct WindowView {
+    path: Option<PathBuf>,
+    view: ViewState,
+}
+
+/// The most-recently-used list and the last session, kept in
+/// `<app data>/recent.json`.
+pub struct RecentFiles {
+    path: PathBuf,
+    history: Mutex<History>,
+    windows: Mutex<BTreeMap<String, WindowView>>,
+}
+
+impl RecentFiles {
+    pub fn load(app: &AppHandle) -> tauri::Result<Self> {
+        let path = app.path().app_data_dir()?.join(RECENT_FILE);
+        let history = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default();
+        Ok(Self {
+            path,
+            history: Mutex::new(history),
+            windows: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    /// The recent files that still exist. Missing ones are dropped from the
+    /// list for good.
+    pub fn files(&self) -> Vec<PathBuf> {
+        let mut history = self.history.lock().unwrap();
+        let before = history.files.len();
+        history.files.retain(|path| path.is_file());
+        if history.files.len() != before {
+            self.persist(&history);
+        }
+        history.files.clone()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut History)) {
+        let mut history = self.history.lock().unwrap();
+        change(&mut history);
+        self.persist(&history);
+    }
+
+    /// Failing to write the list only costs the user their history, so
+    /// errors are ignored.
+    fn persist(&self, history: &History) {
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(bytes) = serde_json::to_vec_pretty(history) {
+            let _ = atomic::write(&self.path, &bytes);
+        }
+    }
+
+    fn take_session(&self) -> Vec<SessionDocument> {
+        let mut session = Vec::new();
+        self.update(|history| session = std::mem::take(&mut history.session));
+        session.retain(|doc| doc.path.is_file());
+        session
+    }
+
+    /// Forgets a closed window, unless it is the last one: closing the last
+    /// window quits the app on most platforms, and its document should be
+    /// part of the session.
+    pub fn remove_window(&self, label: &str) {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > 1 {
+            windows.remove(label);
+        }
+    }
+
+    /// Saves the documents open in every window as the session to restore
+    /// on the next launch. Called on exit.
+    pub fn save_session(&self) {
+        let session = self
+            .windows
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|window| {
+                Some(SessionDocument {
+                    path: window.path.clone()?,
+                    view: window.view.clone(),
+                })
+            })
+            .collect();
+        self.update(|history| history.session = session);
+    }
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Moves `path` to the top of the recent files, e.g. after it was opened or
+/// saved, and refreshes the "Open Recent" menu.
+pub fn record(app: &AppHandle, path: &Path) {
+    let path = canonical(path);
+    app.state::<RecentFiles>().update(|history| {
+        history.files.retain(|file| *file != path);
+        history.files.insert(0, path);
+        history.files.truncate(RECENT_LIMIT);
+    });
+    menu::refresh_recent(app);
+}
+
+fn forget(app: &AppHandle, path: &Path) {
+    let path = canonical(path);
+    app.state::<RecentFiles>()
+        .update(|history| history.files.retain(|file| *file != path));
+    menu::refresh_recent(app);
+}
+
+pub fn clear(app: &AppHandle) {
+    app.state::<RecentFiles>()
+        .update(|history| history.files.clear());
+    menu::refresh_recent(app);
+}
+
+/// Reopens the documents of the last session: the first in the main
+/// window, the rest in new windows, each with its view restored.
+pub fn restore_session(app: &AppHandle) {
+    let session = app.state::<RecentFiles>().take_session();
+    for (index, doc) in session.into_iter().enumerate() {
+        let item = Incoming::Restored {
+            path: doc.path,
+            view: doc.view,
+        };
+        if index == 0 {
+            launch::deliver(app, MAIN_WINDOW, vec![item]);
+        } else {
+            let _ = registry::open_window(app, vec![item]);
+        }
+    }
+}
+
+#[tauri::command]
+pub fn list_recent_files(recent: State<'_, RecentFiles>) -> Vec<PathBuf> {
+    recent.files()
+}
+
+#[tauri::command]
+pub fn remove_recent_file(app: AppHandle, path: PathBuf) {
+    forget(&app, &path);
+}
+
+#[tauri::command]
+pub fn clear_recent_files(app: AppHandle) {
+    clear(&app);
+}
+
+/// Records what the calling window shows, for the session saved on exit.
+#[tauri::command]
+pub fn update_session_view(
+    window: WebviewWindow,
+    path: Option<PathBuf>,
+    view: ViewState,
+    recent: State<'_, RecentFiles>,
+) {
+    recent
+        .windows
+        .lock()
+        .unwrap()
+        .insert(window.label().to_string(), WindowView { path, view });
+}
+
+/// Opens a file from the recent list in the focused window, or in a new
+/// window if none has focus or its document has unsaved changes. Missing
+/// files are dropped from the list.
+pub fn open_recent(app: &AppHandle, path: PathBuf) -> Result<()> {
+    if !path.is_file() {
+        forget(app, &path);
+        return Err(Error::NotFound(format!(
+            "{} no longer exists",
+            path.display()
+        )));
+    }
+    let focused = app
+        .webview_windows()
+        .into_values()
+        .find(|window| window.is_focused().unwrap_or(false));
+    let registry = app.state::<DocumentRegistry>();
+    match focused {
+        Some(window) if registry.dirty_documents(Some(window.label())).is_empty() => {
+            launch::deliver(app, window.label(), vec![Incoming::File { path }])
+        }
+        _ => launch::open_files(app, vec![path]),
+    }
+    Ok(())
+}
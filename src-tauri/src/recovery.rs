@@ -0,0 +1,237 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewWindow};
+
+use crate::atomic;
+use crate::document::TextFormat;
+use crate::error::{Error, Result};
+use crate::launch::{self, Incoming};
+use crate::registry::{self, DocumentId, DocumentRegistry};
+
+pub const JOURNAL_REQUESTED_EVENT: &str = "journal-requested";
+
+const RECOVERY_DIR: &str = "recovery";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RecoveryConfig {
+    /// How often dirty buffers are journaled.
+    pub interval_secs: u64,
+    /// Journals from crashed sessions older than this are deleted unseen.
+    pub retention_days: u64,
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: 30,
+            retention_days: 7,
+        }
+    }
+}
+
+/// An unsaved buffer as written to the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub path: Option<PathBuf>,
+    pub text: String,
+    pub format: Option<TextFormat>,
+    /// Seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+/// Journals unsaved buffers to `<app data>/recovery/<session>/<id>.json`
+/// while the app runs. A clean exit removes the session's directory, so any
+/// directory left over from another session is from a crash.
+pub struct Recovery {
+    session_dir: PathBuf,
+    config: Mutex<RecoveryConfig>,
+    /// Journals found at startup that haven't been restored or discarded.
+    orphans: Mutex<Vec<(PathBuf, JournalEntry)>>,
+    offered: AtomicBool,
+}
+
+impl Recovery {
+    pub fn new(app: &AppHandle, config: RecoveryConfig) -> tauri::Result<Self> {
+        let root = app.path().app_data_dir()?.join(RECOVERY_DIR);
+        let session = format!("{}-{}", now(), std::process::id());
+        let orphans = scan_orphans(&root, config.retention_days);
+        Ok(Self {
+            session_dir: root.join(session),
+            config: Mutex::new(config),
+            orphans: Mutex::new(orphans),
+            offered: AtomicBool::new(false),
+        })
+    }
+
+    pub fn config(&self) -> RecoveryConfig {
+        *self.config.lock().unwrap()
+    }
+
+    pub fn set_config(&self, config: RecoveryConfig) {
+        *self.config.lock().unwrap() = config;
+    }
+
+    /// Deletes this session's journals; called on a clean exit.
+    pub fn clear_session(&self) {
+        let _ = fs::remove_dir_all(&self.session_dir);
+    }
+
+    fn journal_path(&self, id: DocumentId) -> PathBuf {
+        self.session_dir.join(format!("{id}.json"))
+    }
+
+    /// Removes journals of documents that are no longer dirty.
+    fn prune(&self, registry: &DocumentRegistry) {
+        let dirty: Vec<PathBuf> = registry
+            .dirty_documents(None)
+            .iter()
+            .map(|doc| self.journal_path(doc.id))
+            .collect();
+        let Ok(entries) = fs::read_dir(&self.session_dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !dirty.contains(&entry.path()) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Periodically asks every window with unsaved changes to journal them.
+pub fn start(app: AppHandle) {
+    thread::spawn(move || loop {
+        let recovery = app.state::<Recovery>();
+        thread::sleep(Duration::from_secs(recovery.config().interval_secs.max(1)));
+
+        let registry = app.state::<DocumentRegistry>();
+        recovery.prune(&registry);
+        let mut windows: Vec<String> = registry
+            .dirty_documents(None)
+            .into_iter()
+            .map(|doc| doc.window)
+            .collect();
+        windows.sort();
+        windows.dedup();
+        for label in windows {
+            let _ = app.emit_to(&label, JOURNAL_REQUESTED_EVENT, ());
+        }
+    });
+}
+
+/// Collects journals left by other sessions, deleting those past retention.
+fn scan_orphans(root: &Path, retention_days: u64) -> Vec<(PathBuf, JournalEntry)> {
+    let cutoff = now().saturating_sub(retention_days * 24 * 60 * 60);
+    let mut orphans = Vec::new();
+    let Ok(sessions) = fs::read_dir(root) else {
+        return orphans;
+    };
+    for session in sessions.flatten() {
+        let Ok(journals) = fs::read_dir(session.path()) else {
+            continue;
+        };
+        for journal in journals.flatten() {
+            let entry = fs::read(journal.path())
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<JournalEntry>(&bytes).ok());
+            match entry {
+                Some(entry) if entry.saved_at >= cutoff => orphans.push((journal.path(), entry)),
+                _ => {
+                    let _ = fs::remove_file(journal.path());
+                }
+            }
+        }
+        // Only succeeds once the session directory is empty.
+        let _ = fs::remove_dir(session.path());
+    }
+    orphans.sort_by_key(|(_, entry)| entry.saved_at);
+    orphans
+}
+
+fn remove_journals(journals: &[(PathBuf, JournalEntry)]) {
+    for (path, _) in journals {
+        let _ = fs::remove_file(path);
+        if let Some(dir) = path.parent() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Writes the current buffer of a dirty document, in reply to
+/// `journal-requested`.
+#[tauri::command]
+pub async fn journal_document(
+    id: DocumentId,
+    path: Option<PathBuf>,
+    text: String,
+    format: Option<TextFormat>,
+    recovery: State<'_, Recovery>,
+) -> Result<()> {
+    let entry = JournalEntry {
+        path,
+        text,
+        format,
+        saved_at: now(),
+    };
+    let journal = recovery.journal_path(id);
+    fs::create_dir_all(&recovery.session_dir).map_err(|e| Error::io(e, &recovery.session_dir))?;
+    let bytes = serde_json::to_vec(&entry).map_err(|e| Error::Io(e.to_string()))?;
+    atomic::write(&journal, &bytes).map_err(|e| Error::io(e, &journal))
+}
+
+/// Journals left behind by a crash. Only the first call returns them, so
+/// only one window offers to restore.
+#[tauri::command]
+pub fn take_recovered_documents(recovery: State<'_, Recovery>) -> Vec<JournalEntry> {
+    if recovery.offered.swap(true, Ordering::SeqCst) {
+        return Vec::new();
+    }
+    let orphans = recovery.orphans.lock().unwrap();
+    orphans.iter().map(|(_, entry)| entry.clone()).collect()
+}
+
+/// Reopens every recovered buffer: the first in the calling window, the
+/// rest in new windows. They are journaled again on the next tick.
+#[tauri::command]
+pub async fn restore_recovered_documents(
+    app: AppHandle,
+    window: WebviewWindow,
+    recovery: State<'_, Recovery>,
+) -> Result<()> {
+    let orphans = std::mem::take(&mut *recovery.orphans.lock().unwrap());
+    for (index, (_, entry)) in orphans.iter().enumerate() {
+        let item = Incoming::Recovered {
+            path: entry.path.clone(),
+            text: entry.text.clone(),
+            format: entry.format,
+        };
+        if index == 0 {
+            launch::deliver(&app, window.label(), vec![item]);
+        } else {
+            registry::open_window(&app, vec![item])?;
+        }
+    }
+    remove_journals(&orphans);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn discard_recovered_documents(recovery: State<'_, Recovery>) {
+    let orphans = std::mem::take(&mut *recovery.orphans.lock().unwrap());
+    remove_journals(&orphans);
+}
@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewWindow, WebviewWindowBuilder};
+
+use crate::document::TextFormat;
+use crate::error::{Error, Result};
+use crate::launch::{self, Incoming};
+
+pub const DOCUMENT_MOVED_EVENT: &str = "document-moved";
+pub const FOCUS_DOCUMENT_EVENT: &str = "focus-document";
+
+pub type DocumentId = u64;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentEntry {
+    pub id: DocumentId,
+    pub path: Option<PathBuf>,
+    pub dirty: bool,
+    /// Label of the window the document is open in.
+    pub window: String,
+}
+
+/// A document's buffer, handed from one window to another when it moves.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSnapshot {
+    pub text: String,
+    pub saved_text: String,
+    pub format: Option<TextFormat>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: DocumentId,
+    next_window: u32,
+    documents: BTreeMap<DocumentId, DocumentEntry>,
+}
+
+impl Registry {
+    /// The document that already has `path` open, other than `except`.
+    fn find_path(&self, path: &Path, except: Option<DocumentId>) -> Option<&DocumentEntry> {
+        let key = canonical(path);
+        self.documents.values().find(|doc| {
+            Some(doc.id) != except && doc.path.as_deref().map(canonical).as_ref() == Some(&key)
+        })
+    }
+}
+
+/// Every document open in any window. The registry is the single place that
+/// knows which window owns a file, so the same file is never edited in two
+/// diverging buffers.
+#[derive(Default)]
+pub struct DocumentRegistry(Mutex<Registry>);
+
+impl DocumentRegistry {
+    /// Focuses the window that has `path` open. Returns whether there was one.
+    pub fn focus_path(&self, app: &AppHandle, path: &Path) -> bool {
+        let owner = self.0.lock().unwrap().find_path(path, None).cloned();
+        match owner {
+            Some(doc) => {
+                focus_document(app, &doc);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn documents(&self) -> Vec<DocumentEntry> {
+        self.0.lock().unwrap().documents.values().cloned().collect()
+    }
+
+    /// Documents with unsaved changes, optionally only those of one window.
+    pub fn dirty_documents(&self, window: Option<&str>) -> Vec<DocumentEntry> {
+        self.0
+            .lock()
+            .unwrap()
+            .documents
+            .values()
+            .filter(|doc| doc.dirty && window.is_none_or(|label| doc.window == label))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether `path` is open in some window with unsaved changes.
+    pub fn is_dirty(&self, path: &Path) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .find_path(path, None)
+            .is_some_and(|doc| doc.dirty)
+    }
+
+    /// Forgets every document of a window that has been closed.
+    pub fn remove_window(&self, label: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .documents
+            .retain(|_, doc| doc.window != label);
+    }
+
+    fn next_window_label(&self) -> String {
+        let mut registry = self.0.lock().unwrap();
+        registry.next_window += 1;
+        format!("document-{}", registry.next_window)
+    }
+}
+
+/// Opens a new window that will receive `incoming` once its frontend loads.
+/// Labels follow the `document-*` pattern the capabilities are granted to.
+pub fn open_window(app: &AppHandle, incoming: Vec<Incoming>) -> Result<String> {
+    let label = app.state::<DocumentRegistry>().next_window_label();
+    launch::deliver(app, &label, incoming);
+
+    // Reuse the size and title of the window defined in tauri.conf.json.
+    let mut config = app
+        .config()
+        .app
+        .windows
+        .first()
+        .cloned()
+        .unwrap_or_default();
+    config.label = label.clone();
+    WebviewWindowBuilder::from_config(app, &config)
+        .and_then(|builder| builder.build())
+        .map_err(|e| Error::Io(e.to_string()))?;
+    Ok(label)
+}
+
+fn focus_document(app: &AppHandle, doc: &DocumentEntry) {
+    if let Some(window) = app.get_webview_window(&doc.window) {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+        let _ = app.emit_to(&doc.window, FOCUS_DOCUMENT_EVENT, doc.id);
+    }
+}
+
+fn already_open(app: &AppHandle, doc: &DocumentEntry) -> Error {
+    focus_document(app, doc);
+    let path = doc.path.as_deref().unwrap_or(Path::new(""));
+    Error::AlreadyOpen(format!("{} is already open", path.display()))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Registers a document in the calling window. Fails with `alreadyOpen`,
+/// after focusing the owning window, if `path` is open anywhere else.
+#[tauri::command]
+pub fn register_document(
+    app: AppHandle,
+    window: WebviewWindow,
+    path: Option<PathBuf>,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<DocumentEntry> {
+    let mut registry = registry.0.lock().unwrap();
+    if let Some(owner) = path.as_deref().and_then(|p| registry.find_path(p, None)) {
+        return Err(already_open(&app, owner));
+    }
+
+    registry.next_id += 1;
+    let doc = DocumentEntry {
+        id: registry.next_id,
+        path,
+        dirty: false,
+        window: window.label().to_string(),
+    };
+    registry.documents.insert(doc.id, doc.clone());
+    Ok(doc)
+}
+
+/// Records a new path (after "Save As") or dirty state for a document.
+#[tauri::command]
+pub fn update_document(
+    app: AppHandle,
+    id: DocumentId,
+    path: Option<PathBuf>,
+    dirty: bool,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<DocumentEntry> {
+    let mut registry = registry.0.lock().unwrap();
+    if let Some(owner) = path
+        .as_deref()
+        .and_then(|p| registry.find_path(p, Some(id)))
+    {
+        return Err(already_open(&app, owner));
+    }
+    let doc = registry
+        .documents
+        .get_mut(&id)
+        .ok_or_else(|| Error::NotFound(format!("no open document with id {id}")))?;
+    doc.path = path;
+    doc.dirty = dirty;
+    Ok(doc.clone())
+}
+
+#[tauri::command]
+pub fn close_document(id: DocumentId, registry: State<'_, DocumentRegistry>) {
+    registry.0.lock().unwrap().documents.remove(&id);
+}
+
+#[tauri::command]
+pub fn list_documents(registry: State<'_, DocumentRegistry>) -> Vec<DocumentEntry> {
+    registry.documents()
+}
+
+/// Opens a new window, optionally with `path` loaded. Must be async: creating
+/// windows from a synchronous command deadlocks on Windows.
+#[tauri::command]
+pub async fn create_window(
+    app: AppHandle,
+    path: Option<PathBuf>,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<String> {
+    if let Some(path) = &path {
+        if registry.focus_path(&app, path) {
+            return Err(Error::AlreadyOpen(format!(
+                "{} is already open",
+                path.display()
+            )));
+        }
+    }
+    let incoming = path
+        .map(|path| Incoming::File { path })
+        .into_iter()
+        .collect();
+    open_window(&app, incoming)
+}
+
+/// Moves a document, with its unsaved buffer, to `target` or to a new window
+/// when `target` is `None`. Returns the label of the receiving window.
+/// Refused when `target` has unsaved changes the move would replace.
+#[tauri::command]
+pub async fn move_document(
+    app: AppHandle,
+    id: DocumentId,
+    target: Option<String>,
+    snapshot: DocumentSnapshot,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<String> {
+    let source = registry
+        .0
+        .lock()
+        .unwrap()
+        .documents
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| Error::NotFound(format!("no open document with id {id}")))?;
+
+    let incoming = vec![Incoming::Document {
+        id,
+        path: source.path,
+        snapshot,
+    }];
+    let target = match target {
+        Some(label) if app.get_webview_window(&label).is_some() => {
+            if !registry.dirty_documents(Some(&label)).is_empty() {
+                return Err(Error::Invalid(
+                    "The other window has unsaved changes".to_string(),
+                ));
+            }
+            launch::deliver(&app, &label, incoming);
+            label
+        }
+        Some(label) => return Err(Error::NotFound(format!("no window labelled {label}"))),
+        None => open_window(&app, incoming)?,
+    };
+
+    if let Some(doc) = registry.0.lock().unwrap().documents.get_mut(&id) {
+        doc.window = target.clone();
+    }
+    let _ = app.emit_to(&source.window, DOCUMENT_MOVED_EVENT, id);
+    Ok(target)
+}
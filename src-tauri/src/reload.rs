@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use tauri::{Manager, State, WebviewWindow};
+use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+
+use crate::error::{Error, Result};
+use crate::launch::PendingDocuments;
+use crate::registry::{DocumentId, DocumentSnapshot};
+use crate::settings;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ViewMode {
+    Editing,
+    Split,
+    #[default]
+    Reading,
+}
+
+/// Where the user was in a document.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ViewState {
+    pub view_mode: ViewMode,
+    pub selection_start: usize,
+    pub selection_end: usize,
+    pub editor_scroll_top: f64,
+    pub preview_scroll_top: f64,
+}
+
+/// Everything a window needs to come back exactly as it was after its
+/// webview reloads.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WindowSnapshot {
+    pub document_id: Option<DocumentId>,
+    pub path: Option<PathBuf>,
+    pub document: DocumentSnapshot,
+    pub view: ViewState,
+}
+
+/// Snapshots taken right before a reload, keyed by window label.
+#[derive(Default)]
+pub struct ReloadSnapshots(Mutex<HashMap<String, WindowSnapshot>>);
+
+impl ReloadSnapshots {
+    /// Drops the snapshot of a window that closed before it could reload.
+    pub fn remove_window(&self, label: &str) {
+        self.0.lock().unwrap().remove(label);
+    }
+}
+
+fn reload_with(window: &WebviewWindow, snapshot: WindowSnapshot) -> Result<()> {
+    let app = window.app_handle();
+    app.state::<ReloadSnapshots>()
+        .0
+        .lock()
+        .unwrap()
+        .insert(window.label().to_string(), snapshot);
+    // The new page has to ask for queued documents again.
+    app.state::<PendingDocuments>().reset_window(window.label());
+    window.reload().map_err(|e| Error::Io(e.to_string()))
+}
+
+/// Reloads the calling window's webview, keeping its document and view.
+#[tauri::command]
+pub fn reload_window(window: WebviewWindow, snapshot: WindowSnapshot) -> Result<()> {
+    reload_with(&window, snapshot)
+}
+
+/// Returns the snapshot saved before this window last reloaded, if any.
+#[tauri::command]
+pub fn take_reload_snapshot(
+    window: WebviewWindow,
+    snapshots: State<'_, ReloadSnapshots>,
+) -> Option<WindowSnapshot> {
+    snapshots.0.lock().unwrap().remove(window.label())
+}
+
+/// After confirmation, clears the webview's cache and storage, restores the
+/// default settings and reloads. The open document is kept. Returns whether
+/// the reset went ahead.
+#[tauri::command]
+pub async fn reset_app_data(window: WebviewWindow, snapshot: WindowSnapshot) -> Result<bool> {
+    let confirmed = window
+        .dialog()
+        .message(
+            "This clears the app's cache and stored data and restores the default \
+             settings. Your open document is kept.",
+        )
+        .title("Reset App Data")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancelCustom(
+            "Reset".into(),
+            "Cancel".into(),
+        ))
+        .parent(&window)
+        .blocking_show();
+    if !confirmed {
+        return Ok(false);
+    }
+
+    window
+        .clear_all_browsing_data()
+        .map_err(|e| Error::Io(e.to_string()))?;
+    settings::reset(window.app_handle())?;
+    reload_with(&window, snapshot)?;
+    Ok(true)
+}
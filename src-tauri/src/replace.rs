@@ -0,0 +1,442 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, State};
+
+use crate::atomic;
+use crate::document;
+use crate::error::{Error, Result};
+use crate::registry::DocumentRegistry;
+use crate::workspace;
+
+/// Number of applied batches that can still be undone.
+const UNDO_LIMIT: usize = 20;
+
+/// What to replace, the same for the preview and for applying it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplaceQuery {
+    pub find: String,
+    /// With `regex`, `$1` and `${name}` stand for the captured groups.
+    pub replace: String,
+    pub regex: bool,
+    pub match_case: bool,
+}
+
+/// The replacements in one file.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePreview {
+    pub path: PathBuf,
+    /// Identifies the contents the preview was made from; applying refuses
+    /// a file that has changed since.
+    pub hash: String,
+    pub count: usize,
+    pub hunks: Vec<Hunk>,
+}
+
+/// The whole lines a replacement touches, before and after.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hunk {
+    /// 1-based line the hunk starts on.
+    pub line: usize,
+    pub before: String,
+    pub after: String,
+}
+
+/// A file from the preview the user accepted.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptedFile {
+    pub path: PathBuf,
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplaceBatch {
+    pub id: u64,
+    pub files: Vec<PathBuf>,
+    pub count: usize,
+}
+
+struct AppliedFile {
+    path: PathBuf,
+    original: Vec<u8>,
+    /// Hash of what was written, to tell whether the file was edited after.
+    hash: String,
+}
+
+struct AppliedBatch {
+    id: u64,
+    files: Vec<AppliedFile>,
+}
+
+#[derive(Default)]
+struct History {
+    next_id: u64,
+    /// Oldest first.
+    batches: Vec<AppliedBatch>,
+}
+
+/// The replace batches applied in this session, with the original contents
+/// of the files they changed.
+#[derive(Default)]
+pub struct ReplaceHistory(Mutex<History>);
+
+/// Finds what `query` would replace in every markdown file in `root`,
+/// without changing anything.
+#[tauri::command]
+pub async fn preview_replace(
+    app: AppHandle,
+    root: PathBuf,
+    query: ReplaceQuery,
+) -> Result<Vec<FilePreview>> {
+    let root = workspace::checked_root(&app, &root)?;
+    let regex = build(&query)?;
+    let mut previews = Vec::new();
+    for entry in workspace::walker(&root)
+        .build()
+        .filter_map(|entry| entry.ok())
+    {
+        if !entry.file_type().is_some_and(|kind| kind.is_file())
+            || !document::is_markdown(entry.path())
+        {
+            continue;
+        }
+        let Ok(bytes) = fs::read(entry.path()) else {
+            continue;
+        };
+        let (text, _) = document::decode(&bytes);
+        let replacements = replacements(&regex, &query, &text);
+        if replacements.is_empty() {
+            continue;
+        }
+        previews.push(FilePreview {
+            path: entry.into_path(),
+            hash: hash(&bytes),
+            count: replacements.len(),
+            hunks: hunks(&text, &replacements),
+        });
+    }
+    previews.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(previews)
+}
+
+/// Applies `query` to the accepted files. Either every file is written or,
+/// if one can't be, none are changed. Files open with unsaved changes are
+/// refused, since saving the buffer would undo the replacement.
+#[tauri::command]
+pub async fn apply_replace(
+    app: AppHandle,
+    root: PathBuf,
+    query: ReplaceQuery,
+    files: Vec<AcceptedFile>,
+    history: State<'_, ReplaceHistory>,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<ReplaceBatch> {
+    let root = workspace::checked_root(&app, &root)?;
+    let regex = build(&query)?;
+    let mut writes = Vec::new();
+    let mut count = 0;
+    for file in files {
+        let path = dunce::canonicalize(&file.path).map_err(|e| Error::io(e, &file.path))?;
+        if !path.starts_with(&root) {
+            return Err(Error::PermissionDenied(format!(
+                "{} is outside the workspace",
+                path.display()
+            )));
+        }
+        refuse_unsaved(&registry, &path)?;
+        let bytes = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+        if hash(&bytes) != file.hash {
+            return Err(Error::Invalid(format!(
+                "{} changed since the preview; preview the replacement again",
+                path.display()
+            )));
+        }
+        let (text, format) = document::decode(&bytes);
+        let replacements = replacements(&regex, &query, &text);
+        if replacements.is_empty() {
+            continue;
+        }
+        count += replacements.len();
+        let replaced = document::encode(&splice(&text, 0..text.len(), &replacements), &format)?;
+        writes.push((path, replaced, bytes));
+    }
+    write_all(&writes)?;
+
+    let mut history = history.0.lock().unwrap();
+    history.next_id += 1;
+    let batch = ReplaceBatch {
+        id: history.next_id,
+        files: writes.iter().map(|(path, _, _)| path.clone()).collect(),
+        count,
+    };
+    history.batches.push(AppliedBatch {
+        id: batch.id,
+        files: writes
+            .into_iter()
+            .map(|(path, replaced, original)| AppliedFile {
+                path,
+                original,
+                hash: hash(&replaced),
+            })
+            .collect(),
+    });
+    if history.batches.len() > UNDO_LIMIT {
+        history.batches.remove(0);
+    }
+    Ok(batch)
+}
+
+/// Puts back the files a batch changed. Refused, leaving every file as it
+/// is, when one of them was edited since or has unsaved changes.
+#[tauri::command]
+pub async fn undo_replace(
+    id: u64,
+    history: State<'_, ReplaceHistory>,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<Vec<PathBuf>> {
+    let mut history = history.0.lock().unwrap();
+    let Some(index) = history.batches.iter().position(|batch| batch.id == id) else {
+        return Err(Error::NotFound(
+            "That replacement can no longer be undone".to_string(),
+        ));
+    };
+    let batch = &history.batches[index];
+    let mut writes = Vec::new();
+    for file in &batch.files {
+        refuse_unsaved(&registry, &file.path)?;
+        let current = fs::read(&file.path).map_err(|e| Error::io(e, &file.path))?;
+        if hash(&current) != file.hash {
+            return Err(Error::Invalid(format!(
+                "{} was edited after the replacement, so it can't be undone",
+                file.path.display()
+            )));
+        }
+        writes.push((file.path.clone(), file.original.clone(), current));
+    }
+    write_all(&writes)?;
+    history.batches.remove(index);
+    Ok(writes.into_iter().map(|(path, _, _)| path).collect())
+}
+
+/// Refuses a file that a window has open with unsaved changes.
+fn refuse_unsaved(registry: &DocumentRegistry, path: &Path) -> Result<()> {
+    if registry.is_dirty(path) {
+        return Err(Error::Invalid(format!(
+            "{} has unsaved changes; save or close it first",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Writes each `(path, new, old)`, putting the old contents back in the
+/// files already written if one fails.
+fn write_all(writes: &[(PathBuf, Vec<u8>, Vec<u8>)]) -> Result<()> {
+    for (index, (path, bytes, _)) in writes.iter().enumerate() {
+        if let Err(e) = atomic::write(path, bytes) {
+            for (path, _, original) in &writes[..index] {
+                let _ = atomic::write(path, original);
+            }
+            return Err(Error::io(e, path));
+        }
+    }
+    Ok(())
+}
+
+fn build(query: &ReplaceQuery) -> Result<Regex> {
+    if query.find.is_empty() {
+        return Err(Error::Invalid("Nothing to find".to_string()));
+    }
+    let pattern = if query.regex {
+        query.find.clone()
+    } else {
+        regex::escape(&query.find)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!query.match_case)
+        .multi_line(true)
+        .build()
+        .map_err(|e| Error::Invalid(format!("Invalid regular expression: {e}")))
+}
+
+/// Each match in `text` with what replaces it.
+fn replacements(regex: &Regex, query: &ReplaceQuery, text: &str) -> Vec<(Range<usize>, String)> {
+    regex
+        .captures_iter(text)
+        .filter_map(|captures| {
+            let found = captures.get(0)?;
+            let replacement = if query.regex {
+                let mut out = String::new();
+                captures.expand(&query.replace, &mut out);
+                out
+            } else {
+                query.replace.clone()
+            };
+            (found.as_str() != replacement).then(|| (found.range(), replacement))
+        })
+        .collect()
+}
+
+/// `text[range]` with the `replacements` inside it made.
+fn splice(text: &str, range: Range<usize>, replacements: &[(Range<usize>, String)]) -> String {
+    let mut out = String::with_capacity(range.len());
+    let mut last = range.start;
+    for (found, replacement) in replacements {
+        if found.start < range.start || found.end > range.end {
+            continue;
+        }
+        out.push_str(&text[last..found.start]);
+        out.push_str(replacement);
+        last = found.end;
+    }
+    out.push_str(&text[last..range.end]);
+    out
+}
+
+/// Groups the replacements by the lines they touch, merging the ones that
+/// share a line.
+fn hunks(text: &str, replacements: &[(Range<usize>, String)]) -> Vec<Hunk> {
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    for (found, _) in replacements {
+        let start = text[..found.start].rfind('\n').map_or(0, |index| index + 1);
+        let end = text[found.end..]
+            .find('\n')
+            .map_or(text.len(), |index| found.end + index);
+        match spans.last_mut() {
+            Some(last) if start <= last.end => last.end = last.end.max(end),
+            _ => spans.push(start..end),
+        }
+    }
+    spans
+        .into_iter()
+        .map(|span| Hunk {
+            line: line_number(text, span.start),
+            before: text[span.clone()].to_string(),
+            after: splice(text, span, replacements),
+        })
+        .collect()
+}
+
+fn line_number(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+fn hash(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(find: &str, replace: &str, regex: bool, match_case: bool) -> ReplaceQuery {
+        ReplaceQuery {
+            find: find.to_string(),
+            replace: replace.to_string(),
+            regex,
+            match_case,
+        }
+    }
+
+    fn replace_all(query: &ReplaceQuery, text: &str) -> String {
+        let found = replacements(&build(query).unwrap(), query, text);
+        splice(text, 0..text.len(), &found)
+    }
+
+    #[test]
+    fn plain_text_is_escaped() {
+        let query = query("a.b", "x", false, true);
+        assert_eq!(replace_all(&query, "a.b axb a.b"), "x axb x");
+    }
+
+    #[test]
+    fn case_is_ignored_unless_asked() {
+        assert_eq!(
+            replace_all(&query("cat", "dog", false, false), "Cat cat"),
+            "dog dog"
+        );
+        assert_eq!(
+            replace_all(&query("cat", "dog", false, true), "Cat cat"),
+            "Cat dog"
+        );
+    }
+
+    #[test]
+    fn regex_groups_are_expanded() {
+        let query = query(r"(\w+)@(?<host>\w+)", "${host}:$1", true, true);
+        assert_eq!(
+            replace_all(&query, "me@home, you@work"),
+            "home:me, work:you"
+        );
+    }
+
+    #[test]
+    fn unchanged_matches_are_not_counted() {
+        let query = query("[Aa]pple", "apple", true, true);
+        let found = replacements(&build(&query).unwrap(), &query, "apple Apple");
+        assert_eq!(found, [(6..11, "apple".to_string())]);
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert!(matches!(
+            build(&query("", "x", false, true)),
+            Err(Error::Invalid(_))
+        ));
+        assert!(matches!(
+            build(&query("(", "x", true, true)),
+            Err(Error::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn splice_only_inside_range() {
+        let text = "one two one";
+        let found = vec![(0..3, "1".to_string()), (8..11, "1".to_string())];
+        assert_eq!(splice(text, 0..7, &found), "1 two");
+        assert_eq!(splice(text, 4..11, &found), "two 1");
+    }
+
+    #[test]
+    fn hunks_cover_whole_lines() {
+        let text = "first\nfoo and foo\nmiddle\nlast foo";
+        let query = query("foo", "bar", false, true);
+        let found = replacements(&build(&query).unwrap(), &query, text);
+        let hunks = hunks(text, &found);
+        let hunks: Vec<(usize, &str, &str)> = hunks
+            .iter()
+            .map(|hunk| (hunk.line, hunk.before.as_str(), hunk.after.as_str()))
+            .collect();
+        assert_eq!(
+            hunks,
+            [
+                (2, "foo and foo", "bar and bar"),
+                (4, "last foo", "last bar")
+            ]
+        );
+    }
+
+    #[test]
+    fn multiline_matches_make_one_hunk() {
+        let text = "a\nb\nc\n";
+        let query = query(r"a\nb", "ab", true, true);
+        let found = replacements(&build(&query).unwrap(), &query, text);
+        let hunks = hunks(text, &found);
+        assert_eq!(hunks.len(), 1);
+        assert_eq!((hunks[0].line, hunks[0].before.as_str()), (1, "a\nb"));
+        assert_eq!(hunks[0].after, "ab");
+    }
+}
@@ -0,0 +1,603 @@
+use std::collections::HashMap;
+
+use encoding_rs::{Encoding, BIG5, EUC_KR, GBK, MACINTOSH, SHIFT_JIS, WINDOWS_1252};
+
+use crate::gfm::{self, Block, Format, ListItem, Part, Piece};
+use crate::import::hyperlink_field;
+use crate::links;
+
+/// Destinations whose text is never part of the document.
+const SKIPPED: &[&str] = &[
+    "annotation",
+    "atnauthor",
+    "atnid",
+    "colortbl",
+    "colorschememapping",
+    "comment",
+    "datastore",
+    "docvar",
+    "filetbl",
+    "footer",
+    "footerf",
+    "footerl",
+    "footerr",
+    "footnote",
+    "ftnsep",
+    "ftnsepc",
+    "generator",
+    "header",
+    "headerf",
+    "headerl",
+    "headerr",
+    "info",
+    "latentstyles",
+    "listoverridetable",
+    "listtable",
+    "nonshppict",
+    "object",
+    "pgdsctbl",
+    "pict",
+    "private",
+    "revtbl",
+    "rsidtbl",
+    "shp",
+    "themedata",
+    "userprops",
+    "xmlnstbl",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Destination {
+    Text,
+    Fonts,
+    Styles,
+    /// The instruction of a field, like `HYPERLINK "..."`.
+    Instruction,
+    /// The bullet or number Word writes before a list paragraph.
+    ListText,
+    Skip,
+}
+
+/// Character and paragraph properties, which RTF scopes to groups.
+#[derive(Debug, Clone, Copy)]
+struct State {
+    destination: Destination,
+    format: Format,
+    hidden: bool,
+    /// How many fallback characters follow a `\u` escape.
+    unicode_skip: usize,
+    style: Option<i32>,
+    outline: Option<u8>,
+    in_table: bool,
+    level: usize,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            destination: Destination::Text,
+            format: Format::default(),
+            hidden: false,
+            unicode_skip: 1,
+            style: None,
+            outline: None,
+            in_table: false,
+            level: 0,
+        }
+    }
+}
+
+struct Field {
+    /// The group depth the field was opened at.
+    depth: usize,
+    instruction: String,
+    start: usize,
+}
+
+/// Converts RTF, as Word and TextEdit put on the clipboard, to markdown.
+pub fn to_markdown(rtf: &[u8]) -> String {
+    let mut reader = Reader::default();
+    reader.read(rtf);
+    reader.finish_paragraph();
+    reader.finish_table();
+    gfm::render(&gfm::assemble(reader.parts))
+}
+
+#[derive(Default)]
+struct Reader {
+    state: State,
+    states: Vec<State>,
+    encoding: Option<&'static Encoding>,
+    /// Whether each font is monospaced.
+    fonts: HashMap<i32, bool>,
+    font: Option<(i32, bool, String)>,
+    /// Paragraph style names, lowercase.
+    styles: HashMap<i32, String>,
+    style: Option<(i32, String)>,
+    /// Text bytes not decoded yet, so multibyte `\'hh` sequences decode
+    /// together.
+    bytes: Vec<u8>,
+    /// The first half of a UTF-16 surrogate pair written as two `\u`
+    /// escapes, waiting for the second.
+    surrogate: Option<u32>,
+    skip: usize,
+    /// Set by `\*`: an unknown destination that follows is skipped.
+    ignorable: bool,
+    pieces: Vec<Piece>,
+    list_text: String,
+    fields: Vec<Field>,
+    parts: Vec<Part>,
+    cell: Vec<String>,
+    row: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+impl Reader {
+    fn read(&mut self, rtf: &[u8]) {
+        let mut index = 0;
+        while index < rtf.len() {
+            match rtf[index] {
+                b'{' => {
+                    self.flush_bytes();
+                    self.states.push(self.state);
+                    self.skip = 0;
+                    index += 1;
+                }
+                b'}' => {
+                    self.flush_bytes();
+                    self.close_group();
+                    index += 1;
+                }
+                b'\\' => index = self.control(rtf, index + 1),
+                b'\r' | b'\n' => index += 1,
+                byte => {
+                    self.byte(byte);
+                    index += 1;
+                }
+            }
+        }
+        self.flush_bytes();
+    }
+
+    fn close_group(&mut self) {
+        let closes_field = self
+            .fields
+            .last()
+            .is_some_and(|field| field.depth == self.states.len());
+        self.state = self.states.pop().unwrap_or_default();
+        self.skip = 0;
+        if closes_field {
+            if let Some(field) = self.fields.pop() {
+                self.finish_field(field);
+            }
+        }
+    }
+
+    fn byte(&mut self, byte: u8) {
+        if self.skip > 0 {
+            self.skip -= 1;
+        } else {
+            self.bytes.push(byte);
+        }
+    }
+
+    /// Reads the control word or symbol starting at `index`, just after the
+    /// backslash, and returns the index after it.
+    fn control(&mut self, rtf: &[u8], index: usize) -> usize {
+        let Some(&first) = rtf.get(index) else {
+            return index;
+        };
+        if first == b'\'' {
+            let hex = rtf
+                .get(index + 1..index + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if let Some(byte) = hex {
+                self.byte(byte);
+            }
+            return index + 3;
+        }
+        self.flush_bytes();
+        if !first.is_ascii_alphabetic() {
+            match first {
+                b'\\' | b'{' | b'}' => self.text(&char::from(first).to_string()),
+                b'~' => self.text(" "),
+                b'_' => self.text("-"),
+                b'*' => self.ignorable = true,
+                b'\r' | b'\n' => self.finish_paragraph(),
+                _ => {}
+            }
+            return index + 1;
+        }
+        let mut end = index;
+        while end < rtf.len() && rtf[end].is_ascii_alphabetic() {
+            end += 1;
+        }
+        let word = String::from_utf8_lossy(&rtf[index..end]).into_owned();
+        let digits = end;
+        if rtf.get(end) == Some(&b'-') {
+            end += 1;
+        }
+        while end < rtf.len() && rtf[end].is_ascii_digit() {
+            end += 1;
+        }
+        let parameter = std::str::from_utf8(&rtf[digits..end])
+            .ok()
+            .and_then(|number| number.parse::<i32>().ok());
+        if rtf.get(end) == Some(&b' ') {
+            end += 1;
+        }
+        let ignorable = std::mem::take(&mut self.ignorable);
+        self.word(&word, parameter, ignorable);
+        end
+    }
+
+    fn word(&mut self, word: &str, parameter: Option<i32>, ignorable: bool) {
+        let on = parameter != Some(0);
+        let destination = self.state.destination;
+        match word {
+            "fonttbl" => self.state.destination = Destination::Fonts,
+            "stylesheet" => self.state.destination = Destination::Styles,
+            "fldinst" => self.state.destination = Destination::Instruction,
+            "fldrslt" => self.state.destination = Destination::Text,
+            "listtext" | "pntext" => self.state.destination = Destination::ListText,
+            word if SKIPPED.contains(&word) => self.state.destination = Destination::Skip,
+            "ansicpg" => self.encoding = parameter.map(code_page),
+            "mac" => self.encoding = Some(MACINTOSH),
+            "uc" => self.state.unicode_skip = parameter.unwrap_or(1).max(0) as usize,
+            "u" => {
+                if let Some(code) = parameter {
+                    // Values above 32767 are written as negative numbers.
+                    let code = if code < 0 { code + 65536 } else { code } as u32;
+                    self.unicode(code);
+                }
+                self.skip = self.state.unicode_skip;
+            }
+            _ if ignorable => self.state.destination = Destination::Skip,
+            "f" if destination == Destination::Fonts => {
+                self.finish_font();
+                self.font = parameter.map(|number| (number, false, String::new()));
+            }
+            "fmodern" if destination == Destination::Fonts => {
+                if let Some(font) = &mut self.font {
+                    font.1 = true;
+                }
+            }
+            "s" if destination == Destination::Styles => {
+                self.style = parameter.map(|number| (number, String::new()));
+            }
+            "cs" | "ds" | "ts" if destination == Destination::Styles => self.style = None,
+            _ if destination != Destination::Text => {}
+            "par" => self.finish_paragraph(),
+            "line" => self.pieces.push(Piece::Markup("\\\n".to_string())),
+            "tab" => self.text(" "),
+            "emdash" => self.text("\u{2014}"),
+            "endash" => self.text("\u{2013}"),
+            "bullet" => self.text("\u{2022}"),
+            "lquote" => self.text("\u{2018}"),
+            "rquote" => self.text("\u{2019}"),
+            "ldblquote" => self.text("\u{201c}"),
+            "rdblquote" => self.text("\u{201d}"),
+            "enspace" | "emspace" | "qmspace" => self.text(" "),
+            "pard" => {
+                self.state.style = None;
+                self.state.outline = None;
+                self.state.in_table = false;
+                self.state.level = 0;
+            }
+            "plain" => {
+                self.state.format = Format::default();
+                self.state.hidden = false;
+            }
+            "b" => self.state.format.bold = on,
+            "i" => self.state.format.italic = on,
+            "strike" | "striked" => self.state.format.strike = on,
+            "v" => self.state.hidden = on,
+            "f" => {
+                self.state.format.code = parameter
+                    .and_then(|number| self.fonts.get(&number))
+                    .copied()
+                    .unwrap_or(false);
+            }
+            "s" => self.state.style = parameter,
+            "outlinelevel" => self.state.outline = parameter.map(|level| level.clamp(0, 9) as u8),
+            "intbl" => self.state.in_table = true,
+            "ilvl" => self.state.level = parameter.unwrap_or(0).clamp(0, 8) as usize,
+            "cell" => {
+                self.finish_paragraph();
+                self.row.push(std::mem::take(&mut self.cell).join("\\\n"));
+            }
+            "row" => {
+                let row = std::mem::take(&mut self.row);
+                self.rows.push(row);
+            }
+            "field" => self.fields.push(Field {
+                depth: self.states.len(),
+                instruction: String::new(),
+                start: self.pieces.len(),
+            }),
+            _ => {}
+        }
+    }
+
+    /// Adds the UTF-16 code unit of a `\u` escape. Characters outside the
+    /// Basic Multilingual Plane, like emoji, come as two of them.
+    fn unicode(&mut self, code: u32) {
+        let high = self.surrogate.take();
+        if (0xd800..0xdc00).contains(&code) {
+            if high.is_some() {
+                self.text("\u{fffd}");
+            }
+            self.surrogate = Some(code);
+            return;
+        }
+        let c = match high {
+            Some(high) if (0xdc00..0xe000).contains(&code) => {
+                char::from_u32(0x10000 + ((high - 0xd800) << 10) + (code - 0xdc00))
+            }
+            Some(_) => {
+                self.text("\u{fffd}");
+                char::from_u32(code)
+            }
+            None => char::from_u32(code),
+        };
+        self.text(&c.unwrap_or('\u{fffd}').to_string());
+    }
+
+    fn flush_bytes(&mut self) {
+        if self.bytes.is_empty() {
+            return;
+        }
+        let bytes = std::mem::take(&mut self.bytes);
+        let encoding = self.encoding.unwrap_or(WINDOWS_1252);
+        let (text, _) = encoding.decode_without_bom_handling(&bytes);
+        self.text(&text);
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.surrogate.take().is_some() {
+            self.text("\u{fffd}");
+        }
+        match self.state.destination {
+            Destination::Text if !self.state.hidden => {
+                self.pieces
+                    .push(Piece::Text(text.to_string(), self.state.format));
+            }
+            Destination::Fonts => {
+                for c in text.chars() {
+                    match (c, &mut self.font) {
+                        (';', _) => self.finish_font(),
+                        (c, Some((_, _, name))) => name.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            Destination::Styles => {
+                for c in text.chars() {
+                    match (c, &mut self.style) {
+                        (';', _) => {
+                            if let Some((number, name)) = self.style.take() {
+                                self.styles.insert(number, name.trim().to_lowercase());
+                            }
+                        }
+                        (c, Some((_, name))) => name.push(c),
+                        _ => {}
+                    }
+                }
+            }
+            Destination::Instruction => {
+                if let Some(field) = self.fields.last_mut() {
+                    field.instruction.push_str(text);
+                }
+            }
+            Destination::ListText => self.list_text.push_str(text),
+            _ => {}
+        }
+    }
+
+    fn finish_font(&mut self) {
+        if let Some((number, modern, name)) = self.font.take() {
+            let name = name.to_ascii_lowercase();
+            let monospace = modern
+                || ["mono", "courier", "consolas", "menlo", "monaco"]
+                    .iter()
+                    .any(|font| name.contains(font));
+            self.fonts.insert(number, monospace);
+        }
+    }
+
+    fn finish_field(&mut self, field: Field) {
+        let Some((Some(url), anchor)) = hyperlink_field(&field.instruction) else {
+            return;
+        };
+        if !links::is_safe(&url) {
+            return;
+        }
+        let url = match anchor {
+            Some(anchor) => format!("{url}#{anchor}"),
+            None => url,
+        };
+        let pieces = self.pieces.split_off(field.start.min(self.pieces.len()));
+        let text = gfm::inline(&pieces, false);
+        if !text.trim().is_empty() {
+            self.pieces
+                .push(Piece::Markup(gfm::link(&text, &url, None)));
+        }
+    }
+
+    fn finish_paragraph(&mut self) {
+        let pieces = std::mem::take(&mut self.pieces);
+        let list_text = std::mem::take(&mut self.list_text);
+        // Field results end with the paragraph.
+        for field in &mut self.fields {
+            field.start = 0;
+        }
+        let code = !pieces.is_empty()
+            && pieces.iter().all(|piece| match piece {
+                Piece::Text(text, format) => format.code || text.trim().is_empty(),
+                Piece::Markup(_) => false,
+            });
+        let text = gfm::inline(&pieces, code);
+        if self.state.in_table {
+            if !text.trim().is_empty() {
+                self.cell.push(text.trim().to_string());
+            }
+            return;
+        }
+        self.finish_table();
+        let style = self
+            .state
+            .style
+            .and_then(|style| self.styles.get(&style))
+            .map(String::as_str)
+            .unwrap_or_default();
+        let heading = match style {
+            "title" => Some(1),
+            style => style
+                .strip_prefix("heading ")
+                .and_then(|level| level.parse::<u8>().ok()),
+        }
+        .or(self.state.outline.map(|level| level + 1))
+        .filter(|level| (1..=6).contains(level));
+        let marker = list_text.trim();
+        if !marker.is_empty() {
+            let number = marker
+                .strip_suffix(['.', ')'])
+                .filter(|number| !number.is_empty() && number.chars().all(char::is_alphanumeric));
+            self.parts.push(Part::Item(ListItem {
+                level: self.state.level,
+                ordered: number.is_some(),
+                number: number.and_then(|number| number.parse().ok()).unwrap_or(1),
+                text: text.trim().to_string(),
+            }));
+        } else if code {
+            self.parts.push(Part::Code(text));
+        } else if text.trim().is_empty() {
+            // Empty paragraphs only space the blocks apart.
+        } else if let Some(level) = heading {
+            self.parts.push(Part::Block(Block::Heading(level, text)));
+        } else if style.contains("quote") {
+            self.parts.push(Part::Quote(text));
+        } else {
+            self.parts.push(Part::Block(Block::Paragraph(text)));
+        }
+    }
+
+    fn finish_table(&mut self) {
+        if !self.row.is_empty() {
+            let row = std::mem::take(&mut self.row);
+            self.rows.push(row);
+        }
+        if self.rows.is_empty() {
+            return;
+        }
+        let rows = std::mem::take(&mut self.rows);
+        self.parts.push(Part::Block(Block::Table {
+            rows,
+            align: Vec::new(),
+        }));
+    }
+}
+
+fn code_page(number: i32) -> &'static Encoding {
+    match number {
+        932 => SHIFT_JIS,
+        936 => GBK,
+        949 => EUC_KR,
+        950 => BIG5,
+        10000 => MACINTOSH,
+        _ => Encoding::for_label(format!("windows-{number}").as_bytes()).unwrap_or(WINDOWS_1252),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surrogate_pairs() {
+        let rtf = br"{\rtf1\ansi\uc1 Hi \u-10179?\u-8704?!\par}";
+        assert_eq!(to_markdown(rtf).trim(), "Hi \u{1f600}!");
+        let lone = br"{\rtf1\ansi\uc1 a\u-10179?b\par}";
+        assert_eq!(to_markdown(lone).trim(), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn code_pages() {
+        assert_eq!(to_markdown(br"{\rtf1\ansi caf\'e9\par}"), "café\n");
+        let cyrillic = br"{\rtf1\ansi\ansicpg1251 \'cf\'f0\'e8\'e2\'e5\'f2\par}";
+        assert_eq!(to_markdown(cyrillic), "Привет\n");
+        let japanese = br"{\rtf1\ansi\ansicpg932 \'82\'a0\'82\'a2\par}";
+        assert_eq!(to_markdown(japanese), "あい\n");
+        let mac = br"{\rtf1\mac caf\'8e\par}";
+        assert_eq!(to_markdown(mac), "café\n");
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(to_markdown(br"{\rtf1\ansi \u233?t\u233?\par}"), "été\n");
+        assert_eq!(to_markdown(br"{\rtf1\ansi\uc0 \u233 t\u233 \par}"), "été\n");
+        assert_eq!(
+            to_markdown(br"{\rtf1\ansi\uc2 \u8364\'80\'80 5\par}"),
+            "€ 5\n"
+        );
+        // `\uc` is scoped to its group.
+        let scoped = br"{\rtf1\ansi {\uc2 \u8364??}\u8364?\par}";
+        assert_eq!(to_markdown(scoped), "€€\n");
+    }
+
+    #[test]
+    fn hyperlinks() {
+        let rtf = br#"{\rtf1\ansi See {\field{\*\fldinst{HYPERLINK "https://example.com/a"}}{\fldrslt{\ul the site}}}.\par}"#;
+        assert_eq!(to_markdown(rtf), "See [the site](https://example.com/a).\n");
+        let anchor = br#"{\rtf1\ansi {\field{\*\fldinst HYPERLINK "https://x.org" \\l "top"}{\fldrslt up}}\par}"#;
+        assert_eq!(to_markdown(anchor), "[up](https://x.org#top)\n");
+        let script = br#"{\rtf1\ansi {\field{\*\fldinst HYPERLINK "javascript:alert(1)"}{\fldrslt click}}\par}"#;
+        assert_eq!(to_markdown(script), "click\n");
+    }
+
+    #[test]
+    fn lists() {
+        let rtf = br"{\rtf1\ansi
+{\listtext 1.\tab}First\par
+{\listtext 2.\tab}Second\par
+\pard\ilvl1{\listtext \'95\tab}Inner\par
+\pard{\listtext 3.\tab}Third\par
+\pard After\par}";
+        assert_eq!(
+            to_markdown(rtf),
+            "1. First\n2. Second\n   - Inner\n3. Third\n\nAfter\n"
+        );
+    }
+
+    #[test]
+    fn tables() {
+        let rtf = br"{\rtf1\ansi
+\trowd\cellx1000\cellx2000
+\intbl Name\cell Qty\cell\row
+\intbl a|b\par c\cell 1\cell\row
+\pard After\par}";
+        assert_eq!(
+            to_markdown(rtf),
+            "| Name | Qty |\n| --- | --- |\n| a\\|b<br>c | 1 |\n\nAfter\n"
+        );
+    }
+
+    #[test]
+    fn styles_and_fonts() {
+        let rtf = br"{\rtf1\ansi
+{\fonttbl{\f0\froman Times;}{\f1\fmodern Courier New;}{\f2\fswiss Menlo;}}
+{\stylesheet{\s0 Normal;}{\s1 heading 1;}{\s2 Quote;}}
+{\s1 Title here\par}
+\pard\s2 Quoted\par
+\pard\f1 let a = 1;\par
+\f2 let b = 2;\par
+\pard\f0 {\b bold} {\i it}{\v hidden} {\strike gone}\par
+\pard\outlinelevel1 Outline\par}";
+        assert_eq!(
+            to_markdown(rtf),
+            "# Title here\n\n> Quoted\n\n```\nlet a = 1;\nlet b = 2;\n```\n\n\
+             **bold** *it* ~~gone~~\n\n## Outline\n"
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::path::Path;
+
+use tauri::{AppHandle, Manager};
+use tauri_plugin_fs::FsExt;
+
+use crate::error::{Error, Result};
+
+/// Refuses `path` unless it is inside the folders the app may use: the
+/// asset protocol scope from `tauri.conf.json`, or a file or folder the user
+/// picked in a dialog or dropped on a window, which are added at runtime.
+pub fn check(app: &AppHandle, path: &Path) -> Result<()> {
+    let allowed = app.asset_protocol_scope().is_allowed(path)
+        || app
+            .try_fs_scope()
+            .is_some_and(|scope| scope.is_allowed(path));
+    if allowed {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied(format!(
+            "{} is outside the folders the app can open",
+            path.display()
+        )))
+    }
+}
+
+/// Lets the frontend open a file the user chose outside a dialog: one passed
+/// on the command line, through "Open With" or from the recent files.
+pub fn allow_file(app: &AppHandle, path: &Path) {
+    let _ = app.asset_protocol_scope().allow_file(path);
+    if let Some(scope) = app.try_fs_scope() {
+        let _ = scope.allow_file(path);
+    }
+}
@@ -0,0 +1,919 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::UNIX_EPOCH;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use pulldown_cmark::{Event as MarkdownEvent, Tag, TagEnd};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{AppHandle, Manager, State, WebviewWindow};
+
+use crate::atomic;
+use crate::document;
+use crate::error::{Error, Result};
+use crate::markdown;
+use crate::workspace;
+
+/// Bumped whenever `Note` changes shape, so old indexes are rebuilt.
+const INDEX_VERSION: u32 = 1;
+
+const INDEX_DIR: &str = "search";
+
+const DEFAULT_LIMIT: usize = 50;
+
+/// Matching lines shown per file.
+const SNIPPETS_PER_FILE: usize = 3;
+
+/// Longer lines are cut down to this many characters around the first match.
+const SNIPPET_LENGTH: usize = 160;
+
+/// BM25 parameters, at their usual values.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// How much more a match in the title or a heading counts than one in the
+/// text.
+const TITLE_BOOST: f64 = 3.0;
+const HEADING_BOOST: f64 = 1.5;
+const TAG_BOOST: f64 = 2.0;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredIndex {
+    version: u32,
+    notes: HashMap<PathBuf, Note>,
+}
+
+/// `StoredIndex` as it is written, borrowing the notes.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredIndexRef<'a> {
+    version: u32,
+    notes: &'a HashMap<PathBuf, Note>,
+}
+
+/// What the index knows about one markdown file.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Note {
+    /// Modification time in milliseconds and size, to tell whether the file
+    /// changed since it was indexed.
+    modified: u64,
+    size: u64,
+    title: String,
+    headings: Vec<Heading>,
+    tags: Vec<String>,
+    text: String,
+    /// The words of `text` and where they are, rebuilt on load like the
+    /// fields below, so searches don't split every note again.
+    #[serde(skip)]
+    tokens: Vec<Token>,
+    /// How often each word occurs in `text`.
+    #[serde(skip)]
+    terms: HashMap<String, u32>,
+    /// Where each line of `text` starts.
+    #[serde(skip)]
+    lines: Vec<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Heading {
+    /// 1-based, like the line numbers in results.
+    line: usize,
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexStatus {
+    pub files: usize,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResult {
+    pub path: PathBuf,
+    pub title: String,
+    pub score: f64,
+    pub snippets: Vec<Snippet>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Snippet {
+    /// 1-based line number in the file.
+    pub line: usize,
+    pub text: String,
+    /// The matches in `text`, as UTF-16 offsets like JavaScript strings use.
+    pub ranges: Vec<[usize; 2]>,
+}
+
+/// An opened folder and its index.
+struct Workspace {
+    root: PathBuf,
+    file: PathBuf,
+    notes: HashMap<PathBuf, Note>,
+    /// Set by the watcher when something in the folder changed, so the next
+    /// search brings the index up to date first.
+    stale: Arc<AtomicBool>,
+    watcher: Option<RecommendedWatcher>,
+}
+
+/// A folder's index and the labels of the windows that have it open; the
+/// index is dropped with the last.
+struct Opened {
+    windows: HashSet<String>,
+    /// Locked on its own, so indexing one folder doesn't hold up the others.
+    workspace: Arc<Mutex<Workspace>>,
+}
+
+/// The full-text indexes of the opened folders, kept in
+/// `<app data>/search/` between runs.
+pub struct SearchIndex {
+    dir: PathBuf,
+    workspaces: Mutex<HashMap<PathBuf, Opened>>,
+}
+
+impl SearchIndex {
+    pub fn new(app: &AppHandle) -> tauri::Result<Self> {
+        Ok(Self {
+            dir: app.path().app_data_dir()?.join(INDEX_DIR),
+            workspaces: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Stops keeping the folders `label` had open up to date, unless other
+    /// windows have them open too.
+    pub fn remove_window(&self, label: &str) {
+        self.workspaces.lock().unwrap().retain(|_, opened| {
+            opened.windows.remove(label);
+            !opened.windows.is_empty()
+        });
+    }
+
+    fn open(&self, root: PathBuf) -> Workspace {
+        let hash = Sha256::digest(root.to_string_lossy().as_bytes());
+        let name: String = hash[..8].iter().map(|byte| format!("{byte:02x}")).collect();
+        let file = self.dir.join(format!("{name}.json"));
+        let notes = fs::read(&file)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<StoredIndex>(&bytes).ok())
+            .filter(|stored| stored.version == INDEX_VERSION)
+            .map(|stored| stored.notes)
+            .unwrap_or_default();
+        let stale = Arc::new(AtomicBool::new(true));
+        let handler_stale = stale.clone();
+        // Without a watcher the folder is rescanned before every search.
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            if res.is_ok_and(|event| !matches!(event.kind, EventKind::Access(_))) {
+                handler_stale.store(true, Ordering::Relaxed);
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&root, RecursiveMode::Recursive)?;
+            Ok(watcher)
+        })
+        .ok();
+        let mut workspace = Workspace {
+            root,
+            file,
+            notes,
+            stale,
+            watcher,
+        };
+        for note in workspace.notes.values_mut() {
+            note.analyze();
+        }
+        workspace
+    }
+}
+
+impl Workspace {
+    /// Re-indexes the files that changed since the last search.
+    fn refresh(&mut self) {
+        let stale = self.stale.swap(false, Ordering::Relaxed);
+        if (stale || self.watcher.is_none()) && self.sync() {
+            self.persist();
+        }
+    }
+
+    /// Walks the folder, reading the markdown files whose modification time
+    /// or size changed and dropping the ones that are gone. Returns whether
+    /// anything changed.
+    fn sync(&mut self) -> bool {
+        let mut seen = HashSet::new();
+        let mut changed = false;
+        for entry in workspace::walker(&self.root)
+            .build()
+            .filter_map(|entry| entry.ok())
+        {
+            if !entry.file_type().is_some_and(|kind| kind.is_file())
+                || !document::is_markdown(entry.path())
+            {
+                continue;
+            }
+            let path = entry.into_path();
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |time| time.as_millis() as u64);
+            let size = metadata.len();
+            seen.insert(path.clone());
+            let unchanged = self
+                .notes
+                .get(&path)
+                .is_some_and(|note| note.modified == modified && note.size == size);
+            if unchanged {
+                continue;
+            }
+            let Ok(bytes) = fs::read(&path) else {
+                continue;
+            };
+            let (text, _) = document::decode(&bytes);
+            let note = Note::new(&path, text, modified, size);
+            self.notes.insert(path, note);
+            changed = true;
+        }
+        let before = self.notes.len();
+        self.notes.retain(|path, _| seen.contains(path));
+        changed || self.notes.len() != before
+    }
+
+    /// Failing to write the index only costs a rescan next time, so errors
+    /// are ignored.
+    fn persist(&self) {
+        let stored = StoredIndexRef {
+            version: INDEX_VERSION,
+            notes: &self.notes,
+        };
+        if let Some(dir) = self.file.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        if let Ok(bytes) = serde_json::to_vec(&stored) {
+            let _ = atomic::write(&self.file, &bytes);
+        }
+    }
+
+    fn search(&self, clauses: &[Clause], limit: usize) -> Vec<SearchResult> {
+        let count = self.notes.len() as f64;
+        let average = self
+            .notes
+            .values()
+            .map(|note| note.tokens.len())
+            .sum::<usize>() as f64
+            / count.max(1.0);
+        let weights: Vec<f64> = clauses
+            .iter()
+            .map(|clause| match &clause.pattern {
+                Pattern::Words { words, prefix } if clause.field != Field::Tag => words
+                    .iter()
+                    .enumerate()
+                    .map(|(index, word)| {
+                        let prefix = *prefix && index == words.len() - 1;
+                        let frequency = self
+                            .notes
+                            .values()
+                            .filter(|note| note.has_word(word, prefix))
+                            .count() as f64;
+                        (1.0 + (count - frequency + 0.5) / (frequency + 0.5)).ln()
+                    })
+                    .sum(),
+                _ => 1.0,
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        'notes: for (path, note) in &self.notes {
+            if !clauses.iter().all(|clause| note.may_match(clause)) {
+                continue;
+            }
+            let lines = &note.lines;
+            let mut score = 0.0;
+            let mut hit_lines = Vec::new();
+            for (clause, weight) in clauses.iter().zip(&weights) {
+                let in_title = clause.pattern.matches(&note.title);
+                let mut headings = note
+                    .headings
+                    .iter()
+                    .filter(|heading| clause.pattern.matches(&heading.text))
+                    .map(|heading| heading.line)
+                    .peekable();
+                match clause.field {
+                    Field::All => {
+                        let found = clause.pattern.find(&note.text, &note.tokens);
+                        if found.is_empty() {
+                            continue 'notes;
+                        }
+                        let frequency = found.len() as f64;
+                        let length = note.tokens.len() as f64 / average.max(1.0);
+                        score += weight * frequency * (K1 + 1.0)
+                            / (frequency + K1 * (1.0 - B + B * length));
+                        if in_title {
+                            score += weight * TITLE_BOOST;
+                        }
+                        if headings.peek().is_some() {
+                            score += weight * HEADING_BOOST;
+                        }
+                        hit_lines.extend(found.iter().map(|range| line_at(lines, range.start)));
+                    }
+                    Field::Title if in_title => score += weight * TITLE_BOOST,
+                    Field::Heading if headings.peek().is_some() => {
+                        score += weight * HEADING_BOOST;
+                        hit_lines.extend(headings);
+                    }
+                    Field::Tag if note.tags.iter().any(|tag| clause.pattern.matches_tag(tag)) => {
+                        score += weight * TAG_BOOST
+                    }
+                    _ => continue 'notes,
+                }
+            }
+            hit_lines.sort_unstable();
+            hit_lines.dedup();
+            let snippets = hit_lines
+                .into_iter()
+                .take(SNIPPETS_PER_FILE)
+                .filter_map(|line| {
+                    let start = *lines.get(line - 1)?;
+                    let end = lines.get(line).map_or(note.text.len(), |end| end - 1);
+                    Some(snippet(&note.text[start..end], line, clauses))
+                })
+                .collect();
+            results.push(SearchResult {
+                path: path.clone(),
+                title: note.title.clone(),
+                score,
+                snippets,
+            });
+        }
+        results.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        results.truncate(limit);
+        results
+    }
+}
+
+impl Note {
+    fn new(path: &Path, text: String, modified: u64, size: u64) -> Self {
+        let (fields, body) = markdown::front_matter(&text);
+        let body_start = text.len() - body.len();
+        let lines = line_starts(&text);
+        let mut title = None;
+        let mut tags = Vec::new();
+        for (key, values) in fields {
+            match key.as_str() {
+                "title" => title = values.into_iter().next(),
+                "tags" | "tag" | "keywords" => tags.extend(
+                    values
+                        .iter()
+                        .flat_map(|value| value.split(','))
+                        .map(|tag| tag.trim().trim_start_matches('#').to_lowercase())
+                        .filter(|tag| !tag.is_empty()),
+                ),
+                _ => {}
+            }
+        }
+
+        let mut headings = Vec::new();
+        let mut heading: Option<Heading> = None;
+        let mut in_code_block = false;
+        for (event, range) in markdown::events_with_offsets(body) {
+            match event {
+                MarkdownEvent::Start(Tag::Heading { .. }) => {
+                    heading = Some(Heading {
+                        line: line_at(&lines, body_start + range.start),
+                        text: String::new(),
+                    })
+                }
+                MarkdownEvent::End(TagEnd::Heading(_)) => headings.extend(heading.take()),
+                MarkdownEvent::Start(Tag::CodeBlock(_)) => in_code_block = true,
+                MarkdownEvent::End(TagEnd::CodeBlock) => in_code_block = false,
+                MarkdownEvent::Text(text) => {
+                    if let Some(heading) = &mut heading {
+                        heading.text.push_str(&text);
+                    }
+                    if !in_code_block {
+                        inline_tags(&text, &mut tags);
+                    }
+                }
+                MarkdownEvent::Code(text) => {
+                    if let Some(heading) = &mut heading {
+                        heading.text.push_str(&text);
+                    }
+                }
+                _ => {}
+            }
+        }
+        tags.sort();
+        tags.dedup();
+
+        let title = title
+            .filter(|title| !title.trim().is_empty())
+            .or_else(|| markdown::title(body))
+            .or_else(|| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default();
+        let mut note = Self {
+            modified,
+            size,
+            title,
+            headings,
+            tags,
+            text,
+            tokens: Vec::new(),
+            terms: HashMap::new(),
+            lines: Vec::new(),
+        };
+        note.analyze();
+        note
+    }
+
+    /// Fills in the fields searching needs that aren't stored.
+    fn analyze(&mut self) {
+        self.tokens = tokenize(&self.text);
+        self.lines = line_starts(&self.text);
+        self.terms.clear();
+        for token in &self.tokens {
+            *self.terms.entry(token.word.clone()).or_default() += 1;
+        }
+    }
+
+    fn has_word(&self, word: &str, prefix: bool) -> bool {
+        if prefix {
+            self.terms.keys().any(|term| term.starts_with(word))
+        } else {
+            self.terms.contains_key(word)
+        }
+    }
+
+    /// A quick check against the words in the note, before looking for the
+    /// clause in its text.
+    fn may_match(&self, clause: &Clause) -> bool {
+        match (&clause.field, &clause.pattern) {
+            (Field::All, Pattern::Words { words, prefix }) => words
+                .iter()
+                .enumerate()
+                .all(|(index, word)| self.has_word(word, *prefix && index == words.len() - 1)),
+            _ => true,
+        }
+    }
+}
+
+/// Adds the `#tags` written in `text`. A tag starts after a space or at the
+/// start of the text and needs a letter, so `#1` issue numbers aren't tags.
+fn inline_tags(text: &str, tags: &mut Vec<String>) {
+    let mut previous = None;
+    for (index, c) in text.char_indices() {
+        if c == '#' && previous.is_none_or(char::is_whitespace) {
+            let rest = &text[index + 1..];
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '/')))
+                .unwrap_or(rest.len());
+            let tag = rest[..end].trim_end_matches(['-', '/']);
+            if tag.chars().any(char::is_alphabetic) {
+                tags.push(tag.to_lowercase());
+            }
+        }
+        previous = Some(c);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    All,
+    Title,
+    Heading,
+    Tag,
+}
+
+enum Pattern {
+    /// Words that follow each other; the last may be the start of a word.
+    /// A tag is matched as a whole instead.
+    Words {
+        words: Vec<String>,
+        prefix: bool,
+    },
+    Regex(Regex),
+}
+
+struct Clause {
+    field: Field,
+    pattern: Pattern,
+}
+
+impl Pattern {
+    /// The byte ranges of the matches in `text`, whose words are `tokens`.
+    fn find(&self, text: &str, tokens: &[Token]) -> Vec<Range<usize>> {
+        match self {
+            Pattern::Words { words, prefix } => {
+                if words.is_empty() || tokens.len() < words.len() {
+                    return Vec::new();
+                }
+                (0..=tokens.len() - words.len())
+                    .filter(|&start| {
+                        words.iter().enumerate().all(|(index, word)| {
+                            let token = &tokens[start + index].word;
+                            if *prefix && index == words.len() - 1 {
+                                token.starts_with(word.as_str())
+                            } else {
+                                token == word
+                            }
+                        })
+                    })
+                    .map(|start| {
+                        tokens[start].range.start..tokens[start + words.len() - 1].range.end
+                    })
+                    .collect()
+            }
+            Pattern::Regex(regex) => regex
+                .find_iter(text)
+                .filter(|found| !found.is_empty())
+                .map(|found| found.range())
+                .collect(),
+        }
+    }
+
+    fn matches(&self, text: &str) -> bool {
+        !self.find(text, &tokenize(text)).is_empty()
+    }
+
+    fn matches_tag(&self, tag: &str) -> bool {
+        match self {
+            Pattern::Words { words, prefix } => words.first().is_some_and(|word| {
+                tag == word || (*prefix && tag.starts_with(word.as_str()))
+                    // `tag:project` finds `#project/alpha` too.
+                    || tag.strip_prefix(word.as_str()).is_some_and(|rest| rest.starts_with('/'))
+            }),
+            Pattern::Regex(regex) => regex.is_match(tag),
+        }
+    }
+}
+
+/// Parses a query: words that must all be found, `"exact phrases"`,
+/// `prefix*` words and `/regular expressions/`, each of which can be limited
+/// to the title, the headings or the tags with `title:`, `heading:` or
+/// `tag:`. `#tag` is short for `tag:tag`.
+fn parse(query: &str) -> Result<Vec<Clause>> {
+    let mut clauses = Vec::new();
+    let mut rest = query.trim_start();
+    while !rest.is_empty() {
+        let (field, after) = field_prefix(rest);
+        rest = after;
+        let (pattern, after) = if let Some(inner) = rest.strip_prefix('"') {
+            let end = inner.find('"').unwrap_or(inner.len());
+            let after = inner.get(end + 1..).unwrap_or_default();
+            (words(&inner[..end], field, false), after)
+        } else if let Some((expression, after)) = regex_literal(rest) {
+            let regex = RegexBuilder::new(expression)
+                .case_insensitive(true)
+                .build()
+                .map_err(|e| Error::Invalid(format!("Invalid regular expression: {e}")))?;
+            (Some(Pattern::Regex(regex)), after)
+        } else {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let (word, after) = rest.split_at(end);
+            let (word, prefix) = match word.strip_suffix('*') {
+                Some(word) => (word, true),
+                None => (word, false),
+            };
+            (words(word, field, prefix), after)
+        };
+        if let Some(pattern) = pattern {
+            clauses.push(Clause { field, pattern });
+        }
+        rest = after.trim_start();
+    }
+    Ok(clauses)
+}
+
+fn field_prefix(text: &str) -> (Field, &str) {
+    for (name, field) in [
+        ("title:", Field::Title),
+        ("heading:", Field::Heading),
+        ("tag:", Field::Tag),
+    ] {
+        let rest = text
+            .get(..name.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(name))
+            .map(|_| &text[name.len()..]);
+        if let Some(rest) = rest.filter(|rest| !rest.starts_with(char::is_whitespace)) {
+            return (field, rest);
+        }
+    }
+    match text.strip_prefix('#') {
+        Some(rest) if rest.starts_with(|c: char| c.is_alphanumeric()) => (Field::Tag, rest),
+        _ => (Field::All, text),
+    }
+}
+
+/// The expression between `/` and the next unescaped `/`, and what follows.
+fn regex_literal(text: &str) -> Option<(&str, &str)> {
+    let inner = text.strip_prefix('/')?;
+    let mut escaped = false;
+    for (index, c) in inner.char_indices() {
+        match c {
+            '\\' => escaped = !escaped,
+            '/' if !escaped && index > 0 => return Some((&inner[..index], &inner[index + 1..])),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+fn words(text: &str, field: Field, prefix: bool) -> Option<Pattern> {
+    let words: Vec<String> = if field == Field::Tag {
+        let tag = text.trim().trim_start_matches('#').to_lowercase();
+        if tag.is_empty() {
+            return None;
+        }
+        vec![tag]
+    } else {
+        tokenize(text).into_iter().map(|token| token.word).collect()
+    };
+    (!words.is_empty()).then_some(Pattern::Words { words, prefix })
+}
+
+#[derive(Debug)]
+struct Token {
+    word: String,
+    range: Range<usize>,
+}
+
+/// The words in `text`, lowercased: runs of letters, digits and `_`.
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (index, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        let word = c.is_alphanumeric() || c == '_';
+        match (start, word) {
+            (None, true) => start = Some(index),
+            (Some(from), false) => {
+                tokens.push(Token {
+                    word: text[from..index].to_lowercase(),
+                    range: from..index,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// The byte offset each line of `text` starts at.
+fn line_starts(text: &str) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+        .collect()
+}
+
+/// The 1-based line `offset` is on.
+fn line_at(lines: &[usize], offset: usize) -> usize {
+    lines.partition_point(|&start| start <= offset)
+}
+
+/// `line` with the matches of `clauses` marked, cut down around the first
+/// match when it is long.
+fn snippet(line: &str, number: usize, clauses: &[Clause]) -> Snippet {
+    let line = line.trim();
+    let tokens = tokenize(line);
+    let mut found: Vec<Range<usize>> = clauses
+        .iter()
+        .filter(|clause| matches!(clause.field, Field::All | Field::Heading))
+        .flat_map(|clause| clause.pattern.find(line, &tokens))
+        .collect();
+    found.sort_by_key(|range| range.start);
+
+    let mut start = 0;
+    let mut end = line.len();
+    if line.chars().count() > SNIPPET_LENGTH {
+        let first = found.first().map_or(0, |range| range.start);
+        start = line[..first]
+            .char_indices()
+            .rev()
+            .nth(SNIPPET_LENGTH / 4)
+            .map_or(0, |(index, _)| index);
+        end = line[start..]
+            .char_indices()
+            .nth(SNIPPET_LENGTH)
+            .map_or(line.len(), |(index, _)| start + index);
+    }
+    let mut text = String::new();
+    if start > 0 {
+        text.push('…');
+    }
+    let offset = text.len();
+    text.push_str(&line[start..end]);
+    if end < line.len() {
+        text.push('…');
+    }
+    let utf16 = |index: usize| text[..index].encode_utf16().count();
+    let ranges = found
+        .into_iter()
+        .filter(|range| range.start >= start && range.end <= end)
+        .map(|range| {
+            [
+                utf16(range.start - start + offset),
+                utf16(range.end - start + offset),
+            ]
+        })
+        .collect();
+    Snippet {
+        line: number,
+        text,
+        ranges,
+    }
+}
+
+/// Indexes the markdown files in `root`, reusing the index saved last time
+/// for the files that haven't changed, and keeps it up to date while the
+/// folder is open.
+#[tauri::command]
+pub async fn open_search_index(
+    app: AppHandle,
+    window: WebviewWindow,
+    root: PathBuf,
+    index: State<'_, SearchIndex>,
+) -> Result<IndexStatus> {
+    let root = workspace::checked_root(&app, &root)?;
+    let existing = index
+        .workspaces
+        .lock()
+        .unwrap()
+        .get_mut(&root)
+        .map(|opened| {
+            opened.windows.insert(window.label().to_string());
+            opened.workspace.clone()
+        });
+    let workspace = match existing {
+        Some(workspace) => workspace,
+        None => {
+            // Loading the saved index and starting the watcher happen outside
+            // the lock; if another window opened the folder meanwhile, its
+            // index wins.
+            let fresh = index.open(root.clone());
+            let mut workspaces = index.workspaces.lock().unwrap();
+            let opened = workspaces.entry(root).or_insert_with(|| Opened {
+                windows: HashSet::new(),
+                workspace: Arc::new(Mutex::new(fresh)),
+            });
+            opened.windows.insert(window.label().to_string());
+            opened.workspace.clone()
+        }
+    };
+    let mut workspace = workspace.lock().unwrap();
+    workspace.refresh();
+    Ok(IndexStatus {
+        files: workspace.notes.len(),
+    })
+}
+
+/// Stops keeping the index of `root` up to date once no window has it open.
+/// Windows that close without calling this are let go by `remove_window`.
+#[tauri::command]
+pub async fn close_search_index(
+    window: WebviewWindow,
+    root: PathBuf,
+    index: State<'_, SearchIndex>,
+) -> Result<()> {
+    let root = dunce::canonicalize(&root).map_err(|e| Error::io(e, &root))?;
+    let mut workspaces = index.workspaces.lock().unwrap();
+    if let Some(opened) = workspaces.get_mut(&root) {
+        opened.windows.remove(window.label());
+        if opened.windows.is_empty() {
+            workspaces.remove(&root);
+        }
+    }
+    Ok(())
+}
+
+/// Searches the markdown files in `root`, which must have been opened with
+/// `open_search_index`, best matches first. See `parse` for the query
+/// syntax.
+#[tauri::command]
+pub async fn search_workspace(
+    app: AppHandle,
+    root: PathBuf,
+    query: String,
+    limit: Option<usize>,
+    index: State<'_, SearchIndex>,
+) -> Result<Vec<SearchResult>> {
+    let clauses = parse(&query)?;
+    if clauses.is_empty() {
+        return Ok(Vec::new());
+    }
+    let root = workspace::checked_root(&app, &root)?;
+    let workspace = index
+        .workspaces
+        .lock()
+        .unwrap()
+        .get(&root)
+        .map(|opened| opened.workspace.clone())
+        .ok_or_else(|| Error::Invalid(format!("{} is not open", root.display())))?;
+    let mut workspace = workspace.lock().unwrap();
+    workspace.refresh();
+    Ok(workspace.search(&clauses, limit.unwrap_or(DEFAULT_LIMIT)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each clause as its field and its words, with `*` after a prefix, or
+    /// its expression between slashes.
+    fn parsed(query: &str) -> Vec<(Field, String)> {
+        parse(query)
+            .unwrap()
+            .into_iter()
+            .map(|clause| {
+                let pattern = match clause.pattern {
+                    Pattern::Words { words, prefix } => {
+                        words.join(" ") + if prefix { "*" } else { "" }
+                    }
+                    Pattern::Regex(regex) => format!("/{}/", regex.as_str()),
+                };
+                (clause.field, pattern)
+            })
+            .collect()
+    }
+
+    fn clause(field: Field, pattern: &str) -> (Field, String) {
+        (field, pattern.to_string())
+    }
+
+    #[test]
+    fn words_and_phrases() {
+        assert_eq!(
+            parsed("Alpha  \"Beta, gamma\" delt*"),
+            [
+                clause(Field::All, "alpha"),
+                clause(Field::All, "beta gamma"),
+                clause(Field::All, "delt*"),
+            ]
+        );
+        assert_eq!(
+            parsed("\"unclosed phrase"),
+            [clause(Field::All, "unclosed phrase")]
+        );
+        assert!(parsed("  ... ").is_empty());
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(
+            parsed("title:Plan HEADING:\"next steps\" tag:#Work #home/garden"),
+            [
+                clause(Field::Title, "plan"),
+                clause(Field::Heading, "next steps"),
+                clause(Field::Tag, "work"),
+                clause(Field::Tag, "home/garden"),
+            ]
+        );
+        // Without a value, or before a non-word, they are plain words.
+        assert_eq!(
+            parsed("title: x #1st # y"),
+            [
+                clause(Field::All, "title"),
+                clause(Field::All, "x"),
+                clause(Field::Tag, "1st"),
+                clause(Field::All, "y"),
+            ]
+        );
+    }
+
+    #[test]
+    fn regular_expressions() {
+        assert_eq!(
+            parsed(r"/a\/b/ heading:/^To+/ 1/2"),
+            [
+                clause(Field::All, r"/a\/b/"),
+                clause(Field::Heading, "/^To+/"),
+                clause(Field::All, "1 2"),
+            ]
+        );
+        assert!(matches!(parse("/(/"), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn tokens() {
+        let words: Vec<String> = tokenize("Héllo, wörld_2! x")
+            .into_iter()
+            .map(|token| token.word)
+            .collect();
+        assert_eq!(words, ["héllo", "wörld_2", "x"]);
+    }
+}
@@ -0,0 +1,407 @@
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tauri::{AppHandle, Emitter, Manager, State};
+
+use crate::atomic;
+use crate::error::{Error, Result};
+use crate::images::ImageConfig;
+use crate::menu;
+use crate::recovery::{Recovery, RecoveryConfig};
+
+pub const SETTINGS_CHANGED_EVENT: &str = "settings-changed";
+
+const SETTINGS_FILE: &str = "settings.json";
+
+/// Bumped whenever a field is renamed or changes meaning; `migrate` brings
+/// older files up to date.
+const CURRENT_VERSION: u32 = 1;
+
+const FONT_SIZES: RangeInclusive<u32> = 12..=24;
+const BACKUPS: RangeInclusive<usize> = 0..=10;
+const JOURNAL_INTERVALS: RangeInclusive<u64> = 5..=3600;
+const RETENTION_DAYS: RangeInclusive<u64> = 1..=365;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+    Dim,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccentColor {
+    #[default]
+    Blue,
+    Green,
+    Mauve,
+    Flamingo,
+    Peach,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontFamily {
+    #[default]
+    Sans,
+    Serif,
+    Mono,
+}
+
+/// User preferences shared by every window. Missing fields take their
+/// default, so files written by older versions still load.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    pub version: u32,
+    pub theme: Theme,
+    pub accent_color: AccentColor,
+    pub font_family: FontFamily,
+    pub font_size: u32,
+    pub sync_scroll: bool,
+    /// Reopen the last session's documents when launched without files.
+    pub restore_session: bool,
+    /// Number of `.bak` copies kept when saving.
+    pub backups: usize,
+    pub recovery: RecoveryConfig,
+    pub images: ImageConfig,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            theme: Theme::default(),
+            accent_color: AccentColor::default(),
+            font_family: FontFamily::default(),
+            font_size: 16,
+            sync_scroll: true,
+            restore_session: true,
+            backups: 0,
+            recovery: RecoveryConfig::default(),
+            images: ImageConfig::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn validate(&self) -> Result<()> {
+        check("fontSize", &FONT_SIZES, self.font_size)?;
+        check("backups", &BACKUPS, self.backups)?;
+        check(
+            "recovery.intervalSecs",
+            &JOURNAL_INTERVALS,
+            self.recovery.interval_secs,
+        )?;
+        check(
+            "recovery.retentionDays",
+            &RETENTION_DAYS,
+            self.recovery.retention_days,
+        )?;
+        self.images.validate()
+    }
+
+    /// Replaces out-of-range values, e.g. from a hand-edited file, with
+    /// their defaults instead of discarding the whole file.
+    fn sanitized(mut self) -> Self {
+        let defaults = Settings::default();
+        if !FONT_SIZES.contains(&self.font_size) {
+            self.font_size = defaults.font_size;
+        }
+        if !BACKUPS.contains(&self.backups) {
+            self.backups = defaults.backups;
+        }
+        if !JOURNAL_INTERVALS.contains(&self.recovery.interval_secs) {
+            self.recovery.interval_secs = defaults.recovery.interval_secs;
+        }
+        if !RETENTION_DAYS.contains(&self.recovery.retention_days) {
+            self.recovery.retention_days = defaults.recovery.retention_days;
+        }
+        if self.images.validate().is_err() {
+            self.images = defaults.images;
+        }
+        self.version = self.version.max(CURRENT_VERSION);
+        self
+    }
+}
+
+fn check<T: PartialOrd + std::fmt::Display>(
+    name: &str,
+    range: &RangeInclusive<T>,
+    value: T,
+) -> Result<()> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(Error::Invalid(format!(
+            "{name} must be between {} and {}, got {value}",
+            range.start(),
+            range.end()
+        )))
+    }
+}
+
+/// Upgrades a settings file written by an older version to the current
+/// format. Each step is guarded by `if version < N` and rewrites `value`
+/// into version `N`, oldest first. Version 1 is the first format, so there
+/// are no steps yet. Files from a newer version are read as-is: unknown
+/// fields are ignored, and missing ones or values this version doesn't
+/// know take their default.
+fn migrate(value: Value) -> Value {
+    value
+}
+
+/// The settings file in the platform config directory, and its contents.
+pub struct SettingsStore {
+    path: PathBuf,
+    settings: Mutex<Settings>,
+}
+
+impl SettingsStore {
+    /// Loads the settings file, falling back to defaults if it is missing or
+    /// unreadable.
+    pub fn load(app: &AppHandle) -> tauri::Result<Self> {
+        let path = app.path().app_config_dir()?.join(SETTINGS_FILE);
+        let settings = fs::read(&path)
+            .ok()
+            .and_then(|bytes| parse(&bytes))
+            .unwrap_or_default();
+        Ok(Self {
+            path,
+            settings: Mutex::new(settings),
+        })
+    }
+
+    pub fn get(&self) -> Settings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Applies `change` to the current settings, then validates and saves
+    /// the result. The lock is held throughout, so two changes made at once
+    /// can't undo each other.
+    fn update(&self, change: impl FnOnce(&mut Settings)) -> Result<Settings> {
+        let mut current = self.settings.lock().unwrap();
+        let mut settings = current.clone();
+        change(&mut settings);
+        // A file from a newer version keeps its version, so that version
+        // doesn't migrate it again.
+        settings.version = current.version.max(CURRENT_VERSION);
+        settings.validate()?;
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(|e| Error::io(e, dir))?;
+        }
+        let bytes = serde_json::to_vec_pretty(&settings).map_err(|e| Error::Io(e.to_string()))?;
+        atomic::write(&self.path, &bytes).map_err(|e| Error::io(e, &self.path))?;
+        *current = settings.clone();
+        Ok(settings)
+    }
+}
+
+/// Reads a settings file, `None` if it isn't a JSON object. Values that
+/// can't be read, like a theme this version doesn't have, take their
+/// default without affecting the rest.
+fn parse(bytes: &[u8]) -> Option<Settings> {
+    let value = serde_json::from_slice::<Value>(bytes).ok()?;
+    if !value.is_object() {
+        return None;
+    }
+    let mut merged = serde_json::to_value(Settings::default()).ok()?;
+    overlay(&mut merged, &mut Vec::new(), migrate(value));
+    let settings = serde_json::from_value::<Settings>(merged).ok()?;
+    Some(settings.sanitized())
+}
+
+/// Lays `value` over the field of `merged` at `path`, one field at a time
+/// for objects, keeping each only if `merged` still reads as `Settings`.
+fn overlay(merged: &mut Value, path: &mut Vec<String>, value: Value) {
+    let slot = path.iter().fold(&mut *merged, |slot, key| &mut slot[key]);
+    let value = match value {
+        Value::Object(fields) if slot.is_object() => {
+            for (key, field) in fields {
+                path.push(key);
+                overlay(merged, path, field);
+                path.pop();
+            }
+            return;
+        }
+        value => value,
+    };
+    let old = std::mem::replace(slot, value);
+    if serde_json::from_value::<Settings>(merged.clone()).is_err() {
+        *path.iter().fold(merged, |slot, key| &mut slot[key]) = old;
+    }
+}
+
+/// Changes the settings in `store`, saves them and tells every window, and
+/// the backend, about them.
+fn apply(
+    app: &AppHandle,
+    store: &SettingsStore,
+    change: impl FnOnce(&mut Settings),
+) -> Result<Settings> {
+    let settings = store.update(change)?;
+    app.state::<Recovery>().set_config(settings.recovery);
+    menu::sync_settings(app, &settings);
+    let _ = app.emit(SETTINGS_CHANGED_EVENT, &settings);
+    Ok(settings)
+}
+
+/// Changes some settings from the backend, e.g. from the menu.
+pub fn update(app: &AppHandle, change: impl FnOnce(&mut Settings)) -> Result<Settings> {
+    apply(app, &app.state::<SettingsStore>(), change)
+}
+
+/// Restores the default settings, as if the app had never been configured.
+pub fn reset(app: &AppHandle) -> Result<Settings> {
+    apply(app, &app.state::<SettingsStore>(), |settings| {
+        *settings = Settings::default()
+    })
+}
+
+#[tauri::command]
+pub fn get_settings(store: State<'_, SettingsStore>) -> Settings {
+    store.get()
+}
+
+/// Validates and saves `settings`, then broadcasts `settings-changed`.
+#[tauri::command]
+pub async fn set_settings(
+    app: AppHandle,
+    settings: Settings,
+    store: State<'_, SettingsStore>,
+) -> Result<Settings> {
+    apply(&app, &store, |current| *current = settings)
+}
+
+#[tauri::command]
+pub async fn reset_settings(app: AppHandle) -> Result<Settings> {
+    reset(&app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_are_valid() {
+        Settings::default().validate().unwrap();
+    }
+
+    #[test]
+    fn out_of_range_values_are_refused() {
+        let changes: [fn(&mut Settings); 6] = [
+            |settings| settings.font_size = 11,
+            |settings| settings.font_size = 25,
+            |settings| settings.backups = 11,
+            |settings| settings.recovery.interval_secs = 4,
+            |settings| settings.recovery.retention_days = 0,
+            |settings| settings.images.max_dimension = 100,
+        ];
+        for change in changes {
+            let mut settings = Settings::default();
+            change(&mut settings);
+            assert!(matches!(settings.validate(), Err(Error::Invalid(_))));
+        }
+        let mut settings = Settings::default();
+        settings.images.folder = "../outside".to_string();
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn out_of_range_values_are_reset() {
+        let settings = parse(
+            br#"{
+                "theme": "dark",
+                "fontSize": 99,
+                "backups": 3,
+                "recovery": { "intervalSecs": 1, "retentionDays": 30 },
+                "images": { "folder": "/etc", "maxDimension": 1024 }
+            }"#,
+        )
+        .unwrap();
+        let defaults = Settings::default();
+        assert_eq!(settings.theme, Theme::Dark);
+        assert_eq!(settings.font_size, defaults.font_size);
+        assert_eq!(settings.backups, 3);
+        assert_eq!(
+            settings.recovery.interval_secs,
+            defaults.recovery.interval_secs
+        );
+        assert_eq!(settings.recovery.retention_days, 30);
+        // The image settings are checked as a whole.
+        assert_eq!(settings.images, defaults.images);
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn partial_and_unknown_fields() {
+        let settings =
+            parse(br#"{ "version": 0, "fontFamily": "mono", "later": [1, 2] }"#).unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                font_family: FontFamily::Mono,
+                ..Settings::default()
+            }
+        );
+        assert_eq!(parse(b"{}"), Some(Settings::default()));
+    }
+
+    #[test]
+    fn unreadable_values_take_their_default() {
+        let settings = parse(
+            br#"{
+                "theme": "neon",
+                "accentColor": "peach",
+                "fontSize": "large",
+                "syncScroll": false,
+                "recovery": { "intervalSecs": "often", "retentionDays": 30 },
+                "images": 5
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                accent_color: AccentColor::Peach,
+                sync_scroll: false,
+                recovery: RecoveryConfig {
+                    retention_days: 30,
+                    ..RecoveryConfig::default()
+                },
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn newer_files_keep_their_version() {
+        let temp = tempfile::tempdir().unwrap();
+        let settings = parse(br#"{ "version": 7, "theme": "dim" }"#).unwrap();
+        assert_eq!(settings.version, 7);
+        let store = SettingsStore {
+            path: temp.path().join(SETTINGS_FILE),
+            settings: Mutex::new(settings),
+        };
+        let saved = store
+            .update(|settings| *settings = Settings::default())
+            .unwrap();
+        assert_eq!(saved.version, 7);
+        let written = parse(&fs::read(temp.path().join(SETTINGS_FILE)).unwrap()).unwrap();
+        assert_eq!(written, saved);
+    }
+
+    #[test]
+    fn unreadable_files() {
+        assert_eq!(parse(b""), None);
+        assert_eq!(parse(b"[1, 2]"), None);
+        assert_eq!(parse(b"\"theme\""), None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tauri::AppHandle;
+use uuid::Uuid;
+
+use crate::launch;
+
+const APP_IDENTIFIER: &str = "com.uluckaymak.mark-it-down";
+const LOCK_FILE_NAME: &str = "com.uluckaymak.mark-it-down.lock";
+const PORT_FILE_NAME: &str = "com.uluckaymak.mark-it-down.port";
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const IO_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often, and how far apart, a launch tries to reach the instance that
+/// holds the lock, which may still be starting up.
+const FORWARD_ATTEMPTS: u32 = 20;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// What a second launch sends to the running instance.
+#[derive(Serialize, Deserialize)]
+struct Invocation {
+    token: String,
+    cwd: PathBuf,
+    args: Vec<String>,
+}
+
+pub enum Instance {
+    /// No other instance is running; this process should start the app.
+    Primary(Primary),
+    /// The arguments were handed to the running instance; exit quietly.
+    Forwarded,
+}
+
+/// The running instance's end of the handshake. It holds an OS lock on the
+/// lock file for as long as it runs, which a crash releases too, and the port
+/// file next to it records the loopback port it listens on and a token that
+/// only the current user can read, so other users can't inject files.
+pub struct Primary {
+    listener: TcpListener,
+    token: String,
+    lock: LockFile,
+}
+
+pub struct LockFile {
+    file: File,
+    port_file: PathBuf,
+}
+
+/// Forwards `args` to an already running instance if there is one, and
+/// otherwise claims the lock so later launches forward to us. A launch
+/// without files is forwarded too, so the running instance comes to the
+/// front.
+pub fn acquire(cwd: &Path, args: &[String]) -> io::Result<Instance> {
+    acquire_in(&lock_dir()?, cwd, args)
+}
+
+/// `acquire` with the lock and port files in `dir`.
+fn acquire_in(dir: &Path, cwd: &Path, args: &[String]) -> io::Result<Instance> {
+    let file = open_private(&dir.join(LOCK_FILE_NAME), false)?;
+    let port_file = dir.join(PORT_FILE_NAME);
+    for _ in 0..FORWARD_ATTEMPTS {
+        match file.try_lock() {
+            Ok(()) => {
+                let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+                let token = Uuid::new_v4().simple().to_string();
+                let port = listener.local_addr()?.port();
+                open_private(&port_file, true)?
+                    .write_all(format!("{port} {token}\n").as_bytes())?;
+                return Ok(Instance::Primary(Primary {
+                    listener,
+                    token,
+                    lock: LockFile { file, port_file },
+                }));
+            }
+            Err(TryLockError::WouldBlock) => {}
+            Err(TryLockError::Error(err)) => return Err(err),
+        }
+        // The instance holding the lock may not have written its port yet.
+        if let Ok(contents) = fs::read_to_string(&port_file) {
+            match forward(&contents, cwd, args) {
+                Ok(()) => return Ok(Instance::Forwarded),
+                // It is listening but doesn't answer; waiting won't help.
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                    ) =>
+                {
+                    return Err(err)
+                }
+                Err(_) => {}
+            }
+        }
+        thread::sleep(RETRY_DELAY);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "the running instance didn't answer",
+    ))
+}
+
+impl Primary {
+    /// Serves later launches in the background for as long as the app runs.
+    pub fn listen(self, app: AppHandle) -> LockFile {
+        let token = self.token;
+        let listener = self.listener;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Some(invocation) = receive(stream, &token) {
+                    let paths = launch::paths_from_args(invocation.args, &invocation.cwd);
+                    if paths.is_empty() {
+                        launch::focus_app(&app);
+                    } else {
+                        launch::open_files(&app, paths);
+                    }
+                }
+            }
+        });
+        self.lock
+    }
+}
+
+impl LockFile {
+    /// Removes the port file and lets go of the lock on exit.
+    pub fn release(&self) {
+        let _ = fs::remove_file(&self.port_file);
+        let _ = self.file.unlock();
+    }
+}
+
+fn forward(lock: &str, cwd: &Path, args: &[String]) -> io::Result<()> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed lock file");
+    let (port, token) = lock.trim().split_once(' ').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+    let invocation = Invocation {
+        token: token.to_string(),
+        cwd: cwd.to_path_buf(),
+        args: args.to_vec(),
+    };
+    let mut message = serde_json::to_vec(&invocation)?;
+    message.push(b'\n');
+    stream.write_all(&message)?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.trim_end() == "ok" {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected reply",
+        ))
+    }
+}
+
+fn receive(stream: TcpStream, token: &str) -> Option<Invocation> {
+    stream.set_read_timeout(Some(IO_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(IO_TIMEOUT)).ok()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+
+    let invocation: Invocation = serde_json::from_str(&line).ok()?;
+    if invocation.token != token {
+        return None;
+    }
+    reader.get_mut().write_all(b"ok\n").ok()?;
+    Some(invocation)
+}
+
+/// `$XDG_RUNTIME_DIR` on Linux; elsewhere the app's local data dir, which,
+/// unlike the temp dir, is never shared between users.
+fn lock_dir() -> io::Result<PathBuf> {
+    let dir = dirs::runtime_dir()
+        .or_else(|| dirs::data_local_dir().map(|dir| dir.join(APP_IDENTIFIER)))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no folder for the lock file"))?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Opens `path` for writing, readable by the current user only.
+fn open_private(path: &Path, truncate: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(truncate);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn second_launch_is_forwarded() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let cwd = Path::new("/");
+
+        let Ok(Instance::Primary(primary)) = acquire_in(dir, cwd, &[]) else {
+            panic!("the first launch should take the lock");
+        };
+        let Primary {
+            listener,
+            token,
+            lock,
+        } = primary;
+        let server = thread::spawn(move || {
+            let stream = listener.incoming().next().unwrap().unwrap();
+            receive(stream, &token).unwrap()
+        });
+
+        // Without files, so the running instance only comes to the front.
+        assert!(matches!(acquire_in(dir, cwd, &[]), Ok(Instance::Forwarded)));
+        let invocation = server.join().unwrap();
+        assert!(invocation.args.is_empty());
+        assert_eq!(invocation.cwd, cwd);
+
+        lock.release();
+        assert!(!dir.join(PORT_FILE_NAME).exists());
+        assert!(matches!(
+            acquire_in(dir, cwd, &[]),
+            Ok(Instance::Primary(_))
+        ));
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, State};
+
+use crate::document::{self, TextFormat};
+use crate::error::{Error, Result};
+use crate::merge;
+use crate::scope;
+
+pub const DOCUMENT_CHANGED_EVENT: &str = "document-changed";
+
+/// How long a file moved away may wait for its new name to be reported.
+const RENAME_WAIT: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Modified,
+    Removed,
+    Renamed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentChanged {
+    /// The path as the frontend opened it.
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+    pub renamed_to: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub text: String,
+    pub conflicts: usize,
+    /// The on-disk contents the merge was made against; becomes the new
+    /// saved state of the buffer.
+    pub theirs: String,
+    pub format: TextFormat,
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    /// Hash of the contents we last read or wrote, used to ignore events
+    /// caused by our own saves.
+    fingerprint: Option<u64>,
+}
+
+#[derive(Default)]
+struct Watched {
+    files: HashMap<PathBuf, WatchedFile>,
+    /// Number of watched files per directory. Directories are watched
+    /// instead of files so atomic replaces by other editors are still seen.
+    dirs: HashMap<PathBuf, usize>,
+    /// Watched files moved away whose new name may still be reported, by
+    /// the rename's tracker.
+    renaming: HashMap<usize, PathBuf>,
+}
+
+/// Watches open documents for changes made by other programs.
+pub struct DocumentWatcher {
+    watcher: Mutex<RecommendedWatcher>,
+    watched: Arc<Mutex<Watched>>,
+}
+
+impl DocumentWatcher {
+    pub fn new(app: AppHandle) -> notify::Result<Self> {
+        let watched = Arc::new(Mutex::new(Watched::default()));
+        let handler_watched = watched.clone();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+            if let Ok(event) = res {
+                handle_event(&app, &handler_watched, event);
+            }
+        })?;
+        Ok(Self {
+            watcher: Mutex::new(watcher),
+            watched,
+        })
+    }
+
+    /// Starts watching `path`, or refreshes its fingerprint if it is already
+    /// watched. `contents` are the bytes we know to be on disk.
+    pub fn watch(&self, path: &Path, contents: &[u8]) -> notify::Result<()> {
+        let key = canonical(path);
+        let dir = key.parent().map(Path::to_path_buf).unwrap_or_default();
+        {
+            let mut watched = self.watched.lock().unwrap();
+            if let Some(file) = watched.files.get_mut(&key) {
+                file.fingerprint = Some(fingerprint(contents));
+                return Ok(());
+            }
+        }
+
+        // Some backends wait for their event thread while (un)watching, so
+        // `watched` must not be held here or the event handler deadlocks.
+        self.watcher
+            .lock()
+            .unwrap()
+            .watch(&dir, RecursiveMode::NonRecursive)?;
+
+        let mut watched = self.watched.lock().unwrap();
+        *watched.dirs.entry(dir).or_default() += 1;
+        watched.files.insert(
+            key,
+            WatchedFile {
+                path: path.to_path_buf(),
+                fingerprint: Some(fingerprint(contents)),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn unwatch(&self, path: &Path) {
+        let key = canonical(path);
+        let dir = key.parent().map(Path::to_path_buf).unwrap_or_default();
+        {
+            let mut watched = self.watched.lock().unwrap();
+            if watched.files.remove(&key).is_none() {
+                return;
+            }
+            let Some(count) = watched.dirs.get_mut(&dir) else {
+                return;
+            };
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            watched.dirs.remove(&dir);
+        }
+        let _ = self.watcher.lock().unwrap().unwatch(&dir);
+    }
+}
+
+fn handle_event(app: &AppHandle, shared: &Arc<Mutex<Watched>>, event: Event) {
+    let mut watched = shared.lock().unwrap();
+
+    // inotify reports the old name on its own first, then both names once
+    // it sees the new one; only the second is a rename, not a removal.
+    if let (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(tracker)) =
+        (event.kind, event.tracker())
+    {
+        if let [from] = event.paths.as_slice() {
+            if watched.files.contains_key(from) {
+                watched.renaming.insert(tracker, from.clone());
+                wait_for_rename(app.clone(), shared.clone(), tracker);
+                return;
+            }
+        }
+    }
+
+    if let EventKind::Modify(ModifyKind::Name(RenameMode::Both)) = event.kind {
+        if let Some(tracker) = event.tracker() {
+            watched.renaming.remove(&tracker);
+        }
+        if let [from, to] = event.paths.as_slice() {
+            if let Some(file) = watched.files.get_mut(from) {
+                file.fingerprint = None;
+                // The document follows its file, wherever it was moved.
+                scope::allow_file(app, to);
+                emit(app, &file.path, ChangeKind::Renamed, Some(to.clone()));
+                return;
+            }
+        }
+    }
+
+    for path in &event.paths {
+        let Some(file) = watched.files.get_mut(path) else {
+            continue;
+        };
+        match fs::read(path) {
+            Ok(contents) => {
+                let current = Some(fingerprint(&contents));
+                if file.fingerprint != current {
+                    file.fingerprint = current;
+                    emit(app, &file.path, ChangeKind::Modified, None);
+                }
+            }
+            Err(_) if file.fingerprint.is_some() => {
+                file.fingerprint = None;
+                emit(app, &file.path, ChangeKind::Removed, None);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+/// A file moved out of the watched folders never gets its new name
+/// reported, so it counts as removed if that doesn't arrive in time.
+fn wait_for_rename(app: AppHandle, shared: Arc<Mutex<Watched>>, tracker: usize) {
+    thread::spawn(move || {
+        thread::sleep(RENAME_WAIT);
+        let mut watched = shared.lock().unwrap();
+        let Some(path) = watched.renaming.remove(&tracker) else {
+            return;
+        };
+        if let Some(file) = watched.files.get_mut(&path) {
+            if file.fingerprint.is_some() && !path.exists() {
+                file.fingerprint = None;
+                emit(&app, &file.path, ChangeKind::Removed, None);
+            }
+        }
+    });
+}
+
+fn emit(app: &AppHandle, path: &Path, kind: ChangeKind, renamed_to: Option<PathBuf>) {
+    let _ = app.emit(
+        DOCUMENT_CHANGED_EVENT,
+        DocumentChanged {
+            path: path.to_path_buf(),
+            kind,
+            renamed_to,
+        },
+    );
+}
+
+/// Resolves the watched file's directory so keys match the absolute paths
+/// notify reports. The file itself may not exist (e.g. mid-replace).
+fn canonical(path: &Path) -> PathBuf {
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => fs::canonicalize(dir)
+            .map(|dir| dir.join(name))
+            .unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn fingerprint(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[tauri::command]
+pub async fn watch_document(
+    app: AppHandle,
+    path: PathBuf,
+    watcher: State<'_, DocumentWatcher>,
+) -> Result<()> {
+    scope::check(&app, &path)?;
+    let contents = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+    watcher
+        .watch(&path, &contents)
+        .map_err(|e| Error::Io(e.to_string()))
+}
+
+#[tauri::command]
+pub fn unwatch_document(path: PathBuf, watcher: State<'_, DocumentWatcher>) {
+    watcher.unwatch(&path);
+}
+
+/// Merges the on-disk version of `path` into the unsaved buffer, using the
+/// last saved text as the common ancestor.
+#[tauri::command]
+pub async fn merge_document(
+    app: AppHandle,
+    path: PathBuf,
+    base: String,
+    ours: String,
+    watcher: State<'_, DocumentWatcher>,
+) -> Result<MergeResult> {
+    scope::check(&app, &path)?;
+    let bytes = fs::read(&path).map_err(|e| Error::io(e, &path))?;
+    let _ = watcher.watch(&path, &bytes);
+    let (theirs, format) = document::decode(&bytes);
+    let merged = merge::three_way(&base, &ours, &theirs);
+    Ok(MergeResult {
+        text: merged.text,
+        conflicts: merged.conflicts,
+        theirs,
+        format,
+    })
+}
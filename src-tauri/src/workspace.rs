@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+use serde::Serialize;
+use tauri::{AppHandle, State};
+
+use crate::atomic;
+use crate::document;
+use crate::error::{Error, Result};
+use crate::images;
+use crate::links;
+use crate::registry::DocumentRegistry;
+use crate::scope;
+
+/// Characters Windows doesn't allow in file names, refused everywhere so a
+/// vault can be synced between systems.
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryKind {
+    Folder,
+    Markdown,
+    Asset,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub kind: EntryKind,
+}
+
+/// What a rename or move did, so the frontend can follow open documents.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceChange {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Markdown files whose links were rewritten, at their new paths.
+    pub updated: Vec<PathBuf>,
+    /// Markdown files whose links still point at the old paths.
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// A file whose links were left as they were, and why.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// The folders, markdown files and images directly in `dir`, or in the
+/// workspace `root` itself, leaving out hidden and `.gitignore`d ones.
+/// Folders are listed first; their contents are listed when expanded.
+#[tauri::command]
+pub async fn list_workspace(
+    app: AppHandle,
+    root: PathBuf,
+    dir: Option<PathBuf>,
+) -> Result<Vec<TreeEntry>> {
+    let root = checked_root(&app, &root)?;
+    let dir = match dir {
+        Some(dir) => inside(&root, &dir)?,
+        None => root,
+    };
+    let mut entries: Vec<TreeEntry> = walker(&dir)
+        .max_depth(Some(1))
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.depth() == 1)
+        .filter_map(|entry| {
+            let kind = if entry.file_type().is_some_and(|kind| kind.is_dir()) {
+                EntryKind::Folder
+            } else if document::is_markdown(entry.path()) {
+                EntryKind::Markdown
+            } else if images::is_image(entry.path()) {
+                EntryKind::Asset
+            } else {
+                return None;
+            };
+            Some(TreeEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: entry.into_path(),
+                kind,
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| match (a.kind, b.kind) {
+        (EntryKind::Folder, EntryKind::Folder) => compare_names(&a.name, &b.name),
+        (EntryKind::Folder, _) => Ordering::Less,
+        (_, EntryKind::Folder) => Ordering::Greater,
+        _ => compare_names(&a.name, &b.name),
+    });
+    Ok(entries)
+}
+
+/// Creates an empty markdown file, or a folder, in `parent`. A file name
+/// without an extension gets `.md`.
+#[tauri::command]
+pub async fn create_workspace_entry(
+    app: AppHandle,
+    root: PathBuf,
+    parent: PathBuf,
+    name: String,
+    folder: bool,
+) -> Result<TreeEntry> {
+    let root = checked_root(&app, &root)?;
+    let parent = inside(&root, &parent)?;
+    let name = check_name(&name)?;
+    let name = if folder || Path::new(&name).extension().is_some() {
+        name
+    } else {
+        format!("{name}.md")
+    };
+    let path = parent.join(&name);
+    let kind = if folder {
+        fs::create_dir(&path).map_err(|e| Error::io(e, &path))?;
+        EntryKind::Folder
+    } else {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| Error::io(e, &path))?;
+        if document::is_markdown(&path) {
+            EntryKind::Markdown
+        } else {
+            EntryKind::Asset
+        }
+    };
+    Ok(TreeEntry { name, path, kind })
+}
+
+/// Renames a file or folder, updating the links to it.
+#[tauri::command]
+pub async fn rename_workspace_entry(
+    app: AppHandle,
+    root: PathBuf,
+    path: PathBuf,
+    name: String,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<WorkspaceChange> {
+    let root = checked_root(&app, &root)?;
+    let from = entry_inside(&root, &path)?;
+    let name = check_name(&name)?;
+    let to = from.with_file_name(name);
+    move_entry(&root, from, to, &registry)
+}
+
+/// Moves a file or folder into `folder`, updating the links to it.
+#[tauri::command]
+pub async fn move_workspace_entry(
+    app: AppHandle,
+    root: PathBuf,
+    path: PathBuf,
+    folder: PathBuf,
+    registry: State<'_, DocumentRegistry>,
+) -> Result<WorkspaceChange> {
+    let root = checked_root(&app, &root)?;
+    let from = entry_inside(&root, &path)?;
+    let folder = inside(&root, &folder)?;
+    if folder.starts_with(&from) {
+        return Err(Error::Invalid(format!(
+            "Can't move {} into itself",
+            from.display()
+        )));
+    }
+    let Some(name) = from.file_name() else {
+        return Err(Error::Invalid(format!("Can't move {}", from.display())));
+    };
+    let to = folder.join(name);
+    move_entry(&root, from, to, &registry)
+}
+
+/// Moves a file or folder to the system trash.
+#[tauri::command]
+pub async fn delete_workspace_entry(app: AppHandle, root: PathBuf, path: PathBuf) -> Result<()> {
+    let root = checked_root(&app, &root)?;
+    let path = entry_inside(&root, &path)?;
+    if path == root {
+        return Err(Error::Invalid(
+            "Can't delete the workspace folder".to_string(),
+        ));
+    }
+    trash::delete(&path).map_err(|e| Error::Io(format!("{}: {e}", path.display())))
+}
+
+fn move_entry(
+    root: &Path,
+    from: PathBuf,
+    to: PathBuf,
+    registry: &DocumentRegistry,
+) -> Result<WorkspaceChange> {
+    if from == root {
+        return Err(Error::Invalid(
+            "Can't move the workspace folder".to_string(),
+        ));
+    }
+    if from == to {
+        return Ok(WorkspaceChange {
+            from,
+            to,
+            updated: Vec::new(),
+            skipped: Vec::new(),
+        });
+    }
+    // A rename that only changes case finds the file itself on
+    // case-insensitive file systems; anything else there is another file,
+    // including the target of a symlink being renamed.
+    let case_change = from.to_string_lossy().to_lowercase() == to.to_string_lossy().to_lowercase();
+    if fs::symlink_metadata(&to).is_ok()
+        && !(case_change && same_file::is_same_file(&from, &to).unwrap_or(false))
+    {
+        return Err(Error::Invalid(format!("{} already exists", to.display())));
+    }
+    fs::rename(&from, &to).map_err(|e| Error::io(e, &from))?;
+    // The move has happened, so files whose links can't be updated are
+    // reported with it rather than failing it.
+    let (updated, skipped) = update_links(root, &from, &to, registry);
+    Ok(WorkspaceChange {
+        from,
+        to,
+        updated,
+        skipped,
+    })
+}
+
+/// Rewrites the links in the workspace after `from` was moved to `to`:
+/// links pointing into what moved, and the relative links inside it.
+/// Documents open with unsaved changes are left alone, since saving them
+/// would put the old links back.
+fn update_links(
+    root: &Path,
+    from: &Path,
+    to: &Path,
+    registry: &DocumentRegistry,
+) -> (Vec<PathBuf>, Vec<SkippedFile>) {
+    let mut updated = Vec::new();
+    let mut skipped = Vec::new();
+    for entry in walker(root).build().filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) || !document::is_markdown(path) {
+            continue;
+        }
+        let Ok(bytes) = fs::read(path) else {
+            continue;
+        };
+        let (text, format) = document::decode(&bytes);
+        let old_path = match path.strip_prefix(to) {
+            Ok(rest) => from.join(rest),
+            Err(_) => path.to_path_buf(),
+        };
+        let moved = |target: &Path| match target.strip_prefix(from) {
+            Ok(rest) => to.join(rest),
+            Err(_) => target.to_path_buf(),
+        };
+        let Some(text) = rewrite_links(&text, &old_path, path, &moved, &|path| path.exists())
+        else {
+            continue;
+        };
+        let mut skip = |reason: String| {
+            skipped.push(SkippedFile {
+                path: path.to_path_buf(),
+                reason,
+            })
+        };
+        if registry.is_dirty(path) || registry.is_dirty(&old_path) {
+            skip("it has unsaved changes".to_string());
+            continue;
+        }
+        let written = document::encode(&text, &format)
+            .and_then(|bytes| atomic::write(path, &bytes).map_err(|e| Error::io(e, path)));
+        match written {
+            Ok(()) => updated.push(path.to_path_buf()),
+            Err(e) => skip(e.to_string()),
+        }
+    }
+    (updated, skipped)
+}
+
+/// Rewrites the local links in `source`, a document moved from `old_path`
+/// to `new_path`, whose targets moved as `moved` says. `exists` tells
+/// whether a path exists after the move, for links without an extension,
+/// which lead to the `.md` file of that name. Returns `None` when every link
+/// still points where it did.
+fn rewrite_links(
+    source: &str,
+    old_path: &Path,
+    new_path: &Path,
+    moved: &dyn Fn(&Path) -> PathBuf,
+    exists: &dyn Fn(&Path) -> bool,
+) -> Option<String> {
+    let old_dir = old_path.parent()?;
+    let new_dir = new_path.parent()?;
+    let mut out = String::with_capacity(source.len());
+    let mut last = 0;
+    let mut changed = false;
+    for destination in links::destinations(source) {
+        if !links::is_local(&destination.url) {
+            continue;
+        }
+        let (path, suffix) = links::split_suffix(&destination.url);
+        if path.is_empty() {
+            continue;
+        }
+        let written = PathBuf::from(links::percent_decode(path));
+        let old_target = links::normalize(&old_dir.join(&written));
+        let mut target = moved(&old_target);
+        let mut new_target = links::normalize(&new_dir.join(&written));
+        let mut extensionless = false;
+        if written.extension().is_none() && !exists(&target) {
+            let markdown = moved(&old_target.with_extension("md"));
+            if exists(&markdown) {
+                target = markdown;
+                new_target.set_extension("md");
+                extensionless = true;
+            }
+        }
+        if new_target == target {
+            continue;
+        }
+        let mut path = if written.is_absolute() {
+            target
+        } else {
+            links::relative(new_dir, &target)
+        };
+        if extensionless {
+            path.set_extension("");
+        }
+        out.push_str(&source[last..destination.range.start]);
+        out.push_str(&links::to_url(&path, destination.bracketed));
+        out.push_str(suffix);
+        last = destination.range.end;
+        changed = true;
+    }
+    if !changed {
+        return None;
+    }
+    out.push_str(&source[last..]);
+    Some(out)
+}
+
+/// Walks a folder the way git sees it: no hidden files, nothing ignored.
+pub fn walker(dir: &Path) -> WalkBuilder {
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .hidden(true)
+        .git_ignore(true)
+        .git_exclude(true)
+        .git_global(false)
+        .require_git(false);
+    builder
+}
+
+/// The canonical form of `path`, without the `\\?\` prefix Windows adds,
+/// so paths compare equal to the ones the frontend has.
+fn canonical(path: &Path) -> Result<PathBuf> {
+    dunce::canonicalize(path).map_err(|e| Error::io(e, path))
+}
+
+/// The workspace folder `root` made canonical, if the app may use it.
+pub fn checked_root(app: &AppHandle, root: &Path) -> Result<PathBuf> {
+    let root = canonical(root)?;
+    scope::check(app, &root)?;
+    Ok(root)
+}
+
+/// The folder `path` made canonical, if it is in the workspace `root`.
+fn inside(root: &Path, path: &Path) -> Result<PathBuf> {
+    check_inside(root, canonical(path)?)
+}
+
+/// The entry `path` with only its folder made canonical, if it is in the
+/// workspace `root`. A symlink stays the link itself, so renaming, moving
+/// or deleting it never touches what it points to.
+fn entry_inside(root: &Path, path: &Path) -> Result<PathBuf> {
+    let path = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) if !parent.as_os_str().is_empty() => {
+            canonical(parent)?.join(name)
+        }
+        _ => canonical(path)?,
+    };
+    fs::symlink_metadata(&path).map_err(|e| Error::io(e, &path))?;
+    check_inside(root, path)
+}
+
+fn check_inside(root: &Path, path: PathBuf) -> Result<PathBuf> {
+    if path.starts_with(root) {
+        Ok(path)
+    } else {
+        Err(Error::PermissionDenied(format!(
+            "{} is outside the workspace",
+            path.display()
+        )))
+    }
+}
+
+/// A name for a new or renamed entry, refused if it would be a path or
+/// isn't valid on every system.
+fn check_name(name: &str) -> Result<String> {
+    let name = name.trim_matches(' ');
+    let valid = !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.ends_with('.')
+        && !name
+            .chars()
+            .any(|c| c.is_control() || RESERVED_CHARS.contains(&c));
+    if valid {
+        Ok(name.to_string())
+    } else {
+        Err(Error::Invalid(format!(
+            "\"{name}\" is not a valid file name"
+        )))
+    }
+}
+
+/// Case-insensitive, with runs of digits compared as numbers so `2` comes
+/// before `10`.
+fn compare_names(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let number = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+                    let mut digits = String::new();
+                    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                        digits.push(c);
+                    }
+                    digits
+                };
+                let (x, y) = (number(&mut a), number(&mut b));
+                let (x_trimmed, y_trimmed) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                let order = x_trimmed
+                    .len()
+                    .cmp(&y_trimmed.len())
+                    .then_with(|| x_trimmed.cmp(y_trimmed));
+                if order != Ordering::Equal {
+                    return order;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let order = x.to_lowercase().cmp(y.to_lowercase());
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rewrites `source`, at `old_path` before the move and `new_path`
+    /// after, for `from` moved to `to`, with `files` existing afterwards.
+    fn rewrite(
+        source: &str,
+        old_path: &str,
+        new_path: &str,
+        (from, to): (&str, &str),
+        files: &[&str],
+    ) -> Option<String> {
+        let moved = |target: &Path| match target.strip_prefix(from) {
+            Ok(rest) => Path::new(to).join(rest),
+            Err(_) => target.to_path_buf(),
+        };
+        let exists = |path: &Path| files.iter().any(|file| Path::new(file) == path);
+        rewrite_links(
+            source,
+            Path::new(old_path),
+            Path::new(new_path),
+            &moved,
+            &exists,
+        )
+    }
+
+    #[test]
+    fn links_to_a_moved_file() {
+        let source = "See [b](b.md#top), [same](c.md) and [web](https://x.org/b.md).\n";
+        assert_eq!(
+            rewrite(
+                source,
+                "/w/a.md",
+                "/w/a.md",
+                ("/w/b.md", "/w/sub/b 2.md"),
+                &["/w/sub/b 2.md", "/w/c.md"],
+            )
+            .as_deref(),
+            Some("See [b](sub/b%202.md#top), [same](c.md) and [web](https://x.org/b.md).\n")
+        );
+    }
+
+    #[test]
+    fn links_inside_a_moved_file() {
+        let source = "[up](../c.md) ![pic](<img 1.png>)\n";
+        assert_eq!(
+            rewrite(
+                source,
+                "/w/a/doc.md",
+                "/w/doc.md",
+                ("/w/a/doc.md", "/w/doc.md"),
+                &["/w/c.md"],
+            )
+            .as_deref(),
+            Some("[up](c.md) ![pic](<a/img 1.png>)\n")
+        );
+    }
+
+    #[test]
+    fn unchanged_links_are_left_alone() {
+        let source = "[b](b.md) [here](#top) [abs](/w/x.md)\n";
+        assert_eq!(
+            rewrite(source, "/w/a.md", "/w/a.md", ("/w/z.md", "/w/y.md"), &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn extensionless_links_follow_the_markdown_file() {
+        let source = "[b](notes/b) and [gone](missing)\n";
+        assert_eq!(
+            rewrite(
+                source,
+                "/w/a.md",
+                "/w/a.md",
+                ("/w/notes", "/w/archive"),
+                &["/w/archive", "/w/archive/b.md"],
+            )
+            .as_deref(),
+            Some("[b](archive/b) and [gone](missing)\n")
+        );
+    }
+
+    #[test]
+    fn links_to_the_own_folder() {
+        let source = "[index](../a)\n";
+        assert_eq!(
+            rewrite(
+                source,
+                "/w/a/doc.md",
+                "/w/b/doc.md",
+                ("/w/a", "/w/b"),
+                &["/w/b", "/w/b/doc.md"],
+            )
+            .as_deref(),
+            Some("[index](.)\n")
+        );
+    }
+
+    #[test]
+    fn names_sort_naturally() {
+        let mut names = vec![
+            "note 10", "Note 2", "note 1", "b", "A", "note 02x", "note 2",
+        ];
+        names.sort_by(|a, b| compare_names(a, b));
+        assert_eq!(
+            names,
+            ["A", "b", "note 1", "Note 2", "note 2", "note 02x", "note 10"]
+        );
+        assert_eq!(compare_names("a", "A"), Ordering::Equal);
+        assert_eq!(compare_names("x9", "x10"), Ordering::Less);
+        assert_eq!(compare_names("file", "file 1"), Ordering::Less);
+    }
+
+    #[test]
+    fn names_are_checked() {
+        assert_eq!(check_name("  Notes ").unwrap(), "Notes");
+        for name in ["", ".", "..", "a/b", "a\\b", "what?", "dot.", "tab\t"] {
+            assert!(check_name(name).is_err(), "{name:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn renames_refuse_another_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let registry = DocumentRegistry::default();
+        fs::write(dir.join("Note.md"), "one").unwrap();
+        fs::write(dir.join("other.md"), "two").unwrap();
+        let refused = move_entry(dir, dir.join("Note.md"), dir.join("other.md"), &registry);
+        assert!(matches!(refused, Err(Error::Invalid(_))));
+        assert_eq!(fs::read_to_string(dir.join("other.md")).unwrap(), "two");
+
+        let change =
+            move_entry(dir, dir.join("Note.md"), dir.join("Renamed.md"), &registry).unwrap();
+        assert!(change.skipped.is_empty());
+        assert_eq!(fs::read_to_string(dir.join("Renamed.md")).unwrap(), "one");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_moved_themselves() {
+        let temp = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = canonical(temp.path()).unwrap();
+        let registry = DocumentRegistry::default();
+        fs::write(root.join("target.md"), "target").unwrap();
+        fs::write(outside.path().join("secret.md"), "secret").unwrap();
+        std::os::unix::fs::symlink(root.join("target.md"), root.join("link.md")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.md"), root.join("out.md")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("elsewhere")).unwrap();
+
+        let link = entry_inside(&root, &root.join("link.md")).unwrap();
+        assert_eq!(link, root.join("link.md"));
+        let refused = move_entry(&root, link.clone(), root.join("target.md"), &registry);
+        assert!(matches!(refused, Err(Error::Invalid(_))));
+        move_entry(&root, link, root.join("renamed.md"), &registry).unwrap();
+        assert!(fs::symlink_metadata(root.join("renamed.md"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_to_string(root.join("target.md")).unwrap(),
+            "target"
+        );
+
+        let out = entry_inside(&root, &root.join("out.md")).unwrap();
+        move_entry(&root, out, root.join("moved.md"), &registry).unwrap();
+        assert_eq!(
+            fs::read_to_string(outside.path().join("secret.md")).unwrap(),
+            "secret"
+        );
+
+        let through = entry_inside(&root, &root.join("elsewhere/secret.md"));
+        assert!(matches!(through, Err(Error::PermissionDenied(_))));
+    }
+}
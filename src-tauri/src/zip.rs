@@ -0,0 +1,345 @@
+use std::cell::Cell;
+
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+/// Version 2.0, the first with deflate and folders.
+const VERSION: u16 = 20;
+/// Bit 11: file names are UTF-8.
+const UTF8_NAMES: u16 = 1 << 11;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+/// 1980-01-01 00:00 in DOS format, so the same document always produces
+/// the same archive.
+const DOS_DATE: u16 = (1 << 5) | 1;
+const DOS_TIME: u16 = 0;
+/// The most a single entry may unpack to.
+const ENTRY_LIMIT: usize = 64 << 20;
+/// The most all the entries read from one archive may unpack to.
+const TOTAL_LIMIT: usize = 256 << 20;
+
+struct Entry {
+    name: String,
+    method: u16,
+    crc: u32,
+    compressed: u32,
+    size: u32,
+    offset: u32,
+}
+
+/// Builds a zip archive in memory, the container of DOCX and EPUB files.
+/// Entries are written in the order they are added.
+#[derive(Default)]
+pub struct ZipWriter {
+    out: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a deflated file.
+    pub fn add(&mut self, name: &str, data: &[u8]) {
+        self.push(name, data, &compress_to_vec(data, 6), DEFLATED);
+    }
+
+    /// Adds a file without compression, for entries readers look for at a
+    /// fixed offset, like the EPUB `mimetype`.
+    pub fn add_stored(&mut self, name: &str, data: &[u8]) {
+        self.push(name, data, data, STORED);
+    }
+
+    fn push(&mut self, name: &str, data: &[u8], compressed: &[u8], method: u16) {
+        let entry = Entry {
+            name: name.to_string(),
+            method,
+            crc: crc32fast::hash(data),
+            compressed: compressed.len() as u32,
+            size: data.len() as u32,
+            offset: self.out.len() as u32,
+        };
+        let out = &mut self.out;
+        put32(out, LOCAL_HEADER);
+        put16(out, VERSION);
+        put16(out, UTF8_NAMES);
+        put16(out, entry.method);
+        put16(out, DOS_TIME);
+        put16(out, DOS_DATE);
+        put32(out, entry.crc);
+        put32(out, entry.compressed);
+        put32(out, entry.size);
+        put16(out, entry.name.len() as u16);
+        put16(out, 0);
+        out.extend_from_slice(entry.name.as_bytes());
+        out.extend_from_slice(compressed);
+        self.entries.push(entry);
+    }
+
+    /// Writes the central directory and returns the archive.
+    pub fn finish(mut self) -> Vec<u8> {
+        let start = self.out.len() as u32;
+        let out = &mut self.out;
+        for entry in &self.entries {
+            put32(out, CENTRAL_HEADER);
+            put16(out, VERSION);
+            put16(out, VERSION);
+            put16(out, UTF8_NAMES);
+            put16(out, entry.method);
+            put16(out, DOS_TIME);
+            put16(out, DOS_DATE);
+            put32(out, entry.crc);
+            put32(out, entry.compressed);
+            put32(out, entry.size);
+            put16(out, entry.name.len() as u16);
+            // Extra field, comment, disk number, internal attributes.
+            put16(out, 0);
+            put16(out, 0);
+            put16(out, 0);
+            put16(out, 0);
+            put32(out, 0);
+            put32(out, entry.offset);
+            out.extend_from_slice(entry.name.as_bytes());
+        }
+        let size = out.len() as u32 - start;
+        put32(out, END_OF_CENTRAL_DIRECTORY);
+        put16(out, 0);
+        put16(out, 0);
+        put16(out, self.entries.len() as u16);
+        put16(out, self.entries.len() as u16);
+        put32(out, size);
+        put32(out, start);
+        put16(out, 0);
+        self.out
+    }
+}
+
+/// Reads files out of a zip archive held in memory, like an imported DOCX.
+pub struct ZipReader<'a> {
+    data: &'a [u8],
+    entries: Vec<Entry>,
+    /// What is left of `TOTAL_LIMIT` after the entries read so far.
+    budget: Cell<usize>,
+}
+
+impl<'a> ZipReader<'a> {
+    /// Reads the central directory; `None` if `data` is not a zip archive.
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        // The end record is 22 bytes plus a comment of up to 64 KiB.
+        let search = data.len().saturating_sub(22 + u16::MAX as usize);
+        let end = (search..data.len().saturating_sub(21))
+            .rev()
+            .find(|&at| get32(data, at) == Some(END_OF_CENTRAL_DIRECTORY))?;
+        let count = get16(data, end + 10)?;
+        let mut at = get32(data, end + 16)? as usize;
+        let mut entries = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if get32(data, at)? != CENTRAL_HEADER {
+                return None;
+            }
+            let name_length = get16(data, at + 28)? as usize;
+            let extra_length = get16(data, at + 30)? as usize;
+            let comment_length = get16(data, at + 32)? as usize;
+            let name = data.get(at + 46..at + 46 + name_length)?;
+            entries.push(Entry {
+                name: String::from_utf8_lossy(name).into_owned(),
+                method: get16(data, at + 10)?,
+                crc: get32(data, at + 16)?,
+                compressed: get32(data, at + 20)?,
+                size: get32(data, at + 24)?,
+                offset: get32(data, at + 42)?,
+            });
+            at += 46 + name_length + extra_length + comment_length;
+        }
+        Some(Self {
+            data,
+            entries,
+            budget: Cell::new(TOTAL_LIMIT),
+        })
+    }
+
+    /// The contents of `name`, or `None` if it is missing, damaged,
+    /// compressed with a method other than deflate, or larger than the
+    /// limits. The archive declares each entry's size, so entries declaring
+    /// more than `ENTRY_LIMIT`, or more than is left of `TOTAL_LIMIT`, are
+    /// refused before inflating, which then stops at the declared size.
+    pub fn read(&self, name: &str) -> Option<Vec<u8>> {
+        let entry = self.entries.iter().find(|entry| entry.name == name)?;
+        let size = entry.size as usize;
+        if size > ENTRY_LIMIT || size > self.budget.get() {
+            return None;
+        }
+        self.budget.set(self.budget.get() - size);
+        let at = entry.offset as usize;
+        if get32(self.data, at)? != LOCAL_HEADER {
+            return None;
+        }
+        let start =
+            at + 30 + get16(self.data, at + 26)? as usize + get16(self.data, at + 28)? as usize;
+        let compressed = self.data.get(start..start + entry.compressed as usize)?;
+        let data = match entry.method {
+            STORED => compressed.to_vec(),
+            DEFLATED => decompress_to_vec_with_limit(compressed, size).ok()?,
+            _ => return None,
+        };
+        (data.len() == size && crc32fast::hash(&data) == entry.crc).then_some(data)
+    }
+}
+
+fn put16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn get16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn get32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let text = "some text that compresses, some text that compresses\n".repeat(20);
+        let mut zip = ZipWriter::new();
+        zip.add_stored("mimetype", b"application/epub+zip");
+        zip.add("word/document.xml", text.as_bytes());
+        zip.add("empty", b"");
+        let archive = zip.finish();
+
+        let reader = ZipReader::new(&archive).unwrap();
+        assert_eq!(reader.read("mimetype").unwrap(), b"application/epub+zip");
+        assert_eq!(reader.read("word/document.xml").unwrap(), text.as_bytes());
+        assert_eq!(reader.read("empty").unwrap(), b"");
+        assert_eq!(reader.read("missing"), None);
+        // The stored entry is readable at its fixed offset.
+        assert_eq!(&archive[30..38], b"mimetype");
+        assert_eq!(&archive[38..58], b"application/epub+zip");
+    }
+
+    #[test]
+    fn non_ascii_names() {
+        let mut zip = ZipWriter::new();
+        zip.add("médias/日本語 ✓.png", b"image");
+        let archive = zip.finish();
+        let reader = ZipReader::new(&archive).unwrap();
+        assert_eq!(reader.read("médias/日本語 ✓.png").unwrap(), b"image");
+    }
+
+    /// An entry written by a streaming writer: the local header has no sizes
+    /// or checksum, which follow the data in a descriptor instead.
+    fn with_data_descriptor(name: &str, data: &[u8]) -> Vec<u8> {
+        let compressed = compress_to_vec(data, 6);
+        let crc = crc32fast::hash(data);
+        let mut out = Vec::new();
+        put32(&mut out, LOCAL_HEADER);
+        put16(&mut out, VERSION);
+        put16(&mut out, 1 << 3);
+        put16(&mut out, DEFLATED);
+        put16(&mut out, DOS_TIME);
+        put16(&mut out, DOS_DATE);
+        put32(&mut out, 0);
+        put32(&mut out, 0);
+        put32(&mut out, 0);
+        put16(&mut out, name.len() as u16);
+        put16(&mut out, 0);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&compressed);
+        put32(&mut out, 0x0807_4b50);
+        put32(&mut out, crc);
+        put32(&mut out, compressed.len() as u32);
+        put32(&mut out, data.len() as u32);
+
+        let start = out.len() as u32;
+        put32(&mut out, CENTRAL_HEADER);
+        put16(&mut out, VERSION);
+        put16(&mut out, VERSION);
+        put16(&mut out, 1 << 3);
+        put16(&mut out, DEFLATED);
+        put16(&mut out, DOS_TIME);
+        put16(&mut out, DOS_DATE);
+        put32(&mut out, crc);
+        put32(&mut out, compressed.len() as u32);
+        put32(&mut out, data.len() as u32);
+        put16(&mut out, name.len() as u16);
+        for _ in 0..4 {
+            put16(&mut out, 0);
+        }
+        put32(&mut out, 0);
+        put32(&mut out, 0);
+        out.extend_from_slice(name.as_bytes());
+        let size = out.len() as u32 - start;
+        put32(&mut out, END_OF_CENTRAL_DIRECTORY);
+        put16(&mut out, 0);
+        put16(&mut out, 0);
+        put16(&mut out, 1);
+        put16(&mut out, 1);
+        put32(&mut out, size);
+        put32(&mut out, start);
+        put16(&mut out, 0);
+        out
+    }
+
+    #[test]
+    fn data_descriptor() {
+        let archive = with_data_descriptor("doc.xml", b"<w:document/>");
+        let reader = ZipReader::new(&archive).unwrap();
+        assert_eq!(reader.read("doc.xml").unwrap(), b"<w:document/>");
+    }
+
+    /// Offset of the declared uncompressed size in the central directory.
+    fn central_size_offset(archive: &[u8]) -> usize {
+        let end = archive.len() - 22;
+        get32(archive, end + 16).unwrap() as usize + 24
+    }
+
+    #[test]
+    fn inflating_stops_at_the_declared_size() {
+        let data = vec![0u8; 1 << 20];
+        let mut zip = ZipWriter::new();
+        zip.add("bomb", &data);
+        let mut archive = zip.finish();
+        let at = central_size_offset(&archive);
+        archive[at..at + 4].copy_from_slice(&1024u32.to_le_bytes());
+        assert_eq!(ZipReader::new(&archive).unwrap().read("bomb"), None);
+    }
+
+    #[test]
+    fn large_entries_are_refused() {
+        let mut zip = ZipWriter::new();
+        zip.add("big", &[0; 64]);
+        zip.add("small", &[0; 16]);
+        let mut archive = zip.finish();
+        let at = central_size_offset(&archive);
+        archive[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let reader = ZipReader::new(&archive).unwrap();
+        assert_eq!(reader.read("big"), None);
+
+        // Reading the same entry again counts again.
+        reader.budget.set(20);
+        assert_eq!(reader.read("small").unwrap(), [0; 16]);
+        assert_eq!(reader.read("small"), None);
+    }
+
+    #[test]
+    fn damaged_entries_are_refused() {
+        let mut zip = ZipWriter::new();
+        zip.add_stored("a.txt", b"hello");
+        let mut archive = zip.finish();
+        archive[30 + 5] ^= 1;
+        assert_eq!(ZipReader::new(&archive).unwrap().read("a.txt"), None);
+        assert!(ZipReader::new(b"not a zip").is_none());
+    }
+}
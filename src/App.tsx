@@ -1,13 +1,15 @@
-import { useState, ChangeEvent, useRef, useEffect } from "react";
+import { useState, ChangeEvent, ClipboardEvent, ReactNode, SyntheticEvent, useRef, useEffect } from "react";
 import ReactMarkdown from "react-markdown";
 import remarkGfm from "remark-gfm";
-import { open, save } from "@tauri-apps/plugin-dialog";
-import { readTextFile, writeTextFile } from "@tauri-apps/plugin-fs";
+import { open, save, ask, message } from "@tauri-apps/plugin-dialog";
 import { openUrl } from "@tauri-apps/plugin-opener";
-import { convertFileSrc } from "@tauri-apps/api/core";
+import { convertFileSrc, invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
+import { getCurrentWebview } from "@tauri-apps/api/webview";
 import { 
   Plus, Minus, Bold, Italic, List, Code, Link, Table, 
-  Indent, PenLine, Columns2, Eye, Link2, FileImage, X, ChevronUp, ChevronDown
+  Indent, PenLine, Columns2, Eye, Link2, FileImage, X, ChevronUp, ChevronDown,
+  ChevronRight, Folder, FileText, FilePlus, FolderPlus, FolderInput, Pencil, Trash2, RefreshCw, Search
 } from "lucide-react";
 import markdownGuide from "./MarkdownGuide.md?raw";
 import openingMd from "./Opening.md?raw";
@@ -19,6 +21,202 @@ type Theme = 'light' | 'dark' | 'dim';
 type FontFamily = 'sans' | 'serif' | 'mono';
 type ViewMode = 'editing' | 'split' | 'reading';
 
+// Mirrors `document::TextFormat` on the Rust side; handed back on save so the
+// file keeps its encoding, BOM and line endings.
+type TextFormat = {
+  encoding: 'utf8' | 'utf16le' | 'utf16be' | 'windows1252';
+  bom: boolean;
+  lineEnding: 'lf' | 'crlf' | 'cr';
+  trailingNewline: boolean;
+};
+
+type OpenedDocument = {
+  path: string;
+  text: string;
+  format: TextFormat;
+};
+
+type DocumentChanged = {
+  path: string;
+  kind: 'modified' | 'removed' | 'renamed';
+  renamedTo: string | null;
+};
+
+type DocumentEntry = {
+  id: number;
+  path: string | null;
+  dirty: boolean;
+  window: string;
+};
+
+type DocumentSnapshot = {
+  text: string;
+  savedText: string;
+  format: TextFormat | null;
+};
+
+type Incoming =
+  | { kind: 'file'; path: string }
+  | { kind: 'document'; id: number; path: string | null; snapshot: DocumentSnapshot }
+  | { kind: 'recovered'; path: string | null; text: string; format: TextFormat | null }
+  | { kind: 'restored'; path: string; view: ViewState };
+
+type JournalEntry = {
+  path: string | null;
+  text: string;
+  format: TextFormat | null;
+  savedAt: number;
+};
+
+type MergeResult = {
+  text: string;
+  conflicts: number;
+  theirs: string;
+  format: TextFormat;
+};
+
+type ExportReport = {
+  path: string;
+  missingAssets: string[];
+  unsupportedAssets: string[];
+};
+
+type ImportedDocument = {
+  markdown: string;
+  path: string;
+  missingAssets: string[];
+};
+
+type PageSize = 'a4' | 'a5' | 'letter' | 'legal';
+
+// Mirrors `pdf::PdfOptions`; margins are in millimetres.
+type PdfOptions = {
+  pageSize: PageSize;
+  landscape: boolean;
+  margins: { top: number; right: number; bottom: number; left: number };
+  header: string;
+  footer: string;
+  tableOfContents: boolean;
+  fontFamily: FontFamily;
+  fontSize: number;
+};
+
+// Mirrors `settings::Settings`; owned by the backend and shared by every
+// window through `settings-changed`.
+type Settings = {
+  version: number;
+  theme: Theme;
+  accentColor: string;
+  fontFamily: FontFamily;
+  fontSize: number;
+  syncScroll: boolean;
+  restoreSession: boolean;
+  backups: number;
+  recovery: { intervalSecs: number; retentionDays: number };
+  images: { folder: string; maxDimension: number };
+};
+
+// Mirrors `workspace::TreeEntry`
+type TreeEntry = {
+  name: string;
+  path: string;
+  kind: 'folder' | 'markdown' | 'asset';
+};
+
+// Mirrors `workspace::WorkspaceChange`
+type WorkspaceChange = {
+  from: string;
+  to: string;
+  updated: string[];
+  skipped: SkippedFile[];
+};
+
+// Mirrors `workspace::SkippedFile`
+type SkippedFile = {
+  path: string;
+  reason: string;
+};
+
+// Mirrors `search::Snippet`
+type SearchSnippet = {
+  line: number;
+  text: string;
+  // UTF-16 offsets into `text`
+  ranges: [number, number][];
+};
+
+// Mirrors `search::SearchResult`
+type SearchResult = {
+  path: string;
+  title: string;
+  score: number;
+  snippets: SearchSnippet[];
+};
+
+// Mirrors `links::ResolvedLink`
+type ResolvedLink = {
+  path: string;
+  fragment: string | null;
+  exists: boolean;
+  isMarkdown: boolean;
+};
+
+type DocumentHeading = {
+  level: number;
+  text: string;
+  id: string;
+  line: number;
+};
+
+// Mirrors `replace::ReplaceQuery`
+type ReplaceQuery = {
+  find: string;
+  replace: string;
+  regex: boolean;
+  matchCase: boolean;
+};
+
+// Mirrors `replace::FilePreview`
+type FilePreview = {
+  path: string;
+  // Identifies the previewed contents; the backend refuses files changed since
+  hash: string;
+  count: number;
+  hunks: { line: number; before: string; after: string }[];
+};
+
+// Mirrors `replace::ReplaceBatch`
+type ReplaceBatch = {
+  id: number;
+  files: string[];
+  count: number;
+};
+
+// Mirrors `images::SavedImage`
+type SavedImage = {
+  path: string;
+  link: string;
+  markdown: string;
+};
+
+// Mirrors `reload::ViewState`; where the user was in a document.
+type ViewState = {
+  viewMode: ViewMode;
+  selectionStart: number;
+  selectionEnd: number;
+  editorScrollTop: number;
+  previewScrollTop: number;
+};
+
+// Mirrors `reload::WindowSnapshot`; what the window looked like right before
+// its webview reloaded.
+type WindowSnapshot = {
+  documentId: number | null;
+  path: string | null;
+  document: DocumentSnapshot;
+  view: ViewState;
+};
+
 const ACCENT_COLORS = [
   { name: 'blue', light: '#1e66f5', dark: '#89b4fa' },
   { name: 'green', light: '#40a02b', dark: '#a6e3a1' },
@@ -27,16 +225,102 @@ const ACCENT_COLORS = [
   { name: 'peach', light: '#fe640b', dark: '#fab387' },
 ];
 
-const IMAGE_EXTENSIONS = ['.png', '.jpg', '.jpeg', '.gif', '.webp', '.svg', '.bmp'];
+const DEFAULT_SETTINGS: Settings = {
+  version: 1,
+  theme: 'light',
+  accentColor: ACCENT_COLORS[0].name,
+  fontFamily: 'sans',
+  fontSize: 16,
+  syncScroll: true,
+  restoreSession: true,
+  backups: 0,
+  recovery: { intervalSecs: 30, retentionDays: 7 },
+  images: { folder: 'assets', maxDimension: 0 },
+};
+
+// Longest side pasted and dropped images are scaled down to; 0 keeps them
+const IMAGE_SIZES = [0, 1280, 1920, 2560];
+
+const readAsDataUrl = (file: Blob) => new Promise<string>((resolve, reject) => {
+  const reader = new FileReader();
+  reader.onload = () => resolve(reader.result as string);
+  reader.onerror = () => reject(reader.error);
+  reader.readAsDataURL(file);
+});
+
+const DEFAULT_PDF_OPTIONS: PdfOptions = {
+  pageSize: 'a4',
+  landscape: false,
+  margins: { top: 20, right: 20, bottom: 20, left: 20 },
+  header: '',
+  footer: '{page} / {pages}',
+  tableOfContents: false,
+  fontFamily: 'sans',
+  fontSize: 11,
+};
+
+// A local image in the preview, resolved by the backend the way links are:
+// relative to the document, decoded, and only inside the folders the app may read
+function LocalImage({ src, documentPath, style, ...props }: React.ImgHTMLAttributes<HTMLImageElement> & { src: string; documentPath: string | null }) {
+  const isRemote = /^(https?|data|blob):/i.test(src);
+  const [resolved, setResolved] = useState<string | null>(null);
+  useEffect(() => {
+    if (isRemote) return;
+    let current = true;
+    invoke<ResolvedLink>('resolve_link', { documentPath, url: src })
+      .then(link => { if (current) setResolved(link.exists ? convertFileSrc(link.path) : ''); })
+      .catch(() => { if (current) setResolved(''); });
+    return () => { current = false; };
+  }, [src, documentPath, isRemote]);
+  const finalSrc = isRemote ? src : resolved;
+  return (
+    <img
+      {...props}
+      src={finalSrc || undefined}
+      style={finalSrc === '' ? { ...style, opacity: 0.3 } : style}
+      onError={(e) => {
+        (e.target as HTMLImageElement).style.opacity = '0.3';
+      }}
+    />
+  );
+}
 
 function App() {
   const [markdown, setMarkdown] = useState<string>(DEFAULT_MARKDOWN);
   const [savedMarkdown, setSavedMarkdown] = useState<string>(DEFAULT_MARKDOWN);
   const [filePath, setFilePath] = useState<string | null>(null);
+  // Where an imported, not yet saved document is meant to go; its images
+  // were extracted next to it
+  const [suggestedPath, setSuggestedPath] = useState<string | null>(null);
+  const [fileFormat, setFileFormat] = useState<TextFormat | null>(null);
+  const [documentId, setDocumentId] = useState<number | null>(null);
   const [viewMode, setViewMode] = useState<ViewMode>('reading');
+  // Whether text is selected in the editor, for the Format menu
+  const [hasSelection, setHasSelection] = useState(false);
   const [isTOCVisible, setIsTOCVisible] = useState(false);
   const [isSettingsVisible, setIsSettingsVisible] = useState(false);
-  const [isSyncScroll, setIsSyncScroll] = useState(true);
+  const [isPdfExportVisible, setIsPdfExportVisible] = useState(false);
+  const [pdfOptions, setPdfOptions] = useState<PdfOptions>(DEFAULT_PDF_OPTIONS);
+  const [isEpubExportVisible, setIsEpubExportVisible] = useState(false);
+  const [epubChapterLevel, setEpubChapterLevel] = useState(1);
+
+  // Workspace State: a folder opened as a tree, listed a folder at a time
+  const [workspaceRoot, setWorkspaceRoot] = useState<string | null>(null);
+  const [workspaceEntries, setWorkspaceEntries] = useState<Record<string, TreeEntry[]>>({});
+  const [expandedFolders, setExpandedFolders] = useState<string[]>([]);
+  const [renamingPath, setRenamingPath] = useState<string | null>(null);
+  const [creatingEntry, setCreatingEntry] = useState<{ parent: string; folder: boolean } | null>(null);
+  const [searchQuery, setSearchQuery] = useState("");
+  const [searchResults, setSearchResults] = useState<SearchResult[]>([]);
+  const [searchError, setSearchError] = useState<string | null>(null);
+
+  // Link History: the documents visited by following links, for Back and Forward
+  const [linkHistory, setLinkHistory] = useState<{ paths: string[]; index: number }>({ paths: [], index: -1 });
+
+  // Outline: the headings and ids the backend gives the EPUB nav and PDF bookmarks
+  const [headers, setHeaders] = useState<DocumentHeading[]>([]);
+  // Heading a followed link points into, scrolled to once the new outline arrives
+  const pendingFragmentRef = useRef<string | null>(null);
   
   // Find & Replace State
   const [isFindVisible, setIsFindVisible] = useState(false);
@@ -46,29 +330,72 @@ function App() {
   const [findResults, setFindResults] = useState<number[]>([]);
   const [currentResultIndex, setCurrentResultIndex] = useState(-1);
 
+  // Folder Replace State: what replacing in every note of the workspace
+  // would change, and the last batch applied so it can be undone
+  const [isFolderReplaceVisible, setIsFolderReplaceVisible] = useState(false);
+  const [useRegex, setUseRegex] = useState(false);
+  const [replacePreview, setReplacePreview] = useState<{ query: ReplaceQuery; files: FilePreview[] } | null>(null);
+  const [skippedFiles, setSkippedFiles] = useState<string[]>([]);
+  const [replaceBatch, setReplaceBatch] = useState<ReplaceBatch | null>(null);
+
   const textareaRef = useRef<HTMLTextAreaElement>(null);
   const previewRef = useRef<HTMLDivElement>(null);
   const activeSide = useRef<'editor' | 'preview' | null>(null);
   const findInputRef = useRef<HTMLInputElement>(null);
 
   // Settings State
-  const [theme, setTheme] = useState<Theme>('light');
-  const [accentColorName, setAccentColorName] = useState(ACCENT_COLORS[0].name);
-  const [fontSize, setFontSize] = useState(16);
-  const [fontFamily, setFontFamily] = useState<FontFamily>('sans');
+  const [settings, setSettings] = useState<Settings>(DEFAULT_SETTINGS);
+  const { theme, accentColor: accentColorName, fontSize, fontFamily, syncScroll: isSyncScroll } = settings;
 
   const accentColor = ACCENT_COLORS.find(c => c.name === accentColorName)?.[theme === 'light' ? 'light' : 'dark'] || ACCENT_COLORS[0].dark;
 
   const markdownRef = useRef(markdown);
+  const savedMarkdownRef = useRef(savedMarkdown);
   const filePathRef = useRef(filePath);
+  const suggestedPathRef = useRef(suggestedPath);
+  const fileFormatRef = useRef(fileFormat);
+  const documentIdRef = useRef(documentId);
   const isEditing = viewMode === 'editing' || viewMode === 'split';
   const isEditingRef = useRef(isEditing);
+  const viewModeRef = useRef(viewMode);
+  const settingsRef = useRef(settings);
 
   useEffect(() => {
     markdownRef.current = markdown;
+    savedMarkdownRef.current = savedMarkdown;
     filePathRef.current = filePath;
+    suggestedPathRef.current = suggestedPath;
+    fileFormatRef.current = fileFormat;
+    documentIdRef.current = documentId;
     isEditingRef.current = isEditing;
-  }, [markdown, filePath, isEditing]);
+    viewModeRef.current = viewMode;
+    settingsRef.current = settings;
+  }, [markdown, savedMarkdown, filePath, suggestedPath, fileFormat, documentId, isEditing, viewMode, settings]);
+
+  // Image extensions: the backend's list, so drops and links match what it saves and exports
+  const [imageExtensions, setImageExtensions] = useState<string[]>([]);
+  useEffect(() => {
+    invoke<string[]>('image_extensions').then(setImageExtensions);
+  }, []);
+  const isImagePath = (path: string) => imageExtensions.some(ext => path.toLowerCase().endsWith(`.${ext}`));
+
+  // Settings: load them from the backend and follow changes made in any window
+  useEffect(() => {
+    invoke<Settings>('get_settings').then(setSettings);
+    const unlisten = listen<Settings>('settings-changed', ({ payload }) => setSettings(payload));
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  const updateSettings = (patch: Partial<Settings>) => {
+    const next = { ...settingsRef.current, ...patch };
+    setSettings(next);
+    invoke<Settings>('set_settings', { settings: next }).catch(error => {
+      console.error("Failed to save settings:", error);
+      invoke<Settings>('get_settings').then(setSettings);
+    });
+  };
 
   const handleOpenFile = async (path?: string) => {
     try {
@@ -78,35 +405,712 @@ function App() {
       });
 
       if (selected && typeof selected === 'string') {
-        const contents = await readTextFile(selected);
-        setMarkdown(contents);
-        setSavedMarkdown(contents);
-        setFilePath(selected);
+        // Fails (and focuses the other window) if the file is open elsewhere
+        const entry = await invoke<DocumentEntry>('register_document', { path: selected });
+        let doc: OpenedDocument;
+        try {
+          doc = await invoke<OpenedDocument>('open_document', { path: selected });
+        } catch (error) {
+          invoke('close_document', { id: entry.id });
+          throw error;
+        }
+        if (documentIdRef.current !== null) {
+          invoke('close_document', { id: documentIdRef.current });
+        }
+        documentIdRef.current = entry.id;
+        setDocumentId(entry.id);
+        setMarkdown(doc.text);
+        setSavedMarkdown(doc.text);
+        setFilePath(doc.path);
+        setSuggestedPath(null);
+        setFileFormat(doc.format);
+        return true;
       }
     } catch (error) {
       console.error("Failed to open file:", error);
     }
+    return false;
+  };
+
+  const loadFolder = async (root: string, dir: string) => {
+    const entries = await invoke<TreeEntry[]>('list_workspace', { root, dir });
+    setWorkspaceEntries(current => ({ ...current, [dir]: entries }));
+  };
+
+  // Lists every folder on screen again; ones that went away are collapsed
+  const refreshWorkspace = async () => {
+    const root = workspaceRoot;
+    if (!root) return;
+    const listings = await Promise.all([root, ...expandedFolders].map(dir =>
+      invoke<TreeEntry[]>('list_workspace', { root, dir })
+        .then(entries => [dir, entries] as const)
+        .catch(() => null)
+    ));
+    const found = listings.filter(listing => listing !== null);
+    setWorkspaceEntries(Object.fromEntries(found));
+    setExpandedFolders(folders => folders.filter(dir => found.some(([listed]) => listed === dir)));
+  };
+
+  const handleOpenFolder = async () => {
+    try {
+      const selected = await open({ directory: true, multiple: false });
+      if (!selected || typeof selected !== 'string') return;
+      setWorkspaceEntries({});
+      setExpandedFolders([]);
+      await loadFolder(selected, selected);
+      setWorkspaceRoot(selected);
+    } catch (error) {
+      console.error("Failed to open folder:", error);
+      await message(`Could not open the folder:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const toggleFolder = (path: string) => {
+    if (expandedFolders.includes(path)) {
+      setExpandedFolders(folders => folders.filter(dir => dir !== path));
+      return;
+    }
+    setExpandedFolders(folders => [...folders, path]);
+    if (workspaceRoot) loadFolder(workspaceRoot, path).catch(error => console.error("Failed to list folder:", error));
+  };
+
+  // Keeps the open document when it, or a folder holding it, was renamed or
+  // moved, and reloads it if its links were rewritten
+  const followWorkspaceChange = async (change: WorkspaceChange) => {
+    const path = filePathRef.current;
+    if (!path) return;
+    const separator = change.from.includes('\\') ? '\\' : '/';
+    const moved = path === change.from
+      ? change.to
+      : path.startsWith(change.from + separator) ? change.to + path.slice(change.from.length) : path;
+    if (moved !== path) {
+      setFilePath(moved);
+      invoke('watch_document', { path: moved }).catch(() => {});
+    }
+    if (change.updated.includes(moved) && markdownRef.current === savedMarkdownRef.current) {
+      const doc = await invoke<OpenedDocument>('open_document', { path: moved });
+      setMarkdown(doc.text);
+      setSavedMarkdown(doc.text);
+    }
+    if (change.skipped.length > 0) {
+      const files = change.skipped.map(file => `${file.path}: ${file.reason}`).join('\n');
+      await message(`Links in these files still point at the old location:\n${files}`, { kind: 'warning' });
+    }
+  };
+
+  const handleCreateEntry = async (name: string) => {
+    const creating = creatingEntry;
+    setCreatingEntry(null);
+    if (!workspaceRoot || !creating || !name.trim()) return;
+    try {
+      const entry = await invoke<TreeEntry>('create_workspace_entry', {
+        root: workspaceRoot, parent: creating.parent, name, folder: creating.folder
+      });
+      await loadFolder(workspaceRoot, creating.parent);
+      if (entry.kind === 'markdown') await handleOpenFile(entry.path);
+    } catch (error) {
+      await message(`Could not create "${name}":\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const handleRenameEntry = async (entry: TreeEntry, name: string) => {
+    setRenamingPath(null);
+    if (!workspaceRoot || !name.trim() || name === entry.name) return;
+    try {
+      const change = await invoke<WorkspaceChange>('rename_workspace_entry', { root: workspaceRoot, path: entry.path, name });
+      await followWorkspaceChange(change);
+      await refreshWorkspace();
+    } catch (error) {
+      await message(`Could not rename "${entry.name}":\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const handleMoveEntry = async (entry: TreeEntry) => {
+    if (!workspaceRoot) return;
+    try {
+      const folder = await open({ directory: true, multiple: false, defaultPath: workspaceRoot });
+      if (!folder || typeof folder !== 'string') return;
+      const change = await invoke<WorkspaceChange>('move_workspace_entry', { root: workspaceRoot, path: entry.path, folder });
+      await followWorkspaceChange(change);
+      await refreshWorkspace();
+    } catch (error) {
+      await message(`Could not move "${entry.name}":\n${error}`, { kind: 'warning' });
+    }
   };
 
-  const handleSaveFile = async (forceSaveAs: boolean = false) => {
+  const handleDeleteEntry = async (entry: TreeEntry) => {
+    if (!workspaceRoot) return;
+    const confirmed = await ask(`Move "${entry.name}" to the trash?`, { title: 'Delete', kind: 'warning' });
+    if (!confirmed) return;
+    try {
+      await invoke('delete_workspace_entry', { root: workspaceRoot, path: entry.path });
+      await refreshWorkspace();
+    } catch (error) {
+      await message(`Could not delete "${entry.name}":\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  // Keeps the folder's search index up to date while it is open
+  useEffect(() => {
+    if (!workspaceRoot) return;
+    invoke('open_search_index', { root: workspaceRoot })
+      .catch(error => console.error("Failed to index folder:", error));
+    return () => {
+      invoke('close_search_index', { root: workspaceRoot }).catch(() => {});
+      setSearchQuery("");
+    };
+  }, [workspaceRoot]);
+
+  useEffect(() => {
+    if (!workspaceRoot || !searchQuery.trim()) {
+      setSearchResults([]);
+      setSearchError(null);
+      return;
+    }
+    const timer = window.setTimeout(() => {
+      invoke<SearchResult[]>('search_workspace', { root: workspaceRoot, query: searchQuery })
+        .then(results => {
+          setSearchResults(results);
+          setSearchError(null);
+        })
+        .catch(error => setSearchError(error?.message ?? String(error)));
+    }, 200);
+    return () => window.clearTimeout(timer);
+  }, [searchQuery, workspaceRoot]);
+
+  useEffect(() => {
+    const timer = window.setTimeout(() => {
+      invoke<DocumentHeading[]>('document_headings', { source: markdown })
+        .then(setHeaders)
+        .catch(error => console.error("Failed to read headings:", error));
+    }, 200);
+    return () => window.clearTimeout(timer);
+  }, [markdown]);
+
+  useEffect(() => {
+    const fragment = pendingFragmentRef.current;
+    if (!fragment) return;
+    pendingFragmentRef.current = null;
+    document.getElementById(fragment)?.scrollIntoView({ behavior: 'smooth' });
+  }, [headers]);
+
+  // Opens a search result and selects the matching line in the editor
+  const openSearchResult = async (path: string, line: number) => {
+    if (path !== filePathRef.current) await handleOpenFile(path);
+    if (viewModeRef.current === 'reading') setViewMode('editing');
+    // Wait for the document and the editor to render before selecting
+    setTimeout(() => {
+      const textarea = textareaRef.current;
+      if (!textarea) return;
+      const lines = textarea.value.split('\n');
+      const start = lines.slice(0, line - 1).reduce((offset, text) => offset + text.length + 1, 0);
+      textarea.focus();
+      textarea.setSelectionRange(start, start + (lines[line - 1]?.length ?? 0));
+      textarea.scrollTop = (line - 5) * fontSize * 1.5;
+    }, 10);
+  };
+
+  // Asks, like closing the window does, what to do with unsaved changes
+  // before another document replaces this one. Returns whether to go on
+  const confirmLeave = async () => {
+    if (markdownRef.current === savedMarkdownRef.current) return true;
+    const name = filePathRef.current?.split(/[\\/]/).pop() ?? 'Untitled.md';
+    const choice = await message(`Do you want to save the changes you made to "${name}"?`, {
+      title: 'Unsaved Changes',
+      kind: 'warning',
+      buttons: { yes: 'Save', no: "Don't Save", cancel: 'Cancel' },
+    });
+    if (choice === 'Save' || choice === 'Yes') return handleSaveFile();
+    return choice === "Don't Save" || choice === 'No';
+  };
+
+  // Follows a link to another document. The backend resolves it against the
+  // current one and checks the app may open it; files that aren't markdown
+  // open in their own app
+  const followLink = async (href: string) => {
+    const from = filePathRef.current;
+    try {
+      const link = await invoke<ResolvedLink>('resolve_link', { documentPath: from ?? suggestedPath, url: href });
+      if (!link.exists) {
+        await message(`${link.path} does not exist.`, { kind: 'warning' });
+        return;
+      }
+      if (!link.isMarkdown) {
+        const name = link.path.split(/[\\/]/).pop();
+        if (await ask(`Open "${name}" in its default app?`, { title: 'Open Link', kind: 'info' })) {
+          await invoke('open_linked_file', { path: link.path });
+        }
+        return;
+      }
+      if (link.path !== from && !(await confirmLeave() && await handleOpenFile(link.path))) return;
+      setLinkHistory(history => {
+        const paths = history.paths.slice(0, history.index + 1);
+        if (from && paths[paths.length - 1] !== from) paths.push(from);
+        if (paths[paths.length - 1] !== link.path) paths.push(link.path);
+        return { paths, index: paths.length - 1 };
+      });
+      const fragment = link.fragment;
+      if (fragment && link.path === from) {
+        document.getElementById(fragment)?.scrollIntoView({ behavior: 'smooth' });
+      } else if (fragment) {
+        // Heading ids arrive with the new document's outline
+        pendingFragmentRef.current = fragment;
+      }
+    } catch (error) {
+      await message(`Could not follow the link:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const goThroughHistory = async (offset: number) => {
+    const index = linkHistory.index + offset;
+    const path = linkHistory.paths[index];
+    if (!path) return;
+    if (path === filePathRef.current || (await confirmLeave() && await handleOpenFile(path))) {
+      setLinkHistory(history => ({ ...history, index }));
+    }
+  };
+
+  // Import: converts a Word document or web page into a new, unsaved document
+  const handleImport = async () => {
+    try {
+      const selected = await open({
+        multiple: false,
+        filters: [{ name: 'Documents', extensions: ['docx', 'html', 'htm'] }]
+      });
+      if (!selected || typeof selected !== 'string') return;
+      const imported = await invoke<ImportedDocument>('import_document', { path: selected });
+      setMarkdown(imported.markdown);
+      setSavedMarkdown("");
+      setFilePath(null);
+      setSuggestedPath(imported.path);
+      setFileFormat(null);
+      setViewMode('editing');
+      if (imported.missingAssets.length > 0) {
+        await message(`These images could not be extracted:\n${imported.missingAssets.join('\n')}`, { kind: 'warning' });
+      }
+    } catch (error) {
+      console.error("Failed to import file:", error);
+      await message(`Could not import the file:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const handleSaveFile = async (forceSaveAs: boolean = false): Promise<boolean> => {
     try {
       let path = filePathRef.current;
       if (!path || forceSaveAs) {
         path = await save({
+          defaultPath: suggestedPathRef.current ?? undefined,
           filters: [{ name: 'Markdown', extensions: ['md'] }]
         });
       }
       
       if (path) {
-        await writeTextFile(path, markdownRef.current);
+        if (path !== filePathRef.current && documentIdRef.current !== null) {
+          await invoke('update_document', { id: documentIdRef.current, path, dirty: true });
+        }
+        await invoke('save_document', {
+          path,
+          text: markdownRef.current,
+          format: fileFormatRef.current,
+        });
         setFilePath(path);
+        setSuggestedPath(null);
         setSavedMarkdown(markdownRef.current);
+        return true;
       }
     } catch (error) {
       console.error("Failed to save file:", error);
     }
+    return false;
+  };
+
+  // Export: suggests the document's name with the new extension
+  const exportTarget = async (name: string, extension: string) => {
+    const current = filePathRef.current;
+    return save({
+      defaultPath: current ? current.replace(/\.[^./\\]*$/, '') + `.${extension}` : `Untitled.${extension}`,
+      filters: [{ name, extensions: [extension] }]
+    });
+  };
+
+  const handleExportHtml = async (assets: 'embed' | 'folder') => {
+    try {
+      const target = await exportTarget('HTML', 'html');
+      if (!target) return;
+      const report = await invoke<ExportReport>('export_html', {
+        source: markdownRef.current,
+        documentPath: filePathRef.current ?? suggestedPathRef.current,
+        target,
+        assets,
+      });
+      if (report.missingAssets.length > 0) {
+        await message(`These images could not be found and were left as links:\n${report.missingAssets.join('\n')}`, { kind: 'warning' });
+      }
+    } catch (error) {
+      console.error("Failed to export HTML:", error);
+    }
+  };
+
+  const handleExportDocx = async () => {
+    try {
+      const target = await exportTarget('Word Document', 'docx');
+      if (!target) return;
+      const report = await invoke<ExportReport>('export_docx', {
+        source: markdownRef.current,
+        documentPath: filePathRef.current ?? suggestedPathRef.current,
+        target,
+      });
+      if (report.missingAssets.length > 0) {
+        await message(`These images could not be embedded and were left as text:\n${report.missingAssets.join('\n')}`, { kind: 'warning' });
+      }
+    } catch (error) {
+      console.error("Failed to export DOCX:", error);
+    }
+  };
+
+  const handleExportEpub = async () => {
+    try {
+      const target = await exportTarget('EPUB', 'epub');
+      if (!target) return;
+      const report = await invoke<ExportReport>('export_epub', {
+        source: markdownRef.current,
+        documentPath: filePathRef.current ?? suggestedPathRef.current,
+        target,
+        options: { chapterLevel: epubChapterLevel },
+      });
+      setIsEpubExportVisible(false);
+      if (report.missingAssets.length > 0) {
+        await message(`These images could not be found and were left as links:\n${report.missingAssets.join('\n')}`, { kind: 'warning' });
+      }
+      if (report.unsupportedAssets.length > 0) {
+        await message(`These images are in a format EPUB readers may not show and were left out:\n${report.unsupportedAssets.join('\n')}`, { kind: 'warning' });
+      }
+    } catch (error) {
+      console.error("Failed to export EPUB:", error);
+    }
+  };
+
+  const handleExportPdf = async () => {
+    try {
+      const target = await exportTarget('PDF', 'pdf');
+      if (!target) return;
+      await invoke<ExportReport>('export_pdf', {
+        source: markdownRef.current,
+        documentPath: filePathRef.current ?? suggestedPathRef.current,
+        target,
+        options: { ...pdfOptions, fontFamily },
+      });
+      setIsPdfExportVisible(false);
+    } catch (error) {
+      console.error("Failed to export PDF:", error);
+      await message(`Could not export the PDF:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  // Stop watching a file once it is no longer the open document
+  useEffect(() => {
+    if (!filePath) return;
+    return () => {
+      invoke('unwatch_document', { path: filePath });
+    };
+  }, [filePath]);
+
+  const resetDocument = (text: string) => {
+    setMarkdown(text);
+    setSavedMarkdown(text);
+    setFilePath(null);
+    setSuggestedPath(null);
+    setFileFormat(null);
   };
 
+  // Restores an unsaved buffer from the crash recovery journal, diffed
+  // against what is on disk now so it shows up as unsaved
+  const handleRecovered = async (item: Extract<Incoming, { kind: 'recovered' }>) => {
+    let path = item.path;
+    let entry: DocumentEntry;
+    try {
+      entry = await invoke<DocumentEntry>('register_document', { path });
+    } catch {
+      path = null;
+      entry = await invoke<DocumentEntry>('register_document', { path });
+    }
+    let savedText = "";
+    let format = item.format;
+    if (path) {
+      try {
+        const doc = await invoke<OpenedDocument>('open_document', { path });
+        savedText = doc.text;
+        format = format ?? doc.format;
+      } catch {
+        // The file is gone; keep the buffer as a new, unsaved document
+      }
+    }
+    if (documentIdRef.current !== null) {
+      invoke('close_document', { id: documentIdRef.current });
+    }
+    documentIdRef.current = entry.id;
+    setDocumentId(entry.id);
+    setFilePath(path);
+    setSuggestedPath(null);
+    setFileFormat(format);
+    setMarkdown(item.text);
+    setSavedMarkdown(savedText);
+    setViewMode('editing');
+  };
+
+  const handleIncoming = (item: Incoming) => {
+    if (item.kind === 'file') {
+      handleOpenFile(item.path);
+      return;
+    }
+    if (item.kind === 'recovered') {
+      handleRecovered(item);
+      return;
+    }
+    if (item.kind === 'restored') {
+      handleOpenFile(item.path).then(() => restoreView(item.view));
+      return;
+    }
+    // A document moved here from another window keeps its id and edits
+    if (documentIdRef.current !== null && documentIdRef.current !== item.id) {
+      invoke('close_document', { id: documentIdRef.current });
+    }
+    documentIdRef.current = item.id;
+    setDocumentId(item.id);
+    setFilePath(item.path);
+    setSuggestedPath(null);
+    setFileFormat(item.snapshot.format);
+    setMarkdown(item.snapshot.text);
+    setSavedMarkdown(item.snapshot.savedText);
+    setViewMode('editing');
+  };
+
+  const handleMoveToNewWindow = async () => {
+    if (documentIdRef.current === null) return;
+    try {
+      await invoke('move_document', {
+        id: documentIdRef.current,
+        target: null,
+        snapshot: {
+          text: markdownRef.current,
+          savedText: savedMarkdownRef.current,
+          format: fileFormatRef.current,
+        },
+      });
+    } catch (error) {
+      console.error("Failed to move document:", error);
+    }
+  };
+
+  const currentView = (): ViewState => ({
+    viewMode: viewModeRef.current,
+    selectionStart: textareaRef.current?.selectionStart ?? 0,
+    selectionEnd: textareaRef.current?.selectionEnd ?? 0,
+    editorScrollTop: textareaRef.current?.scrollTop ?? 0,
+    previewScrollTop: previewRef.current?.scrollTop ?? 0,
+  });
+
+  const restoreView = (view: ViewState) => {
+    setViewMode(view.viewMode);
+    // Wait for the restored view to render before moving the cursor
+    setTimeout(() => {
+      const textarea = textareaRef.current;
+      if (textarea) {
+        textarea.setSelectionRange(view.selectionStart, view.selectionEnd);
+        textarea.scrollTop = view.editorScrollTop;
+        textarea.focus();
+      }
+      if (previewRef.current) {
+        previewRef.current.scrollTop = view.previewScrollTop;
+      }
+    }, 10);
+  };
+
+  // Session: tell the backend what this window shows, so it can be
+  // reopened the same way on the next launch
+  const sessionTimer = useRef<number | undefined>(undefined);
+  // The menu only offers Bold, Italic and Link with text selected
+  const handleEditorSelect = (e: SyntheticEvent<HTMLTextAreaElement>) => {
+    setHasSelection(e.currentTarget.selectionStart !== e.currentTarget.selectionEnd);
+    scheduleSessionUpdate();
+  };
+
+  const scheduleSessionUpdate = () => {
+    window.clearTimeout(sessionTimer.current);
+    sessionTimer.current = window.setTimeout(() => {
+      invoke('update_session_view', { path: filePathRef.current, view: currentView() })
+        .catch(error => console.error("Failed to update session:", error));
+    }, 500);
+  };
+
+  useEffect(() => {
+    scheduleSessionUpdate();
+  }, [filePath, viewMode]);
+
+  // Reload: snapshot the document, cursor and view into Rust before the
+  // webview reloads, so the new page can pick up where this one left off
+  const snapshotWindow = (): WindowSnapshot => ({
+    documentId: documentIdRef.current,
+    path: filePathRef.current,
+    document: {
+      text: markdownRef.current,
+      savedText: savedMarkdownRef.current,
+      format: fileFormatRef.current,
+    },
+    view: currentView(),
+  });
+
+  const handleReload = () => {
+    invoke('reload_window', { snapshot: snapshotWindow() })
+      .catch(error => console.error("Failed to reload window:", error));
+  };
+
+  const handleResetAppData = () => {
+    invoke<boolean>('reset_app_data', { snapshot: snapshotWindow() })
+      .catch(error => console.error("Failed to reset app data:", error));
+  };
+
+  const restoreSnapshot = (snapshot: WindowSnapshot) => {
+    setMarkdown(snapshot.document.text);
+    setSavedMarkdown(snapshot.document.savedText);
+    setFilePath(snapshot.path);
+    setSuggestedPath(null);
+    setFileFormat(snapshot.document.format);
+    if (snapshot.path) {
+      invoke('watch_document', { path: snapshot.path }).catch(() => {});
+    }
+    restoreView(snapshot.view);
+  };
+
+  // Document Registry: register this window's document (or take it back
+  // after a reload), then take files and documents sent from the command
+  // line, "Open With" or other windows
+  useEffect(() => {
+    let unlisteners: (() => void)[] = [];
+    (async () => {
+      const snapshot = await invoke<WindowSnapshot | null>('take_reload_snapshot');
+      if (snapshot?.documentId != null) {
+        documentIdRef.current = snapshot.documentId;
+        setDocumentId(snapshot.documentId);
+        restoreSnapshot(snapshot);
+      } else {
+        const entry = await invoke<DocumentEntry>('register_document', { path: null });
+        documentIdRef.current = entry.id;
+        setDocumentId(entry.id);
+      }
+
+      unlisteners = await Promise.all([
+        listen<Incoming>('incoming-document', ({ payload }) => handleIncoming(payload)),
+        listen<number>('document-moved', async ({ payload }) => {
+          if (payload !== documentIdRef.current) return;
+          const next = await invoke<DocumentEntry>('register_document', { path: null });
+          documentIdRef.current = next.id;
+          setDocumentId(next.id);
+          resetDocument("");
+        }),
+      ]);
+      const pending = await invoke<Incoming[]>('take_pending_documents');
+      pending.forEach(handleIncoming);
+
+      const recovered = await invoke<JournalEntry[]>('take_recovered_documents');
+      if (recovered.length > 0) {
+        const restore = await ask(
+          `${recovered.length} unsaved document(s) from a previous session were recovered. Restore them?`,
+          { kind: 'warning', okLabel: 'Restore', cancelLabel: 'Discard' }
+        );
+        await invoke(restore ? 'restore_recovered_documents' : 'discard_recovered_documents');
+      }
+    })();
+    return () => {
+      unlisteners.forEach(f => f());
+    };
+  }, []);
+
+  // Save requested by the unsaved-changes prompt on close or quit
+  useEffect(() => {
+    const unlisten = listen('save-requested', async () => {
+      if (await handleSaveFile()) {
+        invoke('finish_close');
+      } else {
+        invoke('cancel_close');
+      }
+    });
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  // Autosave: journal unsaved changes when the recovery timer asks
+  useEffect(() => {
+    const unlisten = listen('journal-requested', () => {
+      if (documentIdRef.current === null || markdownRef.current === savedMarkdownRef.current) return;
+      invoke('journal_document', {
+        id: documentIdRef.current,
+        path: filePathRef.current,
+        text: markdownRef.current,
+        format: fileFormatRef.current,
+      }).catch(error => console.error("Failed to journal document:", error));
+    });
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  // Keep the registry's path and dirty state in sync with this window
+  useEffect(() => {
+    if (documentId === null) return;
+    invoke('update_document', { id: documentId, path: filePath, dirty: markdown !== savedMarkdown })
+      .catch(error => console.error("Failed to update document:", error));
+  }, [documentId, filePath, markdown, savedMarkdown]);
+
+  // External Change Detection
+  useEffect(() => {
+    const unlisten = listen<DocumentChanged>('document-changed', async ({ payload }) => {
+      const path = filePathRef.current;
+      if (!path || payload.path !== path) return;
+      const name = path.split(/[\\/]/).pop();
+
+      if (payload.kind === 'renamed' && payload.renamedTo) {
+        setFilePath(payload.renamedTo);
+        await invoke('watch_document', { path: payload.renamedTo });
+        return;
+      }
+      if (payload.kind === 'removed') {
+        setSavedMarkdown("");
+        await message(`"${name}" was deleted or moved by another program. Save to keep your copy.`, { kind: 'warning' });
+        return;
+      }
+
+      if (markdownRef.current === savedMarkdownRef.current) {
+        const doc = await invoke<OpenedDocument>('open_document', { path });
+        setMarkdown(doc.text);
+        setSavedMarkdown(doc.text);
+        setFileFormat(doc.format);
+        return;
+      }
+
+      const shouldMerge = await ask(
+        `"${name}" was changed by another program. Merge those changes into your unsaved edits?`,
+        { kind: 'warning', okLabel: 'Merge', cancelLabel: 'Keep Mine' }
+      );
+      if (!shouldMerge) return;
+      const result = await invoke<MergeResult>('merge_document', {
+        path,
+        base: savedMarkdownRef.current,
+        ours: markdownRef.current,
+      });
+      setMarkdown(result.text);
+      setSavedMarkdown(result.theirs);
+      setFileFormat(result.format);
+      if (result.conflicts > 0) {
+        await message(`${result.conflicts} conflicting change(s) were marked in the document.`, { kind: 'warning' });
+      }
+    });
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
   const insertMarkdown = (prefix: string, suffix: string = "") => {
     const textarea = textareaRef.current;
     if (!textarea) return;
@@ -127,6 +1131,77 @@ function App() {
     }, 0);
   };
 
+  // Replaces the selection the way typing would, so the paste can be undone
+  const insertText = (text: string, start: number, end: number) => {
+    const textarea = textareaRef.current;
+    if (!textarea) return;
+    textarea.focus();
+    textarea.setSelectionRange(start, end);
+    if (!document.execCommand('insertText', false, text)) {
+      const value = textarea.value;
+      setMarkdown(value.substring(0, start) + text + value.substring(end));
+      setTimeout(() => textarea.setSelectionRange(start + text.length, start + text.length), 0);
+    }
+  };
+
+  // Saves an image into the assets folder next to the document and links it
+  // in place of the selection
+  const addImages = async (sources: object[], start: number, end: number) => {
+    let documentPath = filePathRef.current ?? suggestedPathRef.current;
+    if (!documentPath && await handleSaveFile()) {
+      documentPath = filePathRef.current;
+    }
+    if (!documentPath) return;
+    try {
+      const saved = await Promise.all(sources.map(source =>
+        invoke<SavedImage>('save_image', { documentPath, source })
+      ));
+      insertText(saved.map(image => image.markdown).join('\n'), start, end);
+    } catch (error) {
+      console.error("Failed to add image:", error);
+      await message(`Could not add the image:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const handlePaste = async (e: ClipboardEvent<HTMLTextAreaElement>) => {
+    // Screenshots and copied images come without text
+    const image = Array.from(e.clipboardData.files).find(file => file.type.startsWith('image/'));
+    if (image && !e.clipboardData.getData('text/plain')) {
+      e.preventDefault();
+      const { selectionStart, selectionEnd } = e.currentTarget;
+      const url = await readAsDataUrl(image);
+      await addImages([{ kind: 'data', name: null, url }], selectionStart, selectionEnd);
+      return;
+    }
+    const html = e.clipboardData.getData('text/html');
+    const rtf = e.clipboardData.getData('text/rtf');
+    if (!html && !rtf) return;
+    e.preventDefault();
+    const plain = e.clipboardData.getData('text/plain');
+    const { selectionStart, selectionEnd } = e.currentTarget;
+    try {
+      const converted = await invoke<string | null>('paste_markdown', {
+        flavours: { html: html || null, rtf: rtf || null }
+      });
+      insertText(converted ?? plain, selectionStart, selectionEnd);
+    } catch (error) {
+      console.error("Failed to convert pasted text:", error);
+      insertText(plain, selectionStart, selectionEnd);
+    }
+  };
+
+  const handlePastePlainText = async () => {
+    const textarea = textareaRef.current;
+    if (!textarea || viewMode === 'reading') return;
+    const { selectionStart, selectionEnd } = textarea;
+    try {
+      const text = await invoke<string>('clipboard_text');
+      insertText(text, selectionStart, selectionEnd);
+    } catch (error) {
+      console.error("Failed to read the clipboard:", error);
+    }
+  };
+
   // Find & Replace Logic
   useEffect(() => {
     if (!findText) {
@@ -211,8 +1286,51 @@ function App() {
     setMarkdown(markdown.replace(regex, replaceText));
   };
 
+  // Folder Replace: nothing is written until the preview is accepted
+  const handlePreviewFolderReplace = async () => {
+    if (!workspaceRoot || !findText) return;
+    const query: ReplaceQuery = { find: findText, replace: replaceText, regex: useRegex, matchCase };
+    try {
+      const files = await invoke<FilePreview[]>('preview_replace', { root: workspaceRoot, query });
+      setReplacePreview({ query, files });
+      setSkippedFiles([]);
+      setReplaceBatch(null);
+      setIsFolderReplaceVisible(true);
+    } catch (error) {
+      await message(`Could not search the folder:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  // Open documents pick up the new contents through the document watcher
+  const handleApplyFolderReplace = async () => {
+    if (!workspaceRoot || !replacePreview) return;
+    const files = replacePreview.files
+      .filter(file => !skippedFiles.includes(file.path))
+      .map(({ path, hash }) => ({ path, hash }));
+    if (files.length === 0) return;
+    try {
+      const batch = await invoke<ReplaceBatch>('apply_replace', { root: workspaceRoot, query: replacePreview.query, files });
+      setReplaceBatch(batch);
+      setReplacePreview(null);
+    } catch (error) {
+      await message(`Could not replace in the folder:\n${error}`, { kind: 'warning' });
+    }
+  };
+
+  const handleUndoFolderReplace = async () => {
+    if (!replaceBatch) return;
+    try {
+      await invoke('undo_replace', { id: replaceBatch.id });
+      setReplaceBatch(null);
+      setIsFolderReplaceVisible(false);
+    } catch (error) {
+      await message(`Could not undo the replacement:\n${error}`, { kind: 'warning' });
+    }
+  };
+
   // Direct Sync Logic
   const handleScroll = (side: 'editor' | 'preview') => (e: React.UIEvent<HTMLElement>) => {
+    scheduleSessionUpdate();
     if (!isSyncScroll || viewMode !== 'split') return;
     if (activeSide.current !== side) return;
 
@@ -228,40 +1346,127 @@ function App() {
     }
   };
 
-  // Keyboard Shortcuts
+  // Throws away the unsaved changes and reads the file again
+  const handleRevert = async () => {
+    const path = filePathRef.current;
+    if (!path || markdownRef.current === savedMarkdownRef.current) return;
+    const name = path.split(/[\\/]/).pop();
+    if (!(await ask(`Discard your changes to "${name}"?`, { title: 'Revert to Saved', kind: 'warning' }))) return;
+    try {
+      const doc = await invoke<OpenedDocument>('open_document', { path });
+      setMarkdown(doc.text);
+      setSavedMarkdown(doc.text);
+      setFileFormat(doc.format);
+    } catch (error) {
+      console.error("Failed to revert file:", error);
+    }
+  };
+
+  const handleNewFile = () => {
+    setMarkdown("");
+    setSavedMarkdown("");
+    setFilePath(null);
+    setSuggestedPath(null);
+    setFileFormat(null);
+    setViewMode('editing');
+  };
+
+  // Native Menu: the backend forwards the items it doesn't handle itself.
+  // Goes through a ref so the listener always sees the current state
+  const handleMenuAction = (action: string) => {
+    switch (action) {
+      case 'new': handleNewFile(); break;
+      case 'open': handleOpenFile(); break;
+      case 'open-folder': handleOpenFolder(); break;
+      case 'import': handleImport(); break;
+      case 'paste-plain': handlePastePlainText(); break;
+      case 'save': handleSaveFile(); break;
+      case 'save-as': handleSaveFile(true); break;
+      case 'revert': handleRevert(); break;
+      case 'move-to-new-window': handleMoveToNewWindow(); break;
+      case 'export-html': handleExportHtml('embed'); break;
+      case 'export-html-folder': handleExportHtml('folder'); break;
+      case 'export-pdf': setIsPdfExportVisible(true); break;
+      case 'export-docx': handleExportDocx(); break;
+      case 'export-epub': setIsEpubExportVisible(true); break;
+      case 'reload': handleReload(); break;
+      case 'find':
+        setIsFindVisible(true);
+        setTimeout(() => findInputRef.current?.focus(), 10);
+        break;
+      case 'settings': setIsSettingsVisible(prev => !prev); break;
+      case 'toggle-toc': setIsTOCVisible(prev => !prev); break;
+      case 'go-back': goThroughHistory(-1); break;
+      case 'go-forward': goThroughHistory(1); break;
+      case 'toggle-mode': setViewMode(prev => prev === 'reading' ? 'editing' : 'reading'); break;
+      case 'view-reading': setViewMode('reading'); break;
+      case 'view-editing': setViewMode('editing'); break;
+      case 'view-split': setViewMode('split'); break;
+      case 'markdown-guide':
+        setMarkdown(markdownGuide);
+        setSavedMarkdown(markdownGuide);
+        setFilePath(null);
+        setSuggestedPath(null);
+        setFileFormat(null);
+        setViewMode('reading');
+        break;
+      case 'format-bold': insertMarkdown("**", "**"); break;
+      case 'format-italic': insertMarkdown("_", "_"); break;
+      case 'format-heading-1': insertMarkdown("# "); break;
+      case 'format-heading-2': insertMarkdown("## "); break;
+      case 'format-heading-3': insertMarkdown("### "); break;
+      case 'format-bullet-list': insertMarkdown("- "); break;
+      case 'format-code': insertMarkdown("```\n", "\n```"); break;
+      case 'format-link': insertMarkdown("[", "](url)"); break;
+      case 'format-table': insertMarkdown("| Title | Description |\n| :--- | :--- |\n| Content | Content |"); break;
+    }
+  };
+  const menuActionRef = useRef(handleMenuAction);
+  menuActionRef.current = handleMenuAction;
+
+  // Images dropped on the window are added at the cursor
+  const handleDrop = (paths: string[]) => {
+    const textarea = textareaRef.current;
+    if (!textarea || viewMode === 'reading') return;
+    const images = paths.filter(isImagePath);
+    if (images.length === 0) return;
+    addImages(images.map(path => ({ kind: 'file', path })), textarea.selectionStart, textarea.selectionEnd);
+  };
+  const dropRef = useRef(handleDrop);
+  dropRef.current = handleDrop;
+
+  useEffect(() => {
+    const unlisten = getCurrentWebview().onDragDropEvent(({ payload }) => {
+      if (payload.type === 'drop') dropRef.current(payload.paths);
+    });
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  useEffect(() => {
+    const unlisten = listen<string>('menu-action', ({ payload }) => menuActionRef.current(payload));
+    return () => {
+      unlisten.then(f => f());
+    };
+  }, []);
+
+  const isDirty = markdown !== savedMarkdown;
+  const isEmpty = markdown.trim() === '';
+  const isUntitled = filePath === null;
+
+  // Keep the menu's checked and enabled items in sync with this window
+  useEffect(() => {
+    const state = { viewMode, dirty: isDirty, untitled: isUntitled, empty: isEmpty, selection: hasSelection };
+    invoke('update_menu_state', { state })
+      .catch(error => console.error("Failed to update menu:", error));
+  }, [viewMode, isDirty, isUntitled, isEmpty, hasSelection]);
+
+  // Keyboard Shortcuts: the rest are menu accelerators
   useEffect(() => {
     const handleKeyDown = (e: KeyboardEvent) => {
       const isMod = e.metaKey || e.ctrlKey;
 
-      // Global shortcuts: available in all modes
-      if (isMod) {
-        if (e.key === 'Enter') {
-          e.preventDefault();
-          setViewMode(prev => prev === 'reading' ? 'editing' : 'reading');
-          return;
-        } else if (e.key.toLowerCase() === 's') {
-          e.preventDefault();
-          handleSaveFile();
-          return;
-        } else if (e.key.toLowerCase() === 'o') {
-          e.preventDefault();
-          handleOpenFile();
-          return;
-        } else if (e.key.toLowerCase() === 'n') {
-          e.preventDefault();
-          setMarkdown(""); 
-          setSavedMarkdown(""); 
-          setFilePath(null); 
-          setViewMode('editing');
-          return;
-        } else if (e.key.toLowerCase() === 'f') {
-          e.preventDefault();
-          setIsFindVisible(true);
-          setTimeout(() => findInputRef.current?.focus(), 10);
-          return;
-        }
-      }
-      
       if (e.key === 'Escape') {
         if (isFindVisible) {
           setIsFindVisible(false);
@@ -310,15 +1515,7 @@ function App() {
         return;
       }
 
-      if (isMod) {
-        if (e.key.toLowerCase() === 'b') {
-          e.preventDefault();
-          insertMarkdown("**", "**");
-        } else if (e.key.toLowerCase() === 'i') {
-          e.preventDefault();
-          insertMarkdown("_", "_");
-        }
-      } else if (e.key === 'Enter') {
+      if (e.key === 'Enter' && !isMod) {
         const lineStart = text.lastIndexOf('\n', start - 1) + 1;
         const line = text.substring(lineStart, start);
         const bulletMatch = line.match(/^(\s*[-*+] )/);
@@ -356,7 +1553,6 @@ function App() {
     setMarkdown(e.target.value);
   };
 
-  const isDirty = markdown !== savedMarkdown;
   const fileName = filePath ? (filePath.includes('\\') ? filePath.split('\\').pop() : filePath.split('/').pop()) : "Untitled.md";
   const displayFileName = `${fileName}${isDirty ? ' *' : ''}`;
 
@@ -390,24 +1586,10 @@ function App() {
     return 'font-sans';
   };
 
-  const headers = markdown.split('\n')
-    .filter(line => line.startsWith('#'))
-    .map(line => {
-      const level = line.match(/^#+/)?.[0].length || 1;
-      const text = line.replace(/^#+\s*/, '');
-      const id = text.toLowerCase().replace(/[^\w\s-]/g, '').replace(/\s+/g, '-');
-      return { level, text, id };
-    });
-
-  const resolveLocalPath = (href: string) => {
-    if (href.startsWith('/') || href.includes('://') || (href.includes(':') && href.includes('\\'))) return href;
-    if (!filePath) return href; 
-    const separator = filePath.includes('\\') ? '\\' : '/';
-    const parts = filePath.split(separator);
-    parts.pop(); 
-    const baseDir = parts.join(separator);
-    return `${baseDir}${separator}${href}`;
-  };
+  // Heading ids by the line each heading starts on, for the preview
+  const headingIds = new Map(headers.map(h => [h.line, h.id]));
+  const headingId = (node?: { position?: { start: { line: number } } }) =>
+    node?.position ? headingIds.get(node.position.start.line) : undefined;
 
   // Helper to highlight text in Reading Mode
   const HighlightText = ({ children }: { children: string }) => {
@@ -425,6 +1607,85 @@ function App() {
     );
   };
 
+  // A search snippet with its matches marked
+  const renderSnippet = (snippet: SearchSnippet): ReactNode[] => {
+    const parts: ReactNode[] = [];
+    let last = 0;
+    snippet.ranges.forEach(([start, end], i) => {
+      if (start < last) return;
+      parts.push(snippet.text.slice(last, start));
+      parts.push(<mark key={i} className="search-highlight">{snippet.text.slice(start, end)}</mark>);
+      last = end;
+    });
+    parts.push(snippet.text.slice(last));
+    return parts;
+  };
+
+  // The entries of an expanded folder, with the name field for a new entry
+  const renderWorkspaceFolder = (dir: string, depth: number): ReactNode[] => {
+    const indent = { paddingLeft: `${12 + depth * 12}px` };
+    const rows: ReactNode[] = [];
+    if (creatingEntry?.parent === dir) {
+      rows.push(
+        <div key={`${dir}:new`} className="flex items-center gap-1 pr-2 py-1" style={indent}>
+          <span className="w-3 shrink-0" />
+          {creatingEntry.folder ? <Folder size={12} className="shrink-0 opacity-60" /> : <FileText size={12} className="shrink-0 opacity-60" />}
+          <input
+            autoFocus
+            placeholder={creatingEntry.folder ? 'Folder name' : 'Note name'}
+            onKeyDown={(e) => {
+              if (e.key === 'Enter') handleCreateEntry(e.currentTarget.value);
+              if (e.key === 'Escape') setCreatingEntry(null);
+            }}
+            onBlur={() => setCreatingEntry(null)}
+            className="flex-1 min-w-0 bg-transparent border-b border-[var(--accent-color)] outline-none"
+          />
+        </div>
+      );
+    }
+    for (const entry of workspaceEntries[dir] ?? []) {
+      const isFolder = entry.kind === 'folder';
+      const isExpanded = isFolder && expandedFolders.includes(entry.path);
+      rows.push(
+        <div
+          key={entry.path}
+          onClick={() => {
+            if (isFolder) toggleFolder(entry.path);
+            else if (entry.kind === 'markdown') handleOpenFile(entry.path);
+          }}
+          className={`group flex items-center gap-1 pr-2 py-1 cursor-pointer hover:bg-slate-500/10 ${entry.path === filePath ? 'text-[var(--accent-color)]' : ''} ${entry.kind === 'asset' ? 'opacity-50' : ''}`}
+          style={indent}
+        >
+          {isFolder ? <ChevronRight size={12} className={`shrink-0 transition-transform ${isExpanded ? 'rotate-90' : ''}`} /> : <span className="w-3 shrink-0" />}
+          {isFolder ? <Folder size={12} className="shrink-0 opacity-60" /> : entry.kind === 'asset' ? <FileImage size={12} className="shrink-0 opacity-60" /> : <FileText size={12} className="shrink-0 opacity-60" />}
+          {renamingPath === entry.path ? (
+            <input
+              autoFocus
+              defaultValue={entry.name}
+              onClick={(e) => e.stopPropagation()}
+              onKeyDown={(e) => {
+                if (e.key === 'Enter') handleRenameEntry(entry, e.currentTarget.value);
+                if (e.key === 'Escape') setRenamingPath(null);
+              }}
+              onBlur={() => setRenamingPath(null)}
+              className="flex-1 min-w-0 bg-transparent border-b border-[var(--accent-color)] outline-none"
+            />
+          ) : (
+            <span className="flex-1 truncate" title={entry.name}>{entry.name}</span>
+          )}
+          <div className="hidden group-hover:flex gap-0.5 shrink-0 opacity-60" onClick={(e) => e.stopPropagation()}>
+            {isFolder && <button onClick={() => { setExpandedFolders(folders => folders.includes(entry.path) ? folders : [...folders, entry.path]); if (!isExpanded && workspaceRoot) loadFolder(workspaceRoot, entry.path); setCreatingEntry({ parent: entry.path, folder: false }); }} title="New Note" className="p-0.5 rounded hover:bg-slate-500/10"><FilePlus size={11} /></button>}
+            <button onClick={() => setRenamingPath(entry.path)} title="Rename" className="p-0.5 rounded hover:bg-slate-500/10"><Pencil size={11} /></button>
+            <button onClick={() => handleMoveEntry(entry)} title="Move To…" className="p-0.5 rounded hover:bg-slate-500/10"><FolderInput size={11} /></button>
+            <button onClick={() => handleDeleteEntry(entry)} title="Delete" className="p-0.5 rounded hover:text-red-500"><Trash2 size={11} /></button>
+          </div>
+        </div>
+      );
+      if (isExpanded) rows.push(...renderWorkspaceFolder(entry.path, depth + 1));
+    }
+    return rows;
+  };
+
   return (
     <div 
       className={`flex flex-col h-screen w-screen overflow-hidden transition-colors duration-200 ${getThemeClasses()}`}
@@ -470,7 +1731,7 @@ function App() {
             <button onClick={() => setViewMode('split')} className={`px-2 py-1 rounded transition-all ${viewMode === 'split' ? (theme === 'light' ? 'bg-white shadow-sm' : 'bg-[#45475a] text-white') : 'text-slate-400 hover:text-slate-600'}`}>
               <Columns2 size={14} />
             </button>
-            <button onClick={() => setIsSyncScroll(!isSyncScroll)} className={`px-2 py-1 rounded transition-all ${isSyncScroll && viewMode === 'split' ? (theme === 'light' ? 'bg-white shadow-sm text-[var(--accent-color)]' : 'bg-[#45475a] text-white') : 'text-slate-400 hover:text-slate-600'}`}>
+            <button onClick={() => updateSettings({ syncScroll: !isSyncScroll })} className={`px-2 py-1 rounded transition-all ${isSyncScroll && viewMode === 'split' ? (theme === 'light' ? 'bg-white shadow-sm text-[var(--accent-color)]' : 'bg-[#45475a] text-white') : 'text-slate-400 hover:text-slate-600'}`}>
               <Link2 size={14} />
             </button>
             <button onClick={() => setViewMode('reading')} className={`px-2 py-1 rounded transition-all ${(viewMode as string) === 'reading' ? (theme === 'light' ? 'bg-white shadow-sm' : 'bg-[#45475a] text-white') : 'text-slate-400 hover:text-slate-600'}`}>
@@ -523,6 +1784,18 @@ function App() {
                 <button onClick={handleReplace} className="px-3 py-1 bg-slate-500/10 hover:bg-slate-500/20 rounded text-[10px] font-bold uppercase tracking-wider">Replace</button>
                 <button onClick={handleReplaceAll} className="px-3 py-1 bg-slate-500/10 hover:bg-slate-500/20 rounded text-[10px] font-bold uppercase tracking-wider">All</button>
               </div>
+              {workspaceRoot && (
+                <div className="flex gap-1">
+                  <button
+                    onClick={() => setUseRegex(!useRegex)}
+                    title="Regular expression, for replacing in the folder"
+                    className={`px-2 py-1 rounded text-[10px] font-bold border transition-all ${useRegex ? 'border-[var(--accent-color)] text-[var(--accent-color)] bg-[var(--accent-color)]/10' : 'border-slate-500/20 opacity-40'}`}
+                  >
+                    .*
+                  </button>
+                  <button onClick={handlePreviewFolderReplace} className="px-3 py-1 bg-slate-500/10 hover:bg-slate-500/20 rounded text-[10px] font-bold uppercase tracking-wider">In Folder…</button>
+                </div>
+              )}
             </div>
           )}
 
@@ -531,6 +1804,56 @@ function App() {
       )}
 
       <main className="flex-1 flex overflow-hidden relative min-h-0">
+        {workspaceRoot && (
+          <aside className={`w-60 shrink-0 h-full overflow-y-auto border-r border-slate-500/10 text-xs select-none ${theme === 'dark' ? 'bg-[#181825]' : (theme === 'dim' ? 'bg-[#2a2a2a]' : 'bg-[#e6e9ef]')}`}>
+            <div className="flex items-center justify-between gap-2 px-3 py-2 border-b border-slate-500/10">
+              <span className="text-[10px] font-bold tracking-widest uppercase opacity-50 truncate" title={workspaceRoot}>{workspaceRoot.split(/[\\/]/).filter(Boolean).pop()}</span>
+              <div className="flex gap-0.5 shrink-0">
+                <button onClick={() => setCreatingEntry({ parent: workspaceRoot, folder: false })} title="New Note" className="p-1 rounded hover:bg-slate-500/10 opacity-60"><FilePlus size={12} /></button>
+                <button onClick={() => setCreatingEntry({ parent: workspaceRoot, folder: true })} title="New Folder" className="p-1 rounded hover:bg-slate-500/10 opacity-60"><FolderPlus size={12} /></button>
+                <button onClick={refreshWorkspace} title="Refresh" className="p-1 rounded hover:bg-slate-500/10 opacity-60"><RefreshCw size={12} /></button>
+                <button onClick={() => setWorkspaceRoot(null)} title="Close Folder" className="p-1 rounded hover:text-red-500 opacity-60"><X size={12} /></button>
+              </div>
+            </div>
+            <div className="flex items-center gap-1.5 px-3 py-1.5 border-b border-slate-500/10">
+              <Search size={12} className="shrink-0 opacity-50" />
+              <input
+                value={searchQuery}
+                onChange={(e) => setSearchQuery(e.target.value)}
+                onKeyDown={(e) => { if (e.key === 'Escape') setSearchQuery(""); }}
+                placeholder='Search: "phrase", pre*, /regex/, tag:'
+                className="flex-1 min-w-0 bg-transparent outline-none"
+              />
+              {searchQuery && <button onClick={() => setSearchQuery("")} title="Clear" className="p-0.5 rounded hover:bg-slate-500/10 opacity-60"><X size={11} /></button>}
+            </div>
+            {searchQuery.trim() ? (
+              <div className="py-1 select-text">
+                {searchError && <div className="px-3 py-1 text-red-500">{searchError}</div>}
+                {!searchError && searchResults.length === 0 && <div className="px-3 py-1 opacity-50">No matches</div>}
+                {searchResults.map(result => (
+                  <div key={result.path} className="px-2 py-1">
+                    <div
+                      onClick={() => openSearchResult(result.path, result.snippets[0]?.line ?? 1)}
+                      className={`flex items-center gap-1 px-1 cursor-pointer font-bold hover:text-[var(--accent-color)] ${result.path === filePath ? 'text-[var(--accent-color)]' : ''}`}
+                      title={result.path}
+                    >
+                      <FileText size={12} className="shrink-0 opacity-60" />
+                      <span className="truncate">{result.title}</span>
+                    </div>
+                    {result.snippets.map(snippet => (
+                      <div key={snippet.line} onClick={() => openSearchResult(result.path, snippet.line)} className="flex gap-1.5 pl-5 pr-1 py-0.5 cursor-pointer rounded hover:bg-slate-500/10">
+                        <span className="shrink-0 opacity-40 tabular-nums">{snippet.line}</span>
+                        <span className="min-w-0 break-words opacity-80">{renderSnippet(snippet)}</span>
+                      </div>
+                    ))}
+                  </div>
+                ))}
+              </div>
+            ) : (
+              <div className="py-1">{renderWorkspaceFolder(workspaceRoot, 0)}</div>
+            )}
+          </aside>
+        )}
         {(viewMode === 'editing' || viewMode === 'split') && (
           <div 
             className={`${viewMode === 'split' ? 'w-1/2 border-r border-slate-500/10' : 'w-full'} h-full overflow-hidden`}
@@ -539,10 +1862,12 @@ function App() {
             <textarea
               ref={textareaRef}
               onScroll={handleScroll('editor')}
+              onSelect={handleEditorSelect}
               className="w-full h-full p-10 pb-[30vh] mx-auto resize-none outline-none bg-transparent font-sans leading-relaxed selection:bg-[var(--accent-color)] selection:text-white max-w-[720px] block overflow-y-auto"
               style={{ fontSize: `${fontSize}px`, scrollBehavior: 'auto' }}
               value={markdown}
               onChange={handleEditorChange}
+              onPaste={handlePaste}
               placeholder="Start writing..."
               spellCheck={false}
               autoFocus
@@ -565,31 +1890,31 @@ function App() {
                   components={{
                     // Use standard elements but highlight their text content
                     p: ({node, ...props}) => <p {...props} />,
-                    h1: ({node, ...props}) => <h1 id={props.children?.toString().toLowerCase().replace(/[^\w\s-]/g, '').replace(/\s+/g, '-')} {...props} />,
-                    h2: ({node, ...props}) => <h2 className="text-xl" id={props.children?.toString().toLowerCase().replace(/[^\w\s-]/g, '').replace(/\s+/g, '-')} {...props} />,
-                    h3: ({node, ...props}) => <h3 id={props.children?.toString().toLowerCase().replace(/[^\w\s-]/g, '').replace(/\s+/g, '-')} {...props} />,
+                    h1: ({node, ...props}) => <h1 id={headingId(node)} {...props} />,
+                    h2: ({node, ...props}) => <h2 className="text-xl" id={headingId(node)} {...props} />,
+                    h3: ({node, ...props}) => <h3 id={headingId(node)} {...props} />,
+                    h4: ({node, ...props}) => <h4 id={headingId(node)} {...props} />,
+                    h5: ({node, ...props}) => <h5 id={headingId(node)} {...props} />,
+                    h6: ({node, ...props}) => <h6 id={headingId(node)} {...props} />,
                     // Handle plain text nodes for highlighting
                     text: ({node, ...props}) => <HighlightText>{props.children as string}</HighlightText>,
                     a: ({ node, ...props }) => {
                       const href = props.href || "";
                       const isExternal = href.startsWith("http") || href.startsWith("https");
                       const isAnchor = href.startsWith("#");
-                      const isMd = href.toLowerCase().endsWith(".md");
-                      const isImageFile = IMAGE_EXTENSIONS.some(ext => href.toLowerCase().endsWith(ext));
+                      // `C:` is a Windows drive, not a scheme
+                      const isOtherScheme = /^[a-z][a-z0-9+.-]+:/i.test(href) && !/^file:/i.test(href);
+                      const isImageFile = isImagePath(href);
 
                       if (isImageFile) {
-                        const fullPath = resolveLocalPath(href);
-                        const finalSrc = isExternal ? href : (fullPath ? convertFileSrc(fullPath) : "");
                         return (
                           <span className="block my-6 mx-auto text-center bg-slate-500/5 rounded-lg p-2 group">
-                            <img 
-                              src={finalSrc} 
+                            <LocalImage 
+                              src={href} 
+                              documentPath={filePath ?? suggestedPath}
                               alt={props.children?.toString() || "Markdown Image"}
                               className="max-w-full rounded-lg shadow-lg inline-block transition-transform hover:scale-[1.02]"
                               loading="lazy"
-                              onError={(e) => {
-                                (e.target as HTMLImageElement).style.opacity = '0.3';
-                              }}
                             />
                             <br />
                             <small className="opacity-40 italic mt-2 inline-block group-hover:opacity-60 transition-opacity">{props.children}</small>
@@ -607,29 +1932,32 @@ function App() {
                           if (element) {
                             element.scrollIntoView({ behavior: 'smooth' });
                           }
-                        } else if (isMd) {
+                        } else if (isOtherScheme) {
+                          await openUrl(href);
+                        } else {
                           const normalizedHref = href.startsWith('/') ? href.substring(1) : href;
                           if (normalizedHref === 'MarkdownGuide.md') {
+                            if (!(await confirmLeave())) return;
                             setMarkdown(markdownGuide);
                             setSavedMarkdown(markdownGuide);
                             setFilePath(null);
+                            setSuggestedPath(null);
+                            setFileFormat(null);
                             setViewMode('reading');
                             return;
                           }
                           if (normalizedHref === 'Opening.md') {
+                            if (!(await confirmLeave())) return;
                             setMarkdown(openingMd);
                             setSavedMarkdown(openingMd);
                             setFilePath(null);
+                            setSuggestedPath(null);
+                            setFileFormat(null);
                             setViewMode('reading');
                             return;
                           }
 
-                          const fullPath = resolveLocalPath(href);
-                          if (fullPath) {
-                            handleOpenFile(fullPath);
-                          } else {
-                            handleOpenFile();
-                          }
+                          await followLink(href);
                         }
                       };
 
@@ -641,24 +1969,15 @@ function App() {
                         />
                       );
                     },
-                    img: ({ node, ...props }) => {
-                      const src = props.src || "";
-                      const isExternal = src.startsWith("http") || src.startsWith("https");
-                      const fullPath = resolveLocalPath(src);
-                      const finalSrc = isExternal ? src : (fullPath ? convertFileSrc(fullPath) : "");
-                      
-                      return (
-                        <img 
-                          {...props} 
-                          src={finalSrc} 
-                          className="max-w-full rounded-lg shadow-lg my-6 mx-auto block transition-transform hover:scale-[1.01]"
-                          loading="lazy"
-                          onError={(e) => {
-                            (e.target as HTMLImageElement).style.opacity = '0.3';
-                          }}
-                        />
-                      );
-                    }
+                    img: ({ node, ...props }) => (
+                      <LocalImage 
+                        {...props} 
+                        src={props.src || ""} 
+                        documentPath={filePath ?? suggestedPath}
+                        className="max-w-full rounded-lg shadow-lg my-6 mx-auto block transition-transform hover:scale-[1.01]"
+                        loading="lazy"
+                      />
+                    )
                   }}
                 >
                   {markdown}
@@ -699,7 +2018,7 @@ function App() {
                   onClick={() => {
                     const themes: Theme[] = ['light', 'dark', 'dim'];
                     const nextIndex = (themes.indexOf(theme) + 1) % themes.length;
-                    setTheme(themes[nextIndex]);
+                    updateSettings({ theme: themes[nextIndex] });
                   }} 
                   className={`text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all active:scale-95 border border-transparent ${
                     theme === 'light' ? 'bg-[#dce0e8] text-[var(--accent-color)]' : 
@@ -710,9 +2029,83 @@ function App() {
                   {theme} mode
                 </button>
               </div>
-              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Accent</span><div className="flex gap-1.5">{ACCENT_COLORS.map(c => <button key={c.name} onClick={() => setAccentColorName(c.name)} className={`w-3.5 h-3.5 rounded-full transition-transform active:scale-90 ${accentColorName === c.name ? 'ring-2 ring-offset-2 ring-slate-400 scale-110' : 'opacity-40'}`} style={{ backgroundColor: theme === 'light' ? c.light : c.dark }} />)}</div></div>
-              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Font Size</span><div className="flex items-center gap-2"><button onClick={() => setFontSize(Math.max(12, fontSize - 1))} className="p-1 rounded transition-all hover:bg-slate-500/10"><Minus size={14} /></button><span className="text-[10px] font-bold opacity-40 w-8 text-center">{fontSize}px</span><button onClick={() => setFontSize(Math.min(24, fontSize + 1))} className="p-1 rounded transition-all hover:bg-slate-500/10"><Plus size={14} /></button></div></div>
-              <div className="flex flex-col gap-2 pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">Font Family</span><div className="flex gap-1.5">{(['sans', 'serif', 'mono'] as FontFamily[]).map(f => <button key={f} onClick={() => setFontFamily(f)} className={`flex-1 text-[9px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border ${fontFamily === f ? `bg-white/10 border-transparent text-[var(--accent-color)]` : `border-slate-500/10 opacity-40 hover:opacity-100`}`}>{f}</button>)}</div></div>
+              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Accent</span><div className="flex gap-1.5">{ACCENT_COLORS.map(c => <button key={c.name} onClick={() => updateSettings({ accentColor: c.name })} className={`w-3.5 h-3.5 rounded-full transition-transform active:scale-90 ${accentColorName === c.name ? 'ring-2 ring-offset-2 ring-slate-400 scale-110' : 'opacity-40'}`} style={{ backgroundColor: theme === 'light' ? c.light : c.dark }} />)}</div></div>
+              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Font Size</span><div className="flex items-center gap-2"><button onClick={() => updateSettings({ fontSize: Math.max(12, fontSize - 1) })} className="p-1 rounded transition-all hover:bg-slate-500/10"><Minus size={14} /></button><span className="text-[10px] font-bold opacity-40 w-8 text-center">{fontSize}px</span><button onClick={() => updateSettings({ fontSize: Math.min(24, fontSize + 1) })} className="p-1 rounded transition-all hover:bg-slate-500/10"><Plus size={14} /></button></div></div>
+              <div className="flex flex-col gap-2 pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">Font Family</span><div className="flex gap-1.5">{(['sans', 'serif', 'mono'] as FontFamily[]).map(f => <button key={f} onClick={() => updateSettings({ fontFamily: f })} className={`flex-1 text-[9px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border ${fontFamily === f ? `bg-white/10 border-transparent text-[var(--accent-color)]` : `border-slate-500/10 opacity-40 hover:opacity-100`}`}>{f}</button>)}</div></div>
+              <div className="flex items-center justify-between pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Restore Session</span><button onClick={() => updateSettings({ restoreSession: !settings.restoreSession })} className={`text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all ${settings.restoreSession ? 'text-[var(--accent-color)]' : 'opacity-40 hover:opacity-100'}`}>{settings.restoreSession ? 'On' : 'Off'}</button></div>
+              <div className="flex items-center justify-between pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Image Folder</span><input key={settings.images.folder} defaultValue={settings.images.folder} onBlur={e => { if (e.target.value !== settings.images.folder) updateSettings({ images: { ...settings.images, folder: e.target.value } }); }} className="w-28 text-[10px] text-right bg-transparent border-b border-slate-500/20 outline-none focus:border-[var(--accent-color)]" /></div>
+              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Downsize Images</span><button onClick={() => updateSettings({ images: { ...settings.images, maxDimension: IMAGE_SIZES[(IMAGE_SIZES.indexOf(settings.images.maxDimension) + 1) % IMAGE_SIZES.length] } })} className={`text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all ${settings.images.maxDimension ? 'text-[var(--accent-color)]' : 'opacity-40 hover:opacity-100'}`}>{settings.images.maxDimension ? `${settings.images.maxDimension}px` : 'Off'}</button></div>
+              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">App Data</span><button onClick={handleResetAppData} className="text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all hover:text-red-500">Reset</button></div>
+            </div>
+          </div>
+        )}
+
+        {isFolderReplaceVisible && workspaceRoot && (
+          <div className={`fixed bottom-12 right-4 w-[28rem] max-h-[70vh] flex flex-col border p-4 z-50 animate-in fade-in slide-in-from-bottom-2 duration-200 shadow-xl rounded-xl ${getSecondaryThemeClasses()} ${theme === 'light' ? 'text-[#4c4f69]' : 'text-[#f2f2f2]'}`}>
+            <div className="flex items-center justify-between mb-3 border-b border-slate-500/10 pb-2"><h3 className="text-[10px] font-bold tracking-widest uppercase opacity-40">Replace in Folder</h3><button onClick={() => setIsFolderReplaceVisible(false)} className="text-[10px] font-bold tracking-widest uppercase hover:text-red-500 transition-colors">Close</button></div>
+            {replaceBatch ? (
+              <div className="space-y-4">
+                <div className="text-xs">Replaced {replaceBatch.count} {replaceBatch.count === 1 ? 'match' : 'matches'} in {replaceBatch.files.length} {replaceBatch.files.length === 1 ? 'file' : 'files'}.</div>
+                <button onClick={handleUndoFolderReplace} className="w-full text-[10px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border border-slate-500/10 hover:text-red-500 hover:bg-white/10">Undo</button>
+              </div>
+            ) : replacePreview && replacePreview.files.length === 0 ? (
+              <div className="text-xs opacity-50">No matches in the folder.</div>
+            ) : replacePreview && (
+              <>
+                <div className="flex-1 min-h-0 overflow-y-auto space-y-3 text-xs">
+                  {replacePreview.files.map(file => (
+                    <div key={file.path}>
+                      <label className="flex items-center gap-2 font-bold cursor-pointer">
+                        <input
+                          type="checkbox"
+                          checked={!skippedFiles.includes(file.path)}
+                          onChange={(e) => setSkippedFiles(files => e.target.checked ? files.filter(path => path !== file.path) : [...files, file.path])}
+                          className="accent-[var(--accent-color)]"
+                        />
+                        <span className="flex-1 truncate" title={file.path}>{file.path.slice(workspaceRoot.length).replace(/^[\\/]/, '')}</span>
+                        <span className="opacity-40 font-mono text-[10px]">{file.count}</span>
+                      </label>
+                      {file.hunks.map(hunk => (
+                        <div key={hunk.line} className={`flex gap-2 pl-5 py-0.5 font-mono text-[10px] ${skippedFiles.includes(file.path) ? 'opacity-30' : ''}`}>
+                          <span className="shrink-0 w-6 text-right opacity-40">{hunk.line}</span>
+                          <div className="min-w-0 flex-1 whitespace-pre-wrap break-words">
+                            <div className="text-red-500 line-through opacity-70">{hunk.before}</div>
+                            <div className="text-[var(--accent-color)]">{hunk.after}</div>
+                          </div>
+                        </div>
+                      ))}
+                    </div>
+                  ))}
+                </div>
+                <button onClick={handleApplyFolderReplace} className="mt-3 w-full text-[10px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border border-slate-500/10 text-[var(--accent-color)] hover:bg-white/10">
+                  Replace in {replacePreview.files.length - skippedFiles.length} {replacePreview.files.length - skippedFiles.length === 1 ? 'file' : 'files'}
+                </button>
+              </>
+            )}
+          </div>
+        )}
+
+        {isEpubExportVisible && (
+          <div className={`fixed bottom-12 right-4 w-72 border p-4 z-50 animate-in fade-in slide-in-from-bottom-2 duration-200 shadow-xl rounded-xl ${getSecondaryThemeClasses()} ${theme === 'light' ? 'text-[#4c4f69]' : 'text-[#f2f2f2]'}`}>
+            <div className="flex items-center justify-between mb-3 border-b border-slate-500/10 pb-2"><h3 className="text-[10px] font-bold tracking-widest uppercase opacity-40">Export EPUB</h3><button onClick={() => setIsEpubExportVisible(false)} className="text-[10px] font-bold tracking-widest uppercase hover:text-red-500 transition-colors">Close</button></div>
+            <div className="space-y-4">
+              <div className="flex flex-col gap-2"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">New Chapter At</span><div className="flex gap-1.5">{[1, 2, 3].map(level => <button key={level} onClick={() => setEpubChapterLevel(level)} className={`flex-1 text-[9px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border ${epubChapterLevel === level ? `bg-white/10 border-transparent text-[var(--accent-color)]` : `border-slate-500/10 opacity-40 hover:opacity-100`}`}>H{level}</button>)}</div></div>
+              <div className="text-[10px] opacity-40">Title, author and cover come from the front matter (title, author, cover).</div>
+              <button onClick={handleExportEpub} className="w-full text-[10px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border border-slate-500/10 text-[var(--accent-color)] hover:bg-white/10">Export</button>
+            </div>
+          </div>
+        )}
+
+        {isPdfExportVisible && (
+          <div className={`fixed bottom-12 right-4 w-72 border p-4 z-50 animate-in fade-in slide-in-from-bottom-2 duration-200 shadow-xl rounded-xl ${getSecondaryThemeClasses()} ${theme === 'light' ? 'text-[#4c4f69]' : 'text-[#f2f2f2]'}`}>
+            <div className="flex items-center justify-between mb-3 border-b border-slate-500/10 pb-2"><h3 className="text-[10px] font-bold tracking-widest uppercase opacity-40">Export PDF</h3><button onClick={() => setIsPdfExportVisible(false)} className="text-[10px] font-bold tracking-widest uppercase hover:text-red-500 transition-colors">Close</button></div>
+            <div className="space-y-4">
+              <div className="flex flex-col gap-2"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">Page Size</span><div className="flex gap-1.5">{(['a4', 'a5', 'letter', 'legal'] as PageSize[]).map(size => <button key={size} onClick={() => setPdfOptions({ ...pdfOptions, pageSize: size })} className={`flex-1 text-[9px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border ${pdfOptions.pageSize === size ? `bg-white/10 border-transparent text-[var(--accent-color)]` : `border-slate-500/10 opacity-40 hover:opacity-100`}`}>{size}</button>)}</div></div>
+              <div className="flex items-center justify-between"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Landscape</span><button onClick={() => setPdfOptions({ ...pdfOptions, landscape: !pdfOptions.landscape })} className={`text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all ${pdfOptions.landscape ? 'text-[var(--accent-color)]' : 'opacity-40 hover:opacity-100'}`}>{pdfOptions.landscape ? 'On' : 'Off'}</button></div>
+              <div className="flex flex-col gap-2 pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">Margins (mm)</span><div className="flex gap-1.5">{(['top', 'right', 'bottom', 'left'] as const).map(side => <input key={side} type="number" min={0} max={60} title={side} value={pdfOptions.margins[side]} onChange={(e) => setPdfOptions({ ...pdfOptions, margins: { ...pdfOptions.margins, [side]: Number(e.target.value) } })} className="w-full min-w-0 bg-transparent border border-slate-500/10 rounded px-1.5 py-1 text-[10px] outline-none" />)}</div></div>
+              <div className="flex flex-col gap-2 pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50 mb-1">Header / Footer</span><input type="text" placeholder="Header, e.g. {title}" value={pdfOptions.header} onChange={(e) => setPdfOptions({ ...pdfOptions, header: e.target.value })} className="bg-transparent border border-slate-500/10 rounded px-1.5 py-1 text-[10px] outline-none" /><input type="text" placeholder="Footer, e.g. {page} / {pages}" value={pdfOptions.footer} onChange={(e) => setPdfOptions({ ...pdfOptions, footer: e.target.value })} className="bg-transparent border border-slate-500/10 rounded px-1.5 py-1 text-[10px] outline-none" /></div>
+              <div className="flex items-center justify-between pt-2 border-t border-slate-500/10"><span className="text-[10px] font-bold tracking-widest uppercase opacity-50">Contents</span><button onClick={() => setPdfOptions({ ...pdfOptions, tableOfContents: !pdfOptions.tableOfContents })} className={`text-[10px] font-bold tracking-widest uppercase px-2 py-1 rounded transition-all ${pdfOptions.tableOfContents ? 'text-[var(--accent-color)]' : 'opacity-40 hover:opacity-100'}`}>{pdfOptions.tableOfContents ? 'On' : 'Off'}</button></div>
+              <button onClick={handleExportPdf} className="w-full text-[10px] font-bold tracking-widest uppercase py-1.5 rounded transition-all border border-slate-500/10 text-[var(--accent-color)] hover:bg-white/10">Export</button>
             </div>
           </div>
         )}
@@ -723,7 +2116,7 @@ function App() {
               <div className="flex-1 flex items-center gap-3 text-[9px] font-bold tracking-widest uppercase">
                 <span style={{ color: accentColor }} className={`transition-opacity cursor-default ${viewMode === 'reading' ? 'opacity-50' : 'opacity-100'}`}>{viewMode.toUpperCase()}</span>
                 <button onClick={() => handleOpenFile()} className={`text-[var(--accent-color)] hover:opacity-100 transition-opacity uppercase ${viewMode === 'reading' ? 'opacity-50' : 'opacity-100'}`}>OPEN</button>
-                <button onClick={() => { setMarkdown(""); setSavedMarkdown(""); setFilePath(null); setViewMode('editing'); }} className={`text-[var(--accent-color)] hover:opacity-100 transition-opacity uppercase ${viewMode === 'reading' ? 'opacity-50' : 'opacity-100'}`}>NEW</button>
+                <button onClick={handleNewFile} className={`text-[var(--accent-color)] hover:opacity-100 transition-opacity uppercase ${viewMode === 'reading' ? 'opacity-50' : 'opacity-100'}`}>NEW</button>
                 <button onClick={() => { setIsFindVisible(!isFindVisible); if (!isFindVisible) setTimeout(() => findInputRef.current?.focus(), 10); }} className={`transition-all uppercase text-[var(--accent-color)] ${isFindVisible ? 'opacity-100' : (viewMode === 'reading' ? 'opacity-50 hover:opacity-100' : 'opacity-100')}`}>FIND</button>
               </div>
       